@@ -1,28 +1,398 @@
+use crate::config::BackupConfig;
+use crate::disk_space::DiskSpaceInfo;
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
 const MAX_BACKUPS: usize = 50;
 
+/// Warn about a backup larger than this when no `max_size_gb` is set
+const WARN_BACKUP_SIZE_GB: u64 = 2;
+
+/// Version of `operation.json` written by this build. Version 2 added
+/// per-file checksums and version 3 payload compression; older metadata
+/// has no `version` field.
+pub const METADATA_VERSION: u32 = 3;
+
+fn legacy_metadata_version() -> u32 {
+    1
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackupMetadata {
+    #[serde(default = "legacy_metadata_version")]
+    pub version: u32,
     pub id: String,
     pub timestamp: DateTime<Utc>,
     pub expression: String,
     pub files: Vec<FileBackup>,
 }
 
+/// Copy one file into `backup_dir`, gzip-compressed as `<name>.gz` if
+/// asked, and checksum the stored payload
+fn back_up_file(
+    backup_dir: &Path,
+    file_path: &Path,
+    compression: Compression,
+) -> Result<FileBackup> {
+    let file_name = file_path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid file name: {}", file_path.display()))?;
+
+    let backup_path = match compression {
+        Compression::None => {
+            let backup_path = backup_dir.join(file_name);
+            fs::copy(file_path, &backup_path)
+                .with_context(|| format!("Failed to backup file: {}", file_path.display()))?;
+            backup_path
+        }
+        Compression::Gzip => {
+            let mut payload_name = file_name.to_os_string();
+            payload_name.push(".gz");
+            let backup_path = backup_dir.join(payload_name);
+            gzip_file(file_path, &backup_path)
+                .with_context(|| format!("Failed to backup file: {}", file_path.display()))?;
+            backup_path
+        }
+    };
+
+    let sha256 = sha256_file(&backup_path)?;
+    Ok(FileBackup {
+        original_path: file_path.to_path_buf(),
+        backup_path,
+        sha256: Some(sha256),
+        compression,
+    })
+}
+
+/// Write a gzip-compressed copy of `src` to `dest`, with the permissions of
+/// `src` as `fs::copy` would give it
+fn gzip_file(src: &Path, dest: &Path) -> Result<()> {
+    let mut input = fs::File::open(src)?;
+    let mut encoder =
+        flate2::write::GzEncoder::new(fs::File::create(dest)?, flate2::Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    fs::set_permissions(dest, input.metadata()?.permissions())?;
+    Ok(())
+}
+
+/// Oldest first, with the ID breaking ties between equal timestamps (rapid
+/// runs can share one), so every listing puts backups in the same order
+fn chronological(a: &BackupMetadata, b: &BackupMetadata) -> std::cmp::Ordering {
+    a.timestamp.cmp(&b.timestamp).then_with(|| a.id.cmp(&b.id))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileBackup {
     pub original_path: PathBuf,
     pub backup_path: PathBuf,
+    /// Hex SHA-256 of the backup payload; `None` for version 1 metadata,
+    /// whose backups are restored without verification
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// How the payload is stored; metadata before version 3 only has
+    /// uncompressed copies
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+}
+
+/// How a backup payload is stored on disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// A plain copy of the file
+    #[default]
+    None,
+    /// A gzip stream, named `<file>.gz`
+    Gzip,
+}
+
+impl Compression {
+    fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+}
+
+impl FileBackup {
+    /// The backed-up content of the file, decompressed if the payload is
+    /// compressed
+    pub fn read_contents(&self) -> Result<Vec<u8>> {
+        let read_error = || format!("Failed to read backup file: {}", self.backup_path.display());
+        let payload = fs::read(&self.backup_path).with_context(read_error)?;
+        match self.compression {
+            Compression::None => Ok(payload),
+            Compression::Gzip => {
+                let mut contents = Vec::new();
+                flate2::read::GzDecoder::new(payload.as_slice())
+                    .read_to_end(&mut contents)
+                    .with_context(read_error)?;
+                Ok(contents)
+            }
+        }
+    }
+
+    /// Put the backed-up content and permissions back at `original_path`
+    fn restore(&self) -> Result<()> {
+        let restore_error = || format!("Failed to restore file: {}", self.original_path.display());
+        match self.compression {
+            Compression::None => {
+                fs::copy(&self.backup_path, &self.original_path).with_context(restore_error)?;
+            }
+            Compression::Gzip => {
+                let contents = self.read_contents()?;
+                fs::write(&self.original_path, contents).with_context(restore_error)?;
+                let permissions = fs::metadata(&self.backup_path)?.permissions();
+                fs::set_permissions(&self.original_path, permissions)
+                    .with_context(restore_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Selects backups by the files they contain and their expression
+#[derive(Debug, Clone, Default)]
+pub struct BackupFilter {
+    /// Only backups that include this file
+    pub file: Option<PathBuf>,
+    /// Only backups whose expression contains this substring
+    pub expression: Option<String>,
+}
+
+impl BackupFilter {
+    pub fn matches(&self, backup: &BackupMetadata) -> bool {
+        let file_matches = self.file.as_ref().is_none_or(|file| {
+            // Paths are recorded as given on the command line, so compare
+            // canonical forms too when both files still exist
+            backup
+                .files
+                .iter()
+                .any(|f| is_same_file(&f.original_path, file))
+        });
+        let expression_matches = self
+            .expression
+            .as_ref()
+            .is_none_or(|needle| backup.expression.contains(needle.as_str()));
+        file_matches && expression_matches
+    }
+}
+
+/// Whether `path` names the file recorded as `original_path`, which is kept
+/// as it was given on the command line
+fn is_same_file(original_path: &Path, path: &Path) -> bool {
+    if original_path == path {
+        return true;
+    }
+    match (fs::canonicalize(original_path), fs::canonicalize(path)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The payload's SHA-256 if it differs from the recorded checksum;
+/// payloads without a checksum always pass
+fn checksum_mismatch(file_backup: &FileBackup) -> Result<Option<String>> {
+    let Some(expected) = &file_backup.sha256 else {
+        return Ok(None);
+    };
+    let actual = sha256_file(&file_backup.backup_path)?;
+    Ok((actual != *expected).then_some(actual))
+}
+
+/// Fail if a backed-up file's payload no longer matches its checksum
+fn check_payload(file_backup: &FileBackup) -> Result<()> {
+    if file_backup.backup_path.exists()
+        && let Some(actual) = checksum_mismatch(file_backup)?
+    {
+        anyhow::bail!(
+            "Backup file {} is corrupted: SHA-256 is {}, expected {}. Nothing was restored.",
+            file_backup.backup_path.display(),
+            actual,
+            file_backup.sha256.as_deref().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// What is wrong with a backed-up file's payload, if anything: it is
+/// missing, unreadable or no longer matches its checksum
+fn payload_problem(file_backup: &FileBackup) -> Option<String> {
+    if !file_backup.backup_path.exists() {
+        return Some(format!(
+            "{}: backup file missing: {}",
+            file_backup.original_path.display(),
+            file_backup.backup_path.display()
+        ));
+    }
+    match checksum_mismatch(file_backup) {
+        Ok(None) => None,
+        Ok(Some(actual)) => Some(format!(
+            "{}: backup file {} is corrupted: SHA-256 is {}, expected {}",
+            file_backup.original_path.display(),
+            file_backup.backup_path.display(),
+            actual,
+            file_backup.sha256.as_deref().unwrap_or_default()
+        )),
+        Err(e) => Some(format!("{}: {:#}", file_backup.original_path.display(), e)),
+    }
+}
+
+/// The outcome of checking one backup in `verify_all`
+#[derive(Debug, Clone)]
+pub struct BackupCheck {
+    pub id: String,
+    /// Number of backed-up files checked
+    pub files: usize,
+    /// One line per problem found; empty if the backup is intact
+    pub problems: Vec<String>,
+}
+
+impl BackupCheck {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Totals over every backup in a directory, for `sedx status`
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupStatus {
+    pub backup_dir: PathBuf,
+    pub total_backups: usize,
+    /// Bytes the backed-up file payloads take on disk
+    pub total_size_bytes: u64,
+    /// `total_size_bytes` for people, e.g. "1.5 MB"
+    pub total_size: String,
+    pub oldest: Option<DateTime<Utc>>,
+    pub newest: Option<DateTime<Utc>>,
+    /// The most recent backup, as `sedx rollback` would undo it
+    pub last_operation: Option<LastOperation>,
+}
+
+/// The most recent backup in a `BackupStatus`
+#[derive(Debug, Clone, Serialize)]
+pub struct LastOperation {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub expression: String,
+}
+
+/// Generate unique backup ID with millisecond precision for deterministic sorting
+fn generate_backup_id() -> String {
+    format!(
+        "{}-{}",
+        Utc::now().format("%Y%m%d-%H%M%S%3f"),
+        Uuid::new_v4().to_string().split_at(8).0
+    )
+}
+
+/// Whether `id` has the shape `generate_backup_id` gives (or gave, before
+/// milliseconds were added): `YYYYMMDD-HHMMSS[mmm]-xxxxxxxx`. Anything
+/// else, such as `..` or a path, can't name a backup directory.
+fn is_backup_id(id: &str) -> bool {
+    let parts: Vec<&str> = id.split('-').collect();
+    let digits = |part: &str, lens: &[usize]| {
+        lens.contains(&part.len()) && part.bytes().all(|b| b.is_ascii_digit())
+    };
+    matches!(parts.as_slice(), [date, time, suffix]
+        if digits(date, &[8])
+            && digits(time, &[6, 9])
+            && suffix.len() == 8
+            && suffix.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Archives named `*.tar.gz` or `*.tgz` are gzip-compressed
+fn is_gzip_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// Hex-encoded SHA-256 of a file's contents
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Failed to open file for hashing: {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to hash file: {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Expand a leading `~` or `~user` and `$VAR` / `${VAR}` references in a
+/// backup directory from the command line or config, as a shell would
+pub fn expand_backup_dir(dir: &str) -> Result<PathBuf> {
+    expand_path(dir, |var| std::env::var(var).ok())
+}
+
+/// `expand_backup_dir` with environment variables looked up by `env`
+fn expand_path(dir: &str, env: impl Fn(&str) -> Option<String>) -> Result<PathBuf> {
+    let (prefix, rest) = match dir.strip_prefix('~') {
+        Some(after) => {
+            let (user, rest) = after.split_at(after.find('/').unwrap_or(after.len()));
+            let home = if user.is_empty() {
+                dirs::home_dir()
+                    .ok_or_else(|| anyhow::anyhow!("Cannot determine home directory"))?
+            } else {
+                user_home_dir(user)?
+            };
+            (home.display().to_string(), rest)
+        }
+        None => (String::new(), dir),
+    };
+
+    let mut expanded = prefix;
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+        let braced = chars.next_if_eq(&'{').is_some();
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            name.push(c);
+        }
+        if braced && chars.next_if_eq(&'}').is_none() {
+            anyhow::bail!("Unterminated ${{ in backup directory: {}", dir);
+        }
+        if name.is_empty() {
+            if braced {
+                anyhow::bail!("Empty ${{}} in backup directory: {}", dir);
+            }
+            // A lone $ is just a character
+            expanded.push('$');
+            continue;
+        }
+        let value = env(&name).ok_or_else(|| {
+            anyhow::anyhow!("Backup directory {} uses ${}, which is not set", dir, name)
+        })?;
+        expanded.push_str(&value);
+    }
+
+    Ok(PathBuf::from(expanded))
+}
+
+/// Home directory of `user`, from the password database
+fn user_home_dir(user: &str) -> Result<PathBuf> {
+    let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+    passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() >= 6 && fields[0] == user)
+        .map(|fields| PathBuf::from(fields[5]))
+        .ok_or_else(|| anyhow::anyhow!("Cannot expand ~{}: unknown user", user))
 }
 
 pub struct BackupManager {
     backups_dir: PathBuf,
+    /// Size cap and free-space share enforced by `create_backup`
+    limits: BackupConfig,
+    /// How new backups store their payloads
+    compression: Compression,
 }
 
 impl BackupManager {
@@ -39,12 +409,17 @@ impl BackupManager {
             )
         })?;
 
-        Ok(Self { backups_dir })
+        Ok(Self {
+            backups_dir,
+            limits: BackupConfig::default(),
+            compression: Compression::None,
+        })
     }
 
-    /// Create a BackupManager with a custom backup directory
+    /// Create a BackupManager with a custom backup directory; `~` and
+    /// environment variables in it are expanded
     pub fn with_directory(dir: String) -> Result<Self> {
-        let backups_dir = PathBuf::from(dir);
+        let backups_dir = expand_backup_dir(&dir)?;
 
         // Create backups directory if it doesn't exist
         fs::create_dir_all(&backups_dir).with_context(|| {
@@ -54,7 +429,25 @@ impl BackupManager {
             )
         })?;
 
-        Ok(Self { backups_dir })
+        Ok(Self {
+            backups_dir,
+            limits: BackupConfig::default(),
+            compression: Compression::None,
+        })
+    }
+
+    /// Enforce the `max_size_gb` and `max_disk_usage_percent` settings
+    /// from this config instead of the defaults
+    pub fn with_limits(mut self, config: &BackupConfig) -> Self {
+        self.limits = config.clone();
+        self
+    }
+
+    /// Store the payloads of new backups this way; existing backups keep
+    /// the compression recorded in their metadata
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
     }
 
     /// Get the backup directory path
@@ -62,61 +455,101 @@ impl BackupManager {
         &self.backups_dir
     }
 
+    /// Refuse a backup of `total_size` bytes that is over the configured
+    /// size cap or would use too much of the free space on its partition
+    fn check_backup_limits(&self, total_size: u64) -> Result<()> {
+        // A size cap of 0 means no limit. Without one, a large backup is
+        // only warned about.
+        match self.limits.max_size_gb {
+            None if total_size > WARN_BACKUP_SIZE_GB * 1024 * 1024 * 1024 => {
+                eprintln!(
+                    "⚠️  Warning: This operation will create a large backup ({})",
+                    DiskSpaceInfo::bytes_to_human(total_size)
+                );
+                eprintln!("Consider using --no-backup if you have a recent backup");
+            }
+            Some(max_size_gb)
+                if max_size_gb > 0.0
+                    && total_size > (max_size_gb * 1024.0 * 1024.0 * 1024.0) as u64 =>
+            {
+                anyhow::bail!(
+                    "Cannot create backup: files size {} exceeds the backup size limit of {} GB\n\
+                     \n\
+                     Options:\n\
+                     1. Raise the limit: sedx config (backup.max_size_gb)\n\
+                     2. Skip backup: --no-backup --force (not recommended)",
+                    DiskSpaceInfo::bytes_to_human(total_size),
+                    max_size_gb
+                );
+            }
+            _ => {}
+        }
+
+        // Skip on Windows in test mode (disk_space check not implemented there)
+        #[cfg(not(all(windows, test)))]
+        if let Some(max_percent) = self.limits.max_disk_usage_percent {
+            crate::disk_space::check_disk_space_for_backup(
+                &self.backups_dir,
+                total_size,
+                max_percent,
+            )
+            .map_err(|e| {
+                e.context(format!(
+                    "Cannot create backup. Files size: {}",
+                    DiskSpaceInfo::bytes_to_human(total_size)
+                ))
+            })?;
+        }
+
+        Ok(())
+    }
+
     pub fn create_backup(&mut self, expression: &str, files: &[PathBuf]) -> Result<String> {
-        // Calculate total backup size and check disk space
+        let (id, _) = self.create_backup_of(expression, files, false)?;
+        Ok(id)
+    }
+
+    /// Back up `files` like `create_backup`, but leave out a file that
+    /// can't be backed up instead of failing (`--keep-going`). Returns the
+    /// backup ID and the files left out, with why.
+    pub fn create_backup_keep_going(
+        &mut self,
+        expression: &str,
+        files: &[PathBuf],
+    ) -> Result<(String, Vec<(PathBuf, anyhow::Error)>)> {
+        self.create_backup_of(expression, files, true)
+    }
+
+    fn create_backup_of(
+        &mut self,
+        expression: &str,
+        files: &[PathBuf],
+        keep_going: bool,
+    ) -> Result<(String, Vec<(PathBuf, anyhow::Error)>)> {
+        let mut failures = Vec::new();
+
+        // Calculate total backup size and check disk space. The limits are
+        // checked against the uncompressed sizes, which bound what a
+        // compressed backup can take.
         let mut total_size = 0u64;
         for file_path in files {
             if file_path.exists() {
-                total_size += file_path
-                    .metadata()
-                    .with_context(|| {
-                        format!("Failed to get file metadata: {}", file_path.display())
-                    })?
-                    .len();
+                match file_path.metadata() {
+                    Ok(metadata) => total_size += metadata.len(),
+                    // The copy below fails the same way and reports it
+                    Err(_) if keep_going => {}
+                    Err(e) => {
+                        return Err(e).with_context(|| {
+                            format!("Failed to get file metadata: {}", file_path.display())
+                        });
+                    }
+                }
             }
         }
 
-        // Check disk space before creating backup
-        // Default: warn if backup > 2GB or > 40% of free space
-        // Error if backup > 60% of free space
-        const MAX_BACKUP_SIZE_GB: u64 = 2;
-        #[allow(dead_code)] // Documented threshold for future warning implementation
-        const WARN_PERCENT: f64 = 40.0;
-        #[cfg_attr(windows, allow(dead_code))] // Only used on Unix
-        const ERROR_PERCENT: f64 = 60.0;
-
-        // Warn if backup is very large
-        if total_size > MAX_BACKUP_SIZE_GB * 1024 * 1024 * 1024 {
-            eprintln!(
-                "⚠️  Warning: This operation will create a large backup ({})",
-                crate::disk_space::DiskSpaceInfo::bytes_to_human(total_size)
-            );
-            eprintln!("Consider using --no-backup if you have a recent backup");
-        }
-
-        // Check disk space with error threshold
-        // Skip on Windows in test mode (disk_space check not implemented there)
-        #[cfg(not(all(windows, test)))]
-        let _disk_check_result = crate::disk_space::check_disk_space_for_backup(
-            &self.backups_dir,
-            total_size,
-            ERROR_PERCENT,
-        );
-        #[cfg(not(all(windows, test)))]
-        if let Err(e) = _disk_check_result {
-            // Provide helpful error message
-            return Err(e.context(format!(
-                "Cannot create backup. Files size: {}",
-                crate::disk_space::DiskSpaceInfo::bytes_to_human(total_size)
-            )));
-        }
+        self.check_backup_limits(total_size)?;
 
-        // Generate unique backup ID with millisecond precision for deterministic sorting
-        let id = format!(
-            "{}-{}",
-            Utc::now().format("%Y%m%d-%H%M%S%3f"),
-            Uuid::new_v4().to_string().split_at(8).0
-        );
+        let id = generate_backup_id();
         let backup_dir = self.backups_dir.join(&id);
 
         fs::create_dir_all(&backup_dir).with_context(|| {
@@ -133,23 +566,16 @@ impl BackupManager {
                 continue;
             }
 
-            let file_name = file_path
-                .file_name()
-                .ok_or_else(|| anyhow::anyhow!("Invalid file name: {}", file_path.display()))?;
-
-            let backup_path = backup_dir.join(file_name);
-
-            fs::copy(file_path, &backup_path)
-                .with_context(|| format!("Failed to backup file: {}", file_path.display()))?;
-
-            file_backups.push(FileBackup {
-                original_path: file_path.clone(),
-                backup_path,
-            });
+            match back_up_file(&backup_dir, file_path, self.compression) {
+                Ok(file_backup) => file_backups.push(file_backup),
+                Err(e) if keep_going => failures.push((file_path.clone(), e)),
+                Err(e) => return Err(e),
+            }
         }
 
         // Save metadata
         let metadata = BackupMetadata {
+            version: METADATA_VERSION,
             id: id.clone(),
             timestamp: Utc::now(),
             expression: expression.to_string(),
@@ -166,10 +592,11 @@ impl BackupManager {
         // Cleanup old backups
         self.cleanup_old_backups()?;
 
-        Ok(id)
+        Ok((id, failures))
     }
 
-    pub fn restore_backup(&self, id: &str) -> Result<()> {
+    /// Read the metadata of the backup with this exact ID
+    fn load_metadata(&self, id: &str) -> Result<BackupMetadata> {
         let backup_dir = self.backups_dir.join(id);
         let metadata_path = backup_dir.join("operation.json");
 
@@ -180,8 +607,18 @@ impl BackupManager {
         let metadata_json = fs::read_to_string(&metadata_path)
             .with_context(|| format!("Failed to read metadata: {}", metadata_path.display()))?;
 
-        let metadata: BackupMetadata =
-            serde_json::from_str(&metadata_json).context("Failed to parse metadata")?;
+        serde_json::from_str(&metadata_json).context("Failed to parse metadata")
+    }
+
+    pub fn restore_backup(&self, id: &str) -> Result<()> {
+        let backup_dir = self.backups_dir.join(id);
+        let metadata = self.load_metadata(id)?;
+
+        // Check every payload before touching any file so a corrupted
+        // backup is never partially restored
+        for file_backup in &metadata.files {
+            check_payload(file_backup)?;
+        }
 
         for file_backup in &metadata.files {
             if !file_backup.backup_path.exists() {
@@ -192,12 +629,7 @@ impl BackupManager {
                 continue;
             }
 
-            fs::copy(&file_backup.backup_path, &file_backup.original_path).with_context(|| {
-                format!(
-                    "Failed to restore file: {}",
-                    file_backup.original_path.display()
-                )
-            })?;
+            file_backup.restore()?;
 
             println!("Restored: {}", file_backup.original_path.display());
         }
@@ -215,9 +647,63 @@ impl BackupManager {
         Ok(())
     }
 
+    /// Restore a single file from a backup, leaving the other files and the
+    /// backup itself as they are
+    pub fn restore_file(&self, id: &str, path: &Path) -> Result<()> {
+        let metadata = self.load_metadata(id)?;
+
+        let Some(file_backup) = metadata
+            .files
+            .iter()
+            .find(|f| is_same_file(&f.original_path, path))
+        else {
+            let files: Vec<String> = metadata
+                .files
+                .iter()
+                .map(|f| f.original_path.display().to_string())
+                .collect();
+            anyhow::bail!(
+                "{} is not part of backup {} (it contains: {})",
+                path.display(),
+                id,
+                files.join(", ")
+            );
+        };
+
+        if !file_backup.backup_path.exists() {
+            anyhow::bail!("Backup file missing: {}", file_backup.backup_path.display());
+        }
+        check_payload(file_backup)?;
+
+        file_backup.restore()?;
+        println!("Restored: {}", file_backup.original_path.display());
+
+        Ok(())
+    }
+
+    /// Compare each backed-up file with its current content on disk
+    ///
+    /// Returns every original path with `true` if restoring would change it
+    /// (including when the file no longer exists). Nothing is modified.
+    pub fn verify_backup(&self, id: &str) -> Result<Vec<(PathBuf, bool)>> {
+        let metadata = self.load_metadata(id)?;
+
+        metadata
+            .files
+            .into_iter()
+            .map(|file_backup| {
+                let backup = file_backup.read_contents()?;
+                let differs = match fs::read(&file_backup.original_path) {
+                    Ok(current) => current != backup,
+                    Err(_) => true,
+                };
+                Ok((file_backup.original_path, differs))
+            })
+            .collect()
+    }
+
     pub fn get_last_backup_id(&self) -> Result<Option<String>> {
-        let mut backups = self.list_backups()?;
-        backups.sort_by_key(|b| b.timestamp);
+        let backups = self.list_backups()?;
         Ok(backups.last().map(|b| b.id.clone()))
     }
 
@@ -243,15 +729,86 @@ impl BackupManager {
             }
         }
 
-        // Sort by timestamp to ensure chronological order
-        // When timestamps are equal (rare), use ID as tiebreaker for consistency
-        backups.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.id.cmp(&b.id)));
+        backups.sort_by(chronological);
         Ok(backups)
     }
 
-    fn cleanup_old_backups(&self) -> Result<()> {
+    /// Check that every backup is intact: its metadata can be read and
+    /// each backed-up file exists and matches its recorded checksum.
+    /// Returns one result per backup directory, by ID. Nothing is modified.
+    pub fn verify_all(&self) -> Result<Vec<BackupCheck>> {
+        let mut checks = Vec::new();
+
+        for entry in fs::read_dir(&self.backups_dir).with_context(|| {
+            format!(
+                "Failed to read backups directory: {}",
+                self.backups_dir.display()
+            )
+        })? {
+            let entry = entry?;
+            let metadata_path = entry.path().join("operation.json");
+            if !metadata_path.exists() {
+                continue;
+            }
+            let id = entry.file_name().to_string_lossy().into_owned();
+
+            let metadata = fs::read_to_string(&metadata_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Self::parse_backup_metadata(&json));
+            let check = match metadata {
+                Ok(metadata) => BackupCheck {
+                    id,
+                    files: metadata.files.len(),
+                    problems: metadata.files.iter().filter_map(payload_problem).collect(),
+                },
+                Err(e) => BackupCheck {
+                    id,
+                    files: 0,
+                    problems: vec![format!("unreadable metadata: {:#}", e)],
+                },
+            };
+            checks.push(check);
+        }
+
+        checks.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(checks)
+    }
+
+    /// Count the backups and the disk space their payloads use; a payload
+    /// that has gone missing counts as empty
+    pub fn status(&self) -> Result<BackupStatus> {
+        let backups = self.list_backups()?;
+        let total_size_bytes = backups
+            .iter()
+            .flat_map(|backup| &backup.files)
+            .filter_map(|file| fs::metadata(&file.backup_path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+
+        Ok(BackupStatus {
+            backup_dir: self.backups_dir.clone(),
+            total_backups: backups.len(),
+            total_size_bytes,
+            total_size: DiskSpaceInfo::bytes_to_human(total_size_bytes),
+            oldest: backups.first().map(|backup| backup.timestamp),
+            newest: backups.last().map(|backup| backup.timestamp),
+            last_operation: backups.last().map(|backup| LastOperation {
+                id: backup.id.clone(),
+                timestamp: backup.timestamp,
+                expression: backup.expression.clone(),
+            }),
+        })
+    }
+
+    /// Backups selected by `filter`, oldest first
+    pub fn list_backups_matching(&self, filter: &BackupFilter) -> Result<Vec<BackupMetadata>> {
         let mut backups = self.list_backups()?;
-        backups.sort_by_key(|b| b.timestamp);
+        backups.retain(|backup| filter.matches(backup));
+        Ok(backups)
+    }
+
+    fn cleanup_old_backups(&self) -> Result<()> {
+        let backups = self.list_backups()?;
 
         if backups.len() > MAX_BACKUPS {
             for backup in backups.iter().take(backups.len() - MAX_BACKUPS) {
@@ -265,6 +822,143 @@ impl BackupManager {
         Ok(())
     }
 
+    /// Write a backup (metadata and file payloads) to a tar archive
+    ///
+    /// Entries are stored as `<id>/operation.json` and `<id>/<file>`. The
+    /// archive is gzip-compressed when `dest` ends in `.tar.gz` or `.tgz`.
+    pub fn export_backup(&self, id: &str, dest: &Path) -> Result<()> {
+        let metadata = self.load_metadata(id)?;
+        let backup_dir = self.backups_dir.join(id);
+
+        let file = fs::File::create(dest)
+            .with_context(|| format!("Failed to create archive: {}", dest.display()))?;
+        let write_error = || format!("Failed to write archive: {}", dest.display());
+        if is_gzip_archive(dest) {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            Self::append_backup(id, &backup_dir, &metadata, encoder)?
+                .finish()
+                .with_context(write_error)?;
+        } else {
+            Self::append_backup(id, &backup_dir, &metadata, file)?
+                .sync_all()
+                .with_context(write_error)?;
+        }
+        Ok(())
+    }
+
+    /// Write the tar entries of one backup and return the underlying writer
+    fn append_backup<W: std::io::Write>(
+        id: &str,
+        backup_dir: &Path,
+        metadata: &BackupMetadata,
+        writer: W,
+    ) -> Result<W> {
+        let mut archive = tar::Builder::new(writer);
+        archive
+            .append_path_with_name(
+                backup_dir.join("operation.json"),
+                Path::new(id).join("operation.json"),
+            )
+            .context("Failed to add metadata to archive")?;
+        for file_backup in &metadata.files {
+            let file_name = file_backup.backup_path.file_name().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid backup file name: {}",
+                    file_backup.backup_path.display()
+                )
+            })?;
+            archive
+                .append_path_with_name(&file_backup.backup_path, Path::new(id).join(file_name))
+                .with_context(|| {
+                    format!(
+                        "Failed to add {} to archive",
+                        file_backup.backup_path.display()
+                    )
+                })?;
+        }
+        archive.into_inner().context("Failed to finish archive")
+    }
+
+    /// Register a backup from an archive written by `export_backup`
+    ///
+    /// The backup keeps its original ID unless one with that ID already
+    /// exists, in which case a new ID is generated. Payloads are checked
+    /// against the recorded checksums. Returns the ID it was stored under.
+    pub fn import_backup(&mut self, src: &Path) -> Result<String> {
+        let file = fs::File::open(src)
+            .with_context(|| format!("Failed to open archive: {}", src.display()))?;
+        let reader: Box<dyn std::io::Read> = if is_gzip_archive(src) {
+            Box::new(flate2::read::GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+
+        // Unpack into a staging directory that is removed on failure
+        let staging = tempfile::TempDir::new_in(&self.backups_dir)
+            .context("Failed to create staging directory for import")?;
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries().context("Failed to read archive")? {
+            let mut entry = entry.context("Failed to read archive entry")?;
+            // Only the file name is kept, so entries cannot escape the staging directory
+            let entry_path = entry.path()?.into_owned();
+            let Some(file_name) = entry_path.file_name() else {
+                continue;
+            };
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            entry
+                .unpack(staging.path().join(file_name))
+                .with_context(|| format!("Failed to unpack {}", entry_path.display()))?;
+        }
+
+        let metadata_json = fs::read_to_string(staging.path().join("operation.json"))
+            .context("Archive does not contain a backup (operation.json is missing)")?;
+        let mut metadata = Self::parse_backup_metadata(&metadata_json)?;
+
+        for file_backup in &mut metadata.files {
+            let file_name = file_backup.backup_path.file_name().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid backup file name: {}",
+                    file_backup.backup_path.display()
+                )
+            })?;
+            let payload = staging.path().join(file_name);
+            if let Some(expected) = &file_backup.sha256
+                && sha256_file(&payload)? != *expected
+            {
+                anyhow::bail!(
+                    "Archived file {} does not match its recorded SHA-256",
+                    file_name.to_string_lossy()
+                );
+            }
+            file_backup.backup_path = PathBuf::from(file_name);
+        }
+
+        // The id comes from the archive: keep it only if it is a plain
+        // backup id that isn't taken
+        if !is_backup_id(&metadata.id) || self.backups_dir.join(&metadata.id).exists() {
+            metadata.id = generate_backup_id();
+        }
+        let backup_dir = self.backups_dir.join(&metadata.id);
+        for file_backup in &mut metadata.files {
+            file_backup.backup_path = backup_dir.join(&file_backup.backup_path);
+        }
+
+        let metadata_json =
+            serde_json::to_string_pretty(&metadata).context("Failed to serialize metadata")?;
+        fs::write(staging.path().join("operation.json"), metadata_json)
+            .context("Failed to write imported metadata")?;
+        fs::rename(staging.path(), &backup_dir).with_context(|| {
+            format!(
+                "Failed to create backup directory: {}",
+                backup_dir.display()
+            )
+        })?;
+
+        Ok(metadata.id)
+    }
+
     /// Remove a backup by its ID (used for cleanup when no changes are made)
     #[allow(dead_code)] // Public API - kept for future use
     pub fn remove_backup_by_id(&self, backup_id: &str) -> Result<()> {
@@ -282,24 +976,26 @@ impl BackupManager {
         Ok(metadata)
     }
 
+    /// The backups `prune --keep` removes: all but the `keep_count` most
+    /// recent, oldest first. Backups with the same timestamp are ordered by
+    /// ID, so the higher IDs survive a tie.
+    pub fn backups_to_prune(&self, keep_count: usize) -> Result<Vec<BackupMetadata>> {
+        let mut backups = self.list_backups()?;
+        backups.truncate(backups.len().saturating_sub(keep_count));
+        Ok(backups)
+    }
+
     /// Prune backups keeping only the N most recent ones
     #[allow(dead_code)] // Public API - kept for future use
     pub fn prune_backups(&self, keep_count: usize) -> Result<usize> {
-        let mut backups = self.list_backups()?;
-        backups.sort_by_key(|b| b.timestamp);
-
-        if backups.len() <= keep_count {
-            return Ok(0);
-        }
-
-        let to_remove = backups.len() - keep_count;
-        for backup in backups.iter().take(to_remove) {
+        let to_remove = self.backups_to_prune(keep_count)?;
+        for backup in &to_remove {
             let backup_dir = self.backups_dir.join(&backup.id);
             fs::remove_dir_all(&backup_dir)
                 .with_context(|| format!("Failed to remove backup: {}", backup_dir.display()))?;
         }
 
-        Ok(to_remove)
+        Ok(to_remove.len())
     }
 
     /// Prune backups older than the specified number of days
@@ -347,6 +1043,53 @@ mod tests {
         (manager, temp_dir)
     }
 
+    // ============================================================================
+    // expand_backup_dir() tests
+    // ============================================================================
+
+    #[test]
+    fn test_expand_backup_dir_tilde() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_backup_dir("~/x").unwrap(), home.join("x"));
+        assert_eq!(expand_backup_dir("~").unwrap(), home);
+        // Only a leading ~ is special
+        assert_eq!(
+            expand_backup_dir("/tmp/~/x").unwrap(),
+            PathBuf::from("/tmp/~/x")
+        );
+    }
+
+    #[test]
+    fn test_expand_backup_dir_tilde_user() {
+        // root is in the password database on every Unix system
+        if cfg!(unix) {
+            assert!(expand_backup_dir("~root/x").unwrap().ends_with("x"));
+        }
+        assert!(expand_backup_dir("~no-such-user-sedx/x").is_err());
+    }
+
+    #[test]
+    fn test_expand_backup_dir_env_vars() {
+        let env = |name: &str| (name == "BACKUPS").then(|| "/srv/backups".to_string());
+
+        assert_eq!(
+            expand_path("$BACKUPS/x", env).unwrap(),
+            PathBuf::from("/srv/backups/x")
+        );
+        assert_eq!(
+            expand_path("${BACKUPS}x", env).unwrap(),
+            PathBuf::from("/srv/backupsx")
+        );
+        assert_eq!(
+            expand_path("/tmp/a$", env).unwrap(),
+            PathBuf::from("/tmp/a$")
+        );
+
+        let err = expand_path("$MISSING/x", env).unwrap_err().to_string();
+        assert!(err.contains("$MISSING, which is not set"), "{}", err);
+        assert!(expand_path("${BACKUPS/x", env).is_err());
+    }
+
     // ============================================================================
     // create_backup() tests
     // ============================================================================
@@ -539,6 +1282,365 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_compressed_backup_restores_byte_identical() {
+        let (manager, temp_dir) = create_test_manager();
+        let mut manager = manager.with_compression(Compression::Gzip);
+        // Repetitive text with CRLF, a missing final newline and non-UTF-8 bytes
+        let mut original = "the same line again\r\n".repeat(500).into_bytes();
+        original.extend_from_slice(b"\xff\xfe no newline");
+        let test_file = temp_dir.path().join("big.txt");
+        fs::write(&test_file, &original).unwrap();
+
+        let backup_id = manager
+            .create_backup("s/same/other/", std::slice::from_ref(&test_file))
+            .unwrap();
+        let metadata = manager.load_metadata(&backup_id).unwrap();
+        let file_backup = &metadata.files[0];
+        assert_eq!(file_backup.compression, Compression::Gzip);
+        assert_eq!(file_backup.backup_path.extension().unwrap(), "gz");
+        let stored = fs::metadata(&file_backup.backup_path).unwrap().len();
+        assert!(
+            stored < original.len() as u64 / 10,
+            "stored {} bytes",
+            stored
+        );
+        assert_eq!(file_backup.read_contents().unwrap(), original);
+
+        fs::write(&test_file, "modified").unwrap();
+        assert_eq!(
+            manager.verify_backup(&backup_id).unwrap(),
+            vec![(test_file.clone(), true)]
+        );
+        manager.restore_backup(&backup_id).unwrap();
+        assert_eq!(fs::read(&test_file).unwrap(), original);
+    }
+
+    #[test]
+    fn test_create_backup_refuses_backup_over_size_limit() {
+        let (manager, temp_dir) = create_test_manager();
+        let limits = BackupConfig {
+            max_size_gb: Some(1e-9), // about one byte
+            ..Default::default()
+        };
+        let mut manager = manager.with_limits(&limits);
+        let test_file = create_test_file(temp_dir.path(), "big.txt", "more than a byte");
+
+        let err = manager
+            .create_backup("s/a/b/", std::slice::from_ref(&test_file))
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("exceeds the backup size limit"),
+            "{}",
+            err
+        );
+        assert_eq!(fs::read_dir(manager.backups_dir()).unwrap().count(), 0);
+
+        // Without a cap, a large backup is only warned about
+        let mut limits = BackupConfig {
+            max_disk_usage_percent: None,
+            ..Default::default()
+        };
+        let manager = manager.with_limits(&limits);
+        let three_gb = 3 * 1024 * 1024 * 1024;
+        assert!(manager.check_backup_limits(three_gb).is_ok());
+
+        // A cap set to 2 GB, the warning threshold, is enforced
+        limits.max_size_gb = Some(2.0);
+        let manager = manager.with_limits(&limits);
+        assert!(manager.check_backup_limits(three_gb).is_err());
+        assert!(manager.check_backup_limits(1024).is_ok());
+
+        // A cap of 0 disables the check
+        limits.max_size_gb = Some(0.0);
+        let mut manager = manager.with_limits(&limits);
+        assert!(
+            manager
+                .create_backup("s/a/b/", std::slice::from_ref(&test_file))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_backup_only_includes_changed_files() {
+        use crate::cli::RegexFlavor;
+        use crate::file_processor::FileProcessor;
+        use crate::parser::Parser;
+
+        let (mut manager, temp_dir) = create_test_manager();
+        let changed = create_test_file(temp_dir.path(), "changed.txt", "foo\n");
+        let untouched = create_test_file(temp_dir.path(), "untouched.txt", "bar\n");
+        let files = [changed.clone(), untouched];
+
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/foo/baz/").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        let to_backup: Vec<PathBuf> = files
+            .iter()
+            .filter(|path| {
+                processor
+                    .process_file_with_context(path)
+                    .unwrap()
+                    .has_changes()
+            })
+            .cloned()
+            .collect();
+
+        let backup_id = manager.create_backup("s/foo/baz/", &to_backup).unwrap();
+        let metadata = manager.load_metadata(&backup_id).unwrap();
+        assert_eq!(metadata.files.len(), 1);
+        assert_eq!(metadata.files[0].original_path, changed);
+    }
+
+    #[test]
+    fn test_verify_backup_detects_modified_file() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let changed = create_test_file(temp_dir.path(), "changed.txt", "original content");
+        let same = create_test_file(temp_dir.path(), "same.txt", "untouched");
+
+        let backup_id = manager
+            .create_backup("s/foo/bar/", &[changed.clone(), same.clone()])
+            .unwrap();
+        fs::write(&changed, "modified content").unwrap();
+
+        let result = manager.verify_backup(&backup_id).unwrap();
+        assert_eq!(result, vec![(changed.clone(), true), (same, false)]);
+
+        // Verification must not restore or remove anything
+        assert_eq!(fs::read_to_string(&changed).unwrap(), "modified content");
+        assert!(manager.backups_dir().join(&backup_id).exists());
+    }
+
+    #[test]
+    fn test_verify_backup_unmodified_and_deleted_files() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let test_file = create_test_file(temp_dir.path(), "test.txt", "content");
+
+        let backup_id = manager
+            .create_backup("s/foo/bar/", std::slice::from_ref(&test_file))
+            .unwrap();
+        assert_eq!(
+            manager.verify_backup(&backup_id).unwrap(),
+            vec![(test_file.clone(), false)]
+        );
+
+        // A file that no longer exists would be recreated by a restore
+        fs::remove_file(&test_file).unwrap();
+        assert_eq!(
+            manager.verify_backup(&backup_id).unwrap(),
+            vec![(test_file, true)]
+        );
+
+        assert!(manager.verify_backup("no-such-backup").is_err());
+    }
+
+    #[test]
+    fn test_verify_all_passes_healthy_backups() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let first = create_test_file(temp_dir.path(), "first.txt", "one");
+        let second = create_test_file(temp_dir.path(), "second.txt", "two");
+
+        let id1 = manager
+            .create_backup("s/a/b/", std::slice::from_ref(&first))
+            .unwrap();
+        let id2 = manager
+            .create_backup("s/c/d/", &[first.clone(), second.clone()])
+            .unwrap();
+        // The originals changing doesn't affect the backups
+        fs::write(&first, "changed").unwrap();
+
+        let checks = manager.verify_all().unwrap();
+        assert_eq!(checks.len(), 2);
+        let files_of = |id: &str| checks.iter().find(|c| c.id == id).unwrap().files;
+        assert_eq!(files_of(&id1), 1);
+        assert_eq!(files_of(&id2), 2);
+        assert!(checks.iter().all(BackupCheck::is_ok), "{:?}", checks);
+    }
+
+    #[test]
+    fn test_verify_all_reports_deleted_and_tampered_payloads() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let first = create_test_file(temp_dir.path(), "first.txt", "one");
+        let second = create_test_file(temp_dir.path(), "second.txt", "two");
+
+        let healthy_id = manager
+            .create_backup("s/a/b/", std::slice::from_ref(&first))
+            .unwrap();
+        let broken_id = manager
+            .create_backup("s/c/d/", &[first.clone(), second.clone()])
+            .unwrap();
+        let broken = manager.load_metadata(&broken_id).unwrap();
+        fs::remove_file(&broken.files[0].backup_path).unwrap();
+        fs::write(&broken.files[1].backup_path, "tampered").unwrap();
+
+        let checks = manager.verify_all().unwrap();
+        let healthy = checks.iter().find(|c| c.id == healthy_id).unwrap();
+        assert!(healthy.is_ok());
+        let broken = checks.iter().find(|c| c.id == broken_id).unwrap();
+        assert_eq!(broken.problems.len(), 2, "{:?}", broken.problems);
+        assert!(broken.problems[0].contains("backup file missing"));
+        assert!(broken.problems[1].contains("is corrupted"));
+    }
+
+    #[test]
+    fn test_create_backup_records_checksums() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let test_file = create_test_file(temp_dir.path(), "test.txt", "hello\n");
+
+        let backup_id = manager
+            .create_backup("s/foo/bar/", std::slice::from_ref(&test_file))
+            .unwrap();
+        let metadata = manager.load_metadata(&backup_id).unwrap();
+
+        assert_eq!(metadata.version, METADATA_VERSION);
+        assert_eq!(
+            metadata.files[0].sha256.as_deref(),
+            Some("5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03")
+        );
+    }
+
+    #[test]
+    fn test_restore_backup_rejects_tampered_file() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let first = create_test_file(temp_dir.path(), "a.txt", "original a");
+        let second = create_test_file(temp_dir.path(), "b.txt", "original b");
+
+        let backup_id = manager
+            .create_backup("s/foo/bar/", &[first.clone(), second.clone()])
+            .unwrap();
+        fs::write(&first, "modified a").unwrap();
+        fs::write(&second, "modified b").unwrap();
+
+        // Corrupt one payload after the backup was taken
+        let metadata = manager.load_metadata(&backup_id).unwrap();
+        fs::write(&metadata.files[1].backup_path, "tampered").unwrap();
+
+        let err = manager.restore_backup(&backup_id).unwrap_err().to_string();
+        assert!(err.contains("is corrupted"), "{}", err);
+
+        // Nothing was restored and the backup is kept for inspection
+        assert_eq!(fs::read_to_string(&first).unwrap(), "modified a");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "modified b");
+        assert!(manager.backups_dir().join(&backup_id).exists());
+    }
+
+    #[test]
+    fn test_restore_backup_from_legacy_metadata_without_checksums() {
+        let (manager, temp_dir) = create_test_manager();
+        let test_file = create_test_file(temp_dir.path(), "test.txt", "modified");
+
+        // Version 1 metadata: no `version` field and no `sha256` per file
+        let backup_dir = manager.backups_dir().join("20240201-120000-abc12345");
+        fs::create_dir_all(&backup_dir).unwrap();
+        let backup_path = backup_dir.join("test.txt");
+        fs::write(&backup_path, "original").unwrap();
+        let json = serde_json::json!({
+            "id": "20240201-120000-abc12345",
+            "timestamp": "2024-02-01T12:00:00Z",
+            "expression": "s/a/b/",
+            "files": [{ "original_path": test_file, "backup_path": backup_path }]
+        });
+        fs::write(backup_dir.join("operation.json"), json.to_string()).unwrap();
+
+        let metadata = manager.load_metadata("20240201-120000-abc12345").unwrap();
+        assert_eq!(metadata.version, 1);
+        assert_eq!(metadata.files[0].sha256, None);
+
+        manager.restore_backup("20240201-120000-abc12345").unwrap();
+        assert_eq!(fs::read_to_string(&test_file).unwrap(), "original");
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let test_file = create_test_file(temp_dir.path(), "test.txt", "original content");
+
+        let backup_id = manager
+            .create_backup("s/foo/bar/", std::slice::from_ref(&test_file))
+            .unwrap();
+        fs::write(&test_file, "modified content").unwrap();
+
+        for archive_name in ["backup.tar", "backup.tar.gz"] {
+            let archive = temp_dir.path().join(archive_name);
+            manager.export_backup(&backup_id, &archive).unwrap();
+            manager.remove_backup_by_id(&backup_id).unwrap();
+            assert!(manager.list_backups().unwrap().is_empty());
+
+            let imported_id = manager.import_backup(&archive).unwrap();
+            assert_eq!(imported_id, backup_id);
+            let metadata = manager.load_metadata(&imported_id).unwrap();
+            assert_eq!(metadata.expression, "s/foo/bar/");
+            assert!(
+                metadata.files[0]
+                    .backup_path
+                    .starts_with(manager.backups_dir().join(&imported_id))
+            );
+        }
+
+        manager.restore_backup(&backup_id).unwrap();
+        assert_eq!(fs::read_to_string(&test_file).unwrap(), "original content");
+    }
+
+    #[test]
+    fn test_import_backup_with_existing_id_gets_new_id() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let test_file = create_test_file(temp_dir.path(), "test.txt", "content");
+
+        let backup_id = manager
+            .create_backup("s/foo/bar/", std::slice::from_ref(&test_file))
+            .unwrap();
+        let archive = temp_dir.path().join("backup.tgz");
+        manager.export_backup(&backup_id, &archive).unwrap();
+
+        let imported_id = manager.import_backup(&archive).unwrap();
+        assert_ne!(imported_id, backup_id);
+        assert_eq!(manager.list_backups().unwrap().len(), 2);
+        assert_eq!(
+            manager.verify_backup(&imported_id).unwrap(),
+            vec![(test_file, false)]
+        );
+    }
+
+    #[test]
+    fn test_import_backup_replaces_an_id_that_is_not_a_backup_id() {
+        let (mut manager, temp_dir) = create_test_manager();
+
+        // An archive whose metadata names a directory outside the backups
+        let json = serde_json::json!({
+            "id": "../../escaped",
+            "timestamp": "2024-02-01T12:00:00Z",
+            "expression": "s/a/b/",
+            "files": []
+        })
+        .to_string();
+        let archive = temp_dir.path().join("backup.tar");
+        let mut builder = tar::Builder::new(fs::File::create(&archive).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(json.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "operation.json", json.as_bytes())
+            .unwrap();
+        builder.finish().unwrap();
+
+        let imported_id = manager.import_backup(&archive).unwrap();
+        assert!(is_backup_id(&imported_id), "{imported_id}");
+        assert!(manager.backups_dir().join(&imported_id).is_dir());
+        assert!(!manager.backups_dir().join("../../escaped").exists());
+
+        assert!(is_backup_id(&generate_backup_id()));
+        assert!(is_backup_id("20240201-120000-abc12345"));
+        for id in [
+            "..",
+            "a/b",
+            "",
+            "20240201-120000-abc12345/..",
+            "20240201-1200-abc12345",
+        ] {
+            assert!(!is_backup_id(id), "{id}");
+        }
+    }
+
     #[test]
     fn test_restore_backup_nonexistent_id() {
         let (manager, _) = create_test_manager();
@@ -581,6 +1683,46 @@ mod tests {
         assert_eq!(fs::read_to_string(&file3).unwrap(), "original 3");
     }
 
+    #[test]
+    fn test_restore_file_restores_only_that_file() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let file1 = create_test_file(temp_dir.path(), "file1.txt", "original 1");
+        let file2 = create_test_file(temp_dir.path(), "file2.txt", "original 2");
+
+        let backup_id = manager
+            .create_backup("s/a/b/", &[file1.clone(), file2.clone()])
+            .unwrap();
+        fs::write(&file1, "modified 1").unwrap();
+        fs::write(&file2, "modified 2").unwrap();
+
+        manager.restore_file(&backup_id, &file1).unwrap();
+
+        assert_eq!(fs::read_to_string(&file1).unwrap(), "original 1");
+        assert_eq!(fs::read_to_string(&file2).unwrap(), "modified 2");
+        // The backup stays, so the other file can still be restored
+        manager.restore_file(&backup_id, &file2).unwrap();
+        assert_eq!(fs::read_to_string(&file2).unwrap(), "original 2");
+    }
+
+    #[test]
+    fn test_restore_file_rejects_file_not_in_backup() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let file1 = create_test_file(temp_dir.path(), "file1.txt", "original 1");
+        let other = create_test_file(temp_dir.path(), "other.txt", "other");
+
+        let backup_id = manager
+            .create_backup("s/a/b/", std::slice::from_ref(&file1))
+            .unwrap();
+
+        let err = manager
+            .restore_file(&backup_id, &other)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("is not part of backup"), "{}", err);
+        assert!(err.contains("file1.txt"), "{}", err);
+        assert_eq!(fs::read_to_string(&other).unwrap(), "other");
+    }
+
     #[test]
     fn test_restore_backup_preserves_file_permissions() {
         let (mut manager, temp_dir) = create_test_manager();
@@ -683,6 +1825,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_status_totals_backups() {
+        let (mut manager, temp_dir) = create_test_manager();
+
+        let status = manager.status().unwrap();
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["backup_dir"], manager.backups_dir().to_str().unwrap());
+        assert_eq!(json["total_backups"], 0);
+        assert_eq!(json["total_size_bytes"], 0);
+        assert_eq!(json["total_size"], "0 B");
+        assert!(json["oldest"].is_null() && json["newest"].is_null());
+        assert!(json["last_operation"].is_null());
+
+        let small = create_test_file(temp_dir.path(), "small.txt", "12345");
+        let large = create_test_file(temp_dir.path(), "large.txt", &"x".repeat(2048));
+        let first = manager
+            .create_backup("s/a/b/", std::slice::from_ref(&small))
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let second = manager.create_backup("s/c/d/", &[small, large]).unwrap();
+
+        let backups = manager.list_backups().unwrap();
+        let json = serde_json::to_value(manager.status().unwrap()).unwrap();
+        assert_eq!(json["total_backups"], 2);
+        assert_eq!(json["total_size_bytes"], 5 + 5 + 2048);
+        assert_eq!(json["total_size"], "2.0 KB");
+        assert_eq!(
+            json["oldest"],
+            serde_json::to_value(backups[0].timestamp).unwrap()
+        );
+        assert_eq!(
+            json["newest"],
+            serde_json::to_value(backups[1].timestamp).unwrap()
+        );
+        assert_ne!(json["oldest"], json["newest"]);
+        assert_eq!(backups[0].id, first);
+        assert_eq!(json["last_operation"]["id"], second);
+        assert_eq!(json["last_operation"]["expression"], "s/c/d/");
+    }
+
     #[test]
     fn test_list_backups_multiple() {
         let (mut manager, temp_dir) = create_test_manager();
@@ -729,6 +1911,67 @@ mod tests {
         assert!(backups[1].timestamp < backups[2].timestamp);
     }
 
+    #[test]
+    fn test_list_backups_matching_file_and_expression() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let a = create_test_file(temp_dir.path(), "a.txt", "a");
+        let b = create_test_file(temp_dir.path(), "b.txt", "b");
+
+        let id1 = manager
+            .create_backup("s/foo/bar/", std::slice::from_ref(&a))
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let id2 = manager
+            .create_backup("s/foo/baz/g", &[a.clone(), b.clone()])
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let id3 = manager
+            .create_backup("/x/d", std::slice::from_ref(&b))
+            .unwrap();
+
+        let ids = |filter: BackupFilter| -> Vec<String> {
+            manager
+                .list_backups_matching(&filter)
+                .unwrap()
+                .into_iter()
+                .map(|backup| backup.id)
+                .collect()
+        };
+
+        assert_eq!(
+            ids(BackupFilter::default()),
+            vec![id1.clone(), id2.clone(), id3.clone()]
+        );
+        assert_eq!(
+            ids(BackupFilter {
+                file: Some(b.clone()),
+                ..Default::default()
+            }),
+            vec![id2.clone(), id3]
+        );
+        assert_eq!(
+            ids(BackupFilter {
+                expression: Some("s/foo".to_string()),
+                ..Default::default()
+            }),
+            vec![id1, id2.clone()]
+        );
+        assert_eq!(
+            ids(BackupFilter {
+                file: Some(b),
+                expression: Some("foo".to_string()),
+            }),
+            vec![id2]
+        );
+        assert!(
+            ids(BackupFilter {
+                file: Some(temp_dir.path().join("missing.txt")),
+                ..Default::default()
+            })
+            .is_empty()
+        );
+    }
+
     #[test]
     fn test_list_backups_ignores_invalid_directories() {
         let (manager, _temp_dir) = create_test_manager();
@@ -784,6 +2027,33 @@ mod tests {
     // prune_backups() tests
     // ============================================================================
 
+    #[test]
+    fn test_create_backup_keep_going_leaves_out_failed_files() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let first = create_test_file(temp_dir.path(), "first.txt", "one");
+        let second = create_test_file(temp_dir.path(), "second.txt", "two");
+        // A directory can't be copied, whoever runs the test
+        let broken = temp_dir.path().join("broken");
+        fs::create_dir(&broken).unwrap();
+        let files = vec![first.clone(), broken.clone(), second.clone()];
+
+        assert!(manager.create_backup("s/a/b/", &files).is_err());
+
+        let (id, failures) = manager.create_backup_keep_going("s/a/b/", &files).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, broken);
+        assert!(failures[0].1.to_string().contains("Failed to backup file"));
+
+        let backup = manager
+            .list_backups()
+            .unwrap()
+            .into_iter()
+            .find(|b| b.id == id)
+            .unwrap();
+        let backed_up: Vec<&PathBuf> = backup.files.iter().map(|f| &f.original_path).collect();
+        assert_eq!(backed_up, vec![&first, &second]);
+    }
+
     #[test]
     fn test_prune_backups_keep_all() {
         let (manager, _temp_dir) = create_test_manager();
@@ -827,6 +2097,50 @@ mod tests {
         assert_eq!(backups[1].id, backup_ids[4]); // 5th created (most recent)
     }
 
+    #[test]
+    fn test_prune_backups_breaks_timestamp_ties_by_id() {
+        let (mut manager, temp_dir) = create_test_manager();
+        let test_file = create_test_file(temp_dir.path(), "test.txt", "content");
+
+        let mut backup_ids = Vec::new();
+        for i in 0..4 {
+            backup_ids.push(
+                manager
+                    .create_backup(&format!("s/test{}/", i), std::slice::from_ref(&test_file))
+                    .unwrap(),
+            );
+        }
+
+        // Give every backup the same timestamp, as rapid runs can
+        let timestamp = Utc::now();
+        for id in &backup_ids {
+            let path = manager.backups_dir().join(id).join("operation.json");
+            let mut metadata: BackupMetadata =
+                serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            metadata.timestamp = timestamp;
+            fs::write(&path, serde_json::to_string(&metadata).unwrap()).unwrap();
+        }
+
+        let mut sorted_ids = backup_ids.clone();
+        sorted_ids.sort();
+        let doomed: Vec<String> = manager
+            .backups_to_prune(2)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.id)
+            .collect();
+        assert_eq!(doomed, sorted_ids[..2]);
+
+        assert_eq!(manager.prune_backups(2).unwrap(), 2);
+        let survivors: Vec<String> = manager
+            .list_backups()
+            .unwrap()
+            .into_iter()
+            .map(|b| b.id)
+            .collect();
+        assert_eq!(survivors, sorted_ids[2..]);
+    }
+
     #[test]
     fn test_prune_backups_exact_count() {
         let (mut manager, temp_dir) = create_test_manager();
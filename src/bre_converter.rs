@@ -14,10 +14,18 @@
 /// - `\+` → `+` - Remove escape from plus quantifier
 /// - `\?` → `?` - Remove escape from question mark
 /// - `\|` → `|` - Remove escape from alternation
+/// - `(`, `)`, `{`, `}`, `+`, `?`, `|` → escaped - Bare ERE metacharacters are literals in BRE
 /// - `\1`..\`\9` → `$1`..`$9` - Convert backreferences to Rust regex style
 /// - `\&` → `$&` - Convert match backreference
-/// - `\\` → `\` - Convert double backslash to single
-pub fn convert_bre_to_pcre(pattern: &str) -> String {
+/// - `\\` → `\\` - An escaped backslash is a literal backslash in both
+/// - `` \` `` → `\A`, `\'` → `\z` - GNU start and end of buffer anchors, which
+///   keep matching only at the ends of a pattern space that `N` made multiline
+/// - `\<` → `\b{start}`, `\>` → `\b{end}` - GNU start and end of word
+///
+/// Bracket expressions (`[...]`) are copied as-is, except that a bare `[`
+/// inside one is escaped so the Rust regex crate does not read it as a
+/// nested class.
+pub fn convert(pattern: &str) -> String {
     let mut result = String::new();
     let mut chars = pattern.chars().peekable();
     let mut escape_next = false;
@@ -34,8 +42,8 @@ pub fn convert_bre_to_pcre(pattern: &str) -> String {
                     result.push(c);
                 }
                 '\\' => {
-                    // Double backslash → single backslash
-                    result.push('\\');
+                    // Escaped backslash stays escaped: a literal backslash
+                    result.push_str("\\\\");
                 }
                 '1'..='9' => {
                     // Backreference: \1 → $1
@@ -47,6 +55,10 @@ pub fn convert_bre_to_pcre(pattern: &str) -> String {
                     result.push('$');
                     result.push('&');
                 }
+                '`' => result.push_str("\\A"),
+                '<' => result.push_str("\\b{start}"),
+                '>' => result.push_str("\\b{end}"),
+                '\'' => result.push_str("\\z"),
                 'n' if chars.peek().is_none() => {
                     // \ n at end is literal newline, not escape
                     result.push('\\');
@@ -61,6 +73,12 @@ pub fn convert_bre_to_pcre(pattern: &str) -> String {
             escape_next = false;
         } else if c == '\\' {
             escape_next = true;
+        } else if c == '[' {
+            copy_bracket_expression(&mut chars, &mut result);
+        } else if matches!(c, '(' | ')' | '{' | '}' | '+' | '?' | '|') {
+            // Bare ERE metacharacter is a literal in BRE
+            result.push('\\');
+            result.push(c);
         } else {
             result.push(c);
         }
@@ -74,6 +92,76 @@ pub fn convert_bre_to_pcre(pattern: &str) -> String {
     result
 }
 
+/// Copy a bracket expression whose opening `[` has already been consumed
+///
+/// A `]` right after `[` or `[^` is a literal member, and POSIX classes
+/// such as `[:alpha:]` are copied whole. The regex crate has no collating
+/// symbols or equivalence classes, so a single-character `[.-.]` or
+/// `[=a=]` becomes that character, escaped.
+pub(crate) fn copy_bracket_expression(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    result: &mut String,
+) {
+    result.push('[');
+    if chars.peek() == Some(&'^') {
+        result.push('^');
+        chars.next();
+    }
+    if chars.peek() == Some(&']') {
+        result.push_str("\\]");
+        chars.next();
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            ']' => {
+                result.push(']');
+                return;
+            }
+            '\\' => {
+                result.push('\\');
+                if let Some(next) = chars.next() {
+                    result.push(next);
+                }
+            }
+            '[' if matches!(chars.peek(), Some(':' | '=' | '.')) => {
+                let delimiter = chars.next().unwrap();
+                let mut name = String::new();
+                let mut closed = false;
+                while let Some(inner) = chars.next() {
+                    if inner == delimiter && chars.peek() == Some(&']') {
+                        chars.next();
+                        closed = true;
+                        break;
+                    }
+                    name.push(inner);
+                }
+
+                let mut single = name.chars();
+                match (single.next(), single.next()) {
+                    (Some(member), None) if closed && delimiter != ':' => {
+                        if member.is_ascii_punctuation() {
+                            result.push('\\');
+                        }
+                        result.push(member);
+                    }
+                    _ => {
+                        result.push('[');
+                        result.push(delimiter);
+                        result.push_str(&name);
+                        if closed {
+                            result.push(delimiter);
+                            result.push(']');
+                        }
+                    }
+                }
+            }
+            '[' => result.push_str("\\["),
+            _ => result.push(c),
+        }
+    }
+}
+
 /// Detect if a pattern is in Basic Regular Expression (BRE) format
 ///
 /// # Detection Rules
@@ -97,113 +185,82 @@ pub fn is_bre_pattern(pattern: &str) -> bool {
      pattern.contains("\\9"))
 }
 
-/// Convert sed-style backreferences in replacement string to Rust regex style
-///
-/// # Conversion Rules
-///
-/// - `\1`..`\9` → `$1`..`$9` - Backreference conversion
-/// - `\&` → `$&` - Match reference
-/// - `\\` → `\` - Escape backslash
-///
-/// This is used separately from pattern conversion because replacement strings
-/// have slightly different rules than patterns.
-pub fn convert_sed_backreferences(replacement: &str) -> String {
-    let mut result = String::new();
-    let chars = replacement.chars().peekable();
-    let mut escape_next = false;
-
-    for c in chars {
-        if escape_next {
-            match c {
-                '1'..='9' => {
-                    // Backreference: \1 → $1
-                    result.push('$');
-                    result.push(c);
-                }
-                '&' => {
-                    // Match backreference: \& → $&
-                    result.push('$');
-                    result.push('&');
-                }
-                '\\' => {
-                    // Double backslash → single
-                    result.push('\\');
-                }
-                'n' => {
-                    // Newline escape
-                    result.push('\\');
-                    result.push('n');
-                }
-                _ => {
-                    // Unknown escape, keep literal
-                    result.push('\\');
-                    result.push(c);
-                }
-            }
-            escape_next = false;
-        } else if c == '\\' {
-            escape_next = true;
-        } else {
-            result.push(c);
-        }
-    }
-
-    // Handle trailing backslash
-    if escape_next {
-        result.push('\\');
-    }
-
-    result
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_convert_parentheses() {
-        assert_eq!(convert_bre_to_pcre(r#"\(foo\)"#), "(foo)");
-        assert_eq!(convert_bre_to_pcre(r#"\(a\)\(b\)"#), "(a)(b)");
-        assert_eq!(convert_bre_to_pcre(r#"foo\(bar\)"#), "foo(bar)");
+        assert_eq!(convert(r#"\(foo\)"#), "(foo)");
+        assert_eq!(convert(r#"\(a\)\(b\)"#), "(a)(b)");
+        assert_eq!(convert(r#"foo\(bar\)"#), "foo(bar)");
     }
 
     #[test]
     fn test_convert_braces() {
-        assert_eq!(convert_bre_to_pcre(r#"foo\{3\}"#), "foo{3}");
-        assert_eq!(convert_bre_to_pcre(r#"\{3,5\}"#), "{3,5}");
+        assert_eq!(convert(r#"foo\{3\}"#), "foo{3}");
+        assert_eq!(convert(r#"\{3,5\}"#), "{3,5}");
     }
 
     #[test]
     fn test_convert_quantifiers() {
-        assert_eq!(convert_bre_to_pcre(r#"foo\+"#), "foo+");
-        assert_eq!(convert_bre_to_pcre(r#"foo\?"#), "foo?");
-        assert_eq!(convert_bre_to_pcre(r#"foo\*"#), r#"foo\*"#); // \* is literal asterisk in both BRE and PCRE
+        assert_eq!(convert(r#"foo\+"#), "foo+");
+        assert_eq!(convert(r#"foo\?"#), "foo?");
+        assert_eq!(convert(r#"foo\*"#), r#"foo\*"#); // \* is literal asterisk in both BRE and PCRE
     }
 
     #[test]
     fn test_convert_alternation() {
-        assert_eq!(convert_bre_to_pcre(r#"foo\|bar"#), "foo|bar");
+        assert_eq!(convert(r#"foo\|bar"#), "foo|bar");
     }
 
     #[test]
     fn test_convert_backreferences() {
-        assert_eq!(convert_bre_to_pcre(r#"\1"#), "$1");
-        assert_eq!(convert_bre_to_pcre(r#"\2\1"#), "$2$1");
-        assert_eq!(convert_bre_to_pcre(r#"\&"#), "$&");
+        assert_eq!(convert(r#"\1"#), "$1");
+        assert_eq!(convert(r#"\2\1"#), "$2$1");
+        assert_eq!(convert(r#"\&"#), "$&");
     }
 
     #[test]
     fn test_convert_backslash() {
-        assert_eq!(convert_bre_to_pcre(r#"\\"#), "\\");
-        assert_eq!(convert_bre_to_pcre(r#"foo\\"#), "foo\\");
-        assert_eq!(convert_bre_to_pcre(r#"\\\\)"#), r#"\\)"#); // \\ → \
+        assert_eq!(convert(r#"\\"#), r#"\\"#);
+        assert_eq!(convert(r#"foo\\"#), r#"foo\\"#);
+        assert_eq!(convert(r#"\\\\)"#), r#"\\\\\)"#); // \\\\ stays, bare ) → \)
     }
 
     #[test]
-    fn test_no_conversion_needed() {
-        assert_eq!(convert_bre_to_pcre(r#"(foo)"#), "(foo)");
-        assert_eq!(convert_bre_to_pcre(r#"foo+"#), "foo+");
-        assert_eq!(convert_bre_to_pcre(r#"foo|bar"#), "foo|bar");
+    fn test_bare_metacharacters_are_literals() {
+        assert_eq!(convert(r#"(foo)"#), r#"\(foo\)"#);
+        assert_eq!(convert(r#"foo+"#), r#"foo\+"#);
+        assert_eq!(convert(r#"foo|bar"#), r#"foo\|bar"#);
+    }
+
+    #[test]
+    fn test_convert_table() {
+        for (bre, pcre) in [
+            (r#"\(ab\)"#, "(ab)"),
+            (r#"a+"#, r#"a\+"#),
+            (r#"a\{2,3\}"#, "a{2,3}"),
+            (r#".*"#, ".*"),
+            (r#"a?b"#, r#"a\?b"#),
+            (r#"x{2}"#, r#"x\{2\}"#),
+            (r#"[+(]"#, r#"[+(]"#),
+            (r#"[]a]"#, r#"[\]a]"#),
+            (r#"[[]"#, r#"[\[]"#),
+        ] {
+            assert_eq!(convert(bre), pcre, "BRE {}", bre);
+        }
+    }
+
+    #[test]
+    fn test_converted_pattern_matches_like_gnu_sed() {
+        let re = regex::Regex::new(&convert("a+")).unwrap();
+        assert!(re.is_match("a+"));
+        assert!(!re.is_match("aa"));
+
+        let re = regex::Regex::new(&convert(r#"^a\{2,3\}$"#)).unwrap();
+        assert!(re.is_match("aaa"));
+        assert!(!re.is_match("a"));
     }
 
     #[test]
@@ -219,36 +276,20 @@ mod tests {
         assert!(!is_bre_pattern(r#"foo|bar"#));
     }
 
-    #[test]
-    fn test_convert_sed_backreferences() {
-        assert_eq!(convert_sed_backreferences(r#"\1"#), "$1");
-        assert_eq!(convert_sed_backreferences(r#"\2\1"#), "$2$1");
-        assert_eq!(convert_sed_backreferences(r#"\&"#), "$&");
-        assert_eq!(convert_sed_backreferences(r#"\\"#), "\\");
-        assert_eq!(convert_sed_backreferences(r#"\n"#), "\\n");
-        assert_eq!(convert_sed_backreferences(r#"foo\1bar"#), "foo$1bar");
-    }
-
-    #[test]
-    fn test_no_backreference_conversion() {
-        assert_eq!(convert_sed_backreferences(r#"foo"#), "foo");
-        assert_eq!(convert_sed_backreferences(r#"foo bar"#), "foo bar");
-    }
-
     #[test]
     fn test_complex_bre_pattern() {
         // BRE: \(foo\)\(bar\) \2\1
         // PCRE: (foo)(bar) $2$1
         let bre_pattern = r#"\(foo\)\(bar\) \2\1"#;
-        let pcre_pattern = convert_bre_to_pcre(bre_pattern);
+        let pcre_pattern = convert(bre_pattern);
         assert_eq!(pcre_pattern, r#"(foo)(bar) $2$1"#);
     }
 
     #[test]
-    fn test_pcre_pattern_unchanged() {
-        // PCRE patterns should pass through unchanged
-        assert_eq!(convert_bre_to_pcre(r#"(foo|bar)+"#), r#"(foo|bar)+"#);
-        assert_eq!(convert_bre_to_pcre(r#"foo{3,5}"#), r#"foo{3,5}"#);
+    fn test_pcre_pattern_escaped() {
+        // PCRE syntax is literal text in BRE
+        assert_eq!(convert(r#"(foo|bar)+"#), r#"\(foo\|bar\)\+"#);
+        assert_eq!(convert(r#"foo{3,5}"#), r#"foo\{3,5\}"#);
     }
 
     // Additional comprehensive tests
@@ -256,230 +297,177 @@ mod tests {
     #[test]
     fn test_simple_patterns() {
         // Simple patterns should pass through unchanged
-        assert_eq!(convert_bre_to_pcre("foo"), "foo");
-        assert_eq!(convert_bre_to_pcre("bar123"), "bar123");
-        assert_eq!(convert_bre_to_pcre("test_pattern"), "test_pattern");
-        assert_eq!(convert_bre_to_pcre(""), "");
+        assert_eq!(convert("foo"), "foo");
+        assert_eq!(convert("bar123"), "bar123");
+        assert_eq!(convert("test_pattern"), "test_pattern");
+        assert_eq!(convert(""), "");
     }
 
     #[test]
     fn test_anchors() {
         // Anchors are the same in BRE and PCRE
-        assert_eq!(convert_bre_to_pcre("^foo"), "^foo");
-        assert_eq!(convert_bre_to_pcre("bar$"), "bar$");
-        assert_eq!(convert_bre_to_pcre("^start$"), "^start$");
-        assert_eq!(convert_bre_to_pcre(r#"\^foo"#), r#"\^foo"#); // Escaped anchor
+        assert_eq!(convert("^foo"), "^foo");
+        assert_eq!(convert("bar$"), "bar$");
+        assert_eq!(convert("^start$"), "^start$");
+        assert_eq!(convert(r#"\^foo"#), r#"\^foo"#); // Escaped anchor
+        // GNU buffer anchors
+        assert_eq!(convert(r"\`foo"), r"\Afoo");
+        assert_eq!(convert(r"foo\'"), r"foo\z");
+        assert_eq!(convert(r"\\`"), r"\\`");
+        // GNU word anchors
+        assert_eq!(convert(r"\<the\>"), r"\b{start}the\b{end}");
+        assert_eq!(convert(r"\\<"), r"\\<");
     }
 
     #[test]
     fn test_character_classes() {
         // Character classes are the same in BRE and PCRE
-        assert_eq!(convert_bre_to_pcre("[a-z]"), "[a-z]");
-        assert_eq!(convert_bre_to_pcre("[A-Z0-9]"), "[A-Z0-9]");
-        assert_eq!(convert_bre_to_pcre("[^abc]"), "[^abc]");
-        assert_eq!(convert_bre_to_pcre("[[:alpha:]]"), "[[:alpha:]]");
-        assert_eq!(convert_bre_to_pcre(r#"[a\]z]"#), r#"[a\]z]"#); // Escaped ] in char class
+        assert_eq!(convert("[a-z]"), "[a-z]");
+        assert_eq!(convert("[A-Z0-9]"), "[A-Z0-9]");
+        assert_eq!(convert("[^abc]"), "[^abc]");
+        assert_eq!(convert("[[:alpha:]]"), "[[:alpha:]]");
+        assert_eq!(convert(r#"[a\]z]"#), r#"[a\]z]"#); // Escaped ] in char class
+        // Single-character collating symbols and equivalence classes
+        assert_eq!(convert("[[.-.]a]"), r"[\-a]");
+        assert_eq!(convert("[^[=e=][:space:]]"), "[^e[:space:]]");
+        assert_eq!(convert("[[.space.]]"), "[[.space.]]");
     }
 
     #[test]
     fn test_escaped_sequences() {
         // Various escape sequences
-        assert_eq!(convert_bre_to_pcre(r#"\t"#), r#"\t"#); // Unknown escape, keep as-is
-        assert_eq!(convert_bre_to_pcre(r#"\n"#), r#"\n"#); // Unknown escape, keep as-is
-        assert_eq!(convert_bre_to_pcre(r#"\s"#), r#"\s"#); // Unknown escape, keep as-is
-        assert_eq!(convert_bre_to_pcre(r#"\w"#), r#"\w"#); // Unknown escape, keep as-is
+        assert_eq!(convert(r#"\t"#), r#"\t"#); // Unknown escape, keep as-is
+        assert_eq!(convert(r#"\n"#), r#"\n"#); // Unknown escape, keep as-is
+        assert_eq!(convert(r#"\s"#), r#"\s"#); // Unknown escape, keep as-is
+        assert_eq!(convert(r#"\w"#), r#"\w"#); // Unknown escape, keep as-is
     }
 
     #[test]
     fn test_wildcard() {
         // Wildcard is the same in BRE and PCRE
-        assert_eq!(convert_bre_to_pcre("f.o"), "f.o");
-        assert_eq!(convert_bre_to_pcre(".*"), ".*");
-        assert_eq!(convert_bre_to_pcre(r#"\.\*"#), r#"\.\*"#); // Escaped dot and star
+        assert_eq!(convert("f.o"), "f.o");
+        assert_eq!(convert(".*"), ".*");
+        assert_eq!(convert(r#"\.\*"#), r#"\.\*"#); // Escaped dot and star
     }
 
     #[test]
     fn test_complex_nested_patterns() {
         // Nested groups
-        assert_eq!(convert_bre_to_pcre(r#"\(foo\(bar\)\)"#), "(foo(bar))");
-        assert_eq!(convert_bre_to_pcre(r#"\(a\|\(b\|c\)\)"#), "(a|(b|c))");
+        assert_eq!(convert(r#"\(foo\(bar\)\)"#), "(foo(bar))");
+        assert_eq!(convert(r#"\(a\|\(b\|c\)\)"#), "(a|(b|c))");
 
         // Multiple groups with quantifiers
-        assert_eq!(convert_bre_to_pcre(r#"\(foo\)\+"#), "(foo)+");
-        assert_eq!(convert_bre_to_pcre(r#"\(bar\)\{2,5\}"#), "(bar){2,5}");
+        assert_eq!(convert(r#"\(foo\)\+"#), "(foo)+");
+        assert_eq!(convert(r#"\(bar\)\{2,5\}"#), "(bar){2,5}");
 
         // Complex BRE pattern: \(foo\)\{3\} \(bar\|baz\)
         assert_eq!(
-            convert_bre_to_pcre(r#"\(foo\)\{3\} \(bar\|baz\)"#),
+            convert(r#"\(foo\)\{3\} \(bar\|baz\)"#),
             r#"(foo){3} (bar|baz)"#
         );
     }
 
-    #[test]
-    fn test_multiple_backreferences_in_replacement() {
-        // Multiple backreferences
-        assert_eq!(convert_sed_backreferences(r#"\1\2\3"#), "$1$2$3");
-        assert_eq!(
-            convert_sed_backreferences(r#"\9\8\7\6\5\4\3\2\1"#),
-            "$9$8$7$6$5$4$3$2$1"
-        );
-
-        // Backreferences with text
-        assert_eq!(
-            convert_sed_backreferences(r#"start\1middle\2end"#),
-            "start$1middle$2end"
-        );
-
-        // Multiple consecutive same backreference
-        assert_eq!(convert_sed_backreferences(r#"\1\1\1"#), "$1$1$1");
-    }
-
-    #[test]
-    fn test_match_reference_in_replacement() {
-        // Match reference \&
-        assert_eq!(convert_sed_backreferences(r#"\&"#), "$&");
-        assert_eq!(convert_sed_backreferences(r#"foo\&bar"#), "foo$&bar");
-        assert_eq!(convert_sed_backreferences(r#"\&\&"#), "$&$&");
-        assert_eq!(convert_sed_backreferences(r#"\1\&\2"#), "$1$&$2");
-    }
-
-    #[test]
-    fn test_mixed_backreferences_and_text() {
-        // Complex replacement patterns
-        assert_eq!(
-            convert_sed_backreferences(r#"prefix_\1_suffix"#),
-            "prefix_$1_suffix"
-        );
-        assert_eq!(
-            convert_sed_backreferences(r#"Result: \1, \2"#),
-            "Result: $1, $2"
-        );
-        assert_eq!(convert_sed_backreferences(r#"\1:\&:\2"#), "$1:$&:$2");
-    }
-
-    #[test]
-    fn test_no_backreferences_in_text() {
-        // Regular text without backreferences
-        assert_eq!(convert_sed_backreferences("simple text"), "simple text");
-        assert_eq!(convert_sed_backreferences("1234567890"), "1234567890");
-        assert_eq!(convert_sed_backreferences("!@#$%^&*()"), "!@#$%^&*()");
-        assert_eq!(convert_sed_backreferences(""), "");
-    }
-
     #[test]
     fn test_trailing_backslash_pattern() {
         // Trailing backslash should be preserved
-        assert_eq!(convert_bre_to_pcre(r#"foo\"#), r#"foo\"#);
-        assert_eq!(convert_bre_to_pcre(r#"\("#), r#"("#); // Just opening paren
-        assert_eq!(convert_bre_to_pcre(r#"\"#), r#"\"#); // Just backslash
-    }
-
-    #[test]
-    fn test_trailing_backslash_replacement() {
-        // Trailing backslash in replacement
-        assert_eq!(convert_sed_backreferences(r#"foo\"#), r#"foo\"#);
-        assert_eq!(convert_sed_backreferences(r#"\"#), r#"\"#);
-        assert_eq!(convert_sed_backreferences(r#"\1\"#), r#"$1\"#);
+        assert_eq!(convert(r#"foo\"#), r#"foo\"#);
+        assert_eq!(convert(r#"\("#), r#"("#); // Just opening paren
+        assert_eq!(convert(r#"\"#), r#"\"#); // Just backslash
     }
 
     #[test]
     fn test_double_backslash_conversion() {
-        // Double backslash to single
-        assert_eq!(convert_bre_to_pcre(r#"\\"#), "\\");
-        assert_eq!(convert_bre_to_pcre(r#"foo\\bar"#), "foo\\bar");
-        assert_eq!(convert_bre_to_pcre(r#"\\("#), r#"\("#); // \\ then \( → \ then (
+        // An escaped backslash is kept escaped
+        assert_eq!(convert(r#"\\"#), r#"\\"#);
+        assert_eq!(convert(r#"foo\\bar"#), r#"foo\\bar"#);
+        assert_eq!(convert(r#"\\("#), r#"\\\("#); // \\ then bare ( → \\ then \(
 
         // Triple and quadruple backslash
-        assert_eq!(convert_bre_to_pcre(r#"\\\"#), r#"\\"#); // \\\" → \\
-        assert_eq!(convert_bre_to_pcre(r#"\\\\"#), r#"\\"#); // \\\\ → \\
-    }
+        assert_eq!(convert(r#"\\\"#), r#"\\\"#); // \\ then a trailing \
+        assert_eq!(convert(r#"\\\\"#), r#"\\\\"#); // two literal backslashes
 
-    #[test]
-    fn test_double_backslash_replacement() {
-        // Double backslash to single in replacement
-        assert_eq!(convert_sed_backreferences(r#"\\"#), "\\");
-        assert_eq!(convert_sed_backreferences(r#"foo\\bar"#), "foo\\bar");
-        assert_eq!(convert_sed_backreferences(r#"\1\\n"#), "$1\\n");
+        // A literal backslash, not a word boundary or an unclosed group
+        let re = regex::Regex::new(&convert(r"a\\b")).unwrap();
+        assert!(re.is_match(r"a\b"));
+        let re = regex::Regex::new(&convert(r"a\\\(b\)")).unwrap();
+        assert!(re.is_match(r"a\b"));
     }
 
     #[test]
     fn test_alternation_patterns() {
         // Various alternation patterns
-        assert_eq!(convert_bre_to_pcre(r#"foo\|bar"#), "foo|bar");
+        assert_eq!(convert(r#"foo\|bar"#), "foo|bar");
         // Note: \baz gets \b converted (unknown escape) and then literal baz
-        assert_eq!(
-            convert_bre_to_pcre(r#"\(foo\|bar\|\baz\)"#),
-            r#"(foo|bar|\baz)"#
-        );
-        assert_eq!(convert_bre_to_pcre(r#"a\|b\|c"#), "a|b|c");
+        assert_eq!(convert(r#"\(foo\|bar\|\baz\)"#), r#"(foo|bar|\baz)"#);
+        assert_eq!(convert(r#"a\|b\|c"#), "a|b|c");
         // Clean alternation with escaped bars only
-        assert_eq!(convert_bre_to_pcre(r#"\(foo\|bar\)\+"#), "(foo|bar)+");
+        assert_eq!(convert(r#"\(foo\|bar\)\+"#), "(foo|bar)+");
     }
 
     #[test]
     fn test_repetition_quantifiers() {
         // All BRE quantifiers
-        assert_eq!(convert_bre_to_pcre(r#"foo\+"#), "foo+");
-        assert_eq!(convert_bre_to_pcre(r#"foo\?"#), "foo?");
-        assert_eq!(convert_bre_to_pcre(r#"foo\{3\}"#), "foo{3}");
-        assert_eq!(convert_bre_to_pcre(r#"foo\{3,5\}"#), "foo{3,5}");
-        assert_eq!(convert_bre_to_pcre(r#"foo\{3,\}"#), "foo{3,}");
-        assert_eq!(convert_bre_to_pcre(r#"foo\{,5\}"#), "foo{,5}");
+        assert_eq!(convert(r#"foo\+"#), "foo+");
+        assert_eq!(convert(r#"foo\?"#), "foo?");
+        assert_eq!(convert(r#"foo\{3\}"#), "foo{3}");
+        assert_eq!(convert(r#"foo\{3,5\}"#), "foo{3,5}");
+        assert_eq!(convert(r#"foo\{3,\}"#), "foo{3,}");
+        assert_eq!(convert(r#"foo\{,5\}"#), "foo{,5}");
 
         // Escaped quantifiers remain escaped (literal)
-        assert_eq!(convert_bre_to_pcre(r#"foo\*"#), r#"foo\*"#);
+        assert_eq!(convert(r#"foo\*"#), r#"foo\*"#);
     }
 
     #[test]
     fn test_grouped_commands() {
         // BRE patterns commonly used with grouped commands
-        assert_eq!(convert_bre_to_pcre(r#"/foo\|bar/"#), r#"/foo|bar/"#);
+        assert_eq!(convert(r#"/foo\|bar/"#), r#"/foo|bar/"#);
         // Backreferences in patterns are converted to $1 for SedX internal representation
-        assert_eq!(convert_bre_to_pcre(r#"\(test\).*\1"#), r#"(test).*$1"#);
+        assert_eq!(convert(r#"\(test\).*\1"#), r#"(test).*$1"#);
     }
 
     #[test]
     fn test_digit_backreferences_in_pattern() {
         // In patterns, \1-\9 convert to $1-$9
         // Note: This is for SedX's internal representation
-        assert_eq!(convert_bre_to_pcre(r#"\1"#), "$1");
-        assert_eq!(convert_bre_to_pcre(r#"\2"#), "$2");
-        assert_eq!(convert_bre_to_pcre(r#"\9"#), "$9");
+        assert_eq!(convert(r#"\1"#), "$1");
+        assert_eq!(convert(r#"\2"#), "$2");
+        assert_eq!(convert(r#"\9"#), "$9");
 
         // Digits following backslash that aren't backreferences
-        assert_eq!(convert_bre_to_pcre(r#"\0"#), r#"\0"#); // \0 is not a backreference
+        assert_eq!(convert(r#"\0"#), r#"\0"#); // \0 is not a backreference
     }
 
     #[test]
     fn test_special_characters_preserved() {
         // Characters that should remain unchanged
-        assert_eq!(convert_bre_to_pcre(r#"."#), ".");
-        assert_eq!(convert_bre_to_pcre(r#"*"#), "*");
-        assert_eq!(convert_bre_to_pcre(r#"^"#), "^");
-        assert_eq!(convert_bre_to_pcre(r#"$"#), "$");
-        assert_eq!(convert_bre_to_pcre(r#"["#), "[");
-        assert_eq!(convert_bre_to_pcre(r#"]"#), "]");
+        assert_eq!(convert(r#"."#), ".");
+        assert_eq!(convert(r#"*"#), "*");
+        assert_eq!(convert(r#"^"#), "^");
+        assert_eq!(convert(r#"$"#), "$");
+        assert_eq!(convert(r#"["#), "[");
+        assert_eq!(convert(r#"]"#), "]");
     }
 
     #[test]
     fn test_newline_escape_at_end() {
         // \n at end of pattern is literal newline escape
-        assert_eq!(convert_bre_to_pcre(r#"foo\n"#), r#"foo\n"#);
-        assert_eq!(convert_bre_to_pcre(r#"\n"#), r#"\n"#);
+        assert_eq!(convert(r#"foo\n"#), r#"foo\n"#);
+        assert_eq!(convert(r#"\n"#), r#"\n"#);
     }
 
     #[test]
     fn test_empty_groups() {
         // Empty or simple groups
-        assert_eq!(convert_bre_to_pcre(r#"\(\)"#), "()");
-        assert_eq!(convert_bre_to_pcre(r#"\(\+\)"#), "(+)");
+        assert_eq!(convert(r#"\(\)"#), "()");
+        assert_eq!(convert(r#"\(\+\)"#), "(+)");
     }
 
     #[test]
     fn test_unicode_patterns() {
         // Unicode characters should pass through
-        assert_eq!(convert_bre_to_pcre("föö"), "föö");
-        assert_eq!(convert_bre_to_pcre(r#"\(日本語\)"#), "(日本語)");
-        assert_eq!(convert_bre_to_pcre("test_测试"), "test_测试");
+        assert_eq!(convert("föö"), "föö");
+        assert_eq!(convert(r#"\(日本語\)"#), "(日本語)");
+        assert_eq!(convert("test_测试"), "test_测试");
     }
 }
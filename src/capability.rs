@@ -3,106 +3,226 @@
 //! This module provides functions to determine whether commands can be executed
 //! in streaming mode or require full file buffering.
 
-use crate::command::{Address, Command};
+use crate::command::{Address, Command, CommandInfo};
+use serde::Serialize;
+use std::fmt;
+
+/// Whether a script can run in streaming mode, and if not, why
+/// (`--explain`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamDecision {
+    pub streamable: bool,
+    /// Why the script has to be processed in memory
+    pub reason: Option<String>,
+    /// Index of the top-level command that forced in-memory processing
+    pub offending_index: Option<usize>,
+}
+
+impl StreamDecision {
+    fn streamable() -> Self {
+        Self {
+            streamable: true,
+            reason: None,
+            offending_index: None,
+        }
+    }
+
+    /// In-memory processing, forced by the command at `offending_index`
+    /// or, without one, by something outside the script
+    pub fn rejected(reason: impl Into<String>, offending_index: Option<usize>) -> Self {
+        Self {
+            streamable: false,
+            reason: Some(reason.into()),
+            offending_index,
+        }
+    }
+}
+
+impl fmt::Display for StreamDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.streamable {
+            return write!(f, "streaming");
+        }
+        let reason = self.reason.as_deref().unwrap_or("not streamable");
+        match self.offending_index {
+            Some(index) => write!(f, "in memory (command {}: {})", index + 1, reason),
+            None => write!(f, "in memory ({})", reason),
+        }
+    }
+}
 
 /// Check if a list of commands can be executed in streaming mode
 ///
 /// # Streaming Limitations
 ///
 /// Some commands require full file buffering and cannot run in streaming mode:
-/// - Command groups with ranges
 /// - Hold space operations with non-streamable ranges (e.g., negated addresses)
 /// - Negated addresses in ranges
 /// - Complex mixed ranges (pattern to negated pattern, etc.)
-#[allow(dead_code)] // Kept for potential future use
+/// - Multi-line, flow control, file I/O and shell commands
+#[allow(dead_code)] // Library API; the binary uses stream_decision
 pub fn can_stream(commands: &[Command]) -> bool {
-    for cmd in commands {
-        match cmd {
-            Command::Substitution { range, .. } => {
-                if let Some(range) = range
-                    && !is_range_streamable(range)
-                {
-                    return false;
-                }
-            }
-            Command::Delete { range } | Command::Print { range } => {
-                if !is_range_streamable(range) {
-                    return false;
-                }
-            }
-            Command::Insert { .. } | Command::Append { .. } | Command::Change { .. } => {
-                // Insert/Append/Change are streamable for single-line addresses
-                // but not for ranges
-                return true;
-            }
-            Command::Group {
-                range,
-                commands: inner_cmds,
-            } => {
-                // Chunk 10: Groups are streamable if range is streamable and inner commands are streamable
-                if let Some(r) = range
-                    && !is_range_streamable(r)
-                {
-                    return false;
-                }
-                // Check inner commands
-                if !can_stream(inner_cmds) {
-                    return false;
-                }
-            }
-            Command::Hold { range }
-            | Command::HoldAppend { range }
-            | Command::Get { range }
-            | Command::GetAppend { range }
-            | Command::Exchange { range } => {
-                // Chunk 9: Hold space operations are streamable
-                // Check if range is streamable
-                if let Some(r) = range
-                    && !is_range_streamable(r)
-                {
-                    return false;
-                }
-            }
-            Command::Quit { .. } => {
-                // Quit is streamable
-                continue;
-            }
-            Command::QuitWithoutPrint { .. } => {
-                // Quit without printing is streamable
-                continue;
-            }
-            // Phase 4: Multi-line pattern space commands are NOT streamable (require full file access)
-            Command::Next { .. }
-            | Command::NextAppend { .. }
-            | Command::PrintFirstLine { .. }
-            | Command::DeleteFirstLine { .. } => {
-                return false;
-            }
-            // Phase 5: Flow control commands are NOT streamable (require label tracking and program counter)
-            Command::Label { .. }
-            | Command::Branch { .. }
-            | Command::Test { .. }
-            | Command::TestFalse { .. } => {
-                return false;
-            }
-            // Phase 5: File I/O commands are NOT streamable (require file handle management)
-            Command::ReadFile { .. }
-            | Command::WriteFile { .. }
-            | Command::ReadLine { .. }
-            | Command::WriteFirstLine { .. } => {
-                return false;
+    stream_decision(commands).streamable
+}
+
+/// Decide whether a list of commands can be executed in streaming mode,
+/// naming the first command that can't
+pub fn stream_decision(commands: &[Command]) -> StreamDecision {
+    for (index, cmd) in commands.iter().enumerate() {
+        if let Some(reason) = command_rejection(cmd, false) {
+            return StreamDecision::rejected(reason, Some(index));
+        }
+    }
+    StreamDecision::streamable()
+}
+
+/// A command's row in `sedx commands`: what it is and which engines run it
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandSupport {
+    #[serde(flatten)]
+    pub info: CommandInfo,
+    /// Whether it can run in streaming mode, the one used for large files
+    pub streaming: bool,
+    /// Whether the in-memory engine runs it (it implements every command)
+    pub in_memory: bool,
+    /// Why it can't be streamed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub streaming_note: Option<String>,
+}
+
+/// Every command with the engines that run it, decided the same way as
+/// for a script made of just that command (with no address where the
+/// command allows it)
+pub fn command_support() -> Vec<CommandSupport> {
+    Command::catalog()
+        .into_iter()
+        .map(|cmd| {
+            let decision = stream_decision(std::slice::from_ref(&cmd));
+            CommandSupport {
+                info: cmd.info(),
+                streaming: decision.streamable,
+                in_memory: true,
+                streaming_note: decision.reason,
             }
-            // Phase 5: Additional commands (print line number, print filename, clear pattern space)
-            // PrintLineNumber and PrintFilename write to stdout separately
-            // ClearPatternSpace modifies pattern space state
-            Command::PrintLineNumber { .. }
-            | Command::PrintFilename { .. }
-            | Command::ClearPatternSpace { .. } => {
-                return false;
+        })
+        .collect()
+}
+
+/// Why `cmd` can't be streamed, if it can't. Groups only stream the
+/// commands the streaming group handler knows (`in_group`).
+fn command_rejection(cmd: &Command, in_group: bool) -> Option<String> {
+    match cmd {
+        // s///e runs shell commands, which only the in-memory engine does
+        Command::Substitution { flags, .. } if flags.exec => {
+            Some("s///e runs shell commands, which streaming doesn't do".to_string())
+        }
+        Command::Substitution { flags, .. } if flags.write_file.is_some() => {
+            Some("s///w writes to a file, which streaming doesn't do".to_string())
+        }
+        // Chunk 9: Hold space operations are streamable
+        Command::Substitution { range, .. }
+        | Command::Hold { range }
+        | Command::HoldAppend { range }
+        | Command::Get { range }
+        | Command::GetAppend { range }
+        | Command::Exchange { range } => range.as_ref().and_then(range_rejection),
+        Command::Delete { range } | Command::Print { range } => range_rejection(range),
+        // Chunk 10: Groups are streamable if their range and inner commands are
+        Command::Group { range, commands } => range
+            .as_ref()
+            .and_then(range_rejection)
+            .or_else(|| commands.iter().find_map(|c| command_rejection(c, true))),
+        Command::Insert { .. } | Command::Append { .. } | Command::Change { .. } if in_group => {
+            Some("i, a and c inside a group are not streamable".to_string())
+        }
+        // c over a range prints its text once, where the range ends
+        Command::Change { range, .. } if range.0 != range.1 => {
+            Some("c over a range is not streamable".to_string())
+        }
+        // Insert/Append/Change are streamable for single-line addresses
+        Command::Insert { address, .. }
+        | Command::Append { address, .. }
+        | Command::Change {
+            range: (address, _),
+            ..
+        } => match address {
+            Address::LineNumber(_) | Address::Pattern(_) | Address::Step { .. } => None,
+            _ => Some("i, a and c are only streamable at a line number or /pattern/".to_string()),
+        },
+        Command::Quit { .. } if in_group => Some("q inside a group is not streamable".to_string()),
+        // Quitting needs a line number: the last line isn't known while streaming
+        Command::Quit {
+            address: Some(Address::LineNumber(_)),
+            ..
+        } => None,
+        Command::Quit { .. } => Some("q is only streamable at a line number".to_string()),
+        Command::QuitWithoutPrint { .. } => Some("Q is not streamable".to_string()),
+        // Phase 4: Multi-line pattern space commands are NOT streamable (require full file access)
+        Command::Next { .. }
+        | Command::NextAppend { .. }
+        | Command::PrintFirstLine { .. }
+        | Command::DeleteFirstLine { .. } => {
+            Some("n, N, P and D work across lines and are not streamable".to_string())
+        }
+        // Phase 5: Flow control commands are NOT streamable (require label tracking and program counter)
+        Command::Label { .. }
+        | Command::Branch { .. }
+        | Command::Test { .. }
+        | Command::TestFalse { .. } => {
+            Some("labels and branches (:, b, t, T) are not streamable".to_string())
+        }
+        Command::ReadFile { .. } | Command::ReadLine { .. } if in_group => {
+            Some("r and R inside a group are not streamable".to_string())
+        }
+        // r and R stream their files in after the line at a single address
+        Command::ReadFile { range, .. } | Command::ReadLine { range, .. } => match range {
+            None
+            | Some(
+                Address::LineNumber(_)
+                | Address::LastLine
+                | Address::Pattern(_)
+                | Address::Step { .. },
+            ) => None,
+            Some(_) => {
+                Some("r and R are only streamable at a line number, $ or /pattern/".to_string())
             }
+        },
+        // Phase 5: Writing files is NOT streamable (requires file handle management)
+        Command::WriteFile { .. } | Command::WriteFirstLine { .. } => {
+            Some("file commands (w, W) are not streamable".to_string())
+        }
+        // PrintLineNumber and PrintFilename write to stdout separately
+        Command::PrintLineNumber { .. } | Command::PrintFilename { .. } => {
+            Some("= and F are not streamable".to_string())
+        }
+        Command::ClearPatternSpace { .. } => Some("z is not streamable".to_string()),
+        // List output is produced by the cycle engine only
+        Command::List { .. } => Some("l is not streamable".to_string()),
+        // Shell execution is only wired into the in-memory engines
+        Command::Execute { .. } => {
+            Some("e runs shell commands, which streaming doesn't do".to_string())
         }
     }
-    true
+}
+
+/// Why an address range can't be streamed, if it can't
+fn range_rejection(range: &(Address, Address)) -> Option<String> {
+    use Address::*;
+
+    if is_range_streamable(range) {
+        return None;
+    }
+    let kind = match range {
+        (Negated(_), _) | (_, Negated(_)) => "negated range",
+        (_, LastLine) => "range ending at $",
+        (LastLine, _) => "range starting at $",
+        (Relative { .. }, _) => "range starting with a relative address",
+        (_, Relative { .. }) => "relative range that doesn't start at a /pattern/",
+        (_, MultipleOf(_)) => "range ending at ~N",
+        (FirstLine, _) => "0,/re/M range",
+        _ => "address range",
+    };
+    Some(format!("{} is not streamable", kind))
 }
 
 /// Check if a specific address range type is supported in streaming mode
@@ -114,14 +234,16 @@ pub fn can_stream(commands: &[Command]) -> bool {
 /// - Pattern to pattern: `/start/,/end/`
 /// - Pattern to line number: `/start/,10`
 /// - Line number to pattern: `5,/end/`
+/// - First line to pattern: `0,/end/`
 /// - Pattern with relative offset: `/start/,+5`
 /// - Stepping addresses: `1~2`
+/// - Negated single address: `/keep/!`, `5!`
 ///
 /// # Non-Streamable Ranges
 ///
-/// - Negated addresses: `!/pattern/`
+/// - Negated ranges: `1,/end/!`
+/// - Ranges ending at `$` other than `1,$`
 /// - Complex mixed negated ranges
-#[allow(dead_code)] // Used by can_stream
 fn is_range_streamable(range: &(Address, Address)) -> bool {
     use Address::*;
 
@@ -141,28 +263,22 @@ fn is_range_streamable(range: &(Address, Address)) -> bool {
         // Line number to pattern - streamable (mixed)
         (LineNumber(_), Pattern(_)) => true,
 
+        // 0,/end/ - streamable
+        (FirstLine, Pattern(_)) => true,
+
         // Pattern to relative offset - streamable
         (Pattern(_), Relative { .. }) => true,
 
-        // Line number to relative offset - streamable
-        (LineNumber(_), Relative { .. }) => true,
+        // addr,~N needs its own range state, which streaming doesn't keep
+        (_, MultipleOf(_)) => false,
 
         // Stepping addresses - streamable
         (Step { .. }, _) | (_, Step { .. }) => true,
 
-        // Negated addresses - not streamable
-        (Negated(_), _) | (_, Negated(_)) => false,
-
-        // Relative offsets as start address - not streamable
-        (Relative { .. }, _) => false,
-
-        // First line as start - streamable with most end addresses
-        (FirstLine, LineNumber(_)) => true,
-        (FirstLine, LastLine) => true,
-        (FirstLine, Pattern(_)) => true,
-
-        // Last line as start - not streamable (need to know where end is)
-        (LastLine, _) => false,
+        // Negated single pattern or line: /keep/!d, 5!s/x/y/
+        (Negated(start), Negated(end)) if start == end => {
+            matches!(start.as_ref(), Pattern(_) | LineNumber(_))
+        }
 
         // Default: conservative - not streamable
         _ => false,
@@ -174,6 +290,117 @@ mod tests {
     use super::*;
     use crate::command::SubstitutionFlags;
 
+    fn decide(script: &str) -> StreamDecision {
+        use crate::cli::RegexFlavor;
+        use crate::parser::Parser;
+
+        stream_decision(&Parser::new(RegexFlavor::PCRE).parse(script).unwrap())
+    }
+
+    #[test]
+    fn test_command_support_lists_engines() {
+        let support = command_support();
+        let find = |command: &str| {
+            support
+                .iter()
+                .find(|row| row.info.command == command)
+                .unwrap_or_else(|| panic!("{} missing from the listing", command))
+        };
+
+        assert!(find("s").streaming);
+        assert!(find("r").streaming);
+        let next = find("N");
+        assert!(!next.streaming);
+        assert!(next.streaming_note.is_some());
+        assert!(!find("w").streaming);
+        assert!(support.iter().all(|row| row.in_memory));
+        assert!(
+            support
+                .iter()
+                .filter(|row| row.streaming)
+                .all(|row| row.streaming_note.is_none())
+        );
+    }
+
+    #[test]
+    fn test_stream_decision_streamable_script() {
+        let decision = decide("s/a/b/; /start/,/end/{s/x/y/; /skip/d}; 3q");
+        assert_eq!(
+            decision,
+            StreamDecision {
+                streamable: true,
+                reason: None,
+                offending_index: None,
+            }
+        );
+        assert_eq!(decision.to_string(), "streaming");
+    }
+
+    #[test]
+    fn test_stream_decision_names_offending_command() {
+        for (script, index, reason) in [
+            ("s/a/b/; s/x/date/e", 1, "s///e runs shell commands"),
+            ("s/a/b/; 1,/end/!d", 1, "negated range is not streamable"),
+            ("5,$d", 0, "range ending at $ is not streamable"),
+            ("2,4c\\X", 0, "c over a range is not streamable"),
+            ("$!N; P", 0, "n, N, P and D work across lines"),
+            ("1{s/a/b/; 2i\\\nnew\n}", 0, "i, a and c inside a group"),
+            ("/x/q", 0, "q is only streamable at a line number"),
+            ("2Q", 0, "Q is not streamable"),
+            (":a; s/x/y/; t a", 0, "labels and branches"),
+            ("w out.txt", 0, "file commands (w, W)"),
+            ("s/a/b/; =", 1, "= and F are not streamable"),
+            ("z", 0, "z is not streamable"),
+            ("l", 0, "l is not streamable"),
+            ("e echo hi", 0, "e runs shell commands"),
+        ] {
+            let decision = decide(script);
+            assert!(!decision.streamable, "{}", script);
+            assert_eq!(decision.offending_index, Some(index), "{}", script);
+            let message = decision.reason.clone().unwrap();
+            assert!(message.contains(reason), "{}: {}", script, message);
+            assert!(
+                decision
+                    .to_string()
+                    .starts_with(&format!("in memory (command {}: ", index + 1)),
+                "{}: {}",
+                script,
+                decision
+            );
+        }
+    }
+
+    #[test]
+    fn test_stream_decision_for_i_a_c_addresses() {
+        assert!(decide("3i\\\nnew").streamable);
+        assert!(decide("/x/a\\\nnew").streamable);
+        let decision = decide("$a\\\nnew");
+        assert!(!decision.streamable);
+        assert!(
+            decision
+                .reason
+                .unwrap()
+                .contains("only streamable at a line number or /pattern/")
+        );
+    }
+
+    #[test]
+    fn test_stream_decision_for_read_commands() {
+        assert!(decide("$r footer.txt").streamable);
+        assert!(decide("/x/R lines.txt; 3r header.txt").streamable);
+        for (script, reason) in [
+            (
+                "5!r footer.txt",
+                "r and R are only streamable at a line number",
+            ),
+            ("1{R lines.txt}", "r and R inside a group"),
+        ] {
+            let decision = decide(script);
+            assert!(!decision.streamable, "{}", script);
+            assert!(decision.reason.unwrap().contains(reason), "{}", script);
+        }
+    }
+
     #[test]
     fn test_can_stream_simple_substitution() {
         let cmd = Command::Substitution {
@@ -210,6 +437,20 @@ mod tests {
         assert!(can_stream(&[cmd]));
     }
 
+    #[test]
+    fn test_cannot_stream_substitution_with_exec_flag() {
+        let cmd = Command::Substitution {
+            pattern: ".*".to_string(),
+            replacement: "date".to_string(),
+            flags: SubstitutionFlags {
+                exec: true,
+                ..SubstitutionFlags::default()
+            },
+            range: None,
+        };
+        assert!(!can_stream(&[cmd]));
+    }
+
     #[test]
     fn test_can_stream_hold() {
         // Chunk 9: Hold space operations ARE streamable
@@ -258,6 +499,7 @@ mod tests {
     fn test_can_stream_quit() {
         let cmd = Command::Quit {
             address: Some(Address::LineNumber(10)),
+            exit_code: None,
         };
         assert!(can_stream(&[cmd]));
     }
@@ -1,5 +1,7 @@
+use crate::config::CompatMode;
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 const LONG_VERSION: &str = concat!(
     env!("CARGO_PKG_VERSION"),
@@ -50,6 +52,9 @@ EXAMPLES:
   sedx '/error/s/test/fix/' file.txt       Only in lines matching 'error'
   sedx '5,10d' file.txt                    Delete lines 5-10
   sedx '{s/a/A/g; s/b/B/g}' file.txt      Multiple commands
+  sedx -n '/error/p' app.log               Print only matching lines
+  sedx --dry-run 's/v1/v2/' *.conf         Preview without changing files
+  sedx commands                            List commands and which stream
   sedx --rollback backup.ID                Undo last change"
 )]
 #[command(version = env!("CARGO_PKG_VERSION"))]
@@ -78,6 +83,13 @@ struct Cli {
     #[arg(value_name = "FILE")]
     files: Vec<String>,
 
+    /// Read NUL-separated file names from a file (- for stdin)
+    #[arg(long = "files0-from", value_name = "F")]
+    #[arg(
+        help = "Read the files to process from F, separated by NUL characters\nUse - to read the list from stdin, e.g. find . -name '*.rs' -print0 | sedx --files0-from=- 's/old/new/'"
+    )]
+    files0_from: Option<String>,
+
     /// Dry run mode (preview changes without applying)
     #[arg(short = 'd', long, alias = "dry-run")]
     #[arg(
@@ -85,12 +97,28 @@ struct Cli {
     )]
     dry_run: bool,
 
+    /// Summarize the preview instead of showing diffs
+    #[arg(long, requires = "dry_run")]
+    #[arg(
+        help = "With --dry-run, show per-file counts of modified, added and deleted lines and a total instead of full diffs\nLike git diff --stat"
+    )]
+    stat: bool,
+
     /// Interactive mode (ask before applying changes)
     #[arg(short = 'i', long)]
-    #[arg(help = "Ask for confirmation before applying each change.")]
+    #[arg(
+        help = "Ask about each change before applying it (y/n/a/q)\nOnly the changes you accept are written"
+    )]
     interactive: bool,
 
-    /// Number of context lines to show (default: 2)
+    /// Don't print diffs
+    #[arg(long = "no-preview", conflicts_with_all = ["interactive", "stat"])]
+    #[arg(
+        help = "Don't print the diffs of the preview or of the applied changes\nOnly the one-line summary and the backup ID are shown"
+    )]
+    no_preview: bool,
+
+    /// Number of context lines to show (default: processing.context_lines, else 2)
     #[arg(long, value_name = "NUM")]
     #[arg(
         help = "Number of context lines to show around changes\nUse 0 to show only changed lines (equivalent to --no-context)"
@@ -109,6 +137,19 @@ struct Cli {
     #[arg(help = "Show only changed lines without context\nEquivalent to --context=0")]
     no_context: bool,
 
+    /// Output format for diffs
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Human)]
+    #[arg(
+        help = "Output format for diffs: human, json, or unified\njson emits one JSON object per file (machine-readable)\nunified emits a patch-style diff (like diff -u)"
+    )]
+    format: OutputFormat,
+
+    /// When to color diff output
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    #[arg(help = "Color diff output: auto, always, or never
+auto colors only when stdout is a terminal and NO_COLOR is unset")]
+    color: ColorChoice,
+
     /// Enable streaming mode for large files (>=100MB)
     #[arg(long, alias = "force-streaming")]
     #[arg(
@@ -129,7 +170,13 @@ struct Cli {
     bre: bool,
 
     /// Use Extended Regular Expressions (ERE)
-    #[arg(short = 'E', long, conflicts_with = "bre")]
+    #[arg(
+        short = 'E',
+        long,
+        short_alias = 'r',
+        alias = "regexp-extended",
+        conflicts_with = "bre"
+    )]
     #[arg(help = "Use Extended Regular Expressions (ERE)\nLike sed -E: ( ), { }, +, ?, |")]
     ere: bool,
 
@@ -147,6 +194,13 @@ struct Cli {
     )]
     force: bool,
 
+    /// Keep applying when a file can't be backed up
+    #[arg(long = "keep-going")]
+    #[arg(
+        help = "Skip a file that can't be backed up, with a warning, and apply the rest\nSkipped files are counted as failed in the final summary"
+    )]
+    keep_going: bool,
+
     /// Custom backup directory
     #[arg(long, value_name = "DIR")]
     #[arg(
@@ -154,6 +208,181 @@ struct Cli {
     )]
     backup_dir: Option<String>,
 
+    /// Gzip-compress backup payloads
+    #[arg(long = "backup-compression")]
+    #[arg(
+        help = "Store backups gzip-compressed to save space\nRestores decompress them transparently; config: backup.compression"
+    )]
+    backup_compression: bool,
+
+    /// Write transformed copies instead of editing in place
+    #[arg(long = "out-dir", value_name = "DIR")]
+    #[arg(
+        help = "Write each result to DIR/<relative path> instead of editing in place\nSource files are never modified and no backup is created"
+    )]
+    out_dir: Option<String>,
+
+    /// Edit the file a symlink points to instead of replacing the link
+    #[arg(long = "follow-symlinks")]
+    #[arg(
+        help = "Write edits through symlinks to the files they point to\nWithout it, an edited symlink is replaced by a regular file, like GNU sed"
+    )]
+    follow_symlinks: bool,
+
+    /// Allow commands that execute shell commands (e, s///e)
+    #[arg(long = "allow-exec")]
+    #[arg(
+        help = "Allow the e command and s///e flag to run shell commands\n⚠️  Only use with scripts you trust"
+    )]
+    allow_exec: bool,
+
+    /// Reject commands that do file I/O or run shell commands
+    #[arg(long = "sandbox")]
+    #[arg(
+        help = "Reject scripts using e, r, R, w, W or s///e (like GNU sed --sandbox)\nFor running untrusted scripts"
+    )]
+    sandbox: bool,
+
+    /// Fold only ASCII letters when ignoring case
+    #[arg(long = "ascii-case")]
+    #[arg(
+        help = "Make case-insensitive matching (s///i) fold ASCII letters only\nWithout it, Unicode folding applies, so s/k/x/i also matches the Kelvin sign"
+    )]
+    ascii_case: bool,
+
+    /// Match regexes only as whole words
+    #[arg(short = 'w', long = "word-regexp", alias = "word")]
+    #[arg(
+        help = "Match every substitution and address regex only as a whole word, as if wrapped in \\b...\\b\nA side the pattern already anchors (^, $, \\b) isn't wrapped again"
+    )]
+    word_regexp: bool,
+
+    /// Use POSIX behavior for this run
+    #[arg(long, conflicts_with = "gnu")]
+    #[arg(
+        help = "Behave like POSIX sed for this run, overriding compatibility.mode\nRejects GNU-only commands (e, F, Q, R, T, W, z) and makes N at end of input discard the line"
+    )]
+    posix: bool,
+
+    /// Use GNU behavior for this run
+    #[arg(long, conflicts_with = "posix")]
+    #[arg(help = "Behave like GNU sed for this run, overriding compatibility.mode")]
+    gnu: bool,
+
+    /// Treat files as separate inputs rather than one stream
+    #[arg(short = 's', long)]
+    #[arg(
+        help = "Process each file as a separate input (like sed -s)\nFiles edited in place always are, unless --concatenate is given"
+    )]
+    separate: bool,
+
+    /// Treat files edited in place as one stream
+    #[arg(long, conflicts_with_all = ["separate", "slurp", "paragraph"])]
+    #[arg(
+        help = "Edit several files as one input stream: line numbers, $ and the hold space continue across them\nEach output line goes back to the file holding the last line its cycle read, so lines can move between files"
+    )]
+    concatenate: bool,
+
+    /// Read each input as one record
+    #[arg(long)]
+    #[arg(
+        help = "Read each file (or stdin) as a single record, so the script sees the whole input at once\nLets s/\\n\\n+/\\n/g collapse blank lines across the file without N loops"
+    )]
+    slurp: bool,
+
+    /// Read each paragraph as one record
+    #[arg(long, conflicts_with = "slurp")]
+    #[arg(
+        help = "Read each blank-line-separated paragraph as a single record, with \\n between its lines\nParagraphs are written back with the blank lines that separated them"
+    )]
+    paragraph: bool,
+
+    /// Stop substituting after N changes
+    #[arg(long = "max-count", value_name = "N")]
+    #[arg(
+        help = "Stop substituting once N substitutions have changed a line, counted across all files\nThe rest of the input is left as it is, as if nothing else matched"
+    )]
+    max_count: Option<usize>,
+
+    /// Only run the script on lines matching a regex
+    #[arg(long = "only-matching", value_name = "REGEX")]
+    #[arg(
+        help = "Only run the script on lines matching REGEX; other lines are written unchanged without running any command\nUnlike a /REGEX/ address on each command, skipped lines never reach /start/,/end/ ranges or the hold space. Needs a streamable script"
+    )]
+    only_matching: Option<String>,
+
+    /// Give up if processing takes too long
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    #[arg(
+        help = "Give up with an error if processing takes more than SECS seconds, e.g. a script stuck in a b loop\nFiles are only written once every preview is done, so a timeout leaves them all as they were"
+    )]
+    timeout: Option<u64>,
+
+    /// Fail when nothing would change
+    #[arg(long = "require-change")]
+    #[arg(
+        help = "Exit with status 4 when the script changes nothing and prints nothing, e.g. a pattern with a typo in CI\nThe \"No changes would be made.\" message is still shown"
+    )]
+    require_change: bool,
+
+    /// Show progress while streaming
+    #[arg(long)]
+    #[arg(
+        help = "Show a progress bar on stderr while each file streams: bytes read of the file's size and lines per second
+Only shown when stderr is a terminal"
+    )]
+    progress: bool,
+
+    /// Name for standard input
+    #[arg(long = "stdin-filename", value_name = "NAME")]
+    #[arg(
+        help = "Call standard input NAME: F prints it and errors name it, instead of \"-\" and \"standard input\"\nFor pipelines that feed in a known file, e.g. sedx --stdin-filename config.yaml 'F' < config.yaml"
+    )]
+    stdin_filename: Option<String>,
+
+    /// Number of files to process at once
+    #[arg(short = 'j', long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    #[arg(
+        help = "Preview and apply up to N files concurrently (default: 1)\nOutput stays in input order; backups are still created once, before any file is changed"
+    )]
+    jobs: u16,
+
+    /// Write a per-command cycle trace to a file
+    #[arg(long = "dump-cycle-trace", value_name = "PATH")]
+    #[arg(
+        help = "Write a JSON-lines trace of every command executed by the cycle engine\nEach record has the line number, command index, and pattern/hold space before and after\nForces in-memory processing for files"
+    )]
+    dump_cycle_trace: Option<String>,
+
+    /// Explain whether the script can be streamed
+    #[arg(long)]
+    #[arg(
+        help = "Report whether files are streamed or processed in memory, and which command forced in-memory processing"
+    )]
+    explain: bool,
+
+    /// Print the parsed program and exit
+    #[arg(long = "dump-ast", visible_alias = "print-commands")]
+    #[arg(
+        help = "Print the parsed program, one command per line with its range and flags, group contents indented, and exit without reading any input"
+    )]
+    dump_ast: bool,
+
+    /// Write a debug log for this run
+    #[arg(long)]
+    #[arg(
+        help = "Write a debug log for this run, even if processing.debug is off in the config\nPrints the log file path to stderr"
+    )]
+    debug: bool,
+
+    /// Compare output against GNU sed (maintainer aid)
+    #[cfg(feature = "compare-gnu")]
+    #[arg(long = "compare-gnu")]
+    #[arg(
+        help = "Run the script through both SedX and the system GNU sed and report lines that differ\nReads stdin, or each FILE without modifying it"
+    )]
+    compare_gnu: bool,
+
     /// Subcommands
     #[command(subcommand)]
     command: Option<Commands>,
@@ -170,11 +399,16 @@ Use 'sedx history' to see all available backups.
 EXAMPLES:
   sedx rollback                    Rollback last operation
   sedx rollback backup.12345       Rollback specific backup
-  sedx rollback ~/.sedx/backups/*  Rollback from specific path")]
+  sedx rollback ~/.sedx/backups/*  Rollback from specific path
+  sedx rollback --file app.conf    Restore only app.conf from the last operation")]
     Rollback {
         /// Backup ID to rollback (optional, defaults to last operation)
         #[arg(value_name = "ID")]
         id: Option<String>,
+
+        /// Restore only this file, keeping the backup for the others
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
     },
 
     /// Show operation history
@@ -183,10 +417,27 @@ EXAMPLES:
 Shows timestamp, expression, files affected, and backup location for each operation.
 The most recent operations appear first.
 
+Filters can be combined; --json prints the matching backups as a JSON array.
+
 EXAMPLES:
   sedx history                    Show all operations
-  sedx history | head -10         Show last 10 operations")]
-    History,
+  sedx history | head -10         Show last 10 operations
+  sedx history --file app.conf    Operations that changed app.conf
+  sedx history --grep s/foo       Operations whose expression contains s/foo
+  sedx history --json             Machine-readable history")]
+    History {
+        /// Print the history as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Only operations that changed this file
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+
+        /// Only operations whose expression contains this text
+        #[arg(long, value_name = "SUBSTR")]
+        grep: Option<String>,
+    },
 
     /// Show current backup status
     #[command(long_about = "Display backup directory location and disk usage.
@@ -195,8 +446,57 @@ Shows information about where backups are stored and how much disk space they us
 This helps with backup management and cleanup.
 
 EXAMPLES:
-  sedx status                     Show backup status")]
-    Status,
+  sedx status                     Show backup status
+  sedx status --json              Machine-readable status for dashboards")]
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check a script for errors without running it
+    #[command(
+        long_about = "Parse and validate a sed script without reading any input.
+
+Reports every problem found, not just the first: commands that don't parse,
+unknown substitution flags, and branches to labels that are never defined.
+Each problem is printed with the byte offset of its command in the script.
+Exits with status 1 if there are problems.
+
+EXAMPLES:
+  sedx check 's/foo/bar/g'         Check one expression
+  sedx check -E ':a; s/(x)y/\\1/; t a'  Check an ERE script"
+    )]
+    Check {
+        /// Script to check
+        #[arg(value_name = "EXPRESSION")]
+        expression: String,
+
+        /// Use Basic Regular Expressions (BRE)
+        #[arg(short = 'B', long, conflicts_with = "ere")]
+        bre: bool,
+
+        /// Use Extended Regular Expressions (ERE)
+        #[arg(short = 'E', long, conflicts_with = "bre")]
+        ere: bool,
+    },
+
+    /// List every command and where it can run
+    #[command(long_about = "List every sed command SedX supports.
+
+Shows each command's syntax, what it does, and whether it runs in streaming
+mode, which SedX uses for large files, or only in memory. A script with a
+command that can't stream is processed in memory, whatever the file size.
+
+EXAMPLES:
+  sedx commands                    Show the command table
+  sedx commands --json             Machine-readable listing")]
+    #[command(name = "commands")]
+    CommandList {
+        /// Print the listing as JSON
+        #[arg(long)]
+        json: bool,
+    },
 
     /// Manage backups
     #[command(long_about = "Manage SedX backups.
@@ -206,7 +506,11 @@ Provides subcommands for listing, restoring, removing, and pruning backups.
 EXAMPLES:
   sedx backup list                 List all backups
   sedx backup show <id>            Show backup details
+  sedx backup diff <id>            Preview what a restore would change
+  sedx backup verify               Check every backup for damage
   sedx backup restore <id>         Restore from backup
+  sedx backup export <id> <file>   Export backup to a tar archive
+  sedx backup import <file>        Import backup from a tar archive
   sedx backup remove <id>          Remove a backup
   sedx backup prune --keep=5       Keep only 5 most recent backups
   sedx backup prune --keep-days=7  Keep only backups from last 7 days")]
@@ -226,9 +530,10 @@ If there are any errors, they will be displayed and the file will not be updated
 
 CONFIGURATION OPTIONS:
   [backup]
-    max_size_gb = 2              # Max backup size before warning (GB)
+    max_size_gb = 2              # Refuse larger backups (GB, 0 = no limit; unset warns above 2)
     max_disk_usage_percent = 60   # Max % of free space to use
     backup_dir = \"/path\"         # Custom backup directory (optional)
+    compression = \"none\"        # Backup storage: none or gzip
 
   [compatibility]
     mode = \"pcre\"                # Regex mode: pcre, ere, or bre
@@ -243,7 +548,9 @@ CONFIGURATION OPTIONS:
 EXAMPLES:
   sedx config                     Edit configuration
   sedx config --show              Show current configuration
-  sedx config --log-path          Show log file location")]
+  sedx config --log-path          Show log file location
+  sedx config get processing.streaming
+  sedx config set processing.max_memory_mb 200")]
     Config {
         /// Show current configuration without editing
         #[arg(long = "show")]
@@ -252,6 +559,48 @@ EXAMPLES:
         /// Show log file path
         #[arg(long = "log-path")]
         log_path: bool,
+
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print one configuration value
+    #[command(long_about = "Print one configuration value.
+
+KEY is section.field, e.g. processing.max_memory_mb. Unset values print
+nothing.
+
+EXAMPLES:
+  sedx config get processing.streaming")]
+    Get {
+        /// Key, e.g. processing.streaming
+        #[arg(value_name = "KEY")]
+        key: String,
+    },
+
+    /// Change one configuration value
+    #[command(
+        long_about = "Change one configuration value without opening an editor.
+
+KEY is section.field, e.g. processing.max_memory_mb. The new configuration
+is validated before it is written; invalid values leave the file unchanged.
+Comments in the file are not kept.
+
+EXAMPLES:
+  sedx config set processing.streaming false
+  sedx config set backup.backup_dir /mnt/backups/sedx"
+    )]
+    Set {
+        /// Key, e.g. processing.streaming
+        #[arg(value_name = "KEY")]
+        key: String,
+
+        /// New value
+        #[arg(value_name = "VALUE")]
+        value: String,
     },
 }
 
@@ -289,6 +638,38 @@ EXAMPLES:
         id: String,
     },
 
+    /// Show what restoring a backup would change
+    #[command(
+        long_about = "Compare a backup with the current files without restoring it.
+
+Prints a diff from each file's current content to its backed-up content,
+i.e. the changes a restore would make. Files that already match the
+backup are listed as unchanged.
+
+EXAMPLES:
+  sedx backup diff 20250110-120000-abc123    Preview a restore"
+    )]
+    Diff {
+        /// Backup ID
+        #[arg(value_name = "ID")]
+        id: String,
+    },
+
+    /// Check that every backup is intact
+    #[command(
+        visible_alias = "verify-all",
+        long_about = "Check every backup for damage without restoring anything.
+
+For each backup, confirms its metadata can be read and that every
+backed-up file is still present and matches its recorded SHA-256
+checksum. Prints a pass/fail line per backup and exits with status 1
+if any backup failed.
+
+EXAMPLES:
+  sedx backup verify    Check all backups"
+    )]
+    Verify,
+
     /// Restore from a backup
     #[command(long_about = "Restore files from a backup.
 
@@ -303,6 +684,42 @@ EXAMPLES:
         id: String,
     },
 
+    /// Export a backup to a tar archive
+    #[command(
+        long_about = "Write a backup to a tar archive, e.g. to move it to another machine.
+
+The archive holds the backup metadata and every backed-up file. It is
+gzip-compressed when the name ends in .tar.gz or .tgz.
+
+EXAMPLES:
+  sedx backup export 20250110-120000-abc123 backup.tar.gz"
+    )]
+    Export {
+        /// Backup ID
+        #[arg(value_name = "ID")]
+        id: String,
+
+        /// Archive to write (.tar, .tar.gz or .tgz)
+        #[arg(value_name = "ARCHIVE")]
+        archive: String,
+    },
+
+    /// Import a backup from a tar archive
+    #[command(
+        long_about = "Register a backup from an archive written by 'sedx backup export'.
+
+The backup keeps its original ID, or gets a new one if a backup with that
+ID already exists. It can then be restored with 'sedx rollback <id>'.
+
+EXAMPLES:
+  sedx backup import backup.tar.gz"
+    )]
+    Import {
+        /// Archive to read (.tar, .tar.gz or .tgz)
+        #[arg(value_name = "ARCHIVE")]
+        archive: String,
+    },
+
     /// Remove a backup
     #[command(long_about = "Remove a specific backup.
 
@@ -351,16 +768,25 @@ EXAMPLES:
 }
 
 /// Read sed script from file and extract expressions
-/// Skips empty lines, comments, and shebang lines
+/// Skips empty lines, comments, and shebang lines. A line ending in a
+/// backslash continues on the next one (the text of i, a and c).
 fn read_script_file(path: &str) -> Result<Vec<String>> {
     use std::fs;
 
     let content = fs::read_to_string(path)
         .with_context(|| format!("Failed to read script file: {}", path))?;
 
-    let mut expressions = Vec::new();
+    let mut expressions: Vec<String> = Vec::new();
+    let mut continued = false;
 
     for line in content.lines() {
+        if continued && let Some(last) = expressions.last_mut() {
+            last.push('\n');
+            last.push_str(line);
+            continued = ends_with_escape(line);
+            continue;
+        }
+
         let trimmed = line.trim();
 
         // Skip empty lines
@@ -375,23 +801,117 @@ fn read_script_file(path: &str) -> Result<Vec<String>> {
 
         // Add the expression
         expressions.push(trimmed.to_string());
+        continued = ends_with_escape(trimmed);
     }
 
     Ok(expressions)
 }
 
+/// Put the script file's commands among the -e expressions where -f was
+/// given, so `-e 1d -f script -e 2d` runs in that order, as in sed
+fn order_script_fragments(
+    script: Vec<String>,
+    script_index: usize,
+    expressions: &[String],
+    expression_indices: &[usize],
+) -> Vec<String> {
+    let before = expression_indices
+        .iter()
+        .take_while(|&&index| index < script_index)
+        .count();
+    let mut all_exprs = expressions[..before].to_vec();
+    all_exprs.extend(script);
+    all_exprs.extend_from_slice(&expressions[before..]);
+    all_exprs
+}
+
+/// Whether a line ends in an unescaped backslash
+fn ends_with_escape(line: &str) -> bool {
+    line.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
+/// Read the NUL-separated file list given to `--files0-from` (`-` for stdin)
+pub fn read_files0_from(source: &str) -> Result<Vec<String>> {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    if source == "-" {
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("Failed to read file names from stdin")?;
+    } else {
+        data = std::fs::read(source)
+            .with_context(|| format!("Failed to read file list: {}", source))?;
+    }
+
+    // A trailing NUL terminates the last name rather than starting an empty one
+    if data.last() == Some(&0) {
+        data.pop();
+    }
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    data.split(|&b| b == 0)
+        .enumerate()
+        .map(|(i, name)| {
+            if name.is_empty() {
+                anyhow::bail!(
+                    "{}: invalid zero-length file name (entry {})",
+                    source,
+                    i + 1
+                );
+            }
+            if source == "-" && name == b"-" {
+                anyhow::bail!(
+                    "When reading file names from stdin, the file name '-' is not allowed"
+                );
+            }
+            String::from_utf8(name.to_vec())
+                .map_err(|_| anyhow::anyhow!("{}: file name {} is not valid UTF-8", source, i + 1))
+        })
+        .collect()
+}
+
 pub fn parse_args() -> Result<Args> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
     match cli.command {
-        Some(Commands::Rollback { id }) => Ok(Args::Rollback { id }),
-        Some(Commands::History) => Ok(Args::History),
-        Some(Commands::Status) => Ok(Args::Status),
-        Some(Commands::Config { show, log_path }) => Ok(Args::Config { show, log_path }),
+        Some(Commands::Rollback { id, file }) => Ok(Args::Rollback { id, file }),
+        Some(Commands::History { json, file, grep }) => Ok(Args::History { json, file, grep }),
+        Some(Commands::Status { json }) => Ok(Args::Status { json }),
+        Some(Commands::CommandList { json }) => Ok(Args::Commands { json }),
+        Some(Commands::Check {
+            expression,
+            bre,
+            ere,
+        }) => Ok(Args::Check {
+            expression,
+            regex_flavor: if bre {
+                RegexFlavor::BRE
+            } else if ere {
+                RegexFlavor::ERE
+            } else {
+                RegexFlavor::PCRE
+            },
+        }),
+        Some(Commands::Config {
+            action: Some(action),
+            ..
+        }) => match action {
+            ConfigAction::Get { key } => Ok(Args::ConfigGet { key }),
+            ConfigAction::Set { key, value } => Ok(Args::ConfigSet { key, value }),
+        },
+        Some(Commands::Config { show, log_path, .. }) => Ok(Args::Config { show, log_path }),
         Some(Commands::Backup { action }) => match action {
             BackupAction::List { verbose } => Ok(Args::BackupList { verbose }),
             BackupAction::Show { id } => Ok(Args::BackupShow { id }),
+            BackupAction::Diff { id } => Ok(Args::BackupDiff { id }),
+            BackupAction::Verify => Ok(Args::BackupVerify),
             BackupAction::Restore { id } => Ok(Args::BackupRestore { id }),
+            BackupAction::Export { id, archive } => Ok(Args::BackupExport { id, archive }),
+            BackupAction::Import { archive } => Ok(Args::BackupImport { archive }),
             BackupAction::Remove { id, force } => Ok(Args::BackupRemove { id, force }),
             BackupAction::Prune {
                 keep,
@@ -410,13 +930,17 @@ pub fn parse_args() -> Result<Args> {
                 // Read expressions from script file
                 let script_exprs = read_script_file(script_path)?;
 
-                // Combine script file expressions with -e flags
-                let mut all_exprs = script_exprs;
-
-                // Add -e expressions if provided
-                if !cli.expressions.is_empty() {
-                    all_exprs.extend(cli.expressions.clone());
-                }
+                // Combine script file expressions with -e flags, in the
+                // order they were given on the command line
+                let all_exprs = order_script_fragments(
+                    script_exprs,
+                    matches.index_of("script_file").unwrap_or(0),
+                    &cli.expressions,
+                    &matches
+                        .indices_of("expressions")
+                        .map(Iterator::collect::<Vec<_>>)
+                        .unwrap_or_default(),
+                );
 
                 // If a positional expression was provided, treat it as a file (not an expression)
                 // This handles: sedx -f script.sed file.txt
@@ -458,13 +982,19 @@ pub fn parse_args() -> Result<Args> {
             };
 
             // Note: Empty files vector means read from stdin (like sed)
+            if let Some(source) = &cli.files0_from {
+                if !files.is_empty() {
+                    anyhow::bail!("File operands cannot be combined with --files0-from");
+                }
+                if source == "-" && cli.interactive {
+                    anyhow::bail!(
+                        "--interactive reads answers from stdin, so it can't be used with --files0-from=-"
+                    );
+                }
+            }
 
-            // Determine context size
-            let context = if cli.no_context {
-                0
-            } else {
-                cli.context.unwrap_or(2)
-            };
+            // Determine context size (config fills in when neither flag is given)
+            let context = if cli.no_context { Some(0) } else { cli.context };
 
             // Determine streaming mode (auto-detect at 100MB)
             let streaming = if cli.no_streaming {
@@ -484,17 +1014,57 @@ pub fn parse_args() -> Result<Args> {
                 RegexFlavor::PCRE // Default
             };
 
+            // --posix/--gnu override compatibility.mode from the config
+            let compat_mode = if cli.posix {
+                Some(CompatMode::Posix)
+            } else if cli.gnu {
+                Some(CompatMode::Gnu)
+            } else {
+                None
+            };
+
             Ok(Args::Execute {
                 expression,
                 files,
+                files0_from: cli.files0_from,
                 dry_run: cli.dry_run,
+                stat: cli.stat,
                 interactive: cli.interactive,
+                no_preview: cli.no_preview,
                 context,
                 streaming,
                 regex_flavor,
                 no_backup: cli.no_backup,
+                keep_going: cli.keep_going,
                 backup_dir: cli.backup_dir,
+                backup_compression: cli.backup_compression,
+                out_dir: cli.out_dir,
+                follow_symlinks: cli.follow_symlinks,
                 quiet: cli.quiet,
+                format: cli.format,
+                color: cli.color,
+                dump_cycle_trace: cli.dump_cycle_trace,
+                allow_exec: cli.allow_exec,
+                sandbox: cli.sandbox,
+                compat_mode,
+                ascii_case: cli.ascii_case,
+                word_regexp: cli.word_regexp,
+                separate: cli.separate,
+                concatenate: cli.concatenate,
+                slurp: cli.slurp,
+                paragraph: cli.paragraph,
+                max_count: cli.max_count,
+                only_matching: cli.only_matching,
+                timeout: cli.timeout,
+                require_change: cli.require_change,
+                progress: cli.progress,
+                stdin_filename: cli.stdin_filename,
+                jobs: usize::from(cli.jobs),
+                explain: cli.explain,
+                dump_ast: cli.dump_ast,
+                debug: cli.debug,
+                #[cfg(feature = "compare-gnu")]
+                compare_gnu: cli.compare_gnu,
             })
         }
     }
@@ -512,34 +1082,120 @@ pub enum RegexFlavor {
     PCRE,
 }
 
+/// How diffs are rendered on stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Colored, line-numbered diff with context (default)
+    Human,
+    /// One JSON object per file, for editor and tool integrations
+    Json,
+    /// Unified diff (`diff -u` style)
+    Unified,
+}
+
+/// When diff output uses ANSI colors (`--color`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal and NO_COLOR is unset (default)
+    Auto,
+    /// Always color, e.g. when piping into `less -R`
+    Always,
+    /// Never color
+    Never,
+}
+
+impl OutputFormat {
+    /// Machine-readable formats keep stdout clean: status messages go to stderr
+    pub fn is_machine_readable(self) -> bool {
+        !matches!(self, OutputFormat::Human)
+    }
+}
+
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)] // Built once per run, so boxing saves nothing
 pub enum Args {
     Execute {
         expression: String,
         files: Vec<String>,
+        files0_from: Option<String>,
         dry_run: bool,
+        stat: bool,
         interactive: bool,
-        context: usize,
+        no_preview: bool,
+        context: Option<usize>,
         streaming: bool,
         regex_flavor: RegexFlavor,
         no_backup: bool,
+        keep_going: bool,
         backup_dir: Option<String>,
+        backup_compression: bool,
+        out_dir: Option<String>,
+        follow_symlinks: bool,
         quiet: bool,
+        format: OutputFormat,
+        color: ColorChoice,
+        dump_cycle_trace: Option<String>,
+        allow_exec: bool,
+        sandbox: bool,
+        compat_mode: Option<CompatMode>,
+        ascii_case: bool,
+        word_regexp: bool,
+        separate: bool,
+        concatenate: bool,
+        slurp: bool,
+        paragraph: bool,
+        max_count: Option<usize>,
+        only_matching: Option<String>,
+        timeout: Option<u64>,
+        require_change: bool,
+        progress: bool,
+        stdin_filename: Option<String>,
+        jobs: usize,
+        explain: bool,
+        dump_ast: bool,
+        debug: bool,
+        #[cfg(feature = "compare-gnu")]
+        compare_gnu: bool,
     },
     Rollback {
         id: Option<String>,
+        file: Option<PathBuf>,
+    },
+    History {
+        json: bool,
+        file: Option<PathBuf>,
+        grep: Option<String>,
+    },
+    Status {
+        json: bool,
+    },
+    Commands {
+        json: bool,
+    },
+    Check {
+        expression: String,
+        regex_flavor: RegexFlavor,
     },
-    History,
-    Status,
     BackupList {
         verbose: bool,
     },
     BackupShow {
         id: String,
     },
+    BackupDiff {
+        id: String,
+    },
+    BackupVerify,
     BackupRestore {
         id: String,
     },
+    BackupExport {
+        id: String,
+        archive: String,
+    },
+    BackupImport {
+        archive: String,
+    },
     BackupRemove {
         id: String,
         force: bool,
@@ -553,4 +1209,80 @@ pub enum Args {
         show: bool,
         log_path: bool,
     },
+    ConfigGet {
+        key: String,
+    },
+    ConfigSet {
+        key: String,
+        value: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_files0_from_file() {
+        let list = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(list.path(), b"src/a.rs\0dir with space/b.rs\0").unwrap();
+        let files = read_files0_from(list.path().to_str().unwrap()).unwrap();
+        assert_eq!(files, vec!["src/a.rs", "dir with space/b.rs"]);
+
+        // The final NUL is optional
+        std::fs::write(list.path(), b"a\0b").unwrap();
+        let files = read_files0_from(list.path().to_str().unwrap()).unwrap();
+        assert_eq!(files, vec!["a", "b"]);
+
+        std::fs::write(list.path(), b"").unwrap();
+        assert!(
+            read_files0_from(list.path().to_str().unwrap())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_read_script_file_joins_continued_text() {
+        let script = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(script.path(), "# header\n2a\\\nline1\\\n  line2\n3d\n").unwrap();
+        let expressions = read_script_file(script.path().to_str().unwrap()).unwrap();
+        assert_eq!(expressions, vec!["2a\\\nline1\\\n  line2", "3d"]);
+    }
+
+    #[test]
+    fn test_script_file_keeps_its_place_among_expressions() {
+        let script = vec!["s/a/b/".to_string(), "b end".to_string()];
+        let expressions = vec!["1d".to_string(), ":end".to_string()];
+
+        // -e 1d -f script -e :end
+        assert_eq!(
+            order_script_fragments(script.clone(), 3, &expressions, &[1, 5]),
+            vec!["1d", "s/a/b/", "b end", ":end"]
+        );
+        // -f script -e 1d -e :end
+        assert_eq!(
+            order_script_fragments(script.clone(), 1, &expressions, &[3, 5]),
+            vec!["s/a/b/", "b end", "1d", ":end"]
+        );
+        // -e 1d -e :end -f script
+        assert_eq!(
+            order_script_fragments(script, 5, &expressions, &[1, 3]),
+            vec!["1d", ":end", "s/a/b/", "b end"]
+        );
+    }
+
+    #[test]
+    fn test_read_files0_from_rejects_empty_name() {
+        let list = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(list.path(), b"a\0\0b\0").unwrap();
+        let err = read_files0_from(list.path().to_str().unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.ends_with("invalid zero-length file name (entry 2)"),
+            "{}",
+            err
+        );
+    }
 }
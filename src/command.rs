@@ -4,6 +4,7 @@
 //! both traditional sed syntax and sd-like simple find/replace syntax.
 
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /// Unified command representation that supports both sed and sd syntaxes
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -22,11 +23,17 @@ pub enum Command {
     /// Print lines (sed: 1,10p)
     Print { range: (Address, Address) },
 
-    /// Quit processing (sed: 10q)
-    Quit { address: Option<Address> },
+    /// Quit processing (sed: 10q, or 10q5 to exit with status 5)
+    Quit {
+        address: Option<Address>,
+        exit_code: Option<i32>,
+    },
 
     /// Quit without printing (sed: 10Q) - Phase 4
-    QuitWithoutPrint { address: Option<Address> },
+    QuitWithoutPrint {
+        address: Option<Address>,
+        exit_code: Option<i32>,
+    },
 
     /// Insert text before line (sed: 5i\text)
     Insert { text: String, address: Address },
@@ -34,8 +41,12 @@ pub enum Command {
     /// Append text after line (sed: 5a\text)
     Append { text: String, address: Address },
 
-    /// Change line (sed: 5c\text)
-    Change { text: String, address: Address },
+    /// Change line (sed: 5c\text); over a range, the text replaces the
+    /// whole range and is printed once, at its end (sed: 2,4c\text)
+    Change {
+        text: String,
+        range: (Address, Address),
+    },
 
     /// Command group (sed: {s/foo/bar/; p})
     Group {
@@ -136,6 +147,22 @@ pub enum Command {
     /// Clear pattern space (Phase 5): z - clear pattern space (GNU sed extension)
     /// Sets pattern space to empty string
     ClearPatternSpace { range: Option<Address> },
+
+    /// List (sed: l [width]) - print pattern space unambiguously
+    /// Non-printing characters are escaped, long lines wrap at `width`
+    /// (default 70, 0 disables wrapping), and the end is marked with `$`
+    List {
+        range: Option<(Address, Address)>,
+        width: Option<usize>,
+    },
+
+    /// Execute (sed: e [command]) - GNU sed extension, requires --allow-exec
+    /// Without a command, runs the pattern space in a shell and replaces it
+    /// with the output; with a command, prints its output before the pattern space
+    Execute {
+        command: Option<String>,
+        range: Option<(Address, Address)>,
+    },
 }
 
 /// Substitution flags (unified across sed and sd)
@@ -152,10 +179,24 @@ pub struct SubstitutionFlags {
 
     /// N - substitute Nth occurrence only
     pub nth: Option<usize>,
+
+    /// e - execute the resulting pattern space as a shell command and
+    /// replace it with the command's output (requires --allow-exec)
+    #[serde(default)]
+    pub exec: bool,
+
+    /// M/m - multiline mode: ^ and $ also match at embedded newlines
+    #[serde(default)]
+    pub multiline: bool,
+
+    /// w file - write the pattern space to this file after each
+    /// substitution that changes it
+    #[serde(default)]
+    pub write_file: Option<String>,
 }
 
 /// Unified address representation
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Address {
     /// Specific line number (e.g., 10)
     LineNumber(usize),
@@ -163,7 +204,7 @@ pub enum Address {
     /// Regex pattern match (e.g., /foo/)
     Pattern(String),
 
-    /// First line (special address "0")
+    /// Line 0 (special address "0"), only valid as the start of `0,/re/`
     FirstLine,
 
     /// Last line (special address "$")
@@ -177,12 +218,508 @@ pub enum Address {
 
     /// Step addressing (e.g., 1~2 for every 2nd line from line 1)
     Step { start: usize, step: usize },
+
+    /// Range end up to the next line that is a multiple of N (e.g., 2,~4)
+    MultipleOf(usize),
+}
+
+impl fmt::Display for Address {
+    /// The address in sed notation, e.g. `5`, `/re/`, `$`, `1~2`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::LineNumber(n) => write!(f, "{}", n),
+            Address::Pattern(pattern) => write!(f, "/{}/", pattern),
+            Address::FirstLine => write!(f, "0"),
+            Address::LastLine => write!(f, "$"),
+            Address::Negated(inner) => write!(f, "{}!", inner),
+            Address::Relative { base, offset } => write!(f, "{}{:+}", base, offset),
+            Address::Step { start, step } => write!(f, "{}~{}", start, step),
+            Address::MultipleOf(n) => write!(f, "~{}", n),
+        }
+    }
+}
+
+/// A range in sed notation: one address when both ends are the same
+fn format_range(start: &Address, end: &Address) -> String {
+    match (start, end) {
+        _ if start == end => start.to_string(),
+        (Address::Negated(start), Address::Negated(end)) => format!("{},{}!", start, end),
+        _ => format!("{},{}", start, end),
+    }
+}
+
+/// The flags of an s command, in the order sed accepts them
+fn format_flags(flags: &SubstitutionFlags) -> String {
+    let mut out = flags.nth.map(|n| n.to_string()).unwrap_or_default();
+    for (set, letter) in [
+        (flags.global, 'g'),
+        (flags.print, 'p'),
+        (flags.case_insensitive, 'i'),
+        (flags.exec, 'e'),
+        (flags.multiline, 'M'),
+    ] {
+        if set {
+            out.push(letter);
+        }
+    }
+    out
+}
+
+impl Command {
+    /// One line describing the command: its name, address or range, and
+    /// arguments (strings are quoted, escapes shown)
+    fn describe(&self) -> String {
+        let range = |range: &Option<(Address, Address)>| {
+            range.as_ref().map(|(start, end)| format_range(start, end))
+        };
+        let (name, address, fields): (&str, Option<String>, Vec<String>) = match self {
+            Command::Substitution {
+                pattern,
+                replacement,
+                flags,
+                range: r,
+            } => {
+                let mut fields = vec![
+                    format!("pattern={:?}", pattern),
+                    format!("replacement={:?}", replacement),
+                ];
+                let letters = format_flags(flags);
+                if !letters.is_empty() {
+                    fields.push(format!("flags={}", letters));
+                }
+                if let Some(file) = &flags.write_file {
+                    fields.push(format!("write_file={:?}", file));
+                }
+                ("Substitution", range(r), fields)
+            }
+            Command::Delete {
+                range: (start, end),
+            } => ("Delete", Some(format_range(start, end)), vec![]),
+            Command::Print {
+                range: (start, end),
+            } => ("Print", Some(format_range(start, end)), vec![]),
+            Command::Quit { address, exit_code } => (
+                "Quit",
+                address.as_ref().map(Address::to_string),
+                exit_code_field(*exit_code),
+            ),
+            Command::QuitWithoutPrint { address, exit_code } => (
+                "QuitWithoutPrint",
+                address.as_ref().map(Address::to_string),
+                exit_code_field(*exit_code),
+            ),
+            Command::Insert { text, address } => (
+                "Insert",
+                Some(address.to_string()),
+                vec![format!("text={:?}", text)],
+            ),
+            Command::Append { text, address } => (
+                "Append",
+                Some(address.to_string()),
+                vec![format!("text={:?}", text)],
+            ),
+            Command::Change {
+                text,
+                range: (start, end),
+            } => (
+                "Change",
+                Some(format_range(start, end)),
+                vec![format!("text={:?}", text)],
+            ),
+            Command::Group { range: r, .. } => ("Group", range(r), vec![]),
+            Command::Hold { range: r } => ("Hold", range(r), vec![]),
+            Command::HoldAppend { range: r } => ("HoldAppend", range(r), vec![]),
+            Command::Get { range: r } => ("Get", range(r), vec![]),
+            Command::GetAppend { range: r } => ("GetAppend", range(r), vec![]),
+            Command::Exchange { range: r } => ("Exchange", range(r), vec![]),
+            Command::Next { range: r } => ("Next", range(r), vec![]),
+            Command::NextAppend { range: r } => ("NextAppend", range(r), vec![]),
+            Command::PrintFirstLine { range: r } => ("PrintFirstLine", range(r), vec![]),
+            Command::DeleteFirstLine { range: r } => ("DeleteFirstLine", range(r), vec![]),
+            Command::Label { name } => ("Label", None, vec![format!("name={:?}", name)]),
+            Command::Branch { label, range: r } => ("Branch", range(r), label_field(label)),
+            Command::Test { label, range: r } => ("Test", range(r), label_field(label)),
+            Command::TestFalse { label, range: r } => ("TestFalse", range(r), label_field(label)),
+            Command::ReadFile { filename, range } => (
+                "ReadFile",
+                range.as_ref().map(Address::to_string),
+                vec![format!("filename={:?}", filename)],
+            ),
+            Command::WriteFile { filename, range } => (
+                "WriteFile",
+                range.as_ref().map(Address::to_string),
+                vec![format!("filename={:?}", filename)],
+            ),
+            Command::ReadLine { filename, range } => (
+                "ReadLine",
+                range.as_ref().map(Address::to_string),
+                vec![format!("filename={:?}", filename)],
+            ),
+            Command::WriteFirstLine { filename, range } => (
+                "WriteFirstLine",
+                range.as_ref().map(Address::to_string),
+                vec![format!("filename={:?}", filename)],
+            ),
+            Command::PrintLineNumber { range } => (
+                "PrintLineNumber",
+                range.as_ref().map(Address::to_string),
+                vec![],
+            ),
+            Command::PrintFilename { range } => (
+                "PrintFilename",
+                range.as_ref().map(Address::to_string),
+                vec![],
+            ),
+            Command::ClearPatternSpace { range } => (
+                "ClearPatternSpace",
+                range.as_ref().map(Address::to_string),
+                vec![],
+            ),
+            Command::List { range: r, width } => (
+                "List",
+                range(r),
+                width.iter().map(|w| format!("width={}", w)).collect(),
+            ),
+            Command::Execute { command, range: r } => (
+                "Execute",
+                range(r),
+                command.iter().map(|c| format!("command={:?}", c)).collect(),
+            ),
+        };
+
+        let mut line = name.to_string();
+        if let Some(address) = address {
+            line.push_str(&format!(" [{}]", address));
+        }
+        for field in fields {
+            line.push(' ');
+            line.push_str(&field);
+        }
+        line
+    }
+}
+
+/// A branch's target, if it names one (otherwise it jumps to the end)
+fn label_field(label: &Option<String>) -> Vec<String> {
+    label.iter().map(|l| format!("label={:?}", l)).collect()
+}
+
+fn exit_code_field(exit_code: Option<i32>) -> Vec<String> {
+    exit_code
+        .iter()
+        .map(|c| format!("exit_code={}", c))
+        .collect()
+}
+
+/// A command's entry in the command reference (`sedx commands`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommandInfo {
+    /// The command as it starts in a script, e.g. "s" or "{"
+    pub command: &'static str,
+    /// Its syntax; brackets mark optional parts
+    pub syntax: &'static str,
+    pub description: &'static str,
+}
+
+impl Command {
+    /// One command of every kind, in the order `sedx commands` lists them.
+    /// Addresses are left out where the command allows it, and otherwise
+    /// are line 1.
+    pub fn catalog() -> Vec<Command> {
+        let line = || (Address::LineNumber(1), Address::LineNumber(1));
+        let file = || "FILE".to_string();
+        vec![
+            Command::Substitution {
+                pattern: String::new(),
+                replacement: String::new(),
+                flags: SubstitutionFlags::default(),
+                range: None,
+            },
+            Command::Delete { range: line() },
+            Command::Print { range: line() },
+            Command::Quit {
+                address: None,
+                exit_code: None,
+            },
+            Command::QuitWithoutPrint {
+                address: None,
+                exit_code: None,
+            },
+            Command::Insert {
+                text: String::new(),
+                address: Address::LineNumber(1),
+            },
+            Command::Append {
+                text: String::new(),
+                address: Address::LineNumber(1),
+            },
+            Command::Change {
+                text: String::new(),
+                range: line(),
+            },
+            Command::Group {
+                commands: Vec::new(),
+                range: None,
+            },
+            Command::Hold { range: None },
+            Command::HoldAppend { range: None },
+            Command::Get { range: None },
+            Command::GetAppend { range: None },
+            Command::Exchange { range: None },
+            Command::Next { range: None },
+            Command::NextAppend { range: None },
+            Command::PrintFirstLine { range: None },
+            Command::DeleteFirstLine { range: None },
+            Command::Label {
+                name: "a".to_string(),
+            },
+            Command::Branch {
+                label: None,
+                range: None,
+            },
+            Command::Test {
+                label: None,
+                range: None,
+            },
+            Command::TestFalse {
+                label: None,
+                range: None,
+            },
+            Command::ReadFile {
+                filename: file(),
+                range: None,
+            },
+            Command::ReadLine {
+                filename: file(),
+                range: None,
+            },
+            Command::WriteFile {
+                filename: file(),
+                range: None,
+            },
+            Command::WriteFirstLine {
+                filename: file(),
+                range: None,
+            },
+            Command::PrintLineNumber { range: None },
+            Command::PrintFilename { range: None },
+            Command::ClearPatternSpace { range: None },
+            Command::List {
+                range: None,
+                width: None,
+            },
+            Command::Execute {
+                command: None,
+                range: None,
+            },
+        ]
+    }
+
+    /// The reference entry for this kind of command
+    pub fn info(&self) -> CommandInfo {
+        let (command, syntax, description) = match self {
+            Command::Substitution { .. } => (
+                "s",
+                "[addr[,addr]]s/regex/replacement/[flags]",
+                "Replace text matching regex (flags: g N p i m e w FILE)",
+            ),
+            Command::Delete { .. } => ("d", "[addr[,addr]]d", "Delete the pattern space"),
+            Command::Print { .. } => ("p", "[addr[,addr]]p", "Print the pattern space"),
+            Command::Quit { .. } => (
+                "q",
+                "[addr]q[code]",
+                "Print the pattern space and quit, exiting with code",
+            ),
+            Command::QuitWithoutPrint { .. } => (
+                "Q",
+                "[addr]Q[code]",
+                "Quit without printing, exiting with code",
+            ),
+            Command::Insert { .. } => ("i", "addr i text", "Print text before the line"),
+            Command::Append { .. } => ("a", "addr a text", "Print text after the line"),
+            Command::Change { .. } => (
+                "c",
+                "addr[,addr] c text",
+                "Replace the line, or a whole range, with text",
+            ),
+            Command::Group { .. } => (
+                "{",
+                "[addr[,addr]]{ cmd; ... }",
+                "Run the commands inside on matching lines",
+            ),
+            Command::Hold { .. } => (
+                "h",
+                "[addr[,addr]]h",
+                "Copy the pattern space to the hold space",
+            ),
+            Command::HoldAppend { .. } => (
+                "H",
+                "[addr[,addr]]H",
+                "Append the pattern space to the hold space",
+            ),
+            Command::Get { .. } => (
+                "g",
+                "[addr[,addr]]g",
+                "Copy the hold space to the pattern space",
+            ),
+            Command::GetAppend { .. } => (
+                "G",
+                "[addr[,addr]]G",
+                "Append the hold space to the pattern space",
+            ),
+            Command::Exchange { .. } => ("x", "[addr[,addr]]x", "Swap the pattern and hold spaces"),
+            Command::Next { .. } => (
+                "n",
+                "[addr[,addr]]n",
+                "Print the pattern space and replace it with the next line",
+            ),
+            Command::NextAppend { .. } => (
+                "N",
+                "[addr[,addr]]N",
+                "Append the next line to the pattern space",
+            ),
+            Command::PrintFirstLine { .. } => (
+                "P",
+                "[addr[,addr]]P",
+                "Print the pattern space up to the first newline",
+            ),
+            Command::DeleteFirstLine { .. } => (
+                "D",
+                "[addr[,addr]]D",
+                "Delete up to the first newline and restart the cycle",
+            ),
+            Command::Label { .. } => (":", ":label", "Define a label for b, t and T"),
+            Command::Branch { .. } => (
+                "b",
+                "[addr[,addr]]b [label]",
+                "Branch to label, or to the end of the script",
+            ),
+            Command::Test { .. } => (
+                "t",
+                "[addr[,addr]]t [label]",
+                "Branch if a substitution was made since the last input line or t",
+            ),
+            Command::TestFalse { .. } => (
+                "T",
+                "[addr[,addr]]T [label]",
+                "Branch if no substitution was made since the last input line or t",
+            ),
+            Command::ReadFile { .. } => (
+                "r",
+                "[addr]r FILE",
+                "Output the contents of FILE after the line",
+            ),
+            Command::ReadLine { .. } => (
+                "R",
+                "[addr]R FILE",
+                "Output the next line of FILE after the line",
+            ),
+            Command::WriteFile { .. } => ("w", "[addr]w FILE", "Write the pattern space to FILE"),
+            Command::WriteFirstLine { .. } => (
+                "W",
+                "[addr]W FILE",
+                "Write the pattern space up to the first newline to FILE",
+            ),
+            Command::PrintLineNumber { .. } => ("=", "[addr]=", "Print the line number"),
+            Command::PrintFilename { .. } => ("F", "[addr]F", "Print the input file name"),
+            Command::ClearPatternSpace { .. } => ("z", "[addr]z", "Empty the pattern space"),
+            Command::List { .. } => (
+                "l",
+                "[addr[,addr]]l [width]",
+                "Print the pattern space unambiguously, escapes shown",
+            ),
+            Command::Execute { .. } => (
+                "e",
+                "[addr[,addr]]e [command]",
+                "Run a shell command (needs --allow-exec)",
+            ),
+        };
+        CommandInfo {
+            command,
+            syntax,
+            description,
+        }
+    }
+}
+
+/// The parsed program, one command per line, group contents indented
+/// under their group (`--dump-ast`)
+pub fn format_program(commands: &[Command]) -> String {
+    fn write_commands(commands: &[Command], depth: usize, out: &mut String) {
+        for cmd in commands {
+            out.push_str(&"  ".repeat(depth));
+            out.push_str(&cmd.describe());
+            out.push('\n');
+            if let Command::Group { commands, .. } = cmd {
+                write_commands(commands, depth + 1, out);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    write_commands(commands, 0, &mut out);
+    out
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_catalog_has_every_command_once() {
+        // Adding a Command variant breaks this match until the variant
+        // gets a slot here and an entry in the catalog
+        const VARIANTS: usize = 31;
+        let slot = |cmd: &Command| match cmd {
+            Command::Substitution { .. } => 0,
+            Command::Delete { .. } => 1,
+            Command::Print { .. } => 2,
+            Command::Quit { .. } => 3,
+            Command::QuitWithoutPrint { .. } => 4,
+            Command::Insert { .. } => 5,
+            Command::Append { .. } => 6,
+            Command::Change { .. } => 7,
+            Command::Group { .. } => 8,
+            Command::Hold { .. } => 9,
+            Command::HoldAppend { .. } => 10,
+            Command::Get { .. } => 11,
+            Command::GetAppend { .. } => 12,
+            Command::Exchange { .. } => 13,
+            Command::Next { .. } => 14,
+            Command::NextAppend { .. } => 15,
+            Command::PrintFirstLine { .. } => 16,
+            Command::DeleteFirstLine { .. } => 17,
+            Command::Label { .. } => 18,
+            Command::Branch { .. } => 19,
+            Command::Test { .. } => 20,
+            Command::TestFalse { .. } => 21,
+            Command::ReadFile { .. } => 22,
+            Command::WriteFile { .. } => 23,
+            Command::ReadLine { .. } => 24,
+            Command::WriteFirstLine { .. } => 25,
+            Command::PrintLineNumber { .. } => 26,
+            Command::PrintFilename { .. } => 27,
+            Command::ClearPatternSpace { .. } => 28,
+            Command::List { .. } => 29,
+            Command::Execute { .. } => 30,
+        };
+
+        let mut seen = [0; VARIANTS];
+        for cmd in Command::catalog() {
+            seen[slot(&cmd)] += 1;
+        }
+        assert_eq!(seen, [1; VARIANTS]);
+
+        // Each entry has its own command letter
+        let mut letters: Vec<_> = Command::catalog()
+            .iter()
+            .map(|cmd| cmd.info().command)
+            .collect();
+        letters.sort_unstable();
+        letters.dedup();
+        assert_eq!(letters.len(), VARIANTS);
+    }
+
     #[test]
     fn test_command_equality() {
         let cmd1 = Command::Delete {
@@ -224,6 +761,8 @@ mod tests {
         assert!(!flags.print);
         assert!(!flags.case_insensitive);
         assert!(flags.nth.is_none());
+        assert!(!flags.exec);
+        assert!(!flags.multiline);
     }
 
     #[test]
@@ -233,10 +772,40 @@ mod tests {
             print: false,
             case_insensitive: true,
             nth: Some(3),
+            exec: false,
+            multiline: false,
+            write_file: None,
         };
         assert!(flags.global);
         assert!(!flags.print);
         assert!(flags.case_insensitive);
         assert_eq!(flags.nth, Some(3));
     }
+
+    #[test]
+    fn test_format_program_indents_group_contents() {
+        use crate::cli::RegexFlavor;
+        use crate::parser::Parser;
+
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("2,3{s/a/b/g; p}")
+            .unwrap();
+        assert_eq!(
+            format_program(&commands),
+            "Group [2,3]\n  Substitution pattern=\"a\" replacement=\"b\" flags=g\n  Print [1,$]\n"
+        );
+
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("/x/,$!d; 0,/y/s/a/b/2pw out; 1~2l 5; $a\\end")
+            .unwrap();
+        assert_eq!(
+            format_program(&commands),
+            concat!(
+                "Delete [/x/,$!]\n",
+                "Substitution [0,/y/] pattern=\"a\" replacement=\"b\" flags=2p write_file=\"out\"\n",
+                "List [1~2] width=5\n",
+                "Append [$] text=\"end\"\n",
+            )
+        );
+    }
 }
@@ -12,7 +12,8 @@ const DEFAULT_CONFIG: &str = r#"# SedX Configuration File
 # See 'sedx config' command to edit this file
 
 [backup]
-# Maximum backup size in GB before warning (default: 2)
+# Refuse backups larger than this many GB, 0 for no limit
+# (default: not set, which only warns above 2 GB)
 #max_size_gb = 2
 
 # Maximum percentage of free space to use for backups (default: 60)
@@ -21,9 +22,13 @@ const DEFAULT_CONFIG: &str = r#"# SedX Configuration File
 # Custom backup directory (optional)
 #backup_dir = "/mnt/backups/sedx"
 
+# Store backups gzip-compressed: "none" or "gzip" (default: none)
+#compression = "none"
+
 [compatibility]
-# Regex mode: "pcre" (default), "ere", or "bre"
-#mode = "pcre"
+# Compatibility mode: "gnu" or "posix" (default: GNU behavior)
+# Legacy regex names "pcre", "ere", and "bre" select GNU behavior
+#mode = "gnu"
 
 # Show incompatibility warnings (default: true)
 #show_warnings = true
@@ -68,8 +73,9 @@ impl Default for Config {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackupConfig {
-    /// Maximum backup size in GB before warning
-    #[serde(default = "default_max_size_gb")]
+    /// Maximum backup size in GB (0 means no limit). When not set, a
+    /// backup over 2 GB is only warned about.
+    #[serde(default)]
     pub max_size_gb: Option<f64>,
 
     /// Maximum percentage of free space to use
@@ -79,21 +85,26 @@ pub struct BackupConfig {
     /// Custom backup directory
     #[serde(default)]
     pub backup_dir: Option<String>,
+
+    /// How backup payloads are stored: "none" or "gzip"
+    #[serde(default)]
+    pub compression: Option<String>,
 }
 
 impl Default for BackupConfig {
     fn default() -> Self {
         Self {
-            max_size_gb: Some(2.0),
+            max_size_gb: None,
             max_disk_usage_percent: Some(60.0),
             backup_dir: None,
+            compression: None,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompatibilityConfig {
-    /// Regex mode: "pcre", "ere", or "bre"
+    /// Compatibility mode: "gnu" or "posix" (legacy "pcre", "ere", "bre" mean GNU)
     #[serde(default = "default_mode")]
     pub mode: Option<String>,
 
@@ -102,10 +113,30 @@ pub struct CompatibilityConfig {
     pub show_warnings: Option<bool>,
 }
 
+/// Behavior where GNU sed and POSIX sed differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompatMode {
+    /// GNU sed behavior (default)
+    #[default]
+    Gnu,
+    /// Strict POSIX behavior
+    Posix,
+}
+
+impl CompatibilityConfig {
+    /// Resolve `mode` to GNU or POSIX behavior
+    pub fn compat_mode(&self) -> CompatMode {
+        match self.mode.as_deref() {
+            Some("posix") => CompatMode::Posix,
+            _ => CompatMode::Gnu,
+        }
+    }
+}
+
 impl Default for CompatibilityConfig {
     fn default() -> Self {
         Self {
-            mode: Some("pcre".to_string()),
+            mode: Some("gnu".to_string()),
             show_warnings: Some(true),
         }
     }
@@ -130,10 +161,20 @@ pub struct ProcessingConfig {
     pub debug: Option<bool>,
 }
 
+/// Context lines shown when neither `--context` nor the config sets them
+pub const DEFAULT_CONTEXT_LINES: usize = 2;
+
+impl ProcessingConfig {
+    /// Resolve the diff context size: `--context` wins over `context_lines`
+    pub fn context_size(&self, cli: Option<usize>) -> usize {
+        cli.or(self.context_lines).unwrap_or(DEFAULT_CONTEXT_LINES)
+    }
+}
+
 impl Default for ProcessingConfig {
     fn default() -> Self {
         Self {
-            context_lines: Some(2),
+            context_lines: Some(DEFAULT_CONTEXT_LINES),
             max_memory_mb: Some(100),
             streaming: Some(true),
             debug: Some(false),
@@ -142,20 +183,17 @@ impl Default for ProcessingConfig {
 }
 
 // Default functions for serde
-fn default_max_size_gb() -> Option<f64> {
-    Some(2.0)
-}
 fn default_max_disk_usage_percent() -> Option<f64> {
     Some(60.0)
 }
 fn default_mode() -> Option<String> {
-    Some("pcre".to_string())
+    Some("gnu".to_string())
 }
 fn default_show_warnings() -> Option<bool> {
     Some(true)
 }
 fn default_context_lines() -> Option<usize> {
-    Some(2)
+    Some(DEFAULT_CONTEXT_LINES)
 }
 fn default_max_memory_mb() -> Option<usize> {
     Some(100)
@@ -190,9 +228,10 @@ fn get_default_config_content() -> &'static str {
 # For more information, run: sedx config --help
 
 [backup]
-# Maximum backup size in GB before warning (default: 2)
-# When a single backup exceeds this size, you'll be warned before creation.
-max_size_gb = 2
+# Maximum backup size in GB (0 for no limit)
+# SedX will refuse to create a single backup larger than this. When it's
+# not set, a backup over 2 GB only gets a warning.
+#max_size_gb = 2
 
 # Maximum percentage of free space to use for backups (default: 60)
 # SedX will refuse to create backups if more than this % of free space would be used.
@@ -201,14 +240,21 @@ max_disk_usage_percent = 60
 # Custom backup directory (optional)
 # Uncomment to use a custom backup location instead of ~/.sedx/backups/
 # Useful when your home directory has limited space.
+# A leading ~ or ~user and $VAR / ${VAR} are expanded.
 #backup_dir = "/mnt/backups/sedx"
 
+# Backup compression (default: none)
+# none - store a plain copy of each file
+# gzip - store each file gzip-compressed; restores decompress it
+#compression = "none"
+
 [compatibility]
-# Regex mode: "pcre" (default), "ere", or "bre"
-# pcre - Perl-Compatible Regular Expressions (most modern, powerful)
-# ere  - Extended Regular Expressions (like sed -E)
-# bre  - Basic Regular Expressions (like GNU sed, maximum compatibility)
-mode = "pcre"
+# Compatibility mode where GNU sed and POSIX differ (default: GNU behavior)
+# gnu   - GNU sed behavior (e.g. N on the last line prints the pattern space)
+# posix - Strict POSIX behavior (e.g. N on the last line discards it)
+# The regex names "pcre", "ere", and "bre" are accepted and select GNU behavior;
+# the regex flavor itself is chosen with -E/-B on the command line.
+mode = "gnu"
 
 # Show incompatibility warnings (default: true)
 # Display warnings when using features that differ from GNU sed.
@@ -219,8 +265,11 @@ show_warnings = true
 # More context makes it easier to understand changes, but uses more memory.
 context_lines = 2
 
-# Maximum memory usage for streaming in MB (default: 100)
+# Maximum memory usage in MB (default: 100)
 # Files larger than this threshold will use streaming mode (constant memory).
+# Scripts that can't be streamed run from disk on files that would need more
+# than this in memory (about 4 times the file size), or refuse them if they
+# need every line at once (i or a without D, --slurp, --paragraph).
 max_memory_mb = 100
 
 # Enable streaming mode for files >= threshold (default: true)
@@ -303,7 +352,6 @@ pub fn ensure_complete_config() -> Result<()> {
 }
 
 /// Save configuration to file
-#[allow(dead_code)] // Public API - kept for future use
 pub fn save_config(config: &Config) -> Result<()> {
     let config_path = config_file_path()?;
 
@@ -333,11 +381,23 @@ pub fn validate_config(config: &Config) -> Result<()> {
         );
     }
 
+    if let Some(compression) = &config.backup.compression
+        && !["none", "gzip"].contains(&compression.as_str())
+    {
+        anyhow::bail!(
+            "Invalid compression: {} (must be 'none' or 'gzip')",
+            compression
+        );
+    }
+
     // Validate compatibility mode
     if let Some(mode) = &config.compatibility.mode
-        && !["pcre", "ere", "bre"].contains(&mode.as_str())
+        && !["gnu", "posix", "pcre", "ere", "bre"].contains(&mode.as_str())
     {
-        anyhow::bail!("Invalid mode: {} (must be 'pcre', 'ere', or 'bre')", mode);
+        anyhow::bail!(
+            "Invalid mode: {} (must be 'gnu', 'posix', 'pcre', 'ere', or 'bre')",
+            mode
+        );
     }
 
     // Validate processing settings
@@ -356,6 +416,90 @@ pub fn validate_config(config: &Config) -> Result<()> {
     Ok(())
 }
 
+/// Every key `config get` and `config set` accept, as `section.field`
+pub const CONFIG_KEYS: &[&str] = &[
+    "backup.max_size_gb",
+    "backup.max_disk_usage_percent",
+    "backup.backup_dir",
+    "backup.compression",
+    "compatibility.mode",
+    "compatibility.show_warnings",
+    "processing.context_lines",
+    "processing.max_memory_mb",
+    "processing.streaming",
+    "processing.debug",
+];
+
+fn unknown_key(key: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Unknown config key: {} (known keys: {})",
+        key,
+        CONFIG_KEYS.join(", ")
+    )
+}
+
+/// Value of a dotted key such as `processing.max_memory_mb`, or `None`
+/// if it isn't set
+pub fn get_config_value(config: &Config, key: &str) -> Result<Option<String>> {
+    fn show<T: ToString>(value: &Option<T>) -> Option<String> {
+        value.as_ref().map(T::to_string)
+    }
+
+    Ok(match key {
+        "backup.max_size_gb" => show(&config.backup.max_size_gb),
+        "backup.max_disk_usage_percent" => show(&config.backup.max_disk_usage_percent),
+        "backup.backup_dir" => show(&config.backup.backup_dir),
+        "backup.compression" => show(&config.backup.compression),
+        "compatibility.mode" => show(&config.compatibility.mode),
+        "compatibility.show_warnings" => show(&config.compatibility.show_warnings),
+        "processing.context_lines" => show(&config.processing.context_lines),
+        "processing.max_memory_mb" => show(&config.processing.max_memory_mb),
+        "processing.streaming" => show(&config.processing.streaming),
+        "processing.debug" => show(&config.processing.debug),
+        _ => return Err(unknown_key(key)),
+    })
+}
+
+/// Set a dotted key such as `processing.max_memory_mb` from its text form,
+/// then validate the result. On error `config` may hold the new value, so
+/// only save it if this succeeds.
+pub fn set_config_value(config: &mut Config, key: &str, value: &str) -> Result<()> {
+    fn parse<T: std::str::FromStr>(key: &str, value: &str, expected: &str) -> Result<Option<T>> {
+        value.parse().map(Some).map_err(|_| {
+            anyhow::anyhow!(
+                "Invalid value for {}: {} (expected {})",
+                key,
+                value,
+                expected
+            )
+        })
+    }
+
+    match key {
+        "backup.max_size_gb" => config.backup.max_size_gb = parse(key, value, "a number")?,
+        "backup.max_disk_usage_percent" => {
+            config.backup.max_disk_usage_percent = parse(key, value, "a number")?
+        }
+        "backup.backup_dir" => config.backup.backup_dir = Some(value.to_string()),
+        "backup.compression" => config.backup.compression = Some(value.to_string()),
+        "compatibility.mode" => config.compatibility.mode = Some(value.to_string()),
+        "compatibility.show_warnings" => {
+            config.compatibility.show_warnings = parse(key, value, "true or false")?
+        }
+        "processing.context_lines" => {
+            config.processing.context_lines = parse(key, value, "a whole number")?
+        }
+        "processing.max_memory_mb" => {
+            config.processing.max_memory_mb = parse(key, value, "a whole number")?
+        }
+        "processing.streaming" => config.processing.streaming = parse(key, value, "true or false")?,
+        "processing.debug" => config.processing.debug = parse(key, value, "true or false")?,
+        _ => return Err(unknown_key(key)),
+    }
+
+    validate_config(config)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -374,9 +518,9 @@ mod tests {
     #[test]
     fn test_default_config() {
         let config = Config::default();
-        assert_eq!(config.backup.max_size_gb, Some(2.0));
+        assert_eq!(config.backup.max_size_gb, None);
         assert_eq!(config.backup.max_disk_usage_percent, Some(60.0));
-        assert_eq!(config.compatibility.mode, Some("pcre".to_string()));
+        assert_eq!(config.compatibility.mode, Some("gnu".to_string()));
         assert_eq!(config.compatibility.show_warnings, Some(true));
         assert_eq!(config.processing.context_lines, Some(2));
         assert_eq!(config.processing.max_memory_mb, Some(100));
@@ -459,6 +603,17 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_validate_config_compression() {
+        let mut config = Config::default();
+        config.backup.compression = Some("gzip".to_string());
+        assert!(validate_config(&config).is_ok());
+
+        config.backup.compression = Some("zip".to_string());
+        let err = validate_config(&config).unwrap_err();
+        assert!(err.to_string().contains("Invalid compression: zip"));
+    }
+
     #[test]
     fn test_validate_config_invalid_mode() {
         let mut config = Config::default();
@@ -470,7 +625,7 @@ mod tests {
 
     #[test]
     fn test_validate_config_all_valid_modes() {
-        let modes = vec!["pcre", "ere", "bre"];
+        let modes = vec!["gnu", "posix", "pcre", "ere", "bre"];
         for mode in modes {
             let mut config = Config::default();
             config.compatibility.mode = Some(mode.to_string());
@@ -482,6 +637,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_compat_mode_resolution() {
+        let mut config = Config::default();
+        assert_eq!(config.compatibility.compat_mode(), CompatMode::Gnu);
+
+        config.compatibility.mode = Some("posix".to_string());
+        assert_eq!(config.compatibility.compat_mode(), CompatMode::Posix);
+
+        config.compatibility.mode = Some("gnu".to_string());
+        assert_eq!(config.compatibility.compat_mode(), CompatMode::Gnu);
+
+        // Configs written when mode named a regex flavor still get GNU
+        for legacy in ["pcre", "ere", "bre"] {
+            config.compatibility.mode = Some(legacy.to_string());
+            assert_eq!(config.compatibility.compat_mode(), CompatMode::Gnu);
+        }
+
+        // The file `sedx config` writes names the mode it gets
+        let template: Config = toml::from_str(get_default_config_content()).unwrap();
+        assert_eq!(template.compatibility.mode.as_deref(), Some("gnu"));
+    }
+
+    #[test]
+    fn test_context_size_resolution() {
+        let mut config = Config::default();
+        assert_eq!(config.processing.context_size(None), 2);
+
+        config.processing.context_lines = Some(5);
+        assert_eq!(config.processing.context_size(None), 5);
+        assert_eq!(config.processing.context_size(Some(0)), 0);
+        assert_eq!(config.processing.context_size(Some(3)), 3);
+
+        config.processing.context_lines = None;
+        assert_eq!(config.processing.context_size(None), DEFAULT_CONTEXT_LINES);
+    }
+
     #[test]
     fn test_validate_config_invalid_mode_empty() {
         let mut config = Config::default();
@@ -543,6 +734,7 @@ mod tests {
                 max_size_gb: None,
                 max_disk_usage_percent: None,
                 backup_dir: None,
+                compression: None,
             },
             compatibility: CompatibilityConfig {
                 mode: None,
@@ -607,6 +799,7 @@ mod tests {
                 max_size_gb: Some(5.5),
                 max_disk_usage_percent: Some(80.0),
                 backup_dir: Some("/custom/path".to_string()),
+                compression: Some("gzip".to_string()),
             },
             compatibility: CompatibilityConfig {
                 mode: Some("ere".to_string()),
@@ -653,9 +846,9 @@ mod tests {
     fn test_config_parse_empty_toml_all_defaults() {
         let empty_toml = "";
         let config: Config = toml::from_str(empty_toml).unwrap();
-        assert_eq!(config.backup.max_size_gb, Some(2.0)); // default
+        assert_eq!(config.backup.max_size_gb, None); // default
         assert_eq!(config.backup.max_disk_usage_percent, Some(60.0)); // default
-        assert_eq!(config.compatibility.mode, Some("pcre".to_string())); // default
+        assert_eq!(config.compatibility.mode, Some("gnu".to_string())); // default
         assert_eq!(config.compatibility.show_warnings, Some(true)); // default
         assert_eq!(config.processing.context_lines, Some(2)); // default
         assert_eq!(config.processing.max_memory_mb, Some(100)); // default
@@ -771,7 +964,7 @@ mod tests {
 
         assert_eq!(config.backup.max_size_gb, Some(10.0)); // Custom
         assert_eq!(config.backup.max_disk_usage_percent, Some(60.0)); // Default
-        assert_eq!(config.compatibility.mode, Some("pcre".to_string())); // Default
+        assert_eq!(config.compatibility.mode, Some("gnu".to_string())); // Default
         assert_eq!(config.processing.context_lines, Some(2)); // Default
     }
 
@@ -829,8 +1022,8 @@ mod tests {
 
         let config: Config = toml::from_str(empty_sections).unwrap();
         // All should use defaults
-        assert_eq!(config.backup.max_size_gb, Some(2.0));
-        assert_eq!(config.compatibility.mode, Some("pcre".to_string()));
+        assert_eq!(config.backup.max_size_gb, None);
+        assert_eq!(config.compatibility.mode, Some("gnu".to_string()));
         assert_eq!(config.processing.context_lines, Some(2));
     }
 
@@ -841,7 +1034,7 @@ mod tests {
     #[test]
     fn test_backup_config_default() {
         let config = BackupConfig::default();
-        assert_eq!(config.max_size_gb, Some(2.0));
+        assert_eq!(config.max_size_gb, None);
         assert_eq!(config.max_disk_usage_percent, Some(60.0));
         assert_eq!(config.backup_dir, None);
     }
@@ -852,6 +1045,7 @@ mod tests {
             max_size_gb: Some(5.0),
             max_disk_usage_percent: Some(80.0),
             backup_dir: Some("/mnt/backups".to_string()),
+            compression: None,
         };
         assert_eq!(config.max_size_gb, Some(5.0));
         assert_eq!(config.max_disk_usage_percent, Some(80.0));
@@ -865,7 +1059,7 @@ mod tests {
     #[test]
     fn test_compatibility_config_default() {
         let config = CompatibilityConfig::default();
-        assert_eq!(config.mode, Some("pcre".to_string()));
+        assert_eq!(config.mode, Some("gnu".to_string()));
         assert_eq!(config.show_warnings, Some(true));
     }
 
@@ -997,7 +1191,8 @@ mod tests {
     #[test]
     fn test_config_serialize_order() {
         // TOML doesn't guarantee order, but all fields should be present
-        let config = Config::default();
+        let mut config = Config::default();
+        config.backup.max_size_gb = Some(2.0); // not set by default
         let toml_str = toml::to_string_pretty(&config).unwrap();
 
         // Check all sections are present
@@ -1047,6 +1242,7 @@ mod tests {
                 max_size_gb: None,
                 max_disk_usage_percent: None,
                 backup_dir: None,
+                compression: None,
             },
             compatibility: CompatibilityConfig {
                 mode: None,
@@ -1075,7 +1271,8 @@ mod tests {
         // None values serialize as absent fields or with defaults
         let parsed: Config = toml::from_str(&toml_str).unwrap();
         // When parsed back, serde(default) functions provide default values
-        assert!(parsed.backup.max_size_gb.is_some());
+        assert!(parsed.backup.max_disk_usage_percent.is_some());
+        assert!(parsed.backup.max_size_gb.is_none());
     }
 
     #[test]
@@ -1088,12 +1285,65 @@ mod tests {
         "#;
         let config: Config = toml::from_str(toml_str).unwrap();
         // All fields should have their default values from default functions
-        assert_eq!(config.backup.max_size_gb, Some(2.0));
+        assert_eq!(config.backup.max_size_gb, None);
         assert_eq!(config.backup.max_disk_usage_percent, Some(60.0));
-        assert_eq!(config.compatibility.mode, Some("pcre".to_string()));
+        assert_eq!(config.compatibility.mode, Some("gnu".to_string()));
         assert_eq!(config.compatibility.show_warnings, Some(true));
         assert_eq!(config.processing.context_lines, Some(2));
         assert_eq!(config.processing.max_memory_mb, Some(100));
         assert_eq!(config.processing.streaming, Some(true));
     }
+
+    // =========================================================================
+    // get_config_value() / set_config_value() tests
+    // =========================================================================
+
+    #[test]
+    fn test_set_and_get_config_value_round_trip() {
+        let mut config = Config::default();
+        config.processing.streaming = Some(false);
+
+        set_config_value(&mut config, "processing.streaming", "true").unwrap();
+        set_config_value(&mut config, "processing.max_memory_mb", "250").unwrap();
+
+        // Read back after a save/load cycle through TOML
+        let saved = toml::to_string_pretty(&config).unwrap();
+        let loaded: Config = toml::from_str(&saved).unwrap();
+        assert_eq!(
+            get_config_value(&loaded, "processing.streaming").unwrap(),
+            Some("true".to_string())
+        );
+        assert_eq!(
+            get_config_value(&loaded, "processing.max_memory_mb").unwrap(),
+            Some("250".to_string())
+        );
+        assert_eq!(
+            get_config_value(&loaded, "backup.backup_dir").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_config_value_rejects_unknown_keys_and_bad_values() {
+        let mut config = Config::default();
+
+        let err = get_config_value(&config, "processing.nope").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Unknown config key: processing.nope")
+        );
+        let err = set_config_value(&mut config, "streaming", "true").unwrap_err();
+        assert!(err.to_string().contains("known keys: backup.max_size_gb"));
+
+        let err = set_config_value(&mut config, "processing.streaming", "yes").unwrap_err();
+        assert!(
+            err.to_string().contains("expected true or false"),
+            "{}",
+            err
+        );
+
+        // Parses, but fails validate_config
+        let err = set_config_value(&mut config, "processing.context_lines", "50").unwrap_err();
+        assert!(err.to_string().contains("Invalid context_lines"), "{}", err);
+    }
 }
@@ -1,12 +1,202 @@
-use crate::file_processor::{ChangeType, FileChange, FileDiff};
+use crate::cli::ColorChoice;
+use crate::disk_space::DiskSpaceInfo;
+use crate::file_processor::{ChangeType, FileChange, FileDiff, LineChange, StreamProgress};
 use colored::*;
+use serde::{Deserialize, Serialize};
 use std::io::IsTerminal;
+use std::path::PathBuf;
+
+/// Schema version of the JSON diff output. Bump when fields change meaning.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// JSON representation of a single file's diff (`--format json`)
+///
+/// In streaming mode the processor never holds the whole file, so `lines`
+/// is empty (and omitted) and only `changes` is populated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonDiff {
+    pub schema_version: u32,
+    pub file_path: String,
+    pub is_streaming: bool,
+    pub changes: Vec<LineChange>,
+    pub printed_lines: Vec<String>,
+    /// Every line of the result with its change type (in-memory mode only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lines: Vec<JsonLine>,
+}
+
+/// A line of the processed file as it appears in `JsonDiff::lines`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonLine {
+    pub line_number: usize,
+    pub change_type: ChangeType,
+    pub content: String,
+}
+
+impl From<&FileDiff> for JsonDiff {
+    fn from(diff: &FileDiff) -> Self {
+        JsonDiff {
+            schema_version: JSON_SCHEMA_VERSION,
+            file_path: diff.file_path.clone(),
+            is_streaming: diff.is_streaming,
+            changes: diff.changes.clone(),
+            printed_lines: diff.printed_lines.clone(),
+            lines: diff
+                .all_lines
+                .iter()
+                .map(|(line_number, content, change_type)| JsonLine {
+                    line_number: *line_number,
+                    change_type: change_type.clone(),
+                    content: content.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// One line of a unified diff hunk
+enum UnifiedOp<'a> {
+    Context(&'a str),
+    Remove(&'a str),
+    Insert(&'a str),
+}
+
+/// Totals shown before the apply phase: how many files will be rewritten
+/// and how many bytes will be written and backed up
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplySummary {
+    pub files_to_modify: usize,
+    pub bytes_to_write: u64,
+    /// None when no backup will be created
+    pub bytes_to_backup: Option<u64>,
+}
+
+impl ApplySummary {
+    /// Compute totals from preview diffs. Only files with at least one
+    /// non-unchanged line count as modified; `backup_paths` lists the files
+    /// that will be copied into the backup (None if backup is skipped).
+    pub fn from_diffs(diffs: &[FileDiff], backup_paths: Option<&[PathBuf]>) -> Self {
+        let file_size = |path: &std::path::Path| std::fs::metadata(path).map_or(0, |m| m.len());
+
+        let modified: Vec<&FileDiff> = diffs
+            .iter()
+            .filter(|d| {
+                d.changes
+                    .iter()
+                    .any(|c| c.change_type != ChangeType::Unchanged)
+            })
+            .collect();
+
+        Self {
+            files_to_modify: modified.len(),
+            bytes_to_write: modified
+                .iter()
+                .map(|d| file_size(std::path::Path::new(&d.file_path)))
+                .sum(),
+            bytes_to_backup: backup_paths.map(|paths| paths.iter().map(|p| file_size(p)).sum()),
+        }
+    }
+
+    /// One-line summary, e.g. "Will modify 12 files (3.4 MB), backup 3.4 MB to ~/.sedx/backups"
+    pub fn format(&self, backup_dir: &str) -> String {
+        if self.files_to_modify == 0 {
+            return "No changes to apply".to_string();
+        }
+        let noun = if self.files_to_modify == 1 {
+            "file"
+        } else {
+            "files"
+        };
+        let mut line = format!(
+            "Will modify {} {} ({})",
+            self.files_to_modify,
+            noun,
+            DiskSpaceInfo::bytes_to_human(self.bytes_to_write)
+        );
+        match self.bytes_to_backup {
+            Some(bytes) => line.push_str(&format!(
+                ", backup {} to {}",
+                DiskSpaceInfo::bytes_to_human(bytes),
+                backup_dir
+            )),
+            None => line.push_str(", no backup"),
+        }
+        line
+    }
+}
+
+/// Exit code when some files failed but the others were processed
+pub const EXIT_PARTIAL_FAILURE: u8 = 3;
+
+/// Exit code of a `--require-change` run that would change nothing
+pub const EXIT_NO_CHANGE: u8 = 4;
+
+/// Outcome of a run over several files: how many were processed, how many
+/// of those changed, and how many couldn't be read or written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSummary {
+    pub processed: usize,
+    pub changed: usize,
+    pub failed: usize,
+    /// Exit status a q or Q command asked for (`5q3`), 0 if none did
+    pub quit_status: u8,
+}
+
+impl RunSummary {
+    /// `quit_status` when every file was processed, 1 when none was, and
+    /// `EXIT_PARTIAL_FAILURE` when only some failed
+    pub fn exit_code(&self) -> u8 {
+        match (self.failed, self.processed) {
+            (0, _) => self.quit_status,
+            (_, 0) => 1,
+            _ => EXIT_PARTIAL_FAILURE,
+        }
+    }
+
+    /// One-line summary, e.g. "Processed 7 of 10 files (5 changed), 3 failed"
+    pub fn format(&self) -> String {
+        let total = self.processed + self.failed;
+        let noun = if total == 1 { "file" } else { "files" };
+        format!(
+            "Processed {} of {} {} ({} changed), {} failed",
+            self.processed, total, noun, self.changed, self.failed
+        )
+    }
+}
+
+impl std::fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.processed + self.failed;
+        if self.processed == 0 {
+            write!(f, "None of the {} file(s) could be processed", total)
+        } else {
+            write!(
+                f,
+                "{} of {} file(s) could not be processed",
+                self.failed, total
+            )
+        }
+    }
+}
+
+impl std::error::Error for RunSummary {}
 
 pub struct DiffFormatter;
 
 impl DiffFormatter {
-    /// Auto-detect if we should use colors
-    fn should_use_color() -> bool {
+    /// Decide whether to use colors, auto-detecting unless `--color` says otherwise
+    fn should_use_color(color: ColorChoice) -> bool {
+        match color {
+            ColorChoice::Always => {
+                // `colored` does its own terminal check; override it so
+                // piped output keeps its escape codes
+                colored::control::set_override(true);
+                return true;
+            }
+            ColorChoice::Never => return false,
+            ColorChoice::Auto => {}
+        }
+
         // Check NO_COLOR env var (https://no-color.org/)
         if std::env::var("NO_COLOR").is_ok() {
             return false;
@@ -21,8 +211,9 @@ impl DiffFormatter {
         diff: &FileDiff,
         context_size: usize,
         _expression: &str,
+        color: ColorChoice,
     ) -> String {
-        let use_color = Self::should_use_color();
+        let use_color = Self::should_use_color(color);
         let mut output = String::new();
 
         // If there are printed lines, show only those (print command mode)
@@ -86,12 +277,21 @@ impl DiffFormatter {
                         indicator.dimmed(),
                         content.dimmed()
                     ),
-                    ChangeType::Modified => format!(
-                        "L{}: {} {}\n",
-                        line_num,
-                        indicator.yellow().bold(),
-                        content.yellow().bold()
-                    ),
+                    ChangeType::Modified => {
+                        let spans = diff
+                            .changes
+                            .iter()
+                            .find(|c| {
+                                c.line_number == line_num && c.change_type == ChangeType::Modified
+                            })
+                            .map_or(&[][..], |c| c.spans.as_slice());
+                        format!(
+                            "L{}: {} {}\n",
+                            line_num,
+                            indicator.yellow().bold(),
+                            Self::highlight_spans(&content, spans)
+                        )
+                    }
                     ChangeType::Added => format!(
                         "L{}: {} {}\n",
                         line_num,
@@ -165,6 +365,242 @@ impl DiffFormatter {
         output
     }
 
+    /// Per-file counts of modified, added and deleted lines with a totals
+    /// row, like `git diff --stat` (`--dry-run --stat`). Files without
+    /// changes are left out.
+    pub fn format_stat(diffs: &[FileDiff]) -> String {
+        let count = |diff: &FileDiff, kind: ChangeType| {
+            diff.changes
+                .iter()
+                .filter(|c| c.change_type == kind)
+                .count()
+        };
+        let rows: Vec<(&str, [usize; 3])> = diffs
+            .iter()
+            .filter(|d| d.has_changes())
+            .map(|d| {
+                (
+                    d.file_path.as_str(),
+                    [
+                        count(d, ChangeType::Modified),
+                        count(d, ChangeType::Added),
+                        count(d, ChangeType::Deleted),
+                    ],
+                )
+            })
+            .collect();
+
+        let mut totals = [0; 3];
+        for (_, counts) in &rows {
+            for (total, n) in totals.iter_mut().zip(counts) {
+                *total += n;
+            }
+        }
+        let files = if rows.len() == 1 { "file" } else { "files" };
+        let total_label = format!("Total ({} {})", rows.len(), files);
+        let width = rows
+            .iter()
+            .map(|(path, _)| path.len())
+            .chain([total_label.len()])
+            .max()
+            .unwrap_or(0);
+
+        let mut output = format!(
+            "{:<width$}  Modified  Added  Deleted\n",
+            "File",
+            width = width
+        );
+        let row = |label: &str, [modified, added, deleted]: [usize; 3]| {
+            format!(
+                "{:<width$}  {:>8}  {:>5}  {:>7}\n",
+                label,
+                modified,
+                added,
+                deleted,
+                width = width
+            )
+        };
+        for (path, counts) in &rows {
+            output.push_str(&row(path, *counts));
+        }
+        output.push_str(&row(&total_label, totals));
+        output
+    }
+
+    /// Format file diff as a single-line JSON object (see `JsonDiff`)
+    ///
+    /// JSON in streaming mode contains only `changes`: `lines` is omitted
+    /// because the streaming processor does not keep the whole file.
+    pub fn format_json(diff: &FileDiff) -> String {
+        // Serializing plain strings, numbers and enums cannot fail
+        serde_json::to_string(&JsonDiff::from(diff)).expect("JsonDiff is always serializable")
+    }
+
+    /// Format file diff as a unified diff (`diff -u` style, no colors)
+    ///
+    /// In streaming mode only the context the processor recorded is
+    /// available, so hunks never span lines it skipped.
+    pub fn format_unified(diff: &FileDiff, context_size: usize) -> String {
+        let ops = Self::unified_ops(diff);
+        if ops.iter().all(|op| matches!(op.0, UnifiedOp::Context(_))) {
+            return String::new();
+        }
+
+        let mut output = format!("--- a/{}\n+++ b/{}\n", diff.file_path, diff.file_path);
+
+        // Each op carries the (old, new) line numbers it starts at
+        let changed: Vec<usize> = ops
+            .iter()
+            .enumerate()
+            .filter(|(_, op)| !matches!(op.0, UnifiedOp::Context(_)))
+            .map(|(i, _)| i)
+            .collect();
+
+        // Split ops into runs of contiguous old line numbers. In-memory diffs
+        // are a single run; streaming diffs only keep lines near changes.
+        let mut run_ids = Vec::with_capacity(ops.len());
+        let mut run_bounds: Vec<(usize, usize)> = Vec::new();
+        for (i, op) in ops.iter().enumerate() {
+            if i == 0 || op.1 > ops[i - 1].1 + 1 {
+                run_bounds.push((i, i + 1));
+            } else if let Some(last) = run_bounds.last_mut() {
+                last.1 = i + 1;
+            }
+            run_ids.push(run_bounds.len() - 1);
+        }
+
+        // Merge changes whose context would overlap into a single hunk
+        let mut hunks: Vec<(usize, usize, usize)> = Vec::new();
+        for &idx in &changed {
+            let run = run_ids[idx];
+            let (run_start, run_end) = run_bounds[run];
+            let start = idx.saturating_sub(context_size).max(run_start);
+            let end = (idx + context_size + 1).min(run_end);
+            match hunks.last_mut() {
+                Some(last) if last.2 == run && start <= last.1 => last.1 = end,
+                _ => hunks.push((start, end, run)),
+            }
+        }
+
+        for (start, end, _) in hunks {
+            let slice = &ops[start..end];
+            let old_count = slice
+                .iter()
+                .filter(|op| !matches!(op.0, UnifiedOp::Insert(_)))
+                .count();
+            let new_count = slice
+                .iter()
+                .filter(|op| !matches!(op.0, UnifiedOp::Remove(_)))
+                .count();
+            let (old_start, new_start) = (slice[0].1, slice[0].2);
+            // diff -u convention: an empty side reports the line before it
+            let old_start = if old_count == 0 {
+                old_start - 1
+            } else {
+                old_start
+            };
+            let new_start = if new_count == 0 {
+                new_start - 1
+            } else {
+                new_start
+            };
+
+            output.push_str(&format!(
+                "@@ -{},{} +{},{} @@\n",
+                old_start, old_count, new_start, new_count
+            ));
+            for (op, _, _) in slice {
+                match op {
+                    UnifiedOp::Context(line) => output.push_str(&format!(" {}\n", line)),
+                    UnifiedOp::Remove(line) => output.push_str(&format!("-{}\n", line)),
+                    UnifiedOp::Insert(line) => output.push_str(&format!("+{}\n", line)),
+                }
+            }
+        }
+
+        output
+    }
+
+    /// Flatten a diff into unified-diff operations tagged with their
+    /// starting (old, new) line numbers
+    fn unified_ops(diff: &FileDiff) -> Vec<(UnifiedOp<'_>, usize, usize)> {
+        let mut ops = Vec::new();
+
+        if diff.all_lines.is_empty() {
+            // Streaming mode: changes (plus any recorded context), line numbers taken as-is
+            for change in &diff.changes {
+                let n = change.line_number;
+                match change.change_type {
+                    ChangeType::Unchanged => ops.push((UnifiedOp::Context(&change.content), n, n)),
+                    ChangeType::Modified => {
+                        let old = change.old_content.as_deref().unwrap_or_default();
+                        ops.push((UnifiedOp::Remove(old), n, n));
+                        ops.push((UnifiedOp::Insert(&change.content), n, n));
+                    }
+                    ChangeType::Deleted => ops.push((UnifiedOp::Remove(&change.content), n, n)),
+                    ChangeType::Added => ops.push((UnifiedOp::Insert(&change.content), n, n)),
+                }
+            }
+            return ops;
+        }
+
+        let (mut old_no, mut new_no) = (1, 1);
+        for (line_num, content, change_type) in &diff.all_lines {
+            match change_type {
+                ChangeType::Unchanged => {
+                    ops.push((UnifiedOp::Context(content), old_no, new_no));
+                    old_no += 1;
+                    new_no += 1;
+                }
+                ChangeType::Modified => {
+                    let old = diff
+                        .changes
+                        .iter()
+                        .find(|c| c.line_number == *line_num)
+                        .and_then(|c| c.old_content.as_deref())
+                        .unwrap_or_default();
+                    ops.push((UnifiedOp::Remove(old), old_no, new_no));
+                    old_no += 1;
+                    ops.push((UnifiedOp::Insert(content), old_no, new_no));
+                    new_no += 1;
+                }
+                ChangeType::Deleted => {
+                    ops.push((UnifiedOp::Remove(content), old_no, new_no));
+                    old_no += 1;
+                }
+                ChangeType::Added => {
+                    ops.push((UnifiedOp::Insert(content), old_no, new_no));
+                    new_no += 1;
+                }
+            }
+        }
+
+        ops
+    }
+
+    /// Color a modified line, underlining the byte ranges in `spans` (the
+    /// text substitutions wrote)
+    fn highlight_spans(content: &str, spans: &[(usize, usize)]) -> String {
+        let mut output = String::new();
+        let mut pos = 0;
+        for &(start, len) in spans {
+            let (Some(before), Some(span)) =
+                (content.get(pos..start), content.get(start..start + len))
+            else {
+                continue;
+            };
+            if !before.is_empty() {
+                output.push_str(&before.yellow().bold().to_string());
+            }
+            output.push_str(&span.yellow().bold().underline().to_string());
+            pos = start + len;
+        }
+        if pos == 0 || pos < content.len() {
+            output.push_str(&content[pos..].yellow().bold().to_string());
+        }
+        output
+    }
+
     /// Filter lines to show only changed lines with context, grouping close changes
     fn filter_lines_with_context(
         lines: &[(usize, String, ChangeType)],
@@ -266,8 +702,9 @@ impl DiffFormatter {
     pub fn format_preview(
         expression: &str,
         files_changes: Vec<(String, Vec<FileChange>)>,
+        color: ColorChoice,
     ) -> String {
-        let use_color = Self::should_use_color();
+        let use_color = Self::should_use_color(color);
         let mut output = String::new();
 
         if use_color {
@@ -355,8 +792,9 @@ impl DiffFormatter {
         expression: &str,
         backup_id: &str,
         files_changes: Vec<(String, Vec<FileChange>)>,
+        color: ColorChoice,
     ) -> String {
-        let use_color = Self::should_use_color();
+        let use_color = Self::should_use_color(color);
         let mut output = String::new();
 
         if use_color {
@@ -409,8 +847,11 @@ impl DiffFormatter {
     }
 
     /// Format operation history
-    pub fn format_history(backups: Vec<crate::backup_manager::BackupMetadata>) -> String {
-        let use_color = Self::should_use_color();
+    pub fn format_history(
+        backups: Vec<crate::backup_manager::BackupMetadata>,
+        color: ColorChoice,
+    ) -> String {
+        let use_color = Self::should_use_color(color);
         let mut output = String::new();
 
         if backups.is_empty() {
@@ -448,9 +889,31 @@ impl DiffFormatter {
         output
     }
 
+    /// Format a `--progress` line for a streaming file, meant to be
+    /// redrawn in place on stderr: it starts by returning to the start of
+    /// the line and clearing it, and the last report for the file ends it
+    pub fn format_progress(file: &str, progress: &StreamProgress) -> String {
+        const WIDTH: u64 = 30;
+        let percent = (progress.bytes_read * 100)
+            .checked_div(progress.total_bytes)
+            .unwrap_or(100);
+        let filled = (percent * WIDTH / 100) as usize;
+        format!(
+            "\r\x1b[K{} [{}{}] {:>3}% {} / {}, {:.0} lines/s{}",
+            file,
+            "#".repeat(filled),
+            "-".repeat(WIDTH as usize - filled),
+            percent,
+            DiskSpaceInfo::bytes_to_human(progress.bytes_read),
+            DiskSpaceInfo::bytes_to_human(progress.total_bytes),
+            progress.lines_per_sec(),
+            if progress.done { "\n" } else { "" }
+        )
+    }
+
     /// Format dry run header
-    pub fn format_dry_run_header(expression: &str) -> String {
-        let use_color = Self::should_use_color();
+    pub fn format_dry_run_header(expression: &str, color: ColorChoice) -> String {
+        let use_color = Self::should_use_color(color);
 
         if use_color {
             format!(
@@ -475,6 +938,7 @@ mod tests {
     // Helper function to create a test backup metadata
     fn create_test_backup(id: &str, expression: &str, files: Vec<&str>) -> BackupMetadata {
         BackupMetadata {
+            version: crate::backup_manager::METADATA_VERSION,
             id: id.to_string(),
             timestamp: Utc::now(),
             expression: expression.to_string(),
@@ -483,6 +947,8 @@ mod tests {
                 .map(|f| FileBackup {
                     original_path: PathBuf::from(f),
                     backup_path: PathBuf::from(format!("/tmp/backup/{}", f)),
+                    sha256: None,
+                    compression: Default::default(),
                 })
                 .collect(),
         }
@@ -500,6 +966,7 @@ mod tests {
             all_lines,
             printed_lines: Vec::new(),
             is_streaming: false,
+            exit_code: None,
         }
     }
 
@@ -514,6 +981,7 @@ mod tests {
             change_type,
             content: content.to_string(),
             old_content: None,
+            spans: Vec::new(),
         }
     }
 
@@ -531,7 +999,8 @@ mod tests {
         )];
         let diff = create_test_diff("test.txt", all_lines, changes);
 
-        let result = DiffFormatter::format_diff_with_context(&diff, 0, "s/old/new/");
+        let result =
+            DiffFormatter::format_diff_with_context(&diff, 0, "s/old/new/", ColorChoice::Never);
 
         // Should contain the file path
         assert!(result.contains("test.txt"));
@@ -560,7 +1029,8 @@ mod tests {
         ];
         let diff = create_test_diff("test.txt", all_lines, changes);
 
-        let result = DiffFormatter::format_diff_with_context(&diff, 0, "s/old/new/");
+        let result =
+            DiffFormatter::format_diff_with_context(&diff, 0, "s/old/new/", ColorChoice::Never);
 
         // Should contain all change types
         assert!(result.contains("modified"));
@@ -586,7 +1056,8 @@ mod tests {
         )];
         let diff = create_test_diff("test.txt", all_lines, changes);
 
-        let result = DiffFormatter::format_diff_with_context(&diff, 2, "s/old/new/");
+        let result =
+            DiffFormatter::format_diff_with_context(&diff, 2, "s/old/new/", ColorChoice::Never);
 
         // Should include context lines
         assert!(result.contains("context before"));
@@ -603,7 +1074,8 @@ mod tests {
         ];
         let diff = create_test_diff("test.txt", all_lines, vec![]);
 
-        let result = DiffFormatter::format_diff_with_context(&diff, 0, "s/old/new/");
+        let result =
+            DiffFormatter::format_diff_with_context(&diff, 0, "s/old/new/", ColorChoice::Never);
 
         // Should contain the file path
         assert!(result.contains("test.txt"));
@@ -629,7 +1101,8 @@ mod tests {
         ];
         let diff = create_test_diff("test.txt", all_lines, changes);
 
-        let result = DiffFormatter::format_diff_with_context(&diff, 1, "s/old/new/");
+        let result =
+            DiffFormatter::format_diff_with_context(&diff, 1, "s/old/new/", ColorChoice::Never);
 
         // Should contain "..." placeholder for distant groups
         assert!(result.contains("..."));
@@ -647,9 +1120,11 @@ mod tests {
             all_lines: vec![], // Empty for streaming mode
             printed_lines: vec![],
             is_streaming: true, // Streaming mode
+            exit_code: None,
         };
 
-        let result = DiffFormatter::format_diff_with_context(&diff, 2, "s/old/new/");
+        let result =
+            DiffFormatter::format_diff_with_context(&diff, 2, "s/old/new/", ColorChoice::Never);
 
         // Should still show changes in streaming mode
         assert!(result.contains("modified line 1"));
@@ -665,9 +1140,11 @@ mod tests {
             all_lines: vec![],
             printed_lines: vec!["printed line 1".to_string(), "printed line 2".to_string()],
             is_streaming: false,
+            exit_code: None,
         };
 
-        let result = DiffFormatter::format_diff_with_context(&diff, 0, "/pattern/p");
+        let result =
+            DiffFormatter::format_diff_with_context(&diff, 0, "/pattern/p", ColorChoice::Never);
 
         // Should show printed lines
         assert!(result.contains("printed line 1"));
@@ -678,7 +1155,7 @@ mod tests {
 
     #[test]
     fn test_format_dry_run_header_basic() {
-        let result = DiffFormatter::format_dry_run_header("s/foo/bar/");
+        let result = DiffFormatter::format_dry_run_header("s/foo/bar/", ColorChoice::Never);
 
         assert!(result.contains("Dry run"));
         assert!(result.contains("s/foo/bar/"));
@@ -687,7 +1164,10 @@ mod tests {
 
     #[test]
     fn test_format_dry_run_header_complex_expression() {
-        let result = DiffFormatter::format_dry_run_header("1,10{s/foo/bar/; s/baz/qux/}");
+        let result = DiffFormatter::format_dry_run_header(
+            "1,10{s/foo/bar/; s/baz/qux/}",
+            ColorChoice::Never,
+        );
 
         assert!(result.contains("Dry run"));
         assert!(result.contains("1,10{s/foo/bar/; s/baz/qux/}"));
@@ -695,15 +1175,56 @@ mod tests {
 
     #[test]
     fn test_format_dry_run_header_with_special_chars() {
-        let result = DiffFormatter::format_dry_run_header("s/.*\n\t//g");
+        let result = DiffFormatter::format_dry_run_header("s/.*\n\t//g", ColorChoice::Never);
 
         assert!(result.contains("Dry run"));
         assert!(result.contains("s/.*\n\t//g"));
     }
 
+    #[test]
+    fn test_modified_line_underlines_substituted_spans() {
+        let all_lines = vec![(1, "hello there".to_string(), ChangeType::Modified)];
+        let mut change = create_test_line_change(1, "hello there", ChangeType::Modified);
+        change.spans = vec![(6, 5)];
+        let diff = create_test_diff("test.txt", all_lines, vec![change]);
+
+        let colored =
+            DiffFormatter::format_diff_with_context(&diff, 0, "s/a/b/", ColorChoice::Always);
+        let expected = format!(
+            "{}{}",
+            "hello ".yellow().bold(),
+            "there".yellow().bold().underline()
+        );
+        assert!(colored.contains(&expected), "{:?}", colored);
+
+        // Without color the line is printed as-is
+        let plain = DiffFormatter::format_diff_with_context(&diff, 0, "s/a/b/", ColorChoice::Never);
+        assert!(plain.contains("L1: ~ hello there\n"));
+    }
+
+    #[test]
+    fn test_color_choice_controls_escape_codes() {
+        let all_lines = vec![(1, "new line".to_string(), ChangeType::Modified)];
+        let changes = vec![create_test_line_change(1, "new line", ChangeType::Modified)];
+        let diff = create_test_diff("test.txt", all_lines, changes);
+
+        // `always` colors even though test output is not a terminal
+        let colored =
+            DiffFormatter::format_diff_with_context(&diff, 0, "s/a/b/", ColorChoice::Always);
+        assert!(colored.contains("\x1b["));
+        let header = DiffFormatter::format_dry_run_header("s/a/b/", ColorChoice::Always);
+        assert!(header.contains("\x1b["));
+
+        let plain = DiffFormatter::format_diff_with_context(&diff, 0, "s/a/b/", ColorChoice::Never);
+        assert!(!plain.contains('\x1b'));
+        assert!(plain.contains("L1: ~ new line"));
+        let header = DiffFormatter::format_dry_run_header("s/a/b/", ColorChoice::Never);
+        assert_eq!(header, "Dry run: s/a/b/\n\n");
+    }
+
     #[test]
     fn test_format_history_empty() {
-        let result = DiffFormatter::format_history(vec![]);
+        let result = DiffFormatter::format_history(vec![], ColorChoice::Never);
 
         assert_eq!(result, "No backup history found.\n");
     }
@@ -711,7 +1232,7 @@ mod tests {
     #[test]
     fn test_format_history_single_backup() {
         let backup = create_test_backup("backup-123", "s/foo/bar/", vec!["file1.txt", "file2.txt"]);
-        let result = DiffFormatter::format_history(vec![backup]);
+        let result = DiffFormatter::format_history(vec![backup], ColorChoice::Never);
 
         assert!(result.contains("Operation History"));
         assert!(result.contains("backup-123"));
@@ -724,7 +1245,7 @@ mod tests {
         let backup1 = create_test_backup("backup-001", "s/foo/bar/", vec!["file1.txt"]);
         let backup2 =
             create_test_backup("backup-002", "s/baz/qux/", vec!["file2.txt", "file3.txt"]);
-        let result = DiffFormatter::format_history(vec![backup1, backup2]);
+        let result = DiffFormatter::format_history(vec![backup1, backup2], ColorChoice::Never);
 
         assert!(result.contains("backup-001"));
         assert!(result.contains("s/foo/bar/"));
@@ -744,7 +1265,7 @@ mod tests {
         backup1.timestamp = Utc::now() - chrono::Duration::days(1);
         backup2.timestamp = Utc::now();
 
-        let result = DiffFormatter::format_history(vec![backup1, backup2]);
+        let result = DiffFormatter::format_history(vec![backup1, backup2], ColorChoice::Never);
 
         // Both backups should appear in the result
         assert!(result.contains("backup-old"));
@@ -754,12 +1275,13 @@ mod tests {
     #[test]
     fn test_format_history_with_no_files() {
         let backup = BackupMetadata {
+            version: crate::backup_manager::METADATA_VERSION,
             id: "backup-empty".to_string(),
             timestamp: Utc::now(),
             expression: "s/nochange/nochange/".to_string(),
             files: vec![],
         };
-        let result = DiffFormatter::format_history(vec![backup]);
+        let result = DiffFormatter::format_history(vec![backup], ColorChoice::Never);
 
         assert!(result.contains("backup-empty"));
         assert!(result.contains("Files: 0"));
@@ -775,8 +1297,12 @@ mod tests {
                 new_content: "new".to_string(),
             }],
         )];
-        let result =
-            DiffFormatter::format_execute_result("s/old/new/", "backup-123", files_changes);
+        let result = DiffFormatter::format_execute_result(
+            "s/old/new/",
+            "backup-123",
+            files_changes,
+            ColorChoice::Never,
+        );
 
         assert!(result.contains("Applied"));
         assert!(result.contains("s/old/new/"));
@@ -812,8 +1338,12 @@ mod tests {
                 ],
             ),
         ];
-        let result =
-            DiffFormatter::format_execute_result("s/foo/bar/", "backup-456", files_changes);
+        let result = DiffFormatter::format_execute_result(
+            "s/foo/bar/",
+            "backup-456",
+            files_changes,
+            ColorChoice::Never,
+        );
 
         assert!(result.contains("file1.txt"));
         assert!(result.contains("1 changes"));
@@ -825,8 +1355,12 @@ mod tests {
     #[test]
     fn test_format_execute_result_no_changes() {
         let files_changes = vec![("test.txt".to_string(), vec![])];
-        let result =
-            DiffFormatter::format_execute_result("s/nochange/", "backup-789", files_changes);
+        let result = DiffFormatter::format_execute_result(
+            "s/nochange/",
+            "backup-789",
+            files_changes,
+            ColorChoice::Never,
+        );
 
         assert!(result.contains("Applied"));
         assert!(result.contains("backup-789"));
@@ -845,7 +1379,7 @@ mod tests {
                 new_content: "new".to_string(),
             }],
         )];
-        let result = DiffFormatter::format_preview("s/old/new/", files_changes);
+        let result = DiffFormatter::format_preview("s/old/new/", files_changes, ColorChoice::Never);
 
         assert!(result.contains("Preview"));
         assert!(result.contains("s/old/new/"));
@@ -858,7 +1392,8 @@ mod tests {
     #[test]
     fn test_format_preview_no_changes() {
         let files_changes = vec![("test.txt".to_string(), vec![])];
-        let result = DiffFormatter::format_preview("s/nochange/", files_changes);
+        let result =
+            DiffFormatter::format_preview("s/nochange/", files_changes, ColorChoice::Never);
 
         assert!(result.contains("No changes would be made"));
         assert!(!result.contains("Apply with:"));
@@ -874,7 +1409,7 @@ mod tests {
                 new_content: "new".to_string(),
             }],
         )];
-        let result = DiffFormatter::format_preview("s/old/new/", files_changes);
+        let result = DiffFormatter::format_preview("s/old/new/", files_changes, ColorChoice::Never);
 
         // Should say "1 file" (singular)
         assert!(result.contains("1 file"));
@@ -900,7 +1435,7 @@ mod tests {
                 }],
             ),
         ];
-        let result = DiffFormatter::format_preview("s/old/new/", files_changes);
+        let result = DiffFormatter::format_preview("s/old/new/", files_changes, ColorChoice::Never);
 
         // Should say "2 files" (plural)
         assert!(result.contains("2 files"));
@@ -988,12 +1523,14 @@ mod tests {
                 content: "modified 1".to_string(),
                 change_type: ChangeType::Modified,
                 old_content: None,
+                spans: Vec::new(),
             },
             LineChange {
                 line_number: 5,
                 content: "modified 2".to_string(),
                 change_type: ChangeType::Added,
                 old_content: None,
+                spans: Vec::new(),
             },
         ];
         let result = DiffFormatter::format_changes_streaming(&changes, 2);
@@ -1053,6 +1590,7 @@ mod tests {
             content: "test content".to_string(),
             change_type: ChangeType::Modified,
             old_content: Some("old content".to_string()),
+            spans: Vec::new(),
         };
 
         assert_eq!(line_change.line_number, 42);
@@ -1068,6 +1606,7 @@ mod tests {
             content: "content".to_string(),
             change_type: ChangeType::Added,
             old_content: None,
+            spans: Vec::new(),
         };
         let cloned = line_change.clone();
 
@@ -1092,7 +1631,7 @@ mod tests {
         ];
         let diff = create_test_diff("test.txt", all_lines, changes);
 
-        let result = DiffFormatter::format_diff_with_context(&diff, 0, "test/");
+        let result = DiffFormatter::format_diff_with_context(&diff, 0, "test/", ColorChoice::Never);
 
         // Verify all change types are represented
         assert!(result.contains("modified"));
@@ -1116,7 +1655,7 @@ mod tests {
         ];
         let diff = create_test_diff("test.txt", all_lines, changes);
 
-        let result = DiffFormatter::format_diff_with_context(&diff, 0, "test/");
+        let result = DiffFormatter::format_diff_with_context(&diff, 0, "test/", ColorChoice::Never);
 
         // With context_size=0, unchanged lines are filtered out
         // Check for indicators on changed lines
@@ -1191,10 +1730,289 @@ mod tests {
             create_test_backup("b2", "1,10d", vec!["f2.txt"]),
             create_test_backup("b3", "/pattern/p", vec!["f3.txt"]),
         ];
-        let result = DiffFormatter::format_history(backups);
+        let result = DiffFormatter::format_history(backups, ColorChoice::Never);
 
         assert!(result.contains("s/foo/bar/"));
         assert!(result.contains("1,10d"));
         assert!(result.contains("/pattern/p"));
     }
+
+    #[test]
+    fn test_format_json_round_trip() {
+        let all_lines = vec![
+            (1, "keep".to_string(), ChangeType::Unchanged),
+            (2, "new".to_string(), ChangeType::Modified),
+        ];
+        let changes = vec![LineChange {
+            line_number: 2,
+            change_type: ChangeType::Modified,
+            content: "new".to_string(),
+            old_content: Some("old".to_string()),
+            spans: Vec::new(),
+        }];
+        let mut diff = create_test_diff("test.txt", all_lines, changes.clone());
+        diff.printed_lines = vec!["printed".to_string()];
+
+        let json = DiffFormatter::format_json(&diff);
+        assert!(!json.contains('\n'));
+        assert!(json.contains("\"change_type\":\"modified\""));
+
+        let parsed: JsonDiff = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.schema_version, JSON_SCHEMA_VERSION);
+        assert_eq!(parsed.file_path, "test.txt");
+        assert!(!parsed.is_streaming);
+        assert_eq!(parsed.changes, changes);
+        assert_eq!(parsed.printed_lines, vec!["printed".to_string()]);
+        assert_eq!(parsed.lines.len(), 2);
+        assert_eq!(parsed, JsonDiff::from(&diff));
+    }
+
+    #[test]
+    fn test_format_json_streaming_has_only_changes() {
+        let changes = vec![create_test_line_change(7, "added", ChangeType::Added)];
+        let mut diff = create_test_diff("big.log", Vec::new(), changes);
+        diff.is_streaming = true;
+
+        let json = DiffFormatter::format_json(&diff);
+        assert!(!json.contains("\"lines\""));
+
+        let parsed: JsonDiff = serde_json::from_str(&json).unwrap();
+        assert!(parsed.is_streaming);
+        assert!(parsed.lines.is_empty());
+        assert_eq!(parsed.changes.len(), 1);
+        assert_eq!(parsed.changes[0].change_type, ChangeType::Added);
+    }
+
+    #[test]
+    fn test_format_unified_hunks() {
+        let all_lines = vec![
+            (1, "a".to_string(), ChangeType::Unchanged),
+            (2, "B".to_string(), ChangeType::Modified),
+            (3, "c".to_string(), ChangeType::Unchanged),
+            (4, "d".to_string(), ChangeType::Unchanged),
+            (5, "e".to_string(), ChangeType::Unchanged),
+            (6, "f".to_string(), ChangeType::Unchanged),
+            (7, "G".to_string(), ChangeType::Modified),
+        ];
+        let changes = vec![
+            LineChange {
+                line_number: 2,
+                change_type: ChangeType::Modified,
+                content: "B".to_string(),
+                old_content: Some("b".to_string()),
+                spans: Vec::new(),
+            },
+            LineChange {
+                line_number: 7,
+                change_type: ChangeType::Modified,
+                content: "G".to_string(),
+                old_content: Some("g".to_string()),
+                spans: Vec::new(),
+            },
+        ];
+        let diff = create_test_diff("test.txt", all_lines, changes);
+
+        let result = DiffFormatter::format_unified(&diff, 1);
+        assert_eq!(
+            result,
+            "--- a/test.txt\n+++ b/test.txt\n\
+             @@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n\
+             @@ -6,2 +6,2 @@\n f\n-g\n+G\n"
+        );
+    }
+
+    #[test]
+    fn test_format_unified_streaming_splits_gaps() {
+        let changes = vec![
+            LineChange {
+                line_number: 2,
+                change_type: ChangeType::Modified,
+                content: "B".to_string(),
+                old_content: Some("b".to_string()),
+                spans: Vec::new(),
+            },
+            create_test_line_change(3, "c", ChangeType::Unchanged),
+            LineChange {
+                line_number: 9,
+                change_type: ChangeType::Modified,
+                content: "I".to_string(),
+                old_content: Some("i".to_string()),
+                spans: Vec::new(),
+            },
+        ];
+        let mut diff = create_test_diff("big.log", Vec::new(), changes);
+        diff.is_streaming = true;
+
+        let result = DiffFormatter::format_unified(&diff, 2);
+        assert!(result.contains("@@ -2,2 +2,2 @@\n-b\n+B\n c\n"));
+        assert!(result.contains("@@ -9,1 +9,1 @@\n-i\n+I\n"));
+    }
+
+    #[test]
+    fn test_format_unified_no_changes_is_empty() {
+        let all_lines = vec![(1, "a".to_string(), ChangeType::Unchanged)];
+        let diff = create_test_diff("test.txt", all_lines, Vec::new());
+        assert_eq!(DiffFormatter::format_unified(&diff, 2), "");
+    }
+
+    #[test]
+    fn test_apply_summary_counts_modified_files_and_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let changed = dir.path().join("changed.txt");
+        let untouched = dir.path().join("untouched.txt");
+        std::fs::write(&changed, vec![b'a'; 2048]).unwrap();
+        std::fs::write(&untouched, vec![b'b'; 512]).unwrap();
+
+        let diffs = vec![
+            create_test_diff(
+                changed.to_str().unwrap(),
+                vec![],
+                vec![LineChange {
+                    line_number: 1,
+                    change_type: ChangeType::Modified,
+                    content: "x".to_string(),
+                    old_content: Some("a".to_string()),
+                    spans: Vec::new(),
+                }],
+            ),
+            create_test_diff(untouched.to_str().unwrap(), vec![], vec![]),
+        ];
+        let backup_paths = vec![changed.clone(), untouched.clone()];
+
+        let summary = ApplySummary::from_diffs(&diffs, Some(&backup_paths));
+        assert_eq!(
+            summary,
+            ApplySummary {
+                files_to_modify: 1,
+                bytes_to_write: 2048,
+                bytes_to_backup: Some(2560),
+            }
+        );
+        assert_eq!(
+            summary.format("~/.sedx/backups"),
+            "Will modify 1 file (2.0 KB), backup 2.5 KB to ~/.sedx/backups"
+        );
+
+        let no_backup = ApplySummary::from_diffs(&diffs, None);
+        assert_eq!(
+            no_backup.format("ignored"),
+            "Will modify 1 file (2.0 KB), no backup"
+        );
+
+        let unchanged = ApplySummary::from_diffs(&diffs[1..], Some(&backup_paths));
+        assert_eq!(unchanged.files_to_modify, 0);
+        assert_eq!(unchanged.format("~/.sedx/backups"), "No changes to apply");
+    }
+
+    #[test]
+    fn test_format_stat_counts_per_file_and_total() {
+        let diffs = vec![
+            FileDiff::between(
+                "src/a.txt",
+                &["foo", "keep", "foo", "del"],
+                &["bar".to_string(), "keep".to_string(), "bar".to_string()],
+            ),
+            FileDiff::between("b.txt", &["del", "foo"], &["bar".to_string()]),
+            FileDiff::between("same.txt", &["keep"], &["keep".to_string()]),
+        ];
+
+        assert_eq!(
+            DiffFormatter::format_stat(&diffs),
+            "File             Modified  Added  Deleted\n\
+             src/a.txt               2      0        1\n\
+             b.txt                   1      0        1\n\
+             Total (2 files)         3      0        2\n"
+        );
+    }
+
+    #[test]
+    fn test_format_progress() {
+        let mut progress = StreamProgress {
+            bytes_read: 512 * 1024,
+            total_bytes: 2 * 1024 * 1024,
+            lines: 30_000,
+            elapsed: std::time::Duration::from_secs(2),
+            done: false,
+        };
+        assert_eq!(
+            DiffFormatter::format_progress("big.log", &progress),
+            "\r\x1b[Kbig.log [#######-----------------------]  25% 512.0 KB / 2.0 MB, 15000 lines/s"
+        );
+
+        progress.bytes_read = progress.total_bytes;
+        progress.done = true;
+        let line = DiffFormatter::format_progress("big.log", &progress);
+        assert!(
+            line.contains(&format!("[{}] 100%", "#".repeat(30))),
+            "{line}"
+        );
+        assert!(line.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_run_summary_all_success() {
+        let summary = RunSummary {
+            processed: 3,
+            changed: 2,
+            failed: 0,
+            quit_status: 0,
+        };
+        assert_eq!(summary.exit_code(), 0);
+        assert_eq!(
+            summary.format(),
+            "Processed 3 of 3 files (2 changed), 0 failed"
+        );
+    }
+
+    #[test]
+    fn test_run_summary_partial_failure() {
+        let summary = RunSummary {
+            processed: 7,
+            changed: 5,
+            failed: 3,
+            quit_status: 0,
+        };
+        assert_eq!(summary.exit_code(), EXIT_PARTIAL_FAILURE);
+        assert_eq!(
+            summary.format(),
+            "Processed 7 of 10 files (5 changed), 3 failed"
+        );
+        assert_eq!(
+            summary.to_string(),
+            "3 of 10 file(s) could not be processed"
+        );
+    }
+
+    #[test]
+    fn test_run_summary_exits_with_the_quit_status() {
+        let mut summary = RunSummary {
+            processed: 2,
+            changed: 1,
+            failed: 0,
+            quit_status: 3,
+        };
+        assert_eq!(summary.exit_code(), 3);
+        // A failed file still takes precedence
+        summary.failed = 1;
+        assert_eq!(summary.exit_code(), EXIT_PARTIAL_FAILURE);
+    }
+
+    #[test]
+    fn test_run_summary_total_failure() {
+        let summary = RunSummary {
+            processed: 0,
+            changed: 0,
+            failed: 1,
+            quit_status: 0,
+        };
+        assert_eq!(summary.exit_code(), 1);
+        assert_eq!(
+            summary.format(),
+            "Processed 0 of 1 file (0 changed), 1 failed"
+        );
+        assert_eq!(
+            summary.to_string(),
+            "None of the 1 file(s) could be processed"
+        );
+    }
 }
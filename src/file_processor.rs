@@ -1,12 +1,19 @@
 use crate::command::{Address, Command, SubstitutionFlags};
-use crate::regex_error::compile_regex_with_context;
+use crate::config::CompatMode;
+use crate::disk_space::DiskSpaceInfo;
+use crate::regex_error::compile_regex_with_options;
 use anyhow::{Context, Result};
 use regex::Regex;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use tempfile::NamedTempFile;
 
 // Chunk 8: Key for tracking mixed range states per command
@@ -15,6 +22,43 @@ struct MixedRangeKey {
     command_index: usize,
 }
 
+/// A line being processed in streaming mode, as seen by group commands
+struct StreamedLine {
+    text: String,
+    changed: bool,
+    printed: Vec<String>, // What p printed, in order
+    skip: bool,
+}
+
+/// Output queued in streaming mode to follow the current line
+enum AppendedOutput {
+    /// A line of a, c or R text
+    Text(String),
+    /// The file an r command reads, copied in when the line is written
+    File(String),
+}
+
+/// What one streaming pass produced, besides the output it wrote
+struct StreamedRun {
+    changes: Vec<LineChange>,
+    printed_lines: Vec<String>,
+    exit_code: Option<i32>,
+    /// The last line written is the input's last line, as it was or edited
+    /// (see `ends_with_last_line`)
+    ends_with_last_line: bool,
+}
+
+/// Number of commands nested, at any depth, inside a group. Streaming
+/// numbers them after the top-level commands to key their range states.
+fn nested_command_count(cmd: &Command) -> usize {
+    match cmd {
+        Command::Group { commands, .. } => {
+            commands.iter().map(|c| 1 + nested_command_count(c)).sum()
+        }
+        _ => 0,
+    }
+}
+
 // Chunk 8: State for mixed ranges
 #[derive(Clone, PartialEq)]
 enum MixedRangeState {
@@ -46,30 +90,80 @@ enum PatternRangeState {
 
 /// Iterator for input lines with lookahead support
 /// Required for n and N commands that need to read ahead
-#[derive(Clone)]
 struct LineIterator {
     lines: Vec<String>,
     current: usize,
+    /// A spilled run's input (see `FileProcessor::run_spilled`), read from
+    /// disk as it's needed instead of held in `lines`
+    spilled: Option<SpilledInput>,
+}
+
+/// Lines read from a file one ahead of the script, so `$` is known
+struct SpilledInput {
+    lines: std::io::Lines<BufReader<File>>,
+    next: Option<String>,
+    /// Lines handed out since the end of the last cycle
+    read: Vec<String>,
+    /// Read error that cut the input short
+    error: Option<std::io::Error>,
+}
+
+impl SpilledInput {
+    /// Hand out the line read ahead and read the one after it
+    fn advance(&mut self) -> Option<String> {
+        let line = self.next.take();
+        self.next = match self.lines.next() {
+            Some(Ok(next)) => Some(next),
+            Some(Err(err)) => {
+                self.error.get_or_insert(err);
+                None
+            }
+            None => None,
+        };
+        if let Some(line) = &line {
+            self.read.push(line.clone());
+        }
+        line
+    }
 }
 
 impl LineIterator {
     fn new(lines: Vec<String>) -> Self {
-        Self { lines, current: 0 }
+        Self {
+            lines,
+            current: 0,
+            spilled: None,
+        }
+    }
+
+    /// Read lines from `reader` as the script asks for them
+    fn from_reader(reader: BufReader<File>) -> Self {
+        let mut input = SpilledInput {
+            lines: reader.lines(),
+            next: None,
+            read: Vec::new(),
+            error: None,
+        };
+        input.advance();
+        Self {
+            lines: Vec::new(),
+            current: 0,
+            spilled: Some(input),
+        }
     }
 
     /// Get current line for cycle (advances iterator)
     fn current_line(&mut self) -> Option<String> {
-        if self.current < self.lines.len() {
-            let line = self.lines[self.current].clone();
-            self.current += 1;
-            Some(line)
-        } else {
-            None
-        }
+        self.read_next()
     }
 
     /// Read next line (for n/N commands) without advancing outer loop
     fn read_next(&mut self) -> Option<String> {
+        if let Some(input) = &mut self.spilled {
+            let line = input.advance();
+            self.current += usize::from(line.is_some());
+            return line;
+        }
         if self.current < self.lines.len() {
             let line = self.lines[self.current].clone();
             self.current += 1;
@@ -80,9 +174,24 @@ impl LineIterator {
     }
 
     /// Check if at EOF
-    #[allow(dead_code)] // Kept for potential future use
     fn is_eof(&self) -> bool {
-        self.current >= self.lines.len()
+        match &self.spilled {
+            Some(input) => input.next.is_none(),
+            None => self.current >= self.lines.len(),
+        }
+    }
+
+    /// Spilled input: the lines read since the last call
+    fn take_read(&mut self) -> Vec<String> {
+        self.spilled
+            .as_mut()
+            .map(|input| std::mem::take(&mut input.read))
+            .unwrap_or_default()
+    }
+
+    /// Spilled input: the error that ended it early, if one did
+    fn take_error(&mut self) -> Option<std::io::Error> {
+        self.spilled.as_mut().and_then(|input| input.error.take())
     }
 
     /// Peek at current position without consuming
@@ -92,6 +201,848 @@ impl LineIterator {
     }
 }
 
+/// Map an input file to its `--out-dir` location, keeping its path relative
+/// to the current directory. Paths that climb out with `..` are rejected so
+/// every copy lands inside `out_dir`.
+pub fn out_dir_path(out_dir: &Path, file: &Path) -> Result<PathBuf> {
+    let cwd = std::env::current_dir().context("Failed to get current directory")?;
+    let relative = file.strip_prefix(&cwd).unwrap_or(file);
+
+    let mut target = out_dir.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => target.push(part),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            Component::ParentDir => anyhow::bail!(
+                "Cannot mirror {} into --out-dir: path leaves the current directory",
+                file.display()
+            ),
+        }
+    }
+    Ok(target)
+}
+
+/// Split `files` into the ones to process and later ones naming a file
+/// already listed: the same path twice, `./file` and `file`, or a symlink
+/// to it. Files that can't be resolved are kept, to fail when read.
+pub fn unique_files(files: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut seen = HashSet::new();
+    files.iter().cloned().partition(|file| {
+        let resolved = fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file));
+        seen.insert(resolved)
+    })
+}
+
+/// The path an edit of `file_path` is written to. Edits replace the file,
+/// so a symlink becomes a regular file, as with GNU sed; with
+/// `--follow-symlinks` the file the link resolves to is edited instead.
+pub fn write_target(file_path: &Path, follow_symlinks: bool) -> Result<PathBuf> {
+    if !follow_symlinks {
+        return Ok(file_path.to_path_buf());
+    }
+    fs::canonicalize(file_path)
+        .with_context(|| format!("Failed to resolve symlink: {}", file_path.display()))
+}
+
+/// Copy an input file to its `--out-dir` location (creating directories as
+/// needed) and return the copy's path; edits are then applied to the copy
+pub fn copy_to_out_dir(out_dir: &Path, file: &Path) -> Result<PathBuf> {
+    let target = out_dir_path(out_dir, file)?;
+
+    if target.exists() && fs::canonicalize(&target)? == fs::canonicalize(file)? {
+        anyhow::bail!(
+            "--out-dir would overwrite the source file {}",
+            file.display()
+        );
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    fs::copy(file, &target)
+        .with_context(|| format!("Failed to copy {} to {}", file.display(), target.display()))?;
+    Ok(target)
+}
+
+/// Compiled regexes keyed by (pattern, case_insensitive, multiline), so a
+/// pattern is compiled once per run instead of once per line or command
+#[derive(Default)]
+struct RegexCache {
+    regexes: RefCell<HashMap<(String, bool, bool), Regex>>,
+    // --ascii-case: case-insensitive patterns only fold ASCII letters
+    ascii_case: bool,
+}
+
+impl RegexCache {
+    /// Compiled regex for `pattern`, compiling and validating it on first use
+    fn get(
+        &self,
+        pattern: &str,
+        flavor: crate::cli::RegexFlavor,
+        case_insensitive: bool,
+        multiline: bool,
+    ) -> Result<Regex> {
+        let key = (pattern.to_string(), case_insensitive, multiline);
+        if let Some(re) = self.regexes.borrow().get(&key) {
+            return Ok(re.clone());
+        }
+        let re = if case_insensitive && self.ascii_case {
+            compile_regex_with_options(&ascii_case_insensitive(pattern), flavor, false, multiline)?
+        } else {
+            compile_regex_with_options(pattern, flavor, case_insensitive, multiline)?
+        };
+        self.regexes.borrow_mut().insert(key, re.clone());
+        Ok(re)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.regexes.borrow().len()
+    }
+}
+
+/// Rewrite `pattern` to match ASCII letters in either case and everything
+/// else exactly (--ascii-case). The regex crate's case-insensitive mode
+/// folds by Unicode rules, so `k` would also match the Kelvin sign;
+/// `[kK]` doesn't.
+fn ascii_case_insensitive(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut out = String::with_capacity(pattern.len() * 2);
+    let mut i = 0;
+
+    // Copy chars[i..] up to and including the first of `ends`
+    let copy_through = |out: &mut String, i: &mut usize, ends: &[char]| {
+        while *i < chars.len() {
+            let c = chars[*i];
+            out.push(c);
+            *i += 1;
+            if ends.contains(&c) {
+                break;
+            }
+        }
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' => {
+                // Escapes stay as they are, with any {...} argument
+                out.push(c);
+                i += 1;
+                if i < chars.len() {
+                    let escaped = chars[i];
+                    out.push(escaped);
+                    i += 1;
+                    if matches!(escaped, 'p' | 'P' | 'x' | 'u' | 'U') && chars.get(i) == Some(&'{')
+                    {
+                        copy_through(&mut out, &mut i, &['}']);
+                    } else if matches!(escaped, 'p' | 'P') && i < chars.len() {
+                        out.push(chars[i]);
+                        i += 1;
+                    }
+                }
+            }
+            // Group syntax: (?i) (?:...) (?P<name>...) (?<name>...)
+            '(' if chars.get(i + 1) == Some(&'?') => {
+                copy_through(&mut out, &mut i, &[':', ')', '>']);
+            }
+            '{' => copy_through(&mut out, &mut i, &['}']),
+            '[' => i = ascii_case_class(&chars, i, &mut out),
+            c if c.is_ascii_alphabetic() => {
+                out.push('[');
+                out.push(c.to_ascii_lowercase());
+                out.push(c.to_ascii_uppercase());
+                out.push(']');
+                i += 1;
+            }
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Copy the character class starting at `chars[start]` with the other case
+/// of each ASCII letter and letter range added; returns the index after it
+fn ascii_case_class(chars: &[char], start: usize, out: &mut String) -> usize {
+    let mut i = start + 1;
+    out.push('[');
+    if chars.get(i) == Some(&'^') {
+        out.push('^');
+        i += 1;
+    }
+    // A ] right after [ or [^ is a literal
+    let mut first = true;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ']' && !first {
+            out.push(']');
+            return i + 1;
+        }
+        first = false;
+        match c {
+            '\\' => {
+                out.push(c);
+                if let Some(&escaped) = chars.get(i + 1) {
+                    out.push(escaped);
+                }
+                i += 2;
+            }
+            // [:alpha:] and nested classes
+            '[' if chars.get(i + 1) == Some(&':') => {
+                while i < chars.len() {
+                    out.push(chars[i]);
+                    i += 1;
+                    if chars[i - 1] == ']' {
+                        break;
+                    }
+                }
+            }
+            '[' => i = ascii_case_class(chars, i, out),
+            c if c.is_ascii_alphabetic() => {
+                let range_end = match (chars.get(i + 1), chars.get(i + 2)) {
+                    (Some('-'), Some(&end)) if end != ']' => Some(end),
+                    _ => None,
+                };
+                match range_end {
+                    Some(end) => {
+                        out.extend([c, '-', end]);
+                        let same_case = (c.is_ascii_lowercase() && end.is_ascii_lowercase())
+                            || (c.is_ascii_uppercase() && end.is_ascii_uppercase());
+                        if same_case {
+                            let swap = |c: char| {
+                                if c.is_ascii_lowercase() {
+                                    c.to_ascii_uppercase()
+                                } else {
+                                    c.to_ascii_lowercase()
+                                }
+                            };
+                            out.extend([swap(c), '-', swap(end)]);
+                        }
+                        i += 3;
+                    }
+                    None => {
+                        out.push(c.to_ascii_lowercase());
+                        out.push(c.to_ascii_uppercase());
+                        i += 1;
+                    }
+                }
+            }
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    // Unterminated; let the regex crate report it
+    i
+}
+
+/// How many more substitutions may change a line (`--max-count`). Clones
+/// share the count, so a processor and the ones it delegates to draw from
+/// the same budget.
+#[derive(Clone, Debug)]
+pub struct SubstitutionBudget(Arc<AtomicUsize>);
+
+impl SubstitutionBudget {
+    pub fn new(max_count: usize) -> Self {
+        Self(Arc::new(AtomicUsize::new(max_count)))
+    }
+
+    /// Substitutions left
+    pub fn remaining(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Use up one substitution; false once the budget is spent
+    fn take(&self) -> bool {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+    }
+}
+
+/// When a run has to give up (`--timeout`). Checked as each line is read
+/// and each time a branch or D sends the script back, so a script stuck
+/// in a `b` loop stops too.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    at: Instant,
+    secs: u64,
+}
+
+impl Deadline {
+    /// `secs` seconds from now
+    pub fn after(secs: u64) -> Self {
+        Self {
+            at: Instant::now() + Duration::from_secs(secs),
+            secs,
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if Instant::now() >= self.at {
+            return Err(TimedOut { secs: self.secs }.into());
+        }
+        Ok(())
+    }
+}
+
+/// Fail if the run is past its deadline, if it has one
+fn check_deadline(deadline: &Option<Deadline>) -> Result<()> {
+    deadline.as_ref().map_or(Ok(()), Deadline::check)
+}
+
+/// Error of a run stopped by its `--timeout`
+#[derive(Debug)]
+pub struct TimedOut {
+    pub secs: u64,
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Timed out after {} s (--timeout); the script may loop forever",
+            self.secs
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// How far streaming a file has got, as passed to a `--progress` callback
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamProgress {
+    /// Bytes of the file read so far, counting one newline per line
+    pub bytes_read: u64,
+    /// Size of the file when streaming started
+    pub total_bytes: u64,
+    /// Lines read so far
+    pub lines: usize,
+    /// Time since streaming started
+    pub elapsed: Duration,
+    /// Whether this is the last report for the file
+    pub done: bool,
+}
+
+impl StreamProgress {
+    /// Lines read per second so far
+    pub fn lines_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.lines as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Called with each progress report while a file streams
+pub type ProgressCallback = Arc<dyn Fn(&StreamProgress) + Send + Sync>;
+
+/// A file's diff and its new lines
+pub type FileOutput = (FileDiff, Vec<String>);
+
+/// Reports streaming progress to a callback, at most every
+/// `PROGRESS_INTERVAL` and once more when it's dropped
+struct ProgressMeter {
+    callback: ProgressCallback,
+    progress: StreamProgress,
+    started: Instant,
+    last_report: Instant,
+}
+
+/// How often a streaming file reports its progress
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+impl ProgressMeter {
+    fn new(callback: ProgressCallback, total_bytes: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            callback,
+            progress: StreamProgress {
+                bytes_read: 0,
+                total_bytes,
+                lines: 0,
+                elapsed: Duration::ZERO,
+                done: false,
+            },
+            started: now,
+            last_report: now,
+        }
+    }
+
+    /// Count a line of `len` bytes, reporting if it's time to
+    fn line_read(&mut self, len: usize) {
+        self.progress.lines += 1;
+        self.progress.bytes_read =
+            (self.progress.bytes_read + len as u64 + 1).min(self.progress.total_bytes);
+        // Reading the clock on every line would cost more than the report
+        if self.progress.lines.is_multiple_of(1024)
+            && self.last_report.elapsed() >= PROGRESS_INTERVAL
+        {
+            self.last_report = Instant::now();
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        self.progress.elapsed = self.started.elapsed();
+        (self.callback)(&self.progress);
+    }
+}
+
+/// The last report goes out however streaming ends: at the end of the
+/// file, at a q, on an error, or when the in-memory engine takes over
+impl Drop for ProgressMeter {
+    fn drop(&mut self) {
+        self.progress.done = true;
+        self.report();
+    }
+}
+
+/// Whether a substitution that changes a line may go ahead
+fn substitution_allowed(budget: &Option<SubstitutionBudget>) -> bool {
+    budget.as_ref().is_none_or(SubstitutionBudget::take)
+}
+
+/// Whether an open `addr,~N` range ends on `line_num`: a multiple of N, or
+/// any line for ~0. As with other ends, the start line itself doesn't count.
+fn ends_at_multiple(line_num: usize, n: usize) -> bool {
+    n == 0 || line_num.is_multiple_of(n)
+}
+
+/// Run `f` on every item using up to `jobs` threads (`--jobs`) and return
+/// the results in input order, whichever thread finished first
+pub fn map_in_parallel<I, T, F>(items: &[I], jobs: usize, f: F) -> Vec<T>
+where
+    I: Sync,
+    T: Send,
+    F: Fn(&I) -> T + Sync,
+{
+    if jobs <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, T)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.min(items.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            break;
+                        };
+                        done.push((index, f(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Copies of the input held at once when processing in memory: the file's
+/// text, the input lines, the output lines and the diff
+const IN_MEMORY_COPIES: u64 = 4;
+
+/// Read a whole file as text, naming the first invalid byte if it isn't UTF-8
+fn read_text_file(path: &Path) -> Result<String> {
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    String::from_utf8(bytes).map_err(|e| {
+        anyhow::anyhow!(
+            "{} is not valid UTF-8 (invalid byte at offset {})",
+            path.display(),
+            e.utf8_error().valid_up_to()
+        )
+    })
+}
+
+/// Join an input's lines into the single record --slurp processes. An empty
+/// input stays empty rather than becoming one empty line.
+pub fn slurp_lines(lines: Vec<String>) -> Vec<String> {
+    if lines.is_empty() {
+        lines
+    } else {
+        vec![lines.join("\n")]
+    }
+}
+
+/// Split slurped records back into the lines written to the file
+fn unslurp_lines(records: Vec<String>) -> Vec<String> {
+    records
+        .iter()
+        .flat_map(|record| record.split('\n'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Blank lines before, between and after the paragraphs of an input
+/// (--paragraph), so they can be written back as they were
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParagraphLayout {
+    leading: usize,
+    between: Vec<usize>,
+    trailing: usize,
+}
+
+/// Group an input's lines into paragraphs, one record per run of non-empty
+/// lines, remembering the blank lines that separated them
+pub fn split_paragraphs(lines: Vec<String>) -> (Vec<String>, ParagraphLayout) {
+    let mut records = Vec::new();
+    let mut layout = ParagraphLayout::default();
+    let mut current: Vec<String> = Vec::new();
+    let mut blanks = 0;
+
+    for line in lines {
+        if line.is_empty() {
+            if !current.is_empty() {
+                records.push(std::mem::take(&mut current).join("\n"));
+            }
+            blanks += 1;
+            continue;
+        }
+        if current.is_empty() {
+            if records.is_empty() {
+                layout.leading = blanks;
+            } else {
+                layout.between.push(blanks);
+            }
+            blanks = 0;
+        }
+        current.push(line);
+    }
+    if !current.is_empty() {
+        records.push(current.join("\n"));
+    }
+    if records.is_empty() {
+        layout.leading = blanks;
+    } else {
+        layout.trailing = blanks;
+    }
+
+    (records, layout)
+}
+
+/// Split paragraph records back into lines. The Nth gap gets the blank lines
+/// of the Nth gap in the input, or one if the script made more paragraphs.
+pub fn join_paragraphs(records: Vec<String>, layout: &ParagraphLayout) -> Vec<String> {
+    let mut lines = vec![String::new(); layout.leading];
+    for (i, record) in records.iter().enumerate() {
+        if i > 0 {
+            let blanks = layout.between.get(i - 1).copied().unwrap_or(1);
+            lines.extend(std::iter::repeat_n(String::new(), blanks));
+        }
+        lines.extend(record.split('\n').map(str::to_string));
+    }
+    if !records.is_empty() {
+        lines.extend(std::iter::repeat_n(String::new(), layout.trailing));
+    }
+    lines
+}
+
+/// Whether `commands` use D, including inside groups
+fn uses_delete_first_line(commands: &[Command]) -> bool {
+    commands.iter().any(|cmd| match cmd {
+        Command::DeleteFirstLine { .. } => true,
+        Command::Group { commands, .. } => uses_delete_first_line(commands),
+        _ => false,
+    })
+}
+
+/// Whether the file's first line ends in CRLF. Output uses the first line's
+/// terminator throughout, so CRLF files stay CRLF.
+fn first_line_is_crlf(path: &Path) -> Result<bool> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let mut first_line = Vec::new();
+    BufReader::new(file)
+        .read_until(b'\n', &mut first_line)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    Ok(first_line.ends_with(b"\r\n"))
+}
+
+/// Writer that turns each `\n` into `\r\n` when `crlf` is set
+struct LineEndingWriter<W: Write> {
+    inner: W,
+    crlf: bool,
+}
+
+impl<W: Write> Write for LineEndingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.crlf {
+            return self.inner.write(buf);
+        }
+        let mut chunks = buf.split(|&b| b == b'\n').peekable();
+        while let Some(chunk) = chunks.next() {
+            self.inner.write_all(chunk)?;
+            if chunks.peek().is_some() {
+                self.inner.write_all(b"\r\n")?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether the file's last byte is a newline (false for an empty file)
+fn ends_with_newline(path: &Path) -> Result<bool> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+    let mut last = [0u8; 1];
+    file.seek(SeekFrom::End(-1))
+        .and_then(|_| file.read_exact(&mut last))
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    Ok(last[0] == b'\n')
+}
+
+/// Run `command` with the system shell and return its stdout, minus one
+/// trailing newline (GNU sed's s///e and `e` semantics)
+fn run_shell_command(command: &str) -> Result<String> {
+    #[cfg(unix)]
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output();
+    #[cfg(not(unix))]
+    let output = std::process::Command::new("cmd")
+        .arg("/C")
+        .arg(command)
+        .output();
+
+    let output = output.with_context(|| format!("Failed to execute command: {}", command))?;
+    let mut stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if stdout.ends_with('\n') {
+        stdout.pop();
+    }
+    Ok(stdout)
+}
+
+/// GNU sed case-conversion operators in a replacement
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CaseOp {
+    /// \U - uppercase until \E or \L
+    Upper,
+    /// \L - lowercase until \E or \U
+    Lower,
+    /// \u - uppercase the next character
+    UpperNext,
+    /// \l - lowercase the next character
+    LowerNext,
+    /// \E - stop case conversion
+    End,
+}
+
+/// Check whether the text after a `\u` is a `\uHHHH` Unicode escape
+fn is_unicode_escape(rest: &str) -> bool {
+    let hex: Vec<char> = rest.chars().take(4).collect();
+    hex.len() == 4 && hex.iter().all(|c| c.is_ascii_hexdigit())
+}
+
+/// A replacement template split at case operators; text parts keep their
+/// `$n` references for `Captures::expand`
+#[derive(Debug, Clone, PartialEq)]
+enum ReplacementPart {
+    Text(String),
+    Case(CaseOp),
+}
+
+/// Split a replacement at `\U \L \u \l \E`. Returns None when there are no
+/// case operators, so the template can go to the regex crate unchanged.
+/// `\u` followed by four hex digits is a Unicode escape, not a case operator.
+fn parse_case_conversions(replacement: &str) -> Option<Vec<ReplacementPart>> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = replacement.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        let op = match chars.peek().map(|&(_, next)| next) {
+            Some('U') => Some(CaseOp::Upper),
+            Some('L') => Some(CaseOp::Lower),
+            Some('E') => Some(CaseOp::End),
+            Some('l') => Some(CaseOp::LowerNext),
+            Some('u') if !is_unicode_escape(&replacement[i + 2..]) => Some(CaseOp::UpperNext),
+            _ => None,
+        };
+        match op {
+            Some(op) => {
+                chars.next();
+                if !text.is_empty() {
+                    parts.push(ReplacementPart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(ReplacementPart::Case(op));
+            }
+            None => {
+                // Keep other escapes (including \\) intact for later stages
+                text.push(c);
+                if let Some((_, next)) = chars.next() {
+                    text.push(next);
+                }
+            }
+        }
+    }
+
+    if parts.is_empty() {
+        return None;
+    }
+    if !text.is_empty() {
+        parts.push(ReplacementPart::Text(text));
+    }
+    Some(parts)
+}
+
+/// Expand one match: references are expanded first, then case operators are
+/// applied to the resulting text
+fn expand_case_replacement(caps: &regex::Captures, parts: &[ReplacementPart]) -> String {
+    let mut result = String::new();
+    // Some(true) = uppercase, Some(false) = lowercase
+    let mut mode: Option<bool> = None;
+    let mut next_char: Option<bool> = None;
+
+    for part in parts {
+        match part {
+            ReplacementPart::Case(CaseOp::Upper) => mode = Some(true),
+            ReplacementPart::Case(CaseOp::Lower) => mode = Some(false),
+            ReplacementPart::Case(CaseOp::UpperNext) => next_char = Some(true),
+            ReplacementPart::Case(CaseOp::LowerNext) => next_char = Some(false),
+            ReplacementPart::Case(CaseOp::End) => {
+                mode = None;
+                next_char = None;
+            }
+            ReplacementPart::Text(template) => {
+                let mut expanded = String::new();
+                caps.expand(template, &mut expanded);
+                for c in expanded.chars() {
+                    match next_char.take().or(mode) {
+                        Some(true) => result.extend(c.to_uppercase()),
+                        Some(false) => result.extend(c.to_lowercase()),
+                        None => result.push(c),
+                    }
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Replace the first `limit` matches (0 = all) using a sed replacement
+/// template, honoring case-conversion operators
+fn substitute(re: &Regex, haystack: &str, replacement: &str, limit: usize) -> String {
+    match parse_case_conversions(replacement) {
+        None => re.replacen(haystack, limit, replacement).into_owned(),
+        Some(parts) => re
+            .replacen(haystack, limit, |caps: &regex::Captures| {
+                expand_case_replacement(caps, &parts)
+            })
+            .into_owned(),
+    }
+}
+
+/// Replace the `n`th match (1-based), or with `global` the `n`th and every
+/// later match (GNU `s///Ng`); None if there are fewer than `n` matches
+fn substitute_nth(
+    re: &Regex,
+    haystack: &str,
+    replacement: &str,
+    n: usize,
+    global: bool,
+) -> Option<String> {
+    let take = if global { usize::MAX } else { 1 };
+    substitute_recorded(re, haystack, replacement, n.checked_sub(1)?, take)
+        .map(|(result, _)| result)
+}
+
+/// One replacement made by a substitution: the bytes of the input the
+/// regex matched, and where the replacement text landed in the output
+#[derive(Debug, Clone, PartialEq)]
+struct Replacement {
+    matched: std::ops::Range<usize>,
+    written: std::ops::Range<usize>,
+}
+
+/// Replace up to `take` matches after skipping the first `skip`, recording
+/// each replacement; None if no match was replaced
+fn substitute_recorded(
+    re: &Regex,
+    haystack: &str,
+    replacement: &str,
+    skip: usize,
+    take: usize,
+) -> Option<(String, Vec<Replacement>)> {
+    let parts = parse_case_conversions(replacement);
+    let mut result = String::with_capacity(haystack.len());
+    let mut replacements = Vec::new();
+    let mut last_end = 0;
+
+    for caps in re.captures_iter(haystack).skip(skip).take(take) {
+        let mat = caps.get(0)?;
+        result.push_str(&haystack[last_end..mat.start()]);
+        let written_start = result.len();
+        match &parts {
+            None => caps.expand(replacement, &mut result),
+            Some(parts) => result.push_str(&expand_case_replacement(&caps, parts)),
+        }
+        replacements.push(Replacement {
+            matched: mat.range(),
+            written: written_start..result.len(),
+        });
+        last_end = mat.end();
+    }
+
+    if replacements.is_empty() {
+        return None;
+    }
+    result.push_str(&haystack[last_end..]);
+    Some((result, replacements))
+}
+
+/// Apply a substitution to one line according to its `g` and `N` flags
+fn substitute_with_flags(
+    re: &Regex,
+    haystack: &str,
+    replacement: &str,
+    flags: &SubstitutionFlags,
+) -> String {
+    match flags.nth {
+        Some(n) => substitute_nth(re, haystack, replacement, n, flags.global)
+            .unwrap_or_else(|| haystack.to_string()),
+        None => substitute(re, haystack, replacement, if flags.global { 0 } else { 1 }),
+    }
+}
+
+/// Default line wrap width for the `l` command (GNU sed's `lcmd_out_line_len`)
+const DEFAULT_LIST_WIDTH: usize = 70;
+
 /// Result of applying a command within a cycle
 /// Matches GNU sed's control flow from execute.c
 #[derive(Debug, Clone, PartialEq)]
@@ -136,82 +1087,653 @@ struct CycleState {
     /// File read output (r, R commands) - printed AFTER pattern space (Phase 5)
     file_reads: Vec<String>,
 
-    /// Stdout output for = and F commands (Phase 5)
+    /// Stdout output for =, F and i commands (Phase 5)
     /// These are printed to stdout immediately, not to the output buffer
     stdout_outputs: Vec<String>,
 
-    /// Current filename (for F command - Phase 5)
-    current_filename: String,
+    /// Text of a commands, printed after the pattern space
+    appended: Vec<String>,
+
+    /// Current filename (for F command - Phase 5)
+    current_filename: String,
+
+    /// Input line iterator for n/N commands
+    line_iter: LineIterator,
+
+    /// Range states (for 1,3, /start/,/end/ and 0,/end/ ranges), keyed by
+    /// the range's addresses
+    range_states: HashMap<(Address, Address), RangeState>,
+
+    /// Substitution flag for t/T commands (Phase 5)
+    /// Set to true when any substitution succeeds, reset at start of each cycle
+    substitution_made: bool,
+
+    /// Text of a c command, printed in place of the deleted pattern space
+    change_text: Option<String>,
+
+    /// Byte ranges `(start, len)` of the pattern space written by s
+    /// commands this cycle, for highlighting in the diff
+    spans: Vec<(usize, usize)>,
+}
+
+/// Progress of the cycle engine through one range address
+#[derive(Debug, Clone, Copy, Default)]
+struct RangeState {
+    /// The start matched and the end hasn't yet
+    open: bool,
+    /// addr,+N: the line the range ends on
+    end_line: Option<usize>,
+    /// The line the range was last stepped on, and where that line was
+    /// (see `range_position`)
+    checked: Option<(usize, Option<bool>)>,
+}
+
+impl CycleState {
+    fn new(hold_space: String, lines: Vec<String>, filename: String) -> Self {
+        Self {
+            pattern_space: String::new(),
+            hold_space,
+            line_num: 0,
+            deleted: false,
+            side_effects: Vec::new(),
+            file_reads: Vec::new(),     // Phase 5: Initialize file reads
+            stdout_outputs: Vec::new(), // Phase 5: Initialize stdout outputs
+            appended: Vec::new(),
+            current_filename: filename, // Phase 5: Initialize filename
+            line_iter: LineIterator::new(lines),
+            range_states: HashMap::new(),
+            substitution_made: false, // Phase 5: Initialize substitution flag
+            change_text: None,
+            spans: Vec::new(),
+        }
+    }
+
+    /// Carry the recorded spans over `replacements` just made in the pattern
+    /// space and add the new ones. A span a match cut into grows to cover
+    /// the match's replacement.
+    fn record_replacements(&mut self, replacements: &[Replacement]) {
+        if replacements.is_empty() {
+            return;
+        }
+        // Move `pos` by the change in length of the replacements `before` it
+        let shift = |pos: usize, before: &dyn Fn(&Replacement) -> bool| -> usize {
+            replacements
+                .iter()
+                .filter(|r| before(r))
+                .fold(pos as isize, |pos, r| {
+                    pos + r.written.len() as isize - r.matched.len() as isize
+                }) as usize
+        };
+        let map_start = |pos: usize| match replacements.iter().find(|r| r.matched.contains(&pos)) {
+            Some(r) => r.written.start,
+            None => shift(pos, &|r| r.matched.end <= pos),
+        };
+        let map_end = |pos: usize| match replacements
+            .iter()
+            .find(|r| r.matched.start < pos && pos <= r.matched.end)
+        {
+            Some(r) => r.written.end,
+            None => shift(pos, &|r| r.matched.end < pos),
+        };
+
+        let mut ranges: Vec<(usize, usize)> = self
+            .spans
+            .iter()
+            .map(|&(start, len)| (map_start(start), map_end(start + len)))
+            .chain(
+                replacements
+                    .iter()
+                    .map(|r| (r.written.start, r.written.end)),
+            )
+            .filter(|(start, end)| start < end)
+            .collect();
+        ranges.sort_unstable();
+
+        // Merge the ranges that overlap
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start < last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        self.spans = merged
+            .into_iter()
+            .map(|(start, end)| (start, end - start))
+            .collect();
+    }
+}
+
+// ============================================================================
+// END CYCLE-BASED ARCHITECTURE
+// ============================================================================
+
+/// One command execution in the cycle engine, written as a JSON line by
+/// `--dump-cycle-trace`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CycleTraceRecord {
+    pub line_number: usize,
+    pub command_index: usize,
+    pub command: Command,
+    pub result: String,
+    pub pattern_space_before: String,
+    pub pattern_space_after: String,
+    pub hold_space_before: String,
+    pub hold_space_after: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeType {
+    Unchanged, // Line not modified
+    Modified,  // Line content changed
+    Added,     // New line inserted
+    Deleted,   // Line removed
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineChange {
+    pub line_number: usize,
+    pub change_type: ChangeType,
+    pub content: String,
+    pub old_content: Option<String>, // For Modified type
+    /// Byte ranges `(start, len)` of `content` a substitution wrote, when known
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spans: Vec<(usize, usize)>,
+}
+
+#[derive(Debug)]
+pub struct FileDiff {
+    pub file_path: String,
+    pub changes: Vec<LineChange>,
+    pub all_lines: Vec<(usize, String, ChangeType)>, // (line_number, content, change_type)
+    pub printed_lines: Vec<String>,                  // Lines from print commands
+    pub is_streaming: bool, // True if processed in streaming mode (all_lines may be empty)
+    pub exit_code: Option<i32>, // Exit status a q/Q command asked for (5q3), if one ran
+}
+
+impl FileDiff {
+    /// Diff two versions of a file's lines (not streaming, no printed lines)
+    pub fn between(file_path: &str, original: &[&str], modified: &[String]) -> FileDiff {
+        let ops = diff_lines(original, modified);
+
+        // Deleted lines are numbered as in the original, the rest as in
+        // the new version
+        let all_lines: Vec<(usize, String, ChangeType)> = ops
+            .iter()
+            .map(|op| match *op {
+                DiffOp::Unchanged { new, .. } => {
+                    (new + 1, modified[new].clone(), ChangeType::Unchanged)
+                }
+                DiffOp::Modified { new, .. } => {
+                    (new + 1, modified[new].clone(), ChangeType::Modified)
+                }
+                DiffOp::Deleted { old } => {
+                    (old + 1, original[old].to_string(), ChangeType::Deleted)
+                }
+                DiffOp::Added { new } => (new + 1, modified[new].clone(), ChangeType::Added),
+            })
+            .collect();
+
+        // Collect only changed lines for summary
+        let changes: Vec<LineChange> = ops
+            .iter()
+            .zip(&all_lines)
+            .filter(|(_, (_, _, change_type))| *change_type != ChangeType::Unchanged)
+            .map(|(op, (line_num, content, change_type))| {
+                let old_content = match *op {
+                    DiffOp::Modified { old, .. } => Some(original[old].to_string()),
+                    _ => None,
+                };
+
+                LineChange {
+                    line_number: *line_num,
+                    change_type: change_type.clone(),
+                    content: content.clone(),
+                    old_content,
+                    spans: Vec::new(),
+                }
+            })
+            .collect();
+
+        FileDiff {
+            file_path: file_path.to_string(),
+            changes,
+            all_lines,
+            printed_lines: Vec::new(),
+            is_streaming: false, // In-memory mode
+            exit_code: None,
+        }
+    }
+
+    /// Mark the bytes substitutions wrote in modified lines, given by
+    /// index into the modified lines
+    fn attach_spans(&mut self, spans: &HashMap<usize, Vec<(usize, usize)>>) {
+        for change in &mut self.changes {
+            if change.change_type != ChangeType::Modified {
+                continue;
+            }
+            if let Some(line_spans) = spans.get(&(change.line_number - 1))
+                && line_spans
+                    .iter()
+                    .all(|&(start, len)| change.content.get(start..start + len).is_some())
+            {
+                change.spans = line_spans.clone();
+            }
+        }
+    }
+
+    /// Whether executing the script would touch this file or print from it.
+    /// Streaming diffs also keep unchanged context lines in `changes`.
+    pub fn has_changes(&self) -> bool {
+        self.changes
+            .iter()
+            .any(|c| c.change_type != ChangeType::Unchanged)
+            || !self.printed_lines.is_empty()
+    }
+
+    /// Hunks of the diff: runs of consecutive changed entries in
+    /// `all_lines`, as index ranges (empty for streaming diffs)
+    pub fn hunks(&self) -> Vec<std::ops::Range<usize>> {
+        let mut hunks: Vec<std::ops::Range<usize>> = Vec::new();
+        for (i, (_, _, change_type)) in self.all_lines.iter().enumerate() {
+            if *change_type == ChangeType::Unchanged {
+                continue;
+            }
+            match hunks.last_mut() {
+                Some(hunk) if hunk.end == i => hunk.end = i + 1,
+                _ => hunks.push(i..i + 1),
+            }
+        }
+        hunks
+    }
+
+    /// The file's lines with only the `accepted` hunks (indices into
+    /// `hunks()`) applied to `original`
+    fn lines_with_hunks(&self, original: &[&str], accepted: &[usize]) -> Vec<String> {
+        let mut accepted_entries = vec![false; self.all_lines.len()];
+        for (i, hunk) in self.hunks().into_iter().enumerate() {
+            if accepted.contains(&i) {
+                accepted_entries[hunk].fill(true);
+            }
+        }
+
+        let mut lines = Vec::with_capacity(original.len());
+        // Index of the entry's line in `original` (Added lines have none)
+        let mut old = 0;
+        for ((_, content, change_type), accepted) in self.all_lines.iter().zip(accepted_entries) {
+            match change_type {
+                ChangeType::Unchanged => lines.push(content.clone()),
+                ChangeType::Modified if accepted => lines.push(content.clone()),
+                ChangeType::Modified => lines.push(original[old].to_string()),
+                ChangeType::Added if accepted => lines.push(content.clone()),
+                ChangeType::Deleted if !accepted => lines.push(content.clone()),
+                ChangeType::Added | ChangeType::Deleted => {}
+            }
+            if *change_type != ChangeType::Added {
+                old += 1;
+            }
+        }
+        lines
+    }
+}
+
+/// One entry of a line diff, by index into the original and new lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffOp {
+    Unchanged {
+        old: usize,
+        new: usize,
+    },
+    /// A changed line: one removed and one added in the same place
+    Modified {
+        old: usize,
+        new: usize,
+    },
+    Deleted {
+        old: usize,
+    },
+    Added {
+        new: usize,
+    },
+}
+
+/// Most edits (removed plus added lines) the Myers search looks for in the
+/// section both versions don't start and end with; past it, lines are
+/// paired by position, so a file whose every line changed stays fast. The
+/// search keeps one frontier per edit, so this also bounds its memory.
+const MAX_DIFF_EDITS: usize = 2_000;
+
+/// Diff two versions of a file's lines into a shortest edit script, so an
+/// inserted or deleted line is one Added or Deleted entry rather than
+/// shifting every line after it. Within a run of changes, removed and added
+/// lines are paired up as Modified.
+fn diff_lines(original: &[&str], modified: &[String]) -> Vec<DiffOp> {
+    let prefix = original
+        .iter()
+        .zip(modified)
+        .take_while(|(a, b)| **a == b.as_str())
+        .count();
+    let suffix = original[prefix..]
+        .iter()
+        .rev()
+        .zip(modified[prefix..].iter().rev())
+        .take_while(|(a, b)| **a == b.as_str())
+        .count();
+    let (old_end, new_end) = (original.len() - suffix, modified.len() - suffix);
+
+    let mut ops: Vec<DiffOp> = (0..prefix)
+        .map(|i| DiffOp::Unchanged { old: i, new: i })
+        .collect();
+
+    let (old, new) = (&original[prefix..old_end], &modified[prefix..new_end]);
+    let middle = subsequence_ops(old, new)
+        .or_else(|| myers_ops(old, new, MAX_DIFF_EDITS).map(pair_changes))
+        .unwrap_or_else(|| positional_ops(old, new));
+    ops.extend(middle.into_iter().map(|op| match op {
+        DiffOp::Unchanged { old, new } => DiffOp::Unchanged {
+            old: old + prefix,
+            new: new + prefix,
+        },
+        DiffOp::Modified { old, new } => DiffOp::Modified {
+            old: old + prefix,
+            new: new + prefix,
+        },
+        DiffOp::Deleted { old } => DiffOp::Deleted { old: old + prefix },
+        DiffOp::Added { new } => DiffOp::Added { new: new + prefix },
+    }));
+
+    ops.extend((0..suffix).map(|i| DiffOp::Unchanged {
+        old: old_end + i,
+        new: new_end + i,
+    }));
+    ops
+}
+
+/// Pure inserts or pure deletes, found in one pass: when the shorter side
+/// is a subsequence of the longer one, every other line was added (or
+/// removed), however many there are
+fn subsequence_ops(original: &[&str], modified: &[String]) -> Option<Vec<DiffOp>> {
+    let inserting = original.len() <= modified.len();
+    let (short, long) = if inserting {
+        (original.len(), modified.len())
+    } else {
+        (modified.len(), original.len())
+    };
+    let same = |s: usize, l: usize| {
+        if inserting {
+            original[s] == modified[l].as_str()
+        } else {
+            original[l] == modified[s].as_str()
+        }
+    };
+
+    let mut ops = Vec::with_capacity(long);
+    let mut s = 0;
+    for l in 0..long {
+        if s < short && same(s, l) {
+            ops.push(if inserting {
+                DiffOp::Unchanged { old: s, new: l }
+            } else {
+                DiffOp::Unchanged { old: l, new: s }
+            });
+            s += 1;
+        } else if inserting {
+            ops.push(DiffOp::Added { new: l });
+        } else {
+            ops.push(DiffOp::Deleted { old: l });
+        }
+    }
+    (s == short).then_some(ops)
+}
+
+/// Unchanged, Deleted and Added entries along a shortest edit script
+/// (Myers' O((N+M)·D) greedy search), or None if it takes more than
+/// `max_edits` edits
+fn myers_ops(original: &[&str], modified: &[String], max_edits: usize) -> Option<Vec<DiffOp>> {
+    let (n, m) = (original.len() as isize, modified.len() as isize);
+    let limit = (original.len() + modified.len()).min(max_edits) as isize;
+    // v[k + offset]: furthest x reached on diagonal k = x - y
+    let offset = limit + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // trace[d]: the frontier before edit d, for diagonals -(d + 1)..=d + 1
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: {
+        for d in 0..=limit {
+            trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+            for k in (-d..=d).step_by(2) {
+                let at = |k: isize| v[(k + offset) as usize];
+                let mut x = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+                    at(k + 1)
+                } else {
+                    at(k - 1) + 1
+                };
+                let mut y = x - k;
+                while x < n && y < m && original[x as usize] == modified[y as usize].as_str() {
+                    x += 1;
+                    y += 1;
+                }
+                v[(k + offset) as usize] = x;
+                if x >= n && y >= m {
+                    break 'search;
+                }
+            }
+        }
+        return None;
+    }
 
-    /// Input line iterator for n/N commands
-    line_iter: LineIterator,
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, frontier) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| frontier[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(DiffOp::Unchanged {
+                old: x as usize,
+                new: y as usize,
+            });
+        }
+        if d > 0 {
+            if x == prev_x {
+                y -= 1;
+                ops.push(DiffOp::Added { new: y as usize });
+            } else {
+                x -= 1;
+                ops.push(DiffOp::Deleted { old: x as usize });
+            }
+        }
+    }
+    ops.reverse();
+    Some(ops)
+}
 
-    /// Pattern range states (for /start/,/end/ ranges)
-    #[allow(dead_code)] // Reserved for future use
-    pattern_range_states: HashMap<(String, String), PatternRangeState>,
+/// Line i against line i, for changes too large to search
+fn positional_ops(original: &[&str], modified: &[String]) -> Vec<DiffOp> {
+    (0..original.len().max(modified.len()))
+        .map(|i| match (original.get(i), modified.get(i)) {
+            (Some(a), Some(b)) if *a == b.as_str() => DiffOp::Unchanged { old: i, new: i },
+            (Some(_), Some(_)) => DiffOp::Modified { old: i, new: i },
+            (Some(_), None) => DiffOp::Deleted { old: i },
+            _ => DiffOp::Added { new: i },
+        })
+        .collect()
+}
 
-    /// Mixed range states for tracking complex ranges (Chunk 8)
-    #[allow(dead_code)] // Reserved for future use
-    mixed_range_states: HashMap<MixedRangeKey, MixedRangeState>,
+/// Pair the removed and added lines of each run of changes as Modified,
+/// in order; the rest of the longer side stays Deleted or Added
+fn pair_changes(ops: Vec<DiffOp>) -> Vec<DiffOp> {
+    fn flush(deleted: &mut Vec<usize>, added: &mut Vec<usize>, out: &mut Vec<DiffOp>) {
+        let paired = deleted.len().min(added.len());
+        out.extend(
+            deleted
+                .iter()
+                .zip(added.iter())
+                .map(|(&old, &new)| DiffOp::Modified { old, new }),
+        );
+        out.extend(deleted[paired..].iter().map(|&old| DiffOp::Deleted { old }));
+        out.extend(added[paired..].iter().map(|&new| DiffOp::Added { new }));
+        deleted.clear();
+        added.clear();
+    }
 
-    /// Line number range states (for 1,3 ranges)
-    /// Maps (start_line, end_line) -> (in_range, ended)
-    /// in_range: true if we're currently inside the range
-    /// ended: true if we've passed the end of the range
-    line_range_states: HashMap<(usize, usize), (bool, bool)>,
+    let mut out = Vec::with_capacity(ops.len());
+    let (mut deleted, mut added) = (Vec::new(), Vec::new());
+    for op in ops {
+        match op {
+            DiffOp::Deleted { old } => deleted.push(old),
+            DiffOp::Added { new } => added.push(new),
+            _ => {
+                flush(&mut deleted, &mut added, &mut out);
+                out.push(op);
+            }
+        }
+    }
+    flush(&mut deleted, &mut added, &mut out);
+    out
+}
 
-    /// Substitution flag for t/T commands (Phase 5)
-    /// Set to true when any substitution succeeds, reset at start of each cycle
-    substitution_made: bool,
+/// Output of a spilled run (see `FileProcessor::run_spilled`): each
+/// cycle's lines go to a temp file when the cycle ends, diffed against the
+/// lines it read. Like a streaming diff, only changes and the unchanged
+/// lines around them are kept.
+struct SpilledOutput {
+    writer: BufWriter<LineEndingWriter<File>>,
+    /// Input and output lines so far
+    lines_read: usize,
+    lines_written: usize,
+    /// The diff, unless the run is applying changes rather than previewing
+    changes: Option<Vec<LineChange>>,
+    /// Unchanged lines since the last change, up to `context_size`
+    context: VecDeque<LineChange>,
+    /// Unchanged lines still to keep after the last change
+    context_after: usize,
+    context_size: usize,
+    /// The last line written is the last line read, as it was or edited
+    ends_with_last_line: bool,
 }
 
-impl CycleState {
-    fn new(hold_space: String, lines: Vec<String>, filename: String) -> Self {
+impl SpilledOutput {
+    fn new(writer: BufWriter<LineEndingWriter<File>>, diff: bool, context_size: usize) -> Self {
         Self {
-            pattern_space: String::new(),
-            hold_space,
-            line_num: 0,
-            deleted: false,
-            side_effects: Vec::new(),
-            file_reads: Vec::new(),     // Phase 5: Initialize file reads
-            stdout_outputs: Vec::new(), // Phase 5: Initialize stdout outputs
-            current_filename: filename, // Phase 5: Initialize filename
-            line_iter: LineIterator::new(lines),
-            pattern_range_states: HashMap::new(),
-            mixed_range_states: HashMap::new(),
-            line_range_states: HashMap::new(),
-            substitution_made: false, // Phase 5: Initialize substitution flag
+            writer,
+            lines_read: 0,
+            lines_written: 0,
+            changes: diff.then(Vec::new),
+            context: VecDeque::new(),
+            context_after: 0,
+            context_size,
+            ends_with_last_line: false,
         }
     }
-}
 
-// ============================================================================
-// END CYCLE-BASED ARCHITECTURE
-// ============================================================================
+    /// Write a cycle's output and diff it against the lines it read; spans
+    /// are keyed by index into `output`
+    fn end_cycle(
+        &mut self,
+        read: &[String],
+        output: Vec<String>,
+        spans: &HashMap<usize, Vec<(usize, usize)>>,
+    ) -> Result<()> {
+        for line in &output {
+            writeln!(self.writer, "{}", line).with_context(|| "Failed to write to temp file")?;
+        }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum ChangeType {
-    Unchanged, // Line not modified
-    Modified,  // Line content changed
-    Added,     // New line inserted
-    Deleted,   // Line removed
-}
+        let read_lines: Vec<&str> = read.iter().map(String::as_str).collect();
+        if !output.is_empty() || !read.is_empty() {
+            self.ends_with_last_line = ends_with_last_line(&read_lines, &output);
+        }
 
-#[derive(Debug, Clone)]
-pub struct LineChange {
-    pub line_number: usize,
-    pub change_type: ChangeType,
-    pub content: String,
-    pub old_content: Option<String>, // For Modified type
-}
+        if self.changes.is_some() {
+            for op in diff_lines(&read_lines, &output) {
+                let (line_number, change_type, content, old_content) = match op {
+                    DiffOp::Unchanged { new, .. } => (
+                        self.lines_written + new,
+                        ChangeType::Unchanged,
+                        &output[new],
+                        None,
+                    ),
+                    DiffOp::Modified { old, new } => (
+                        self.lines_written + new,
+                        ChangeType::Modified,
+                        &output[new],
+                        Some(read[old].clone()),
+                    ),
+                    DiffOp::Deleted { old } => {
+                        (self.lines_read + old, ChangeType::Deleted, &read[old], None)
+                    }
+                    DiffOp::Added { new } => (
+                        self.lines_written + new,
+                        ChangeType::Added,
+                        &output[new],
+                        None,
+                    ),
+                };
+                let spans = match op {
+                    DiffOp::Modified { new, .. } => spans
+                        .get(&new)
+                        .filter(|spans| {
+                            spans
+                                .iter()
+                                .all(|&(start, len)| content.get(start..start + len).is_some())
+                        })
+                        .cloned()
+                        .unwrap_or_default(),
+                    _ => Vec::new(),
+                };
+                self.record(LineChange {
+                    line_number: line_number + 1,
+                    change_type,
+                    content: content.clone(),
+                    old_content,
+                    spans,
+                });
+            }
+        }
 
-#[derive(Debug)]
-pub struct FileDiff {
-    pub file_path: String,
-    pub changes: Vec<LineChange>,
-    pub all_lines: Vec<(usize, String, ChangeType)>, // (line_number, content, change_type)
-    pub printed_lines: Vec<String>,                  // Lines from print commands
-    pub is_streaming: bool, // True if processed in streaming mode (all_lines may be empty)
+        self.lines_read += read.len();
+        self.lines_written += output.len();
+        Ok(())
+    }
+
+    /// Keep a change with the context before it, or an unchanged line if
+    /// it's context
+    fn record(&mut self, change: LineChange) {
+        let Some(changes) = &mut self.changes else {
+            return;
+        };
+        if change.change_type != ChangeType::Unchanged {
+            changes.extend(self.context.drain(..));
+            changes.push(change);
+            self.context_after = self.context_size;
+        } else if self.context_after > 0 {
+            changes.push(change);
+            self.context_after -= 1;
+        } else {
+            self.context.push_back(change);
+            if self.context.len() > self.context_size {
+                self.context.pop_front();
+            }
+        }
+    }
+
+    /// Flush the temp file and return the diff
+    fn finish(mut self) -> Result<Vec<LineChange>> {
+        self.writer
+            .flush()
+            .with_context(|| "Failed to flush temp file")?;
+        Ok(self.changes.unwrap_or_default())
+    }
 }
 
 // Legacy structure for backward compatibility
@@ -240,6 +1762,41 @@ pub struct FileProcessor {
     read_positions: HashMap<String, usize>, // Current line position for R command (filename -> line_index)
     // Regex flavor for enhanced error reporting
     regex_flavor: crate::cli::RegexFlavor,
+    // --dump-cycle-trace destination (JSON lines, one per command execution)
+    cycle_trace: Option<BufWriter<File>>,
+    // --allow-exec: permit s///e to run shell commands
+    allow_exec: bool,
+    // GNU vs POSIX behavior (config `compatibility.mode`)
+    compat_mode: CompatMode,
+    // Every regex the script uses, compiled once
+    regex_cache: RegexCache,
+    // Name printed by the F command: the input path, or "-" for stdin
+    current_filename: String,
+    // Concatenated input: (first line number, path) for each file
+    input_segments: Vec<(usize, String)>,
+    // Concatenated input: (line number, output length, printed lines
+    // length) at the end of every cycle, to split the output per file
+    cycle_ends: Option<Vec<(usize, usize, usize)>>,
+    // --max-count: substitutions still allowed to change a line
+    max_count: Option<SubstitutionBudget>,
+    // processing.max_memory_mb, in bytes: inputs estimated to need more
+    // are run from disk (see run_spilled), or refused if the script can't be
+    memory_limit: Option<u64>,
+    // Unchanged lines kept around each change in a spilled run's diff
+    context_size: usize,
+    // --timeout: when to give up on the script
+    deadline: Option<Deadline>,
+    // Where the cycle engine sends each cycle's output during a spilled run
+    spill: Option<SpilledOutput>,
+    // --slurp: each input is one record rather than one record per line
+    slurp: bool,
+    // --paragraph: each run of non-empty lines is one record
+    paragraph: bool,
+    // Bytes s commands wrote into each output line of the last cycle-engine
+    // run, keyed by output index (single-line pattern spaces only)
+    output_spans: HashMap<usize, Vec<(usize, usize)>>,
+    // Exit status of the q or Q command that ended the last run, if one did
+    exit_code: Option<i32>,
 }
 
 /// Result of applying a command in streaming mode
@@ -251,6 +1808,136 @@ enum StreamResult {
     StopProcessing, // Quit command encountered
 }
 
+/// Give `file` the permissions and, where allowed, the owner of the file
+/// `original` describes: a file written afresh would get the defaults
+fn restore_metadata(file: &File, original: &fs::Metadata) -> Result<()> {
+    // Ownership first: changing it can clear the setuid and setgid bits
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Only root may give a file away; anyone else keeps their own
+        std::os::unix::fs::fchown(file, Some(original.uid()), Some(original.gid())).ok();
+    }
+    file.set_permissions(original.permissions())
+        .with_context(|| "Failed to restore file permissions")
+}
+
+/// Whether the last of `lines` is the last of `original`, as it was or
+/// edited. Only that line keeps a missing final newline: text written
+/// after it (`$a`) or an earlier line left last (`$d`) ends in a newline.
+fn ends_with_last_line(original: &[&str], lines: &[String]) -> bool {
+    let (Some(last_old), Some(last_new)) =
+        (original.len().checked_sub(1), lines.len().checked_sub(1))
+    else {
+        return false;
+    };
+    diff_lines(original, lines).into_iter().any(|op| match op {
+        DiffOp::Unchanged { old, new } => old == last_old && new == last_new,
+        // An earlier line that N joined the last one onto
+        DiffOp::Modified { old, new } => {
+            new == last_new && (old == last_old || lines[new].ends_with(original[last_old]))
+        }
+        _ => false,
+    })
+}
+
+/// Write `lines` to `file_path`, keeping the final newline (or its
+/// absence), the CRLF line endings of `original` and the file's
+/// permissions and owner
+fn write_lines_like(file_path: &Path, original: &str, lines: &[String]) -> Result<()> {
+    let mut new_content = lines.join("\n");
+    if !lines.is_empty()
+        && (original.ends_with('\n')
+            || !ends_with_last_line(&original.lines().collect::<Vec<_>>(), lines))
+    {
+        new_content.push('\n');
+    }
+    let first_line_crlf = original
+        .split_once('\n')
+        .is_some_and(|(first, _)| first.ends_with('\r'));
+    if first_line_crlf {
+        new_content = new_content.replace('\n', "\r\n");
+    }
+    let metadata = fs::metadata(file_path).ok();
+    // Like the streaming rename, replace a symlink rather than write
+    // through it (see write_target)
+    if fs::symlink_metadata(file_path).is_ok_and(|m| m.file_type().is_symlink()) {
+        fs::remove_file(file_path)
+            .with_context(|| format!("Failed to replace symlink: {}", file_path.display()))?;
+    }
+    let mut file = File::create(file_path)
+        .with_context(|| format!("Failed to write file: {}", file_path.display()))?;
+    file.write_all(new_content.as_bytes())
+        .with_context(|| format!("Failed to write file: {}", file_path.display()))?;
+    match metadata {
+        Some(metadata) => restore_metadata(&file, &metadata),
+        None => Ok(()),
+    }
+}
+
+/// Write the text queued to follow line `line_num`, tracking it as added
+/// lines in `changes` unless it's `None` (a pipeline keeps no diff)
+fn write_appended(
+    writer: &mut impl Write,
+    appended: Vec<AppendedOutput>,
+    line_num: usize,
+    mut changes: Option<&mut Vec<LineChange>>,
+) -> Result<()> {
+    let mut write_line = |text: String| -> Result<()> {
+        writeln!(writer, "{}", text).with_context(|| "Failed to write appended line")?;
+        if let Some(changes) = changes.as_deref_mut() {
+            changes.push(LineChange {
+                line_number: line_num + 1,
+                change_type: ChangeType::Added,
+                content: text,
+                old_content: None,
+                spans: Vec::new(),
+            });
+        }
+        Ok(())
+    };
+
+    for output in appended {
+        match output {
+            AppendedOutput::Text(text) => write_line(text)?,
+            // r copies the file line by line rather than reading it whole
+            AppendedOutput::File(filename) => {
+                let file = File::open(&filename)
+                    .with_context(|| format!("Failed to read file: {}", filename))?;
+                for text in BufReader::new(file).lines() {
+                    write_line(
+                        text.with_context(|| format!("Failed to read file: {}", filename))?,
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the lines p printed so far to a pipeline's output
+fn write_printed(writer: &mut impl Write, printed: &mut Vec<String>) -> Result<()> {
+    for text in printed.drain(..) {
+        writeln!(writer, "{}", text).with_context(|| "Failed to write printed line")?;
+    }
+    Ok(())
+}
+
+/// Apply only the `accepted` hunks of `diff` (indices into `diff.hunks()`)
+/// to `file_path`, which must still hold the content `diff` was made from
+pub fn write_selected_hunks(file_path: &Path, diff: &FileDiff, accepted: &[usize]) -> Result<()> {
+    let original = read_text_file(file_path)?;
+    let original_lines: Vec<&str> = original.lines().collect();
+    let lines = diff.lines_with_hunks(&original_lines, accepted);
+    write_lines_like(file_path, &original, &lines)
+}
+
+/// Replace the contents of `file_path` with `lines`, keeping its line endings
+pub fn write_output_lines(file_path: &Path, lines: &[String]) -> Result<()> {
+    let original = read_text_file(file_path)?;
+    write_lines_like(file_path, &original, lines)
+}
+
 /// Processor for streaming large files with constant memory usage
 pub struct StreamProcessor {
     commands: Vec<Command>,
@@ -261,14 +1948,35 @@ pub struct StreamProcessor {
     context_size: usize,
     // State for reading context after a change
     context_lines_to_read: usize, // How many more lines to read as context
-    // Pattern range states (Chunk 8): (start_pattern, end_pattern) -> state
-    pattern_range_states: HashMap<(String, String), PatternRangeState>,
+    // Pattern range states (Chunk 8), per command
+    pattern_range_states: HashMap<MixedRangeKey, PatternRangeState>,
     // Chunk 8: Mixed range states for tracking complex ranges
     mixed_range_states: HashMap<MixedRangeKey, MixedRangeState>,
     // Dry run mode: if true, don't persist changes to disk
     dry_run: bool,
     // Regex flavor for enhanced error reporting
     regex_flavor: crate::cli::RegexFlavor,
+    // GNU vs POSIX behavior, passed on when delegating to in-memory processing
+    compat_mode: CompatMode,
+    // Compiled regexes, so each pattern is compiled once per run rather
+    // than once per line
+    regex_cache: RegexCache,
+    // Replacements with escape sequences already processed
+    replacement_cache: HashMap<String, String>,
+    // -n flag: only lines printed by p or s///p reach the output
+    no_default_output: bool,
+    // --max-count: substitutions still allowed to change a line
+    max_count: Option<SubstitutionBudget>,
+    // --only-matching: lines this doesn't match skip the script
+    only_matching: Option<Regex>,
+    // --timeout: when to give up on the script
+    deadline: Option<Deadline>,
+    // R: an open reader per file, so each R reads on where the last one stopped
+    read_line_sources: HashMap<String, std::io::Lines<BufReader<File>>>,
+    // --progress: told how far each streamed file has got
+    progress: Option<ProgressCallback>,
+    // --stdin-filename: what to call piped input in errors
+    input_name: Option<String>,
 }
 
 impl StreamProcessor {
@@ -292,6 +2000,16 @@ impl StreamProcessor {
             mixed_range_states: HashMap::new(),
             dry_run: false,
             regex_flavor,
+            compat_mode: CompatMode::default(),
+            regex_cache: RegexCache::default(),
+            replacement_cache: HashMap::new(),
+            no_default_output: false,
+            max_count: None,
+            only_matching: None,
+            deadline: None,
+            read_line_sources: HashMap::new(),
+            progress: None,
+            input_name: None,
         }
     }
 
@@ -307,6 +2025,73 @@ impl StreamProcessor {
         self
     }
 
+    /// Choose GNU or POSIX behavior where the two differ
+    pub fn with_compat_mode(mut self, mode: CompatMode) -> Self {
+        self.compat_mode = mode;
+        self
+    }
+
+    /// Make case-insensitive matching fold ASCII letters only (--ascii-case)
+    pub fn with_ascii_case(mut self, value: bool) -> Self {
+        self.regex_cache.ascii_case = value;
+        self
+    }
+
+    /// Suppress automatic output of each line (-n flag)
+    pub fn with_no_default_output(mut self, value: bool) -> Self {
+        self.no_default_output = value;
+        self
+    }
+
+    /// Stop substituting once `budget` is spent (--max-count)
+    pub fn with_max_count(mut self, budget: Option<SubstitutionBudget>) -> Self {
+        self.max_count = budget;
+        self
+    }
+
+    /// Only run the script on lines `filter` matches; the rest are written
+    /// unchanged (--only-matching)
+    pub fn with_only_matching(mut self, filter: Option<Regex>) -> Self {
+        self.only_matching = filter;
+        self
+    }
+
+    /// Give up with a `TimedOut` error once `deadline` passes (--timeout)
+    pub fn with_deadline(mut self, deadline: Option<Deadline>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Report how far each streamed file has got to `callback` (--progress)
+    pub fn with_progress(mut self, callback: Option<ProgressCallback>) -> Self {
+        self.progress = callback;
+        self
+    }
+
+    /// Name piped input after the file it holds (--stdin-filename), rather
+    /// than "standard input"
+    pub fn with_input_name(mut self, name: Option<String>) -> Self {
+        self.input_name = name;
+        self
+    }
+
+    /// In-memory processor for commands streaming can't handle
+    fn in_memory_processor(&self) -> Result<FileProcessor> {
+        if self.only_matching.is_some() {
+            anyhow::bail!(
+                "--only-matching only works when every line is streamed, but this script needs in-memory processing"
+            );
+        }
+        let mut processor =
+            FileProcessor::with_regex_flavor(self.commands.clone(), self.regex_flavor);
+        processor.set_compat_mode(self.compat_mode);
+        processor.set_ascii_case(self.regex_cache.ascii_case);
+        processor.set_no_default_output(self.no_default_output);
+        processor.max_count = self.max_count.clone();
+        processor.set_deadline(self.deadline);
+        Ok(processor)
+    }
+
     /// Flush buffer to changes when we encounter a changed line
     fn flush_buffer_to_changes(&mut self, changes: &mut Vec<LineChange>) {
         for (line_num, content, change_type) in self.context_buffer.drain(..) {
@@ -315,6 +2100,7 @@ impl StreamProcessor {
                 change_type,
                 content,
                 old_content: None,
+                spans: Vec::new(),
             });
         }
     }
@@ -326,54 +2112,35 @@ impl StreamProcessor {
         file_size >= STREAMING_THRESHOLD
     }
 
+    /// Compiled regex for `pattern`, compiling it on first use
+    fn regex(&self, pattern: &str, case_insensitive: bool, multiline: bool) -> Result<Regex> {
+        self.regex_cache
+            .get(pattern, self.regex_flavor, case_insensitive, multiline)
+    }
+
     /// Apply substitution to a single line
     fn apply_substitution_to_line(
-        &self,
+        &mut self,
         line: &str,
         pattern: &str,
         replacement: &str,
         flags: &SubstitutionFlags,
     ) -> Result<String> {
-        let global = flags.global;
-        let case_insensitive = flags.case_insensitive;
-        let nth_occurrence = flags.nth;
-
         // Process escape sequences in replacement
-        let processed_replacement = self.process_replacement_escapes(replacement);
-
-        let re = compile_regex_with_context(pattern, self.regex_flavor, case_insensitive)?;
-
-        match nth_occurrence {
-            Some(n) if n > 0 => {
-                // Replace only the Nth occurrence
-                let mut result = line.to_string();
-                let mut count = 0;
-                for mat in re.find_iter(line) {
-                    count += 1;
-                    if count == n {
-                        result = format!(
-                            "{}{}{}",
-                            &line[..mat.start()],
-                            processed_replacement,
-                            &line[mat.end()..]
-                        );
-                        break;
-                    }
-                }
-                Ok(result)
-            }
-            Some(_) => Ok(line.to_string()), // 0 means no substitution
-            None => {
-                // Standard behavior
-                if global {
-                    Ok(re
-                        .replace_all(line, processed_replacement.as_str())
-                        .to_string())
-                } else {
-                    Ok(re.replace(line, processed_replacement.as_str()).to_string())
-                }
-            }
+        if !self.replacement_cache.contains_key(replacement) {
+            let processed = self.process_replacement_escapes(replacement);
+            self.replacement_cache
+                .insert(replacement.to_string(), processed);
+        }
+
+        let re = self.regex(pattern, flags.case_insensitive, flags.multiline)?;
+
+        let substituted =
+            substitute_with_flags(&re, line, &self.replacement_cache[replacement], flags);
+        if substituted != line && !substitution_allowed(&self.max_count) {
+            return Ok(line.to_string());
         }
+        Ok(substituted)
     }
 
     /// Process escape sequences in replacement string
@@ -384,7 +2151,7 @@ impl StreamProcessor {
 
         while let Some(c) = chars.next() {
             if c == '\\' {
-                match chars.peek() {
+                match chars.peek().copied() {
                     Some('n') => {
                         result.push('\n');
                         chars.next();
@@ -417,8 +2184,8 @@ impl StreamProcessor {
                             result.push(byte as char);
                         }
                     }
-                    Some('u') => {
-                        // Unicode escape: \uHHHH
+                    Some('u') if is_unicode_escape(&chars.clone().skip(1).collect::<String>()) => {
+                        // Unicode escape: \uHHHH (a bare \u is the case operator)
                         chars.next(); // consume 'u'
                         let mut hex = String::new();
                         for _ in 0..4 {
@@ -435,7 +2202,7 @@ impl StreamProcessor {
                             result.push(c);
                         }
                     }
-                    Some(&c) => {
+                    Some(c) => {
                         // Unknown escape, keep as-is
                         result.push('\\');
                         result.push(c);
@@ -469,18 +2236,22 @@ impl StreamProcessor {
     }
 
     /// Check if a line is within a pattern range, updating state as needed (Chunk 8)
-    fn check_pattern_range(&mut self, line: &str, start_pat: &str, end_pat: &str) -> Result<bool> {
-        let key = (start_pat.to_string(), end_pat.to_string());
+    fn check_pattern_range(
+        &mut self,
+        line: &str,
+        start_pat: &str,
+        end_pat: &str,
+        command_index: usize,
+    ) -> Result<bool> {
+        let start_re = self.regex(start_pat, false, false)?;
+        let end_re = self.regex(end_pat, false, false)?;
+
+        let key = MixedRangeKey { command_index };
         let state = self
             .pattern_range_states
-            .entry(key.clone())
+            .entry(key)
             .or_insert(PatternRangeState::LookingForStart);
 
-        let start_re = Regex::new(start_pat)
-            .with_context(|| format!("Invalid regex pattern: {}", start_pat))?;
-        let end_re =
-            Regex::new(end_pat).with_context(|| format!("Invalid regex pattern: {}", end_pat))?;
-
         let in_range = match state {
             PatternRangeState::LookingForStart => {
                 if start_re.is_match(line) {
@@ -514,25 +2285,20 @@ impl StreamProcessor {
         end_line: usize,
         command_index: usize,
     ) -> Result<bool> {
+        let start_re = self.regex(start_pat, false, false)?;
+
         let key = MixedRangeKey { command_index };
         let state = self
             .mixed_range_states
             .entry(key)
             .or_insert(MixedRangeState::LookingForPattern);
 
-        let start_re = Regex::new(start_pat)
-            .with_context(|| format!("Invalid regex pattern: {}", start_pat))?;
-
         let in_range = match state {
-            MixedRangeState::LookingForPattern => {
-                if start_re.is_match(line) {
-                    *state = MixedRangeState::InRangeUntilLine {
-                        target_line: end_line,
-                    };
-                    true
-                } else {
-                    false
-                }
+            MixedRangeState::LookingForPattern if start_re.is_match(line) => {
+                *state = MixedRangeState::InRangeUntilLine {
+                    target_line: end_line,
+                };
+                true
             }
             MixedRangeState::InRangeUntilLine { target_line } => {
                 if self.current_line >= *target_line {
@@ -556,6 +2322,8 @@ impl StreamProcessor {
         end_pat: &str,
         command_index: usize,
     ) -> Result<bool> {
+        let end_re = self.regex(end_pat, false, false)?;
+
         let key = MixedRangeKey { command_index };
         let state = self
             .mixed_range_states
@@ -563,19 +2331,13 @@ impl StreamProcessor {
             .or_insert(MixedRangeState::LookingForPattern);
 
         let in_range = match state {
-            MixedRangeState::LookingForPattern => {
-                if self.current_line >= start_line {
-                    *state = MixedRangeState::InRangeUntilPattern {
-                        end_pattern: end_pat.to_string(),
-                    };
-                    true
-                } else {
-                    false
-                }
+            MixedRangeState::LookingForPattern if self.current_line >= start_line => {
+                *state = MixedRangeState::InRangeUntilPattern {
+                    end_pattern: end_pat.to_string(),
+                };
+                true
             }
-            MixedRangeState::InRangeUntilPattern { end_pattern } => {
-                let end_re = Regex::new(end_pattern)
-                    .with_context(|| format!("Invalid regex pattern: {}", end_pattern))?;
+            MixedRangeState::InRangeUntilPattern { .. } => {
                 if end_re.is_match(line) {
                     *state = MixedRangeState::LookingForPattern; // Reset for next occurrence
                     true // Include the end line
@@ -589,6 +2351,34 @@ impl StreamProcessor {
         Ok(in_range)
     }
 
+    /// Check `0,/end/` range: active from line 1, ends at the first match
+    fn check_first_line_to_pattern(
+        &mut self,
+        line: &str,
+        end_pat: &str,
+        command_index: usize,
+    ) -> Result<bool> {
+        let end_re = self.regex(end_pat, false, false)?;
+
+        let key = MixedRangeKey { command_index };
+        let state =
+            self.mixed_range_states
+                .entry(key)
+                .or_insert(MixedRangeState::InRangeUntilPattern {
+                    end_pattern: end_pat.to_string(),
+                });
+
+        // Unlike 5,/end/ the range never restarts once it has ended
+        if *state == MixedRangeState::LookingForPattern {
+            return Ok(false);
+        }
+
+        if end_re.is_match(line) {
+            *state = MixedRangeState::LookingForPattern;
+        }
+        Ok(true)
+    }
+
     /// Check relative range: /start/,+5 (Chunk 8)
     fn check_relative_range(
         &mut self,
@@ -600,8 +2390,7 @@ impl StreamProcessor {
         let key = MixedRangeKey { command_index };
 
         // Remove old state and check fresh each time
-        let pat_re =
-            Regex::new(pattern).with_context(|| format!("Invalid regex pattern: {}", pattern))?;
+        let pat_re = self.regex(pattern, false, false)?;
 
         if pat_re.is_match(line) {
             // Pattern matched - start counting
@@ -652,15 +2441,22 @@ impl StreamProcessor {
             // Single pattern address: /foo/d (not a range!)
             // When both patterns are the same, match each line independently
             (Pattern(start_pat), Pattern(end_pat)) if start_pat == end_pat => {
-                // Compile pattern and match current line only (no state machine)
-                let re = Regex::new(start_pat)
-                    .with_context(|| format!("Invalid regex pattern: {}", start_pat))?;
+                // Match current line only (no state machine)
+                let re = self.regex(start_pat, false, false)?;
                 Ok(re.is_match(line))
             }
 
+            // Negated single address: /keep/!d, 5!s/x/y/
+            (Negated(start), Negated(end))
+                if start == end && matches!(start.as_ref(), Pattern(_) | LineNumber(_)) =>
+            {
+                let inner = (start.as_ref().clone(), end.as_ref().clone());
+                Ok(!self.should_apply_command_with_range(line, &inner, command_index)?)
+            }
+
             // Pattern-to-pattern: /start/,/end/
             (Pattern(start_pat), Pattern(end_pat)) => {
-                self.check_pattern_range(line, start_pat, end_pat)
+                self.check_pattern_range(line, start_pat, end_pat, command_index)
             }
 
             // Mixed pattern-to-line: /start/,10
@@ -668,6 +2464,12 @@ impl StreamProcessor {
                 self.check_mixed_pattern_to_line(line, start_pat, *end_line, command_index)
             }
 
+            // 0,/end/: in range from line 1 until the end pattern first matches,
+            // which may be on line 1
+            (FirstLine, Pattern(end_pat)) => {
+                self.check_first_line_to_pattern(line, end_pat, command_index)
+            }
+
             // Mixed line-to-pattern: 5,/end/
             (LineNumber(start_line), Pattern(end_pat)) => {
                 self.check_mixed_line_to_pattern(line, *start_line, end_pat, command_index)
@@ -678,24 +2480,163 @@ impl StreamProcessor {
                 self.check_relative_range(line, start_pat, *offset, command_index)
             }
 
-            // Line range: 5,10
+            // Line range: 5,10. An end at or before the start (5,2) covers
+            // just the start line.
             (LineNumber(start), LineNumber(end)) => {
-                Ok(self.current_line >= *start && self.current_line <= *end)
+                Ok(self.current_line >= *start && self.current_line <= *end.max(start))
+            }
+
+            // All lines: 1,$
+            (LineNumber(1), LastLine) => Ok(true),
+
+            // Stepping: 1~2
+            (Step { start, step }, _) | (_, Step { start, step }) => {
+                Ok(self.check_stepping(*start, *step))
+            }
+
+            _ => {
+                // Other range types not supported in streaming - delegate to in-memory
+                Ok(false)
+            }
+        }
+    }
+
+    /// Run the commands of a group on one line. Inner commands take range-state
+    /// keys from `first_key` on, in the order `nested_command_count` counts
+    /// them, so they never share state with each other or with top-level
+    /// commands. Returns false if the group holds a command streaming can't
+    /// handle.
+    fn apply_group_streaming(
+        &mut self,
+        line: &str,
+        commands: &[Command],
+        first_key: usize,
+        streamed: &mut StreamedLine,
+    ) -> Result<bool> {
+        let mut key = first_key;
+        for cmd in commands {
+            let cmd_key = key;
+            key += 1 + nested_command_count(cmd);
+
+            let range = match cmd {
+                Command::Substitution { range, .. }
+                | Command::Hold { range }
+                | Command::HoldAppend { range }
+                | Command::Get { range }
+                | Command::GetAppend { range }
+                | Command::Exchange { range }
+                | Command::Group { range, .. } => range.as_ref(),
+                Command::Delete { range } | Command::Print { range } => Some(range),
+                _ => return Ok(false),
+            };
+            if let Some(range) = range
+                && !self.should_apply_command_with_range(line, range, cmd_key)?
+            {
+                continue;
+            }
+
+            match cmd {
+                Command::Substitution {
+                    pattern,
+                    replacement,
+                    flags,
+                    ..
+                } => {
+                    let substituted = self.apply_substitution_to_line(
+                        &streamed.text,
+                        pattern,
+                        replacement,
+                        flags,
+                    )?;
+                    if substituted != streamed.text {
+                        streamed.text = substituted;
+                        streamed.changed = true;
+                        // Handle print flag in substitution (GNU sed compatible)
+                        if flags.print {
+                            streamed.printed.push(streamed.text.clone());
+                        }
+                    }
+                }
+                Command::Delete { .. } => {
+                    // Stop processing this group and any enclosing ones
+                    streamed.skip = true;
+                    return Ok(true);
+                }
+                Command::Print { .. } => streamed.printed.push(streamed.text.clone()),
+                Command::Hold { .. } => self.hold_space = streamed.text.clone(),
+                Command::HoldAppend { .. } => {
+                    if !self.hold_space.is_empty() {
+                        self.hold_space.push('\n');
+                    }
+                    self.hold_space.push_str(&streamed.text);
+                }
+                Command::Get { .. } => {
+                    if !self.hold_space.is_empty() {
+                        streamed.text = self.hold_space.clone();
+                        streamed.changed = true;
+                    }
+                }
+                Command::GetAppend { .. } => {
+                    if !self.hold_space.is_empty() {
+                        streamed.text.push('\n');
+                        streamed.text.push_str(&self.hold_space);
+                        streamed.changed = true;
+                    }
+                }
+                Command::Exchange { .. } => {
+                    std::mem::swap(&mut streamed.text, &mut self.hold_space);
+                    streamed.changed = true;
+                }
+                Command::Group { commands, .. } => {
+                    if !self.apply_group_streaming(line, commands, cmd_key + 1, streamed)? {
+                        return Ok(false);
+                    }
+                    if streamed.skip {
+                        return Ok(true);
+                    }
+                }
+                _ => return Ok(false),
             }
+        }
 
-            // All lines: 1,$
-            (LineNumber(1), LastLine) => Ok(true),
+        Ok(true)
+    }
 
-            // Stepping: 1~2
-            (Step { start, step }, _) | (_, Step { start, step }) => {
-                Ok(self.check_stepping(*start, *step))
+    /// Check the single address of an `i`, `a` or `c` command against the
+    /// current line. `None` means the address can't be decided while
+    /// streaming.
+    fn streaming_address_matches(
+        &mut self,
+        line: &str,
+        address: &Address,
+        command_index: usize,
+    ) -> Result<Option<bool>> {
+        match address {
+            Address::LineNumber(n) => Ok(Some(*n == self.current_line)),
+            Address::Pattern(_) | Address::Step { .. } => {
+                let range = (address.clone(), address.clone());
+                self.should_apply_command_with_range(line, &range, command_index)
+                    .map(Some)
             }
+            _ => Ok(None),
+        }
+    }
 
-            _ => {
-                // Other range types not supported in streaming - delegate to in-memory
-                Ok(false)
+    /// The next line of `filename` for an R command, or None once the file
+    /// is used up
+    fn read_next_line(&mut self, filename: &str) -> Result<Option<String>> {
+        let lines = match self.read_line_sources.entry(filename.to_string()) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let file = File::open(filename)
+                    .with_context(|| format!("Failed to read file: {}", filename))?;
+                entry.insert(BufReader::new(file).lines())
             }
-        }
+        };
+        lines
+            .next()
+            .transpose()
+            .with_context(|| format!("Failed to read file: {}", filename))
     }
 
     /// Process a file using streaming approach (constant memory)
@@ -709,7 +2650,7 @@ impl StreamProcessor {
 
         if !Self::should_use_streaming(metadata.len()) {
             // File is small, delegate to in-memory processing
-            let mut processor = FileProcessor::new(self.commands.clone());
+            let mut processor = self.in_memory_processor()?;
             return processor.process_file_with_context(file_path);
         }
 
@@ -735,454 +2676,541 @@ impl StreamProcessor {
         let input_file = File::open(file_path)
             .with_context(|| format!("Failed to open file: {}", file_path.display()))?;
 
+        // The file's size up front, so progress can be shown as a fraction
+        let total_bytes = input_file
+            .metadata()
+            .with_context(|| format!("Failed to read metadata: {}", file_path.display()))?
+            .len();
         let reader = BufReader::new(input_file);
+        let crlf = first_line_is_crlf(file_path)?;
+
+        // Write using a separate block to ensure writer is dropped before persist
+        let run = {
+            let mut writer = BufWriter::new(LineEndingWriter {
+                inner: temp_file.as_file(),
+                crlf,
+            });
+            let run = self.stream_lines(
+                reader,
+                &mut writer,
+                &file_path.display().to_string(),
+                Some(total_bytes),
+            )?;
+
+            // Ensure all data is written to disk
+            writer
+                .flush()
+                .with_context(|| "Failed to flush temp file")?;
+            run
+        }; // writer dropped here
+
+        let Some(run) = run else {
+            // A command streaming can't handle: the in-memory engine takes over
+            let mut processor = self.in_memory_processor()?;
+            return processor.process_file_with_context(file_path);
+        };
+
+        // Every line was written with a newline; drop the last one if the
+        // input had none and its last line is still last
+        if run.ends_with_last_line && !ends_with_newline(file_path)? {
+            let file = temp_file.as_file();
+            let len = file
+                .metadata()
+                .with_context(|| "Failed to read temp file metadata")?
+                .len();
+            let newline_len = if crlf { 2 } else { 1 };
+            if len >= newline_len {
+                file.set_len(len - newline_len)
+                    .with_context(|| "Failed to truncate temp file")?;
+            }
+        }
+
+        // Atomic rename: temp file becomes the actual file
+        // In dry-run mode, don't persist (temp file will be automatically deleted when dropped)
+        if !self.dry_run {
+            // The temp file replaces the original, so it takes over its
+            // permissions and owner
+            let metadata = fs::metadata(file_path)
+                .with_context(|| format!("Failed to read metadata: {}", file_path.display()))?;
+            restore_metadata(temp_file.as_file(), &metadata)?;
+            temp_file.persist(file_path).with_context(|| {
+                format!("Failed to persist temp file to {}", file_path.display())
+            })?;
+        }
+        // If dry_run, temp_file is dropped here and automatically deleted
+
+        // Build FileDiff result
+        // NOTE: In streaming mode, we don't populate all_lines to save memory
+        // The diff formatter will handle this differently for streaming mode
+        let all_lines = Vec::new(); // Empty in streaming mode
+
+        Ok(FileDiff {
+            file_path: file_path.display().to_string(),
+            changes: run.changes,
+            all_lines,
+            printed_lines: run.printed_lines,
+            is_streaming: true, // Streaming mode
+            exit_code: run.exit_code,
+        })
+    }
+
+    /// Stream `input` to `output` the way sed runs in a pipeline: each
+    /// line's p output, inserted text, the line itself and appended text
+    /// are written in the order the script produces them, and nothing is
+    /// kept for a diff. Returns the exit status a q command asked for.
+    pub fn process_pipe(&mut self, input: impl BufRead, output: impl Write) -> Result<Option<i32>> {
+        let mut writer = BufWriter::new(output);
+        let source = self
+            .input_name
+            .clone()
+            .unwrap_or_else(|| "standard input".to_string());
+        let Some(run) = self.stream_lines(input, &mut writer, &source, None)? else {
+            anyhow::bail!("This script needs in-memory processing and can't be streamed");
+        };
+        writer
+            .flush()
+            .with_context(|| "Failed to write to standard output")?;
+        Ok(run.exit_code)
+    }
 
+    /// Run the script over every line of `reader`, writing the output to
+    /// `writer`. A file gives its size as `total_bytes`, and its p output is
+    /// collected for `FileDiff::printed_lines` along with a diff. Without
+    /// one, as in a pipe, p output goes to `writer` too and no diff is
+    /// tracked. Returns None if the script needs the in-memory engine after all.
+    fn stream_lines(
+        &mut self,
+        reader: impl BufRead,
+        writer: &mut impl Write,
+        source: &str,
+        total_bytes: Option<u64>,
+    ) -> Result<Option<StreamedRun>> {
+        let pipe = total_bytes.is_none();
+        let mut meter = self
+            .progress
+            .clone()
+            .zip(total_bytes)
+            .map(|(callback, total)| ProgressMeter::new(callback, total));
         let mut line_num = 0;
         let mut changes: Vec<LineChange> = Vec::new();
+        // Range-state key of the first command inside each top-level group
+        let mut next_key = self.commands.len();
+        let group_keys: Vec<usize> = self
+            .commands
+            .iter()
+            .map(|cmd| {
+                let key = next_key;
+                next_key += nested_command_count(cmd);
+                key
+            })
+            .collect();
+        // Lines from p / s///p, collected rather than printed so that a preview
+        // pass followed by an apply pass doesn't print them twice
+        let mut printed_lines: Vec<String> = Vec::new();
+        // Exit status of the q command that stopped processing, if one did
+        let mut exit_code = None;
+        let mut ends_with_last_line = false;
+
+        // Read line by line, looking one line ahead only when a $ address
+        // asks whether this is the last line
+        let mut lines = reader.lines().peekable();
+        'outer: while let Some(line_result) = lines.next() {
+            let line = line_result.map_err(|e| {
+                if e.kind() == std::io::ErrorKind::InvalidData {
+                    anyhow::anyhow!(
+                        "{} is not valid UTF-8 (invalid byte on line {})",
+                        source,
+                        line_num + 1
+                    )
+                } else {
+                    anyhow::Error::new(e).context(format!("Failed to read line from {}", source))
+                }
+            })?;
 
-        // Write using a separate block to ensure writer is dropped before persist
-        {
-            let mut writer = BufWriter::new(temp_file.as_file());
-
-            // Read line by line
-            'outer: for line_result in reader.lines() {
-                let line = line_result
-                    .with_context(|| format!("Failed to read line from {}", file_path.display()))?;
-
-                line_num += 1;
-                self.current_line = line_num;
-
-                // Apply sed commands to this line
-                let mut processed_line = line.clone();
-                let mut line_changed = false;
-                let mut skip_line = false; // For delete command
-                let mut print_line = false; // For print command
-                let mut append_text: Option<String> = None; // For append command
-                let mut should_quit_after_line = false; // For quit command
-
-                // Clone commands to avoid borrow checker issues with pattern range state updates
-                let commands = self.commands.clone();
-                for (cmd_index, cmd) in commands.iter().enumerate() {
-                    match cmd {
-                        Command::Substitution {
-                            pattern,
-                            replacement,
-                            flags,
-                            range,
-                        } => {
-                            // Check if we should apply this substitution (Chunk 8: pattern range support)
-                            let should_apply = match range {
-                                Some(range) => {
-                                    self.should_apply_command_with_range(&line, range, cmd_index)?
-                                }
-                                None => true, // No range means apply to all lines
-                            };
+            check_deadline(&self.deadline)?;
+            line_num += 1;
+            self.current_line = line_num;
+            if let Some(meter) = meter.as_mut() {
+                meter.line_read(line.len());
+            }
 
-                            if should_apply {
-                                let original_line = processed_line.clone();
-                                processed_line = self.apply_substitution_to_line(
-                                    &processed_line,
-                                    pattern,
-                                    replacement,
-                                    flags,
-                                )?;
-                                line_changed = processed_line != original_line;
-
-                                // Handle print flag in substitution (GNU sed compatible)
-                                if line_changed && flags.print {
-                                    print_line = true;
-                                }
+            ends_with_last_line = false;
+
+            // Apply sed commands to this line
+            let mut processed_line = line.clone();
+            let mut line_changed = false;
+            let mut skip_line = false; // For delete command
+            let mut printed: Vec<String> = Vec::new(); // What p printed, in order
+            let mut appended: Vec<AppendedOutput> = Vec::new(); // a, c, r and R output
+            let mut should_quit_after_line = false; // For quit command
+
+            // Clone commands to avoid borrow checker issues with pattern range state updates.
+            // Lines --only-matching filters out run no commands, so
+            // they are written unchanged.
+            let commands = match &self.only_matching {
+                Some(filter) if !filter.is_match(&line) => Vec::new(),
+                _ => self.commands.clone(),
+            };
+            for (cmd_index, cmd) in commands.iter().enumerate() {
+                match cmd {
+                    Command::Substitution {
+                        pattern,
+                        replacement,
+                        flags,
+                        range,
+                    } => {
+                        // Check if we should apply this substitution (Chunk 8: pattern range support)
+                        let should_apply = match range {
+                            Some(range) => {
+                                self.should_apply_command_with_range(&line, range, cmd_index)?
                             }
-                        }
-                        Command::Delete {
-                            range: (start, end),
-                        } => {
-                            // Check if we should apply this deletion (Chunk 8: unified range support)
-                            let range = (start.clone(), end.clone());
-                            let should_delete =
-                                self.should_apply_command_with_range(&line, &range, cmd_index)?;
-
-                            if should_delete {
-                                skip_line = true;
+                            None => true, // No range means apply to all lines
+                        };
+
+                        if should_apply {
+                            let original_line = processed_line.clone();
+                            processed_line = self.apply_substitution_to_line(
+                                &processed_line,
+                                pattern,
+                                replacement,
+                                flags,
+                            )?;
+                            // A later substitution that misses must not hide
+                            // an earlier one's change
+                            let was_changed = processed_line != original_line;
+                            line_changed = line_changed || was_changed;
+
+                            // Handle print flag in substitution (GNU sed compatible)
+                            if was_changed && flags.print {
+                                printed.push(processed_line.clone());
                             }
                         }
-                        Command::Print {
-                            range: (start, end),
-                        } => {
-                            // Check if we should print this line (Chunk 8: unified range support)
-                            let range = (start.clone(), end.clone());
-                            let should_print =
-                                self.should_apply_command_with_range(&line, &range, cmd_index)?;
-
-                            if should_print {
-                                print_line = true;
-                            }
+                    }
+                    Command::Delete {
+                        range: (start, end),
+                    } => {
+                        // Check if we should apply this deletion (Chunk 8: unified range support)
+                        let range = (start.clone(), end.clone());
+                        let should_delete =
+                            self.should_apply_command_with_range(&line, &range, cmd_index)?;
+
+                        if should_delete {
+                            // d ends the cycle: later commands don't run
+                            skip_line = true;
+                            break;
+                        }
+                    }
+                    Command::Print {
+                        range: (start, end),
+                    } => {
+                        // Check if we should print this line (Chunk 8: unified range support)
+                        let range = (start.clone(), end.clone());
+                        let should_print =
+                            self.should_apply_command_with_range(&line, &range, cmd_index)?;
+
+                        if should_print {
+                            printed.push(processed_line.clone());
                         }
-                        Command::Insert { text, address } => {
-                            // Insert text BEFORE the specified line
-                            match address {
-                                Address::LineNumber(n) if *n == line_num => {
-                                    // Insert before current line
-                                    writeln!(writer, "{}", text)
+                    }
+                    Command::Insert { text, address } => {
+                        // Insert text BEFORE the matching line
+                        match self.streaming_address_matches(&line, address, cmd_index)? {
+                            Some(true) => {
+                                // In a pipeline, what p printed so far comes first
+                                if pipe {
+                                    write_printed(writer, &mut printed)?;
+                                }
+                                for text_line in text.split('\n') {
+                                    writeln!(writer, "{}", text_line)
                                         .with_context(|| "Failed to write inserted line")?;
                                     // Track the inserted line for diff
-                                    changes.push(LineChange {
-                                        line_number: line_num,
-                                        change_type: ChangeType::Added,
-                                        content: text.clone(),
-                                        old_content: None,
-                                    });
-                                }
-                                Address::LineNumber(_) => {
-                                    // Not at the target line yet, continue
-                                }
-                                _ => {
-                                    // Complex addresses (patterns) not yet supported - delegate to in-memory
-                                    drop(writer);
-                                    let mut processor = FileProcessor::new(self.commands.clone());
-                                    return processor.process_file_with_context(file_path);
+                                    if !pipe {
+                                        changes.push(LineChange {
+                                            line_number: line_num,
+                                            change_type: ChangeType::Added,
+                                            content: text_line.to_string(),
+                                            old_content: None,
+                                            spans: Vec::new(),
+                                        });
+                                    }
                                 }
                             }
-                        }
-                        Command::Append { text, address } => {
-                            // Append text AFTER the specified line
-                            match address {
-                                Address::LineNumber(n) if *n == line_num => {
-                                    // Store text to append after current line
-                                    append_text = Some(text.clone());
-                                }
-                                Address::LineNumber(_) => {
-                                    // Not at the target line yet or already passed it, continue
-                                }
-                                _ => {
-                                    // Complex addresses (patterns) not yet supported - delegate to in-memory
-                                    drop(writer);
-                                    let mut processor = FileProcessor::new(self.commands.clone());
-                                    return processor.process_file_with_context(file_path);
-                                }
+                            Some(false) => {}
+                            None => {
+                                // Other addresses ($, negation) need the in-memory engine
+                                return Ok(None);
                             }
                         }
-                        Command::Change { text, address } => {
-                            // Change (replace) the specified line with new text
-                            match address {
-                                Address::LineNumber(n) if *n == line_num => {
-                                    // Replace current line with new text
-                                    processed_line = text.clone();
-                                    line_changed = true;
-                                }
-                                Address::LineNumber(_) => {
-                                    // Not at the target line yet, continue
-                                }
-                                _ => {
-                                    // Complex addresses (patterns) not yet supported - delegate to in-memory
-                                    drop(writer);
-                                    let mut processor = FileProcessor::new(self.commands.clone());
-                                    return processor.process_file_with_context(file_path);
-                                }
+                    }
+                    Command::Append { text, address } => {
+                        // Append text AFTER the matching line
+                        match self.streaming_address_matches(&line, address, cmd_index)? {
+                            Some(true) => appended.extend(
+                                text.split('\n')
+                                    .map(|text_line| AppendedOutput::Text(text_line.to_string())),
+                            ),
+                            Some(false) => {}
+                            None => {
+                                return Ok(None);
                             }
                         }
-                        Command::Quit { address } => {
-                            // Stop processing at specified line
-                            match address {
-                                None => {
-                                    // Quit immediately - don't process or write this line
-                                    break 'outer;
-                                }
-                                Some(Address::LineNumber(n)) if *n == line_num => {
-                                    // Quit after processing and writing this line
-                                    should_quit_after_line = true;
-                                }
-                                Some(Address::LineNumber(_)) => {
-                                    // Not at the target line yet, continue
-                                }
-                                Some(Address::LastLine) => {
-                                    // Quit after processing this line
-                                    should_quit_after_line = true;
-                                }
-                                _ => {
-                                    // Complex addresses (patterns) not yet supported - delegate to in-memory
-                                    drop(writer);
-                                    let mut processor = FileProcessor::new(self.commands.clone());
-                                    return processor.process_file_with_context(file_path);
-                                }
+                    }
+                    Command::Change { text, range } => {
+                        // Change (replace) the matching line with new text.
+                        // Over a range the text goes where the range ends,
+                        // which only the in-memory engine tracks.
+                        let matched = if range.0 == range.1 {
+                            self.streaming_address_matches(&line, &range.0, cmd_index)?
+                        } else {
+                            None
+                        };
+                        match matched {
+                            Some(true) => {
+                                // Lines after the first come out like appended text
+                                let mut text_lines = text.split('\n').map(String::from);
+                                processed_line = text_lines.next().unwrap_or_default();
+                                appended.splice(0..0, text_lines.map(AppendedOutput::Text));
+                                line_changed = true;
+                            }
+                            Some(false) => {}
+                            None => {
+                                return Ok(None);
                             }
                         }
-                        // Chunk 9: Hold space operations in streaming mode
-                        Command::Hold { range } => {
-                            // h - Copy current line to hold space (overwrite)
-                            let should_apply = match &range {
-                                None => true, // No range means apply to all lines
-                                Some((start, end)) => self.should_apply_command_with_range(
-                                    &line,
-                                    &(start.clone(), end.clone()),
-                                    cmd_index,
-                                )?,
-                            };
-                            if should_apply {
-                                self.hold_space = processed_line.clone();
+                    }
+                    Command::Quit {
+                        address,
+                        exit_code: code,
+                    } => {
+                        // Stop processing at specified line
+                        match address {
+                            None => {
+                                // Quit immediately - don't process or write this line
+                                exit_code = Some(code.unwrap_or(0));
+                                break 'outer;
+                            }
+                            Some(Address::LineNumber(n)) if *n == line_num => {
+                                // q ends the cycle: later commands don't run,
+                                // and the line is written unless -n is given
+                                should_quit_after_line = true;
+                                exit_code = Some(code.unwrap_or(0));
+                                break;
+                            }
+                            Some(Address::LineNumber(_)) => {
+                                // Not at the target line yet, continue
+                            }
+                            Some(Address::LastLine) => {
+                                // Quit after processing this line
+                                should_quit_after_line = true;
+                                exit_code = Some(code.unwrap_or(0));
+                            }
+                            _ => {
+                                // Complex addresses (patterns) not yet supported - delegate to in-memory
+                                return Ok(None);
                             }
                         }
-                        Command::HoldAppend { range } => {
-                            // H - Append current line to hold space
-                            let should_apply = match &range {
-                                None => true, // No range means apply to all lines
-                                Some((start, end)) => self.should_apply_command_with_range(
-                                    &line,
-                                    &(start.clone(), end.clone()),
-                                    cmd_index,
-                                )?,
-                            };
-                            if should_apply {
-                                if !self.hold_space.is_empty() {
-                                    self.hold_space.push('\n');
+                    }
+                    // r and R queue their output to follow the line; r's
+                    // file is copied in when it's written
+                    Command::ReadFile { filename, range }
+                    | Command::ReadLine { filename, range } => {
+                        let matched = match range {
+                            None => Some(true),
+                            Some(Address::LastLine) => Some(lines.peek().is_none()),
+                            Some(address) => {
+                                self.streaming_address_matches(&line, address, cmd_index)?
+                            }
+                        };
+                        match matched {
+                            Some(true) if matches!(cmd, Command::ReadFile { .. }) => {
+                                appended.push(AppendedOutput::File(filename.clone()));
+                            }
+                            Some(true) => {
+                                if let Some(text) = self.read_next_line(filename)? {
+                                    appended.push(AppendedOutput::Text(text));
                                 }
-                                self.hold_space.push_str(&processed_line);
                             }
-                        }
-                        Command::Get { range } => {
-                            // g - Replace current line with hold space
-                            let should_apply = match &range {
-                                None => true, // No range means apply to all lines
-                                Some((start, end)) => self.should_apply_command_with_range(
-                                    &line,
-                                    &(start.clone(), end.clone()),
-                                    cmd_index,
-                                )?,
-                            };
-                            if should_apply && !self.hold_space.is_empty() {
-                                processed_line = self.hold_space.clone();
-                                line_changed = true;
+                            Some(false) => {}
+                            None => {
+                                return Ok(None);
                             }
                         }
-                        Command::GetAppend { range } => {
-                            // G - Append hold space to current line
-                            let should_apply = match &range {
-                                None => true, // No range means apply to all lines
-                                Some((start, end)) => self.should_apply_command_with_range(
-                                    &line,
-                                    &(start.clone(), end.clone()),
-                                    cmd_index,
-                                )?,
-                            };
-                            if should_apply && !self.hold_space.is_empty() {
-                                processed_line.push('\n');
-                                processed_line.push_str(&self.hold_space);
-                                line_changed = true;
-                            }
+                    }
+                    // Chunk 9: Hold space operations in streaming mode
+                    Command::Hold { range } => {
+                        // h - Copy current line to hold space (overwrite)
+                        let should_apply = match &range {
+                            None => true, // No range means apply to all lines
+                            Some((start, end)) => self.should_apply_command_with_range(
+                                &line,
+                                &(start.clone(), end.clone()),
+                                cmd_index,
+                            )?,
+                        };
+                        if should_apply {
+                            self.hold_space = processed_line.clone();
                         }
-                        Command::Exchange { range } => {
-                            // x - Swap current line with hold space
-                            let should_apply = match &range {
-                                None => true, // No range means apply to all lines
-                                Some((start, end)) => self.should_apply_command_with_range(
-                                    &line,
-                                    &(start.clone(), end.clone()),
-                                    cmd_index,
-                                )?,
-                            };
-                            if should_apply {
-                                std::mem::swap(&mut processed_line, &mut self.hold_space);
-                                line_changed = true;
+                    }
+                    Command::HoldAppend { range } => {
+                        // H - Append current line to hold space
+                        let should_apply = match &range {
+                            None => true, // No range means apply to all lines
+                            Some((start, end)) => self.should_apply_command_with_range(
+                                &line,
+                                &(start.clone(), end.clone()),
+                                cmd_index,
+                            )?,
+                        };
+                        if should_apply {
+                            if !self.hold_space.is_empty() {
+                                self.hold_space.push('\n');
                             }
+                            self.hold_space.push_str(&processed_line);
+                        }
+                    }
+                    Command::Get { range } => {
+                        // g - Replace current line with hold space
+                        let should_apply = match &range {
+                            None => true, // No range means apply to all lines
+                            Some((start, end)) => self.should_apply_command_with_range(
+                                &line,
+                                &(start.clone(), end.clone()),
+                                cmd_index,
+                            )?,
+                        };
+                        if should_apply && !self.hold_space.is_empty() {
+                            processed_line = self.hold_space.clone();
+                            line_changed = true;
+                        }
+                    }
+                    Command::GetAppend { range } => {
+                        // G - Append hold space to current line
+                        let should_apply = match &range {
+                            None => true, // No range means apply to all lines
+                            Some((start, end)) => self.should_apply_command_with_range(
+                                &line,
+                                &(start.clone(), end.clone()),
+                                cmd_index,
+                            )?,
+                        };
+                        if should_apply && !self.hold_space.is_empty() {
+                            processed_line.push('\n');
+                            processed_line.push_str(&self.hold_space);
+                            line_changed = true;
+                        }
+                    }
+                    Command::Exchange { range } => {
+                        // x - Swap current line with hold space
+                        let should_apply = match &range {
+                            None => true, // No range means apply to all lines
+                            Some((start, end)) => self.should_apply_command_with_range(
+                                &line,
+                                &(start.clone(), end.clone()),
+                                cmd_index,
+                            )?,
+                        };
+                        if should_apply {
+                            std::mem::swap(&mut processed_line, &mut self.hold_space);
+                            line_changed = true;
                         }
-                        // Chunk 10: Command grouping in streaming mode
-                        Command::Group {
-                            range,
-                            commands: group_commands,
-                        } => {
-                            // Check if we're in the group's range
-                            let should_apply = match &range {
-                                None => true, // No range means apply to all lines
-                                Some((start, end)) => self.should_apply_command_with_range(
-                                    &line,
-                                    &(start.clone(), end.clone()),
-                                    cmd_index,
-                                )?,
+                    }
+                    // Chunk 10: Command grouping in streaming mode
+                    Command::Group {
+                        range,
+                        commands: group_commands,
+                    } => {
+                        // Check if we're in the group's range
+                        let should_apply = match &range {
+                            None => true, // No range means apply to all lines
+                            Some((start, end)) => self.should_apply_command_with_range(
+                                &line,
+                                &(start.clone(), end.clone()),
+                                cmd_index,
+                            )?,
+                        };
+
+                        if should_apply {
+                            let mut streamed = StreamedLine {
+                                text: processed_line,
+                                changed: line_changed,
+                                printed,
+                                skip: skip_line,
                             };
-
-                            if should_apply {
-                                // Apply each command in the group to the current line
-                                // We need to handle the streaming semantics carefully here
-                                for group_cmd in group_commands {
-                                    match group_cmd {
-                                        Command::Substitution {
-                                            pattern,
-                                            replacement,
-                                            flags,
-                                            range,
-                                        } => {
-                                            let should_apply_sub = match range {
-                                                None => true,
-                                                Some(r) => self.should_apply_command_with_range(
-                                                    &line, r, cmd_index,
-                                                )?,
-                                            };
-                                            if should_apply_sub {
-                                                let original = processed_line.clone();
-                                                processed_line = self.apply_substitution_to_line(
-                                                    &processed_line,
-                                                    pattern,
-                                                    replacement,
-                                                    flags,
-                                                )?;
-                                                let was_changed = processed_line != original;
-                                                line_changed = line_changed || was_changed;
-
-                                                // Handle print flag in substitution (GNU sed compatible)
-                                                if was_changed && flags.print {
-                                                    print_line = true;
-                                                }
-                                            }
-                                        }
-                                        Command::Delete {
-                                            range: (start, end),
-                                        } => {
-                                            let range = (start.clone(), end.clone());
-                                            let should_delete = self
-                                                .should_apply_command_with_range(
-                                                    &line, &range, cmd_index,
-                                                )?;
-                                            if should_delete {
-                                                skip_line = true;
-                                                break; // Stop processing group commands
-                                            }
-                                        }
-                                        Command::Print {
-                                            range: (start, end),
-                                        } => {
-                                            let range = (start.clone(), end.clone());
-                                            let should_print = self
-                                                .should_apply_command_with_range(
-                                                    &line, &range, cmd_index,
-                                                )?;
-                                            if should_print {
-                                                print_line = true;
-                                            }
-                                        }
-                                        Command::Hold { range } => {
-                                            let should_apply = match &range {
-                                                None => true,
-                                                Some((start, end)) => self
-                                                    .should_apply_command_with_range(
-                                                        &line,
-                                                        &(start.clone(), end.clone()),
-                                                        cmd_index,
-                                                    )?,
-                                            };
-                                            if should_apply {
-                                                self.hold_space = processed_line.clone();
-                                            }
-                                        }
-                                        Command::HoldAppend { range } => {
-                                            let should_apply = match &range {
-                                                None => true,
-                                                Some((start, end)) => self
-                                                    .should_apply_command_with_range(
-                                                        &line,
-                                                        &(start.clone(), end.clone()),
-                                                        cmd_index,
-                                                    )?,
-                                            };
-                                            if should_apply {
-                                                if !self.hold_space.is_empty() {
-                                                    self.hold_space.push('\n');
-                                                }
-                                                self.hold_space.push_str(&processed_line);
-                                            }
-                                        }
-                                        Command::Get { range } => {
-                                            let should_apply = match &range {
-                                                None => true,
-                                                Some((start, end)) => self
-                                                    .should_apply_command_with_range(
-                                                        &line,
-                                                        &(start.clone(), end.clone()),
-                                                        cmd_index,
-                                                    )?,
-                                            };
-                                            if should_apply && !self.hold_space.is_empty() {
-                                                processed_line = self.hold_space.clone();
-                                                line_changed = true;
-                                            }
-                                        }
-                                        Command::GetAppend { range } => {
-                                            let should_apply = match &range {
-                                                None => true,
-                                                Some((start, end)) => self
-                                                    .should_apply_command_with_range(
-                                                        &line,
-                                                        &(start.clone(), end.clone()),
-                                                        cmd_index,
-                                                    )?,
-                                            };
-                                            if should_apply && !self.hold_space.is_empty() {
-                                                processed_line.push('\n');
-                                                processed_line.push_str(&self.hold_space);
-                                                line_changed = true;
-                                            }
-                                        }
-                                        Command::Exchange { range } => {
-                                            let should_apply = match &range {
-                                                None => true,
-                                                Some((start, end)) => self
-                                                    .should_apply_command_with_range(
-                                                        &line,
-                                                        &(start.clone(), end.clone()),
-                                                        cmd_index,
-                                                    )?,
-                                            };
-                                            if should_apply {
-                                                std::mem::swap(
-                                                    &mut processed_line,
-                                                    &mut self.hold_space,
-                                                );
-                                                line_changed = true;
-                                            }
-                                        }
-                                        // Other commands in groups (a, i, c, q, nested groups) delegate to in-memory
-                                        _ => {
-                                            // Delegate entire file to in-memory processing
-                                            drop(writer);
-                                            let mut processor =
-                                                FileProcessor::new(self.commands.clone());
-                                            return processor.process_file_with_context(file_path);
-                                        }
-                                    }
-                                }
+                            let supported = self.apply_group_streaming(
+                                &line,
+                                group_commands,
+                                group_keys[cmd_index],
+                                &mut streamed,
+                            )?;
+                            if !supported {
+                                // a, i, c, q etc. in groups delegate to in-memory
+                                return Ok(None);
+                            }
+                            processed_line = streamed.text;
+                            line_changed = streamed.changed;
+                            printed = streamed.printed;
+                            skip_line = streamed.skip;
+                            if skip_line {
+                                break; // d inside the group ended the cycle
                             }
-                            // After processing the group, continue to next command in the loop
-                            continue;
-                        }
-                        // Other commands not yet supported - delegate to in-memory
-                        _ => {
-                            drop(writer);
-                            let mut processor = FileProcessor::new(self.commands.clone());
-                            return processor.process_file_with_context(file_path);
                         }
+                        // After processing the group, continue to next command in the loop
+                        continue;
+                    }
+                    // Other commands not yet supported - delegate to in-memory
+                    _ => {
+                        return Ok(None);
                     }
                 }
+            }
 
-                // Handle print command (print to stdout)
-                if print_line {
-                    println!("{}", processed_line);
-                }
+            // In a pipeline, p output goes out ahead of the line, as in
+            // sed; for a file it's reported via FileDiff::printed_lines
+            let print_line = !printed.is_empty();
+            if pipe {
+                write_printed(writer, &mut printed)?;
+            } else {
+                printed_lines.append(&mut printed);
+            }
 
-                // Skip writing if line was deleted
-                if skip_line {
+            // Skip writing if line was deleted, or with -n unless it
+            // was explicitly printed (a pipeline has printed it already)
+            let write_line = if pipe {
+                !self.no_default_output && !skip_line
+            } else if self.no_default_output {
+                print_line
+            } else {
+                !skip_line
+            };
+            if !write_line {
+                if !pipe {
                     changes.push(LineChange {
                         line_number: line_num,
                         change_type: ChangeType::Deleted,
                         content: line.clone(),
                         old_content: None,
+                        spans: Vec::new(),
                     });
-                    continue; // Don't write this line
                 }
+                // What a, r and R queued still follows a line -n left out
+                if !skip_line {
+                    write_appended(writer, appended, line_num, (!pipe).then_some(&mut changes))?;
+                }
+                if should_quit_after_line {
+                    self.flush_buffer_to_changes(&mut changes);
+                    break 'outer;
+                }
+                continue; // Don't write this line
+            }
 
-                // Write the processed line
-                writeln!(writer, "{}", processed_line)
-                    .with_context(|| "Failed to write to temp file".to_string())?;
+            // Write the processed line
+            writeln!(writer, "{}", processed_line)
+                .with_context(|| "Failed to write to temp file".to_string())?;
+            ends_with_last_line = !pipe && appended.is_empty() && lines.peek().is_none();
 
-                // Track line for diff (with sliding window logic for Chunk 7)
+            // Track line for diff (with sliding window logic for Chunk 7);
+            // a pipeline keeps no diff
+            if !pipe {
                 let change_type = if line_changed {
                     ChangeType::Modified
                 } else {
@@ -1190,7 +3218,7 @@ impl StreamProcessor {
                 };
 
                 // Sliding window logic (Chunk 7)
-                let is_changed = line_changed || skip_line || append_text.is_some();
+                let is_changed = line_changed || skip_line || !appended.is_empty();
 
                 if is_changed {
                     // CHANGE DETECTED: Flush buffer (previous context) + add changed line
@@ -1202,6 +3230,7 @@ impl StreamProcessor {
                         change_type,
                         content: processed_line,
                         old_content: if line_changed { Some(line) } else { None },
+                        spans: Vec::new(),
                     });
 
                     // Set flag to read next context_size lines as context
@@ -1213,6 +3242,7 @@ impl StreamProcessor {
                         change_type,
                         content: processed_line,
                         old_content: None,
+                        spans: Vec::new(),
                     });
                     self.context_lines_to_read -= 1;
                 } else {
@@ -1228,62 +3258,33 @@ impl StreamProcessor {
                         self.context_buffer.pop_front();
                     }
                 }
-
-                // Handle append command - write appended text after the current line
-                if let Some(text) = &append_text {
-                    writeln!(writer, "{}", text)
-                        .with_context(|| "Failed to write appended line")?;
-                    // Track the appended line for diff
-                    changes.push(LineChange {
-                        line_number: line_num + 1,
-                        change_type: ChangeType::Added,
-                        content: text.clone(),
-                        old_content: None,
-                    });
-                }
-
-                // Check if we should quit after processing this line
-                if should_quit_after_line {
-                    // Flush remaining buffer before quitting
-                    self.flush_buffer_to_changes(&mut changes);
-                    break 'outer;
-                }
             }
 
-            // Flush remaining buffer (unchanged lines at the end of file)
-            self.flush_buffer_to_changes(&mut changes);
-
-            // Ensure all data is written to disk
-            writer
-                .flush()
-                .with_context(|| "Failed to flush temp file")?;
-        } // writer dropped here
+            // Write what a, c, r and R queued after the current line
+            write_appended(writer, appended, line_num, (!pipe).then_some(&mut changes))?;
 
-        // Atomic rename: temp file becomes the actual file
-        // In dry-run mode, don't persist (temp file will be automatically deleted when dropped)
-        if !self.dry_run {
-            temp_file.persist(file_path).with_context(|| {
-                format!("Failed to persist temp file to {}", file_path.display())
-            })?;
+            // Check if we should quit after processing this line
+            if should_quit_after_line {
+                // Flush remaining buffer before quitting
+                self.flush_buffer_to_changes(&mut changes);
+                break 'outer;
+            }
         }
-        // If dry_run, temp_file is dropped here and automatically deleted
 
-        // Build FileDiff result
-        // NOTE: In streaming mode, we don't populate all_lines to save memory
-        // The diff formatter will handle this differently for streaming mode
-        let all_lines = Vec::new(); // Empty in streaming mode
+        // Flush remaining buffer (unchanged lines at the end of file)
+        self.flush_buffer_to_changes(&mut changes);
 
-        Ok(FileDiff {
-            file_path: file_path.display().to_string(),
+        Ok(Some(StreamedRun {
             changes,
-            all_lines,
-            printed_lines: Vec::new(),
-            is_streaming: true, // Streaming mode
-        })
+            printed_lines,
+            exit_code,
+            ends_with_last_line,
+        }))
     }
 }
 
 impl FileProcessor {
+    #[allow(dead_code)] // Part of public API for library users
     pub fn new(commands: Vec<Command>) -> Self {
         Self::with_regex_flavor(commands, crate::cli::RegexFlavor::PCRE)
     }
@@ -1306,34 +3307,242 @@ impl FileProcessor {
             write_handles: HashMap::new(),
             read_positions: HashMap::new(),
             regex_flavor,
+            cycle_trace: None,
+            allow_exec: false,
+            compat_mode: CompatMode::default(),
+            regex_cache: RegexCache::default(),
+            current_filename: String::from("-"),
+            input_segments: Vec::new(),
+            cycle_ends: None,
+            max_count: None,
+            memory_limit: None,
+            context_size: 2,
+            deadline: None,
+            spill: None,
+            slurp: false,
+            paragraph: false,
+            output_spans: HashMap::new(),
+            exit_code: None,
+        }
+    }
+
+    /// Compiled regex for `pattern`, compiling it on first use
+    fn regex(&self, pattern: &str, case_insensitive: bool, multiline: bool) -> Result<Regex> {
+        self.regex_cache
+            .get(pattern, self.regex_flavor, case_insensitive, multiline)
+    }
+
+    /// Build a registry mapping label names to command indices (Phase 5)
+    /// This allows the b/t/T commands to jump to specific commands
+    fn build_label_registry(commands: &[Command]) -> HashMap<String, usize> {
+        let mut registry = HashMap::new();
+
+        for (idx, cmd) in commands.iter().enumerate() {
+            if let Command::Label { name } = cmd {
+                // Register the label with the NEXT command's index
+                // Labels are placed BEFORE the command they label
+                // e.g., ":loop s/foo/bar/" - "loop" labels the s command
+                if idx + 1 < commands.len() {
+                    registry.insert(name.clone(), idx + 1);
+                } else {
+                    // Label at end of script: branch to end
+                    registry.insert(name.clone(), commands.len());
+                }
+            }
+        }
+
+        registry
+    }
+
+    /// Set the -n flag (suppress automatic output)
+    pub fn set_no_default_output(&mut self, value: bool) {
+        self.no_default_output = value;
+    }
+
+    /// Allow the s///e flag to execute shell commands (--allow-exec)
+    pub fn set_allow_exec(&mut self, value: bool) {
+        self.allow_exec = value;
+    }
+
+    /// Choose GNU or POSIX behavior where the two differ
+    pub fn set_compat_mode(&mut self, mode: CompatMode) {
+        self.compat_mode = mode;
+    }
+
+    /// Make case-insensitive matching fold ASCII letters only (--ascii-case)
+    pub fn set_ascii_case(&mut self, value: bool) {
+        self.regex_cache.ascii_case = value;
+    }
+
+    /// Stop substituting once `budget` is spent (--max-count)
+    pub fn set_max_count(&mut self, budget: Option<SubstitutionBudget>) {
+        self.max_count = budget;
+    }
+
+    /// Treat each input as a single record, so the script sees the whole
+    /// file in the pattern space (--slurp)
+    pub fn set_slurp(&mut self, value: bool) {
+        self.slurp = value;
+    }
+
+    /// Treat each blank-line-separated paragraph as a single record
+    /// (--paragraph)
+    pub fn set_paragraph(&mut self, value: bool) {
+        self.paragraph = value;
+    }
+
+    /// Run files whose in-memory processing is estimated to need more than
+    /// `bytes` (config `processing.max_memory_mb`) from disk instead, or
+    /// refuse them if the script can't run that way
+    pub fn set_memory_limit(&mut self, bytes: Option<u64>) {
+        self.memory_limit = bytes;
+    }
+
+    /// Unchanged lines to keep around each change in the diff of a file
+    /// run from disk, which has no full listing to take context from
+    pub fn set_context_size(&mut self, size: usize) {
+        self.context_size = size;
+    }
+
+    /// Give up with a `TimedOut` error once `deadline` passes (--timeout)
+    pub fn set_deadline(&mut self, deadline: Option<Deadline>) {
+        self.deadline = deadline;
+    }
+
+    /// Name F prints for input that isn't read from a file, instead of "-"
+    /// (--stdin-filename)
+    pub fn set_input_name(&mut self, name: &str) {
+        self.current_filename = name.to_string();
+    }
+
+    /// Whether `file_path` is over the memory limit but the script can run
+    /// on it from disk: one record per line through the cycle engine, which
+    /// reads its input in order (the batch engine needs every line loaded)
+    fn should_spill(&self, file_path: &Path) -> bool {
+        !self.slurp
+            && !self.paragraph
+            && self.uses_cycle_engine()
+            && self.check_memory_limit(&[file_path]).is_err()
+    }
+
+    /// Whether the script runs in the cycle engine. A cycle trace is
+    /// only written there, so a traced run uses it even for i and a.
+    fn uses_cycle_engine(&self) -> bool {
+        self.cycle_trace.is_some() || Self::supports_cycle_based_processing(&self.commands)
+    }
+
+    /// Fail with advice if loading `file_paths` would exceed the memory limit
+    fn check_memory_limit(&self, file_paths: &[&Path]) -> Result<()> {
+        let Some(limit) = self.memory_limit else {
+            return Ok(());
+        };
+
+        let mut size: u64 = 0;
+        for path in file_paths {
+            let metadata = fs::metadata(path)
+                .with_context(|| format!("Failed to read file metadata: {}", path.display()))?;
+            size += metadata.len();
+        }
+        let estimate = size.saturating_mul(IN_MEMORY_COPIES);
+        if estimate <= limit {
+            return Ok(());
+        }
+
+        let input = match file_paths {
+            [path] => path.display().to_string(),
+            _ => format!("{} files", file_paths.len()),
+        };
+        anyhow::bail!(
+            "Processing {} ({}) in memory needs about {}, over the {} limit (processing.max_memory_mb).\n\
+             Run with --explain to see what keeps the script from streaming, or raise the limit:\n  \
+             sedx config set processing.max_memory_mb {}",
+            input,
+            DiskSpaceInfo::bytes_to_human(size),
+            DiskSpaceInfo::bytes_to_human(estimate),
+            DiskSpaceInfo::bytes_to_human(limit),
+            estimate.div_ceil(1024 * 1024).max(10)
+        );
+    }
+
+    /// Run a shell command for the e command or the s///e flag
+    fn exec_shell(&self, command: &str) -> Result<String> {
+        if !self.allow_exec {
+            anyhow::bail!(
+                "The e command and s///e flag execute shell commands and are disabled by default.\n\
+                 Use --allow-exec to enable them."
+            );
         }
+        run_shell_command(command)
     }
 
-    /// Build a registry mapping label names to command indices (Phase 5)
-    /// This allows the b/t/T commands to jump to specific commands
-    fn build_label_registry(commands: &[Command]) -> HashMap<String, usize> {
-        let mut registry = HashMap::new();
+    /// Append a line to a w/W/s///w output file. Each file is created
+    /// (truncated) the first time it is written and kept open for the run.
+    fn write_line_to(&mut self, filename: &str, text: &str) -> Result<()> {
+        if !self.write_handles.contains_key(filename) {
+            let file = std::fs::File::create(filename)
+                .with_context(|| format!("Failed to create file: {}", filename))?;
+            self.write_handles
+                .insert(filename.to_string(), BufWriter::new(file));
+        }
+        let writer = self
+            .write_handles
+            .get_mut(filename)
+            .expect("handle opened above");
+        writeln!(writer, "{}", text)
+            .with_context(|| format!("Failed to write to file: {}", filename))?;
+        writer
+            .flush()
+            .with_context(|| format!("Failed to flush file: {}", filename))
+    }
 
-        for (idx, cmd) in commands.iter().enumerate() {
-            if let Command::Label { name } = cmd {
-                // Register the label with the NEXT command's index
-                // Labels are placed BEFORE the command they label
-                // e.g., ":loop s/foo/bar/" - "loop" labels the s command
-                if idx + 1 < commands.len() {
-                    registry.insert(name.clone(), idx + 1);
-                } else {
-                    // Label at end of script: branch to end
-                    registry.insert(name.clone(), commands.len());
-                }
-            }
+    /// Batch-mode tail of a substitution: apply the e, p and w flags to a
+    /// line whose content changed
+    fn finish_batch_substitution(
+        &mut self,
+        line: &mut String,
+        original: &str,
+        flags: &SubstitutionFlags,
+    ) -> Result<()> {
+        if *line == original {
+            return Ok(());
+        }
+        if !substitution_allowed(&self.max_count) {
+            *line = original.to_string();
+            return Ok(());
+        }
+        if flags.exec {
+            *line = self.exec_shell(line)?;
+        }
+        if flags.print {
+            self.printed_lines.push(line.clone());
+        }
+        if let Some(filename) = &flags.write_file {
+            self.write_line_to(filename, line)?;
         }
+        Ok(())
+    }
 
-        registry
+    /// Record every command executed by the cycle engine to `file` as JSON lines
+    pub fn set_cycle_trace(&mut self, file: File) {
+        self.cycle_trace = Some(BufWriter::new(file));
     }
 
-    /// Set the -n flag (suppress automatic output)
-    pub fn set_no_default_output(&mut self, value: bool) {
-        self.no_default_output = value;
+    /// Append one record to the cycle trace (no-op when tracing is off)
+    fn write_cycle_trace(&mut self, record: &CycleTraceRecord) -> Result<()> {
+        if let Some(writer) = self.cycle_trace.as_mut() {
+            serde_json::to_writer(&mut *writer, record)
+                .context("Failed to serialize cycle trace record")?;
+            writeln!(writer).context("Failed to write cycle trace")?;
+        }
+        Ok(())
+    }
+
+    /// Flush buffered cycle trace records to disk
+    fn flush_cycle_trace(&mut self) -> Result<()> {
+        if let Some(writer) = self.cycle_trace.as_mut() {
+            writer.flush().context("Failed to flush cycle trace")?;
+        }
+        Ok(())
     }
 
     /// Get the lines that were printed by print commands (for quiet mode)
@@ -1342,10 +3551,22 @@ impl FileProcessor {
         &self.printed_lines
     }
 
+    /// Exit status asked for by the q or Q command that ended the last run
+    /// (`5q3` asks for 3), or None if the script didn't quit
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
     /// Check if all commands support cycle-based processing
-    fn supports_cycle_based_processing(commands: &[Command]) -> bool {
+    pub fn supports_cycle_based_processing(commands: &[Command]) -> bool {
         use Command::*;
 
+        // D has to restart the cycle, which only the cycle engine can do,
+        // so it handles i and a too in scripts that use D
+        if uses_delete_first_line(commands) {
+            return true;
+        }
+
         for cmd in commands {
             match cmd {
                 // Supported commands (Phase 5: flow control + file I/O commands now supported)
@@ -1363,7 +3584,6 @@ impl FileProcessor {
                 | Get { .. }
                 | GetAppend { .. }
                 | Exchange { .. }
-                | Group { .. }
                 | Label { .. }
                 | Branch { .. }
                 | Test { .. }
@@ -1374,11 +3594,20 @@ impl FileProcessor {
                 | WriteFirstLine { .. }
                 | PrintLineNumber { .. }
                 | PrintFilename { .. }
-                | ClearPatternSpace { .. } => {
+                | ClearPatternSpace { .. }
+                | List { .. }
+                | Execute { .. }
+                | Change { .. } => {
                     // Supported (Phase 5: flow control + file I/O + additional commands added)
                 }
+                // A group runs in the cycle engine if everything inside it does
+                Group { commands, .. } => {
+                    if !Self::supports_cycle_based_processing(commands) {
+                        return false;
+                    }
+                }
                 // Unsupported commands (fall back to batch processing)
-                Insert { .. } | Append { .. } | Change { .. } => {
+                Insert { .. } | Append { .. } => {
                     return false;
                 }
             }
@@ -1406,14 +3635,84 @@ impl FileProcessor {
 
     /// New method - returns detailed diff with context
     pub fn process_file_with_context(&mut self, file_path: &Path) -> Result<FileDiff> {
-        let content = fs::read_to_string(file_path)
-            .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+        if self.should_spill(file_path) {
+            return self.run_spilled(file_path, true).map(|(diff, _)| diff);
+        }
+        self.process_file_with_output(file_path)
+            .map(|(diff, _)| diff)
+    }
+
+    /// Preview `file_path` in memory, returning its new lines along with
+    /// the diff, so they can be written without running the script again
+    /// (a script that runs shell commands must run them only once)
+    pub fn process_file_with_output(
+        &mut self,
+        file_path: &Path,
+    ) -> Result<(FileDiff, Vec<String>)> {
+        self.check_memory_limit(&[file_path])?;
+        let content = read_text_file(file_path)?;
 
         let original_lines: Vec<&str> = content.lines().collect();
         let input_lines: Vec<String> = original_lines.iter().map(|s| s.to_string()).collect();
 
+        self.current_filename = file_path.display().to_string();
+        let modified_lines = self.run_script(input_lines)?;
+
+        let mut diff = FileDiff::between(
+            &file_path.display().to_string(),
+            &original_lines,
+            &modified_lines,
+        );
+        // Without -n, p output is written along with the other lines, so
+        // the diff shows it; with -n it is the only output
+        if self.no_default_output {
+            diff.printed_lines = self.printed_lines.clone();
+        }
+        diff.exit_code = self.exit_code;
+        // Records are output lines unless they were regrouped
+        if !self.slurp && !self.paragraph {
+            diff.attach_spans(&self.output_spans);
+        }
+        Ok((diff, modified_lines))
+    }
+
+    pub fn apply_to_file(&mut self, file_path: &Path) -> Result<usize> {
+        if self.should_spill(file_path) {
+            return self.run_spilled(file_path, false).map(|(_, lines)| lines);
+        }
+        self.check_memory_limit(&[file_path])?;
+        let content = read_text_file(file_path)?;
+
+        let input_lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+        self.current_filename = file_path.display().to_string();
+        let lines = self.run_script(input_lines)?;
+
+        write_lines_like(file_path, &content, &lines)?;
+
+        Ok(lines.len())
+    }
+
+    /// Run the script over one file's lines with fresh state, the same way
+    /// for preview and apply
+    fn run_script(&mut self, input_lines: Vec<String>) -> Result<Vec<String>> {
+        if self.slurp {
+            let output = self.run_records(slurp_lines(input_lines))?;
+            return Ok(unslurp_lines(output));
+        }
+        if self.paragraph {
+            let (records, layout) = split_paragraphs(input_lines);
+            let output = self.run_records(records)?;
+            return Ok(join_paragraphs(output, &layout));
+        }
+        self.run_records(input_lines)
+    }
+
+    /// Run the script over `input_lines`, one record per line
+    fn run_records(&mut self, input_lines: Vec<String>) -> Result<Vec<String>> {
         // Clear printed lines from previous run
         self.printed_lines.clear();
+        self.output_spans.clear();
+        self.exit_code = None;
         // Reset hold space for each file
         self.hold_space.clear();
         // Reset pattern space for each file
@@ -1421,107 +3720,209 @@ impl FileProcessor {
         self.current_line_index = 0;
 
         // Choose processing method based on command support
-        let use_cycle_based = Self::supports_cycle_based_processing(&self.commands);
-
-        let modified_lines = if use_cycle_based {
+        if self.uses_cycle_engine() {
             // Use cycle-based processing (supports multi-line commands like n, N, P, D)
-            self.apply_cycle_based(input_lines)?
-        } else {
-            // Fall back to batch processing (for i, a, c, { } commands)
-            let mut lines = input_lines.clone();
-            let commands = self.commands.clone();
-            for cmd in &commands {
-                let should_continue = self.apply_command(&mut lines, cmd)?;
-                if !should_continue {
-                    break; // Quit command encountered
-                }
+            return self.apply_cycle_based(input_lines);
+        }
+
+        // Fall back to batch processing (for i, a, c commands)
+        let mut lines = input_lines;
+        let commands = self.commands.clone();
+        for cmd in &commands {
+            check_deadline(&self.deadline)?;
+            let should_continue = self.apply_command(&mut lines, cmd)?;
+            if !should_continue {
+                break; // Quit command encountered
             }
-            lines
-        };
+        }
+        Ok(lines)
+    }
 
-        // Clone modified_lines for diff generation (to avoid borrow issues)
-        let modified_lines_clone = modified_lines.clone();
+    /// Apply only some of the script's changes to `file_path`: the hunks
+    /// of its diff whose indices are in `accepted` (see `FileDiff::hunks`)
+    pub fn apply_selected_changes(&mut self, file_path: &Path, accepted: &[usize]) -> Result<()> {
+        let diff = self.process_file_with_context(file_path)?;
+        if diff.is_streaming {
+            anyhow::bail!(
+                "{} is too large to apply only some of its changes",
+                file_path.display()
+            );
+        }
+        write_selected_hunks(file_path, &diff, accepted)
+    }
 
-        // Generate detailed diff using simple comparison
-        let all_lines = self.generate_simple_diff(&original_lines, &modified_lines_clone);
+    /// Run the script over a file too large to load (see `should_spill`):
+    /// the cycle engine reads its lines from disk as it goes and writes
+    /// each cycle's output to a temp file beside it, which replaces the
+    /// file unless this is a preview. The diff keeps only changes and the
+    /// lines around them, like a streaming one.
+    ///
+    /// Returns the diff and the number of lines written
+    fn run_spilled(&mut self, file_path: &Path, preview: bool) -> Result<(FileDiff, usize)> {
+        let parent_dir = file_path.parent().unwrap_or(Path::new("."));
+        let temp_file = NamedTempFile::new_in(parent_dir)
+            .with_context(|| format!("Failed to create temp file in {}", parent_dir.display()))?;
+        let input = File::open(file_path)
+            .with_context(|| format!("Failed to open file: {}", file_path.display()))?;
+        let writer = BufWriter::new(LineEndingWriter {
+            inner: temp_file
+                .as_file()
+                .try_clone()
+                .with_context(|| "Failed to open temp file")?,
+            crlf: first_line_is_crlf(file_path)?,
+        });
 
-        // Collect only changed lines for summary
-        let changes: Vec<LineChange> = all_lines
-            .iter()
-            .filter(|(_, _, change_type)| *change_type != ChangeType::Unchanged)
-            .map(|(line_num, content, change_type)| {
-                let old_content = if *change_type == ChangeType::Modified {
-                    original_lines.get(line_num - 1).map(|s| s.to_string())
-                } else {
-                    None
-                };
+        self.printed_lines.clear();
+        self.output_spans.clear();
+        self.exit_code = None;
+        self.hold_space.clear();
+        self.pattern_space = None;
+        self.current_line_index = 0;
+        self.current_filename = file_path.display().to_string();
+        self.spill = Some(SpilledOutput::new(writer, preview, self.context_size));
+
+        let mut state = CycleState::new(String::new(), Vec::new(), self.current_filename.clone());
+        state.line_iter = LineIterator::from_reader(BufReader::new(input));
+        let run = self.run_cycles(&mut state);
+        let mut spill = self.spill.take().expect("spill output set above");
+        run?;
+
+        // Lines a q left unread are dropped, as in memory
+        while state.line_iter.read_next().is_some() {
+            spill.end_cycle(&state.line_iter.take_read(), Vec::new(), &HashMap::new())?;
+        }
+        if let Some(err) = state.line_iter.take_error() {
+            return Err(err)
+                .with_context(|| format!("Failed to read file: {}", file_path.display()));
+        }
+        let lines_written = spill.lines_written;
+        let ends_with_last_line = spill.ends_with_last_line;
+        let changes = spill.finish()?;
+
+        // Every line was written with a newline; drop the last one if the
+        // input had none and its last line is still last
+        if ends_with_last_line && !ends_with_newline(file_path)? {
+            let file = temp_file.as_file();
+            let len = file
+                .metadata()
+                .with_context(|| "Failed to read temp file metadata")?
+                .len();
+            let newline_len = if first_line_is_crlf(file_path)? { 2 } else { 1 };
+            if len >= newline_len {
+                file.set_len(len - newline_len)
+                    .with_context(|| "Failed to truncate temp file")?;
+            }
+        }
 
-                LineChange {
-                    line_number: *line_num,
-                    change_type: change_type.clone(),
-                    content: content.clone(),
-                    old_content,
-                }
-            })
-            .collect();
+        if !preview {
+            let metadata = fs::metadata(file_path)
+                .with_context(|| format!("Failed to read metadata: {}", file_path.display()))?;
+            restore_metadata(temp_file.as_file(), &metadata)?;
+            temp_file.persist(file_path).with_context(|| {
+                format!("Failed to persist temp file to {}", file_path.display())
+            })?;
+        }
 
-        Ok(FileDiff {
+        let diff = FileDiff {
             file_path: file_path.display().to_string(),
             changes,
-            all_lines,
-            printed_lines: self.printed_lines.clone(),
-            is_streaming: false, // In-memory mode
-        })
+            all_lines: Vec::new(),
+            printed_lines: if self.no_default_output {
+                self.printed_lines.clone()
+            } else {
+                Vec::new()
+            },
+            is_streaming: true,
+            exit_code: self.exit_code,
+        };
+        Ok((diff, lines_written))
     }
 
-    fn generate_simple_diff(
-        &self,
-        original: &[&str],
-        modified: &[String],
-    ) -> Vec<(usize, String, ChangeType)> {
-        let mut result = Vec::new();
-
-        // Simple line-by-line comparison for now
-        let max_len = original.len().max(modified.len());
-
-        for i in 0..max_len {
-            if i < original.len() && i < modified.len() {
-                if original[i] == modified[i].as_str() {
-                    result.push((i + 1, original[i].to_string(), ChangeType::Unchanged));
-                } else {
-                    result.push((i + 1, modified[i].clone(), ChangeType::Modified));
-                }
-            } else if i < original.len() {
-                // Line was deleted
-                result.push((i + 1, original[i].to_string(), ChangeType::Deleted));
-            } else {
-                // Line was added
-                result.push((i + 1, modified[i].clone(), ChangeType::Added));
+    /// Run the script over several files as one input stream, like GNU sed
+    /// without -s: line numbers, `$`, ranges and the hold space continue
+    /// across files. Each cycle's output goes to the file holding the last
+    /// line it read; files after a `q` are left as they were. A file that
+    /// can't be read is left out of the stream and gets its error instead.
+    ///
+    /// Returns the diff and new lines of each file, in order
+    pub fn process_files_concatenated(
+        &mut self,
+        file_paths: &[PathBuf],
+    ) -> Result<Vec<Result<FileOutput>>> {
+        // Missing files are reported when they're read below
+        let paths: Vec<&Path> = file_paths
+            .iter()
+            .map(PathBuf::as_path)
+            .filter(|path| path.exists())
+            .collect();
+        self.check_memory_limit(&paths)?;
+
+        let mut contents = Vec::with_capacity(file_paths.len());
+        let mut input = Vec::new();
+        self.input_segments.clear();
+        for path in file_paths {
+            let content = read_text_file(path);
+            if let Ok(content) = &content {
+                self.input_segments
+                    .push((input.len() + 1, path.display().to_string()));
+                input.extend(content.lines().map(str::to_string));
             }
+            contents.push(content);
         }
+        let total_lines = input.len();
 
-        result
-    }
-
-    pub fn apply_to_file(&mut self, file_path: &Path) -> Result<usize> {
-        let content = fs::read_to_string(file_path)
-            .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
-
-        let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-
-        let commands = self.commands.clone();
-        for cmd in &commands {
-            let should_continue = self.apply_command(&mut lines, cmd)?;
-            if !should_continue {
-                break; // Quit command encountered
+        self.printed_lines.clear();
+        self.exit_code = None;
+        self.hold_space.clear();
+        self.pattern_space = None;
+        self.current_line_index = 0;
+        self.cycle_ends = Some(Vec::new());
+        let output = self.apply_cycle_based(input);
+        let cycle_ends = self.cycle_ends.take().unwrap_or_default();
+        let segments = std::mem::take(&mut self.input_segments);
+        let output = output?;
+        let last_line = cycle_ends.last().map_or(0, |&(line, _, _)| line);
+
+        let mut results = Vec::with_capacity(file_paths.len());
+        let (mut output_start, mut printed_start) = (0, 0);
+        let mut segment = 0;
+        for (path, content) in file_paths.iter().zip(contents) {
+            let content = match content {
+                Ok(content) => content,
+                Err(e) => {
+                    results.push(Err(e));
+                    continue;
+                }
+            };
+            let original_lines: Vec<&str> = content.lines().collect();
+            let first_line = segments[segment].0;
+
+            let (lines, printed) = if first_line > last_line {
+                // Never reached: processing stopped in an earlier file
+                let lines = original_lines.iter().map(|s| s.to_string()).collect();
+                (lines, Vec::new())
+            } else {
+                let end_line = segments.get(segment + 1).map_or(total_lines, |s| s.0 - 1);
+                let ended = cycle_ends.partition_point(|&(line, _, _)| line <= end_line);
+                let (output_end, printed_end) = ended
+                    .checked_sub(1)
+                    .map_or((0, 0), |j| (cycle_ends[j].1, cycle_ends[j].2));
+                let lines = output[output_start..output_end].to_vec();
+                let printed = self.printed_lines[printed_start..printed_end].to_vec();
+                output_start = output_end;
+                printed_start = printed_end;
+                (lines, printed)
+            };
+            segment += 1;
+
+            let mut diff = FileDiff::between(&path.display().to_string(), &original_lines, &lines);
+            if self.no_default_output {
+                diff.printed_lines = printed;
             }
+            diff.exit_code = self.exit_code;
+            results.push(Ok((diff, lines)));
         }
-
-        let new_content = lines.join("\n") + "\n";
-        fs::write(file_path, new_content)
-            .with_context(|| format!("Failed to write file: {}", file_path.display()))?;
-
-        Ok(lines.len())
+        Ok(results)
     }
 
     // ============================================================================
@@ -1533,15 +3934,39 @@ impl FileProcessor {
     ///
     /// Matches GNU sed execute.c:1685 (main loop) + execute_program (command loop)
     pub fn apply_cycle_based(&mut self, lines: Vec<String>) -> Result<Vec<String>> {
-        let mut state = CycleState::new(self.hold_space.clone(), lines, String::from("(stdin)"));
+        let mut state = CycleState::new(
+            self.hold_space.clone(),
+            lines,
+            self.current_filename.clone(),
+        );
+        self.run_cycles(&mut state)
+    }
+
+    /// Run every cycle of the script over `state`'s input. In a spilled
+    /// run, each cycle's output goes to disk when it ends and none is
+    /// returned.
+    fn run_cycles(&mut self, state: &mut CycleState) -> Result<Vec<String>> {
         let mut output = Vec::new();
 
         // Outer loop: read each line into pattern space (matches execute.c:1685)
         while let Some(line) = state.line_iter.current_line() {
+            check_deadline(&self.deadline)?;
             state.pattern_space = line;
+            state.spans.clear();
             state.line_num += 1;
             state.substitution_made = false; // Phase 5: Reset substitution flag at start of cycle
 
+            // Concatenated input: F names the file this line came from
+            if let Some((_, name)) = self
+                .input_segments
+                .iter()
+                .rev()
+                .find(|(first, _)| *first <= state.line_num)
+                && *name != state.current_filename
+            {
+                state.current_filename = name.clone();
+            }
+
             // Clone commands to avoid borrow checker issues
             let commands = self.commands.clone();
             let num_commands = commands.len();
@@ -1558,13 +3983,32 @@ impl FileProcessor {
                 }
 
                 // Check if command applies to current cycle state
-                if !self.should_apply_to_cycle(cmd, &mut state) {
+                if !self.should_apply_to_cycle(cmd, state) {
                     pc += 1;
                     continue;
                 }
 
+                // Snapshot spaces for --dump-cycle-trace before the command runs
+                let spaces_before = self
+                    .cycle_trace
+                    .is_some()
+                    .then(|| (state.pattern_space.clone(), state.hold_space.clone()));
+
                 // Apply command to pattern space
-                let result = self.apply_command_to_cycle(cmd, &mut state)?;
+                let result = self.apply_command_to_cycle(cmd, state)?;
+
+                if let Some((pattern_space_before, hold_space_before)) = spaces_before {
+                    self.write_cycle_trace(&CycleTraceRecord {
+                        line_number: state.line_num,
+                        command_index: pc,
+                        command: cmd.clone(),
+                        result: format!("{:?}", result),
+                        pattern_space_before,
+                        pattern_space_after: state.pattern_space.clone(),
+                        hold_space_before,
+                        hold_space_after: state.hold_space.clone(),
+                    })?;
+                }
 
                 // Handle cycle result (matches execute.c switch statement)
                 match result {
@@ -1574,6 +4018,7 @@ impl FileProcessor {
                     }
                     CycleResult::Branch(target_pc) => {
                         // Jump to target command (Phase 5: b/t/T commands)
+                        check_deadline(&self.deadline)?;
                         pc = target_pc;
                     }
                     CycleResult::DeleteLine => {
@@ -1582,10 +4027,24 @@ impl FileProcessor {
                         break;
                     }
                     CycleResult::RestartCycle => {
-                        // Restart command loop from beginning (matches D command)
+                        // Restart command loop from beginning (matches D command).
+                        // Output so far goes out first, as a new cycle would
+                        // write it, but the pattern space isn't printed.
+                        output.append(&mut state.stdout_outputs);
+                        for side_effect in state.side_effects.drain(..) {
+                            output.push(side_effect.clone());
+                            self.printed_lines.push(side_effect);
+                        }
+                        output.append(&mut state.appended);
+                        for file_read in state.file_reads.drain(..) {
+                            output.push(file_read.clone());
+                            self.printed_lines.push(file_read);
+                        }
+                        check_deadline(&self.deadline)?;
                         pc = 0;
                     }
-                    CycleResult::Quit(_code) => {
+                    CycleResult::Quit(code) => {
+                        self.exit_code = Some(code);
                         // Add side effects before quitting
                         for side_effect in state.side_effects.drain(..) {
                             output.push(side_effect.clone());
@@ -1595,8 +4054,18 @@ impl FileProcessor {
                         for stdout_output in state.stdout_outputs.drain(..) {
                             output.push(stdout_output);
                         }
+                        // q prints the pattern space on its way out; Q doesn't
+                        if !state.deleted && !self.no_default_output {
+                            self.record_output_spans(state, output.len());
+                            output.push(state.pattern_space.clone());
+                        }
+                        if let Some(ends) = &mut self.cycle_ends {
+                            ends.push((state.line_num, output.len(), self.printed_lines.len()));
+                        }
+                        self.end_spilled_cycle(state, &mut output)?;
                         // Update hold space from final state
                         self.hold_space = state.hold_space.clone();
+                        self.flush_cycle_trace()?;
                         // Return output early (quit program)
                         return Ok(output);
                     }
@@ -1621,9 +4090,18 @@ impl FileProcessor {
             // Add pattern space to output (unless deleted or in quiet mode)
             // This is the default output at the end of the cycle
             if !state.deleted && !self.no_default_output {
+                self.record_output_spans(state, output.len());
                 output.push(state.pattern_space.clone());
             }
 
+            // c text takes the pattern space's place, even with -n
+            if let Some(text) = state.change_text.take() {
+                output.extend(text.split('\n').map(String::from));
+            }
+
+            // a text comes out after the pattern space, even with -n
+            output.append(&mut state.appended);
+
             // Add file read outputs (r, R commands) - these are printed AFTER the pattern space
             // This matches GNU sed behavior where r command output appears after the current line
             for file_read in state.file_reads.drain(..) {
@@ -1633,10 +4111,16 @@ impl FileProcessor {
 
             // Reset deletion flag for next cycle
             state.deleted = false;
+
+            if let Some(ends) = &mut self.cycle_ends {
+                ends.push((state.line_num, output.len(), self.printed_lines.len()));
+            }
+            self.end_spilled_cycle(state, &mut output)?;
         }
 
         // Update hold space from final state
         self.hold_space = state.hold_space.clone();
+        self.flush_cycle_trace()?;
 
         Ok(output)
     }
@@ -1716,11 +4200,23 @@ impl FileProcessor {
                 Some((start, end)) => self.check_range_inclusive(state, start, end),
             },
 
-            // Insert/Append/Change handle their own addresses
-            Command::Insert { .. } | Command::Append { .. } | Command::Change { .. } => true,
+            Command::List { range, .. } => match range {
+                None => true,
+                Some((start, end)) => self.check_range_inclusive(state, start, end),
+            },
+
+            Command::Execute { range, .. } => match range {
+                None => true,
+                Some((start, end)) => self.check_range_inclusive(state, start, end),
+            },
+
+            Command::Change { range, .. } => self.check_range_inclusive(state, &range.0, &range.1),
+
+            // Insert/Append handle their own addresses
+            Command::Insert { .. } | Command::Append { .. } => true,
 
             // Quit commands: check address if present
-            Command::Quit { address } | Command::QuitWithoutPrint { address } => {
+            Command::Quit { address, .. } | Command::QuitWithoutPrint { address, .. } => {
                 match address {
                     None => true, // No address = quit immediately
                     Some(addr) => self.address_matches_cycle(addr, state),
@@ -1781,7 +4277,7 @@ impl FileProcessor {
 
             Address::Pattern(pattern) => {
                 // Check if current pattern space matches the pattern
-                if let Ok(re) = Regex::new(pattern) {
+                if let Ok(re) = self.regex(pattern, false, false) {
                     re.is_match(&state.pattern_space)
                 } else {
                     false
@@ -1790,11 +4286,8 @@ impl FileProcessor {
 
             Address::FirstLine => state.line_num == 1,
 
-            Address::LastLine => {
-                // In cycle mode, we don't know the total line count yet
-                // For now, assume this matches (will be refined when needed)
-                true
-            }
+            // The current line is the last one once the input is exhausted
+            Address::LastLine => state.line_iter.is_eof(),
 
             Address::Negated(inner) => {
                 // Negation: match if inner address doesn't match
@@ -1820,6 +4313,8 @@ impl FileProcessor {
                     false
                 }
             }
+
+            Address::MultipleOf(n) => ends_at_multiple(state.line_num, *n),
         }
     }
 
@@ -1832,61 +4327,93 @@ impl FileProcessor {
         end: &Address,
     ) -> bool {
         match (start, end) {
-            // Line number range: 1,3
-            (Address::LineNumber(start_line), Address::LineNumber(end_line)) => {
-                // Special case: single line address (start == end)
-                if start_line == end_line {
-                    return state.line_num == *start_line;
-                }
+            // A single address parses as a range from itself to itself
+            _ if start == end => self.address_matches_cycle(start, state),
 
-                // Multi-line range: use state tracking
-                let key = (*start_line, *end_line);
-                let (in_range, ended) =
-                    state.line_range_states.entry(key).or_insert((false, false));
+            // Line to end of input: N,$ (also bare `d`, which parses as 1,$)
+            (Address::LineNumber(start_line), Address::LastLine) => state.line_num >= *start_line,
 
-                // If range has ended, stay ended
-                if *ended {
-                    return false;
-                }
-
-                // Check if we're entering the range
-                if state.line_num == *start_line {
-                    *in_range = true;
-                    return true;
-                }
+            // addr1,addr2!: every line the range without the ! doesn't match
+            (_, Address::Negated(end_inner)) => {
+                let start = match start {
+                    Address::Negated(start_inner) => start_inner,
+                    _ => start,
+                };
+                !self.check_range_inclusive(state, start, end_inner)
+            }
+            (Address::Negated(start_inner), _) => {
+                !self.check_range_inclusive(state, start_inner, end)
+            }
 
-                // Check if we're exiting the range
-                if state.line_num == *end_line {
-                    *ended = true;
-                    return true; // Include the end line
-                }
+            // Line, pattern and mixed ranges: 1,3 /start/,/end/ 2,/end/ /start/,+2
+            // and 0,/end/
+            _ => self.range_position(state, start, end).is_some(),
+        }
+    }
 
-                // Return true if we're currently in the range
-                *in_range
-            }
+    /// Step a range through the current line: None if the line is outside
+    /// it, Some(true) if the range ends on it, Some(false) if it goes on.
+    /// Like GNU sed, the end is only looked for after the start line,
+    /// unless it is a line number that has already been reached. A 0,/end/
+    /// range is open before line 1, so its end may match on line 1 itself.
+    fn range_position(
+        &self,
+        state: &mut CycleState,
+        start: &Address,
+        end: &Address,
+    ) -> Option<bool> {
+        let key = (start.clone(), end.clone());
+        let mut range = state.range_states.get(&key).copied().unwrap_or(RangeState {
+            open: *start == Address::FirstLine,
+            ..RangeState::default()
+        });
+        // Several commands (or a restarted cycle) may check the same range
+        // on one line; step it only once
+        if let Some((line, position)) = range.checked
+            && line == state.line_num
+        {
+            return position;
+        }
 
-            // Pattern range: /start/,/end/
-            (Address::Pattern(start_pat), Address::Pattern(end_pat)) => {
-                // Special case: same pattern for start and end
-                if start_pat == end_pat {
-                    return self.address_matches_cycle(start, state);
+        let position = if range.open {
+            let ends = match end {
+                Address::LineNumber(n) => state.line_num >= *n,
+                Address::Relative { .. } => range.end_line.is_some_and(|n| state.line_num >= n),
+                Address::MultipleOf(n) => ends_at_multiple(state.line_num, *n),
+                _ => self.address_matches_cycle(end, state),
+            };
+            range.open = !ends;
+            Some(ends)
+        } else if self.address_matches_cycle(start, state) {
+            let ends = match end {
+                Address::LineNumber(n) => *n <= state.line_num,
+                Address::Relative { offset, .. } => {
+                    range.end_line = Some(state.line_num.saturating_add_signed(*offset));
+                    *offset <= 0
                 }
+                Address::LastLine => state.line_iter.is_eof(),
+                Address::MultipleOf(n) => *n == 0,
+                _ => false,
+            };
+            range.open = !ends;
+            Some(ends)
+        } else {
+            None
+        };
 
-                // NOTE: In-memory batch mode uses stateless pattern matching.
-                // For proper pattern range state tracking, use streaming mode (which is the default for groups).
-                let start_match = self.address_matches_cycle(start, state);
-                let end_match = self.address_matches_cycle(end, state);
-                start_match || end_match
-            }
+        range.checked = Some((state.line_num, position));
+        state.range_states.insert(key, range);
+        position
+    }
 
-            // Mixed range: line,pattern or pattern,line
-            _ => {
-                // NOTE: Mixed ranges use stateless matching in batch mode.
-                // Streaming mode has proper mixed range state tracking (the default).
-                let start_match = self.address_matches_cycle(start, state);
-                let end_match = self.address_matches_cycle(end, state);
-                start_match || end_match
-            }
+    /// Whether a range that matched the current line ends on it (c prints
+    /// its text there). Negated ranges end on every line they match.
+    fn range_ends_on_line(&self, state: &mut CycleState, start: &Address, end: &Address) -> bool {
+        match (start, end) {
+            _ if start == end => true,
+            (Address::LineNumber(_), Address::LastLine) => state.line_iter.is_eof(),
+            (Address::Negated(_), _) | (_, Address::Negated(_)) => true,
+            _ => self.range_position(state, start, end) == Some(true),
         }
     }
 
@@ -1913,6 +4440,33 @@ impl FileProcessor {
             // d command: delete pattern space, end cycle (matches execute.c:1328)
             Command::Delete { range: _ } => Ok(CycleResult::DeleteLine),
 
+            // c command: delete pattern space and print the text instead; a
+            // range prints it once, on its last line (matches execute.c:1308)
+            Command::Change { text, range } => {
+                if self.range_ends_on_line(state, &range.0, &range.1) {
+                    state.change_text = Some(text.clone());
+                }
+                Ok(CycleResult::DeleteLine)
+            }
+
+            // i command: output the text now, before the pattern space
+            Command::Insert { text, address } => {
+                if self.address_matches_cycle(address, state) {
+                    state
+                        .stdout_outputs
+                        .extend(text.split('\n').map(String::from));
+                }
+                Ok(CycleResult::Continue)
+            }
+
+            // a command: queue the text for the end of the cycle
+            Command::Append { text, address } => {
+                if self.address_matches_cycle(address, state) {
+                    state.appended.extend(text.split('\n').map(String::from));
+                }
+                Ok(CycleResult::Continue)
+            }
+
             // p command: print pattern space (matches execute.c:1491)
             Command::Print { range: _ } => {
                 state.side_effects.push(state.pattern_space.clone());
@@ -1945,6 +4499,7 @@ impl FileProcessor {
             // g command: copy hold space to pattern space (matches execute.c:1528)
             Command::Get { range: _ } => {
                 state.pattern_space = state.hold_space.clone();
+                state.spans.clear();
                 Ok(CycleResult::Continue)
             }
 
@@ -1960,12 +4515,16 @@ impl FileProcessor {
             // x command: exchange pattern and hold spaces (matches execute.c:1532)
             Command::Exchange { range: _ } => {
                 std::mem::swap(&mut state.pattern_space, &mut state.hold_space);
+                state.spans.clear();
                 Ok(CycleResult::Continue)
             }
 
             // q/Q commands: quit (matches execute.c:1504, 1511)
-            Command::Quit { .. } => Ok(CycleResult::Quit(0)),
-            Command::QuitWithoutPrint { .. } => Ok(CycleResult::Quit(0)),
+            Command::Quit { exit_code, .. } => Ok(CycleResult::Quit(exit_code.unwrap_or(0))),
+            Command::QuitWithoutPrint { exit_code, .. } => {
+                state.deleted = true;
+                Ok(CycleResult::Quit(exit_code.unwrap_or(0)))
+            }
 
             // Phase 5: Flow control commands
             Command::Label { .. } => {
@@ -2042,8 +4601,15 @@ impl FileProcessor {
                 range: _,
                 commands: group_commands,
             } => {
-                // Execute each command in the group in sequence
+                // Execute each command in the group in sequence, on the
+                // current pattern space, if its own address matches
                 for group_cmd in group_commands {
+                    if let Command::Label { .. } = group_cmd {
+                        continue;
+                    }
+                    if !self.should_apply_to_cycle(group_cmd, state) {
+                        continue;
+                    }
                     let result = self.apply_command_to_cycle(group_cmd, state)?;
 
                     // Handle flow control results within the group
@@ -2077,48 +4643,18 @@ impl FileProcessor {
             // Note: Write commands now work with &mut self access
             Command::WriteFile { filename, range: _ } => {
                 // w command: Write pattern space to file (Phase 5)
-                // Write the entire pattern space to the file
-                if let Some(writer) = self.write_handles.get_mut(filename) {
-                    writeln!(writer, "{}", state.pattern_space)
-                        .with_context(|| format!("Failed to write to file: {}", filename))?;
-                } else {
-                    // Open file for writing (create if doesn't exist, truncate if exists)
-                    let file = std::fs::File::create(filename)
-                        .with_context(|| format!("Failed to create file: {}", filename))?;
-                    let mut writer = BufWriter::new(file);
-                    writeln!(writer, "{}", state.pattern_space)
-                        .with_context(|| format!("Failed to write to file: {}", filename))?;
-                    writer
-                        .flush()
-                        .with_context(|| format!("Failed to flush file: {}", filename))?;
-                    self.write_handles.insert(filename.clone(), writer);
-                }
+                let text = state.pattern_space.clone();
+                self.write_line_to(filename, &text)?;
                 Ok(CycleResult::Continue)
             }
             Command::WriteFirstLine { filename, range: _ } => {
                 // W command: Write first line of pattern space to file (Phase 5)
                 // Write only the first line (up to newline or entire pattern space if no newline)
-                let first_line = if let Some(idx) = state.pattern_space.find('\n') {
-                    &state.pattern_space[..idx]
-                } else {
-                    &state.pattern_space
+                let first_line = match state.pattern_space.find('\n') {
+                    Some(idx) => state.pattern_space[..idx].to_string(),
+                    None => state.pattern_space.clone(),
                 };
-
-                if let Some(writer) = self.write_handles.get_mut(filename) {
-                    writeln!(writer, "{}", first_line)
-                        .with_context(|| format!("Failed to write to file: {}", filename))?;
-                } else {
-                    // Open file for writing
-                    let file = std::fs::File::create(filename)
-                        .with_context(|| format!("Failed to create file: {}", filename))?;
-                    let mut writer = BufWriter::new(file);
-                    writeln!(writer, "{}", first_line)
-                        .with_context(|| format!("Failed to write to file: {}", filename))?;
-                    writer
-                        .flush()
-                        .with_context(|| format!("Failed to flush file: {}", filename))?;
-                    self.write_handles.insert(filename.clone(), writer);
-                }
+                self.write_line_to(filename, &first_line)?;
                 Ok(CycleResult::Continue)
             }
             Command::ReadFile { filename, range: _ } => {
@@ -2173,7 +4709,7 @@ impl FileProcessor {
             }
             Command::PrintFilename { range: _ } => {
                 // Print filename to stdout (Phase 5: F command)
-                // GNU sed extension - prints current filename
+                // GNU sed extension - prints the input path, "-" for stdin
                 state.stdout_outputs.push(state.current_filename.clone());
                 Ok(CycleResult::Continue)
             }
@@ -2181,13 +4717,76 @@ impl FileProcessor {
                 // Clear the pattern space (set to empty string)
                 // GNU sed extension - zaps the pattern space
                 state.pattern_space.clear();
+                state.spans.clear();
+                Ok(CycleResult::Continue)
+            }
+
+            // l command: print pattern space unambiguously (matches execute.c:1378)
+            Command::List { width, range: _ } => {
+                let width = width.unwrap_or(DEFAULT_LIST_WIDTH);
+                state
+                    .side_effects
+                    .push(Self::format_list_output(&state.pattern_space, width));
+                Ok(CycleResult::Continue)
+            }
+
+            // e command: without an argument the pattern space is the command
+            // and is replaced by its output; otherwise the output is printed
+            // ahead of the pattern space (matches execute.c:1432)
+            Command::Execute { command, range: _ } => {
+                match command {
+                    None => {
+                        state.pattern_space = self.exec_shell(&state.pattern_space)?;
+                        state.spans.clear();
+                    }
+                    Some(command) => {
+                        let output = self.exec_shell(command)?;
+                        if !output.is_empty() {
+                            state.side_effects.push(output);
+                        }
+                    }
+                }
                 Ok(CycleResult::Continue)
             }
+        }
+    }
+
+    /// Render text the way `l` shows it (matches execute.c:do_list)
+    ///
+    /// Backslash and the C escapes (\a \b \f \n \r \t \v) are written as
+    /// escapes, other non-printable or non-ASCII bytes as 3-digit octal. Output
+    /// lines are wrapped to `width - 1` characters plus a trailing `\`, without
+    /// splitting an escape; a width of 0 or 1 disables wrapping. The result
+    /// ends with `$`.
+    fn format_list_output(text: &str, width: usize) -> String {
+        let mut output = String::new();
+        let mut line_len = 0;
+
+        for &byte in text.as_bytes() {
+            let escaped = match byte {
+                b'\\' => "\\\\".to_string(),
+                0x07 => "\\a".to_string(),
+                0x08 => "\\b".to_string(),
+                0x0c => "\\f".to_string(),
+                b'\n' => "\\n".to_string(),
+                b'\r' => "\\r".to_string(),
+                b'\t' => "\\t".to_string(),
+                0x0b => "\\v".to_string(),
+                0x20..=0x7e => (byte as char).to_string(),
+                _ => format!("\\{:03o}", byte),
+            };
+
+            if width > 1 && line_len + escaped.len() > width - 1 {
+                output.push_str("\\\n");
+                line_len = 0;
+            }
 
-            // Commands that use batch implementation fall back to existing code.
-            // Most important commands are already ported to cycle model.
-            _ => Ok(CycleResult::Continue),
+            line_len += escaped.len();
+            output.push_str(&escaped);
         }
+
+        output.push('$');
+        output
     }
 
     /// n command: print current, read next, continue with remaining commands
@@ -2201,6 +4800,7 @@ impl FileProcessor {
         // 2. Read next line into pattern space
         if let Some(next_line) = state.line_iter.read_next() {
             state.pattern_space = next_line;
+            state.spans.clear();
             state.line_num += 1;
             Ok(CycleResult::Continue) // Continue with remaining commands!
         } else {
@@ -2220,9 +4820,12 @@ impl FileProcessor {
             state.line_num += 1;
             Ok(CycleResult::Continue)
         } else {
-            // At EOF: don't modify pattern space, just continue
-            // GNU sed doesn't add a newline at EOF
-            Ok(CycleResult::Continue)
+            // At EOF no later command runs. GNU sed autoprints the pattern
+            // space (branch to end of script); POSIX discards it.
+            match self.compat_mode {
+                CompatMode::Gnu => Ok(CycleResult::Branch(self.commands.len())),
+                CompatMode::Posix => Ok(CycleResult::DeleteLine),
+            }
         }
     }
 
@@ -2230,13 +4833,14 @@ impl FileProcessor {
     /// Matches execute.c:1496-1502
     fn apply_print_first_line_cycle(&mut self, state: &mut CycleState) -> Result<CycleResult> {
         // Find first newline
-        if let Some(idx) = state.pattern_space.find('\n') {
-            // Print text up to first newline
-            state
-                .side_effects
-                .push(state.pattern_space[..idx].to_string());
-        }
-        // If no newline, P command does nothing (GNU sed behavior)
+        // Print text up to first newline, or all of it without one
+        let end = state
+            .pattern_space
+            .find('\n')
+            .unwrap_or(state.pattern_space.len());
+        state
+            .side_effects
+            .push(state.pattern_space[..end].to_string());
         Ok(CycleResult::Continue)
     }
 
@@ -2247,6 +4851,7 @@ impl FileProcessor {
         if let Some(idx) = state.pattern_space.find('\n') {
             // Delete first line up to (and including) newline
             state.pattern_space = state.pattern_space[idx + 1..].to_string();
+            state.spans.clear();
             Ok(CycleResult::RestartCycle)
         } else {
             // No newline: delete entire pattern space
@@ -2257,7 +4862,7 @@ impl FileProcessor {
     /// s command: substitution
     /// Matches execute.c:1384-1457
     fn apply_substitution_cycle(
-        &self,
+        &mut self,
         state: &mut CycleState,
         pattern: &str,
         replacement: &str,
@@ -2269,52 +4874,41 @@ impl FileProcessor {
         let nth_occurrence = flags.nth;
 
         // Compile regex with enhanced error handling
-        let re = compile_regex_with_context(pattern, self.regex_flavor, case_insensitive)?;
+        let re = self.regex(pattern, case_insensitive, flags.multiline)?;
 
-        // Save original for print flag comparison
+        // Save original for print/exec flag comparison
         let original = state.pattern_space.clone();
-
-        // Apply substitution
-        if let Some(n) = nth_occurrence {
-            // Replace only the Nth occurrence (1-indexed)
-            let mut count = 0;
-            let mut result = state.pattern_space.clone();
-            let mut found = false;
-
-            for mat in re.find_iter(&state.pattern_space) {
-                count += 1;
-                if count == n {
-                    result = format!(
-                        "{}{}{}",
-                        &state.pattern_space[..mat.start()],
-                        replacement,
-                        &state.pattern_space[mat.end()..]
-                    );
-                    found = true;
-                    break;
-                }
-            }
-
-            if found {
-                state.pattern_space = result;
-                state.substitution_made = true; // Phase 5: Mark substitution as successful
-            }
-        } else if global {
-            // Replace all occurrences
-            let before = state.pattern_space.clone();
-            state.pattern_space = re
-                .replace_all(&state.pattern_space, replacement)
-                .to_string();
-            if state.pattern_space != before {
-                state.substitution_made = true; // Phase 5: Mark substitution as successful
-            }
-        } else {
-            // Replace first occurrence only
-            let before = state.pattern_space.clone();
-            state.pattern_space = re.replace(&state.pattern_space, replacement).to_string();
-            if state.pattern_space != before {
+        let substitution_made = state.substitution_made;
+
+        // Apply substitution: the Nth occurrence (1-indexed) and later ones
+        // with g, or all occurrences (g) or the first one
+        let skip = nth_occurrence.map_or(0, |n| n.saturating_sub(1));
+        let take = if global { usize::MAX } else { 1 };
+        let mut replacements = Vec::new();
+        if nth_occurrence != Some(0)
+            && let Some((result, made)) =
+                substitute_recorded(&re, &state.pattern_space, replacement, skip, take)
+        {
+            // Replacing a match with the same text only counts for s///N
+            if nth_occurrence.is_some() || result != original {
                 state.substitution_made = true; // Phase 5: Mark substitution as successful
             }
+            state.pattern_space = result;
+            replacements = made;
+        }
+
+        // Once --max-count is reached, s behaves as if nothing matched
+        if state.pattern_space != original && !substitution_allowed(&self.max_count) {
+            state.pattern_space = original;
+            state.substitution_made = substitution_made;
+            return Ok(CycleResult::Continue);
+        }
+        state.record_replacements(&replacements);
+
+        // Handle exec flag (e flag in s///e): only lines that were changed run
+        if flags.exec && state.pattern_space != original {
+            state.pattern_space = self.exec_shell(&state.pattern_space)?;
+            state.spans.clear();
         }
 
         // Handle print flag (p flag in s///p)
@@ -2322,9 +4916,39 @@ impl FileProcessor {
             state.side_effects.push(state.pattern_space.clone());
         }
 
+        // Handle write flag (w file in s///w)
+        if let Some(filename) = &flags.write_file
+            && state.pattern_space != original
+        {
+            self.write_line_to(filename, &state.pattern_space)?;
+        }
+
         Ok(CycleResult::Continue)
     }
 
+    /// Keep the spans s commands wrote into the pattern space, about to
+    /// become output line `index`
+    /// In a spilled run, hand the cycle's output and the lines it read to
+    /// the spill, which writes them out
+    fn end_spilled_cycle(
+        &mut self,
+        state: &mut CycleState,
+        output: &mut Vec<String>,
+    ) -> Result<()> {
+        let Some(spill) = &mut self.spill else {
+            return Ok(());
+        };
+        let spans = std::mem::take(&mut self.output_spans);
+        spill.end_cycle(&state.line_iter.take_read(), std::mem::take(output), &spans)
+    }
+
+    fn record_output_spans(&mut self, state: &mut CycleState, index: usize) {
+        if !state.spans.is_empty() && !state.pattern_space.contains('\n') {
+            self.output_spans
+                .insert(index, std::mem::take(&mut state.spans));
+        }
+    }
+
     // ============================================================================
     // END CYCLE-BASED PROCESSING
     // ============================================================================
@@ -2349,18 +4973,19 @@ impl FileProcessor {
             Command::Append { text, address } => {
                 self.apply_append(lines, text, address)?;
             }
-            Command::Change { text, address } => {
-                self.apply_change(lines, text, address)?;
+            Command::Change { text, range } => {
+                self.apply_change(lines, text, range)?;
             }
             Command::Print { range } => {
                 // Collect lines to print (doesn't modify the file)
                 self.collect_print_lines(lines, range)?;
             }
-            Command::Quit { address } => {
+            Command::Quit { address, exit_code } => {
                 // Check if we should quit
                 if let Some(addr) = address {
                     let idx = self.resolve_address(addr, lines, 0)?;
                     if idx < lines.len() {
+                        self.exit_code = Some(exit_code.unwrap_or(0));
                         // Quit at this line - truncate the file to this line
                         // Keep lines 0..=idx (inclusive), remove the rest
                         let lines_to_remove = lines.len().saturating_sub(idx + 1);
@@ -2371,18 +4996,20 @@ impl FileProcessor {
                 } else {
                     // Quit immediately - clear all lines
                     lines.clear();
+                    self.exit_code = Some(exit_code.unwrap_or(0));
                 }
                 // Always stop processing after quit
                 return Ok(false);
             }
             // Phase 4: Q command (quit without printing)
-            Command::QuitWithoutPrint { address } => {
+            Command::QuitWithoutPrint { address, exit_code } => {
                 // Q command: quit without printing current pattern space
                 // For stdin mode: clear all lines to prevent output
                 // For file mode: same as q (truncates file)
                 if let Some(addr) = address {
                     let idx = self.resolve_address(addr, lines, 0)?;
                     if idx < lines.len() {
+                        self.exit_code = Some(exit_code.unwrap_or(0));
                         // For Q, we need to keep lines up to but NOT including the quit line
                         // This prevents the quit line from being printed
                         lines.truncate(idx);
@@ -2390,6 +5017,7 @@ impl FileProcessor {
                 } else {
                     // Quit immediately - clear all lines WITHOUT printing
                     lines.clear();
+                    self.exit_code = Some(exit_code.unwrap_or(0));
                 }
                 // Always stop processing after quit
                 return Ok(false);
@@ -2428,6 +5056,9 @@ impl FileProcessor {
             Command::DeleteFirstLine { range } => {
                 self.apply_delete_first_line(lines, range)?;
             }
+            Command::Execute { command, range } => {
+                self.apply_execute(lines, command, range)?;
+            }
             // Phase 5: Flow control commands (delegated to cycle-based processing)
             // These commands are not supported in legacy batch mode
             Command::Label { .. }
@@ -2448,7 +5079,8 @@ impl FileProcessor {
             // Phase 5: Additional commands (delegated to cycle-based processing)
             Command::PrintLineNumber { .. }
             | Command::PrintFilename { .. }
-            | Command::ClearPatternSpace { .. } => {
+            | Command::ClearPatternSpace { .. }
+            | Command::List { .. } => {
                 // Additional commands require cycle-based execution
                 // For now, just continue - they'll be handled properly in cycle mode
             }
@@ -2464,10 +5096,9 @@ impl FileProcessor {
         flags: &SubstitutionFlags,
         range: &Option<(Address, Address)>,
     ) -> Result<()> {
-        let global = flags.global;
         let case_insensitive = flags.case_insensitive;
 
-        let re = compile_regex_with_context(pattern, self.regex_flavor, case_insensitive)?;
+        let re = self.regex(pattern, case_insensitive, flags.multiline)?;
 
         // Check for negated pattern range
         if let Some((start, end)) = range
@@ -2476,21 +5107,15 @@ impl FileProcessor {
                 (start_inner.as_ref(), end_inner.as_ref())
         {
             // Apply substitution to lines NOT matching the pattern
-            let pattern_re = compile_regex_with_context(start_pat, self.regex_flavor, false)?;
+            let pattern_re = self.regex(start_pat, false, false)?;
 
             for line in lines.iter_mut() {
                 if !pattern_re.is_match(line) {
                     let original = line.clone();
-                    if global {
-                        *line = re.replace_all(line, replacement).to_string();
-                    } else {
-                        *line = re.replace(line, replacement).to_string();
-                    }
+                    *line = substitute_with_flags(&re, line, replacement, flags);
 
-                    // Handle print flag
-                    if flags.print && *line != original {
-                        self.printed_lines.push(line.clone());
-                    }
+                    // Handle exec and print flags
+                    self.finish_batch_substitution(line, &original, flags)?;
                 }
             }
             return Ok(());
@@ -2501,14 +5126,7 @@ impl FileProcessor {
             && let (Address::Pattern(start_pat), Address::Pattern(end_pat)) = (start, end)
             && start_pat == end_pat
         {
-            return self.apply_pattern_substitution(
-                lines,
-                start_pat,
-                &re,
-                replacement,
-                global,
-                flags.print,
-            );
+            return self.apply_pattern_substitution(lines, start_pat, &re, replacement, flags);
         }
 
         match range {
@@ -2516,35 +5134,23 @@ impl FileProcessor {
                 // Apply to all lines
                 for line in lines.iter_mut() {
                     let original = line.clone();
-                    if global {
-                        *line = re.replace_all(line, replacement).to_string();
-                    } else {
-                        *line = re.replace(line, replacement).to_string();
-                    }
+                    *line = substitute_with_flags(&re, line, replacement, flags);
 
-                    // Handle print flag
-                    if flags.print && *line != original {
-                        self.printed_lines.push(line.clone());
-                    }
+                    // Handle exec and print flags
+                    self.finish_batch_substitution(line, &original, flags)?;
                 }
             }
             Some((start, end)) => {
                 // Apply to specified range
                 let start_idx = self.resolve_address(start, lines, 0)?;
-                let end_idx = self.resolve_address(end, lines, lines.len())?;
+                let end_idx = self.resolve_range_end(start, start_idx, end, lines, lines.len())?;
 
                 for i in start_idx..=end_idx.min(lines.len() - 1) {
                     let original = lines[i].clone();
-                    if global {
-                        lines[i] = re.replace_all(&lines[i], replacement).to_string();
-                    } else {
-                        lines[i] = re.replace(&lines[i], replacement).to_string();
-                    }
+                    lines[i] = substitute_with_flags(&re, &lines[i], replacement, flags);
 
-                    // Handle print flag
-                    if flags.print && lines[i] != original {
-                        self.printed_lines.push(lines[i].clone());
-                    }
+                    // Handle exec and print flags
+                    self.finish_batch_substitution(&mut lines[i], &original, flags)?;
                 }
             }
         }
@@ -2562,36 +5168,26 @@ impl FileProcessor {
     /// * `pattern_str` - Pattern string to match lines against
     /// * `pattern_regex` - Compiled regex for the substitution pattern
     /// * `replacement` - Replacement string (with backreferences converted)
-    /// * `global` - If true, replace all occurrences in each line
+    /// * `flags` - Substitution flags (g, p, e are honored)
     fn apply_pattern_substitution(
         &mut self,
         lines: &mut [String],
         pattern_str: &str,
         pattern_regex: &Regex,
         replacement: &str,
-        global: bool,
-        print_flag: bool,
+        flags: &SubstitutionFlags,
     ) -> Result<()> {
-        use regex::Regex;
-
         // Create regex to find matching lines
-        let line_pattern_re = Regex::new(pattern_str)
-            .with_context(|| format!("Invalid regex pattern: {}", pattern_str))?;
+        let line_pattern_re = self.regex(pattern_str, false, false)?;
 
         // Apply substitution to all lines matching the pattern
         for line in lines.iter_mut() {
             if line_pattern_re.is_match(line) {
                 let original = line.clone();
-                if global {
-                    *line = pattern_regex.replace_all(line, replacement).to_string();
-                } else {
-                    *line = pattern_regex.replace(line, replacement).to_string();
-                }
+                *line = substitute_with_flags(pattern_regex, line, replacement, flags);
 
-                // Handle print flag
-                if print_flag && *line != original {
-                    self.printed_lines.push(line.clone());
-                }
+                // Handle exec and print flags
+                self.finish_batch_substitution(line, &original, flags)?;
             }
         }
 
@@ -2620,7 +5216,7 @@ impl FileProcessor {
 
         // For line numbers or mixed addresses, use simple range resolution
         let start_idx = self.resolve_address(&range.0, lines, 0)?;
-        let end_idx = self.resolve_address(&range.1, lines, lines.len())?;
+        let end_idx = self.resolve_range_end(&range.0, start_idx, &range.1, lines, lines.len())?;
 
         // Remove lines from end_idx to start_idx (in reverse to maintain indices)
         for i in (start_idx..=end_idx.min(lines.len() - 1)).rev() {
@@ -2631,10 +5227,7 @@ impl FileProcessor {
     }
 
     fn apply_pattern_delete(&self, lines: &mut Vec<String>, pattern: &str) -> Result<()> {
-        use regex::Regex;
-
-        let re =
-            Regex::new(pattern).with_context(|| format!("Invalid regex pattern: {}", pattern))?;
+        let re = self.regex(pattern, false, false)?;
 
         // Delete all lines matching the pattern
         let mut indices_to_delete = Vec::new();
@@ -2653,10 +5246,7 @@ impl FileProcessor {
     }
 
     fn apply_negated_pattern_delete(&self, lines: &mut Vec<String>, pattern: &str) -> Result<()> {
-        use regex::Regex;
-
-        let re =
-            Regex::new(pattern).with_context(|| format!("Invalid regex pattern: {}", pattern))?;
+        let re = self.regex(pattern, false, false)?;
 
         // Delete lines that DO NOT match the pattern
         let mut indices_to_delete = Vec::new();
@@ -2680,12 +5270,8 @@ impl FileProcessor {
         start_pat: &str,
         end_pat: &str,
     ) -> Result<()> {
-        use regex::Regex;
-
-        let start_re = Regex::new(start_pat)
-            .with_context(|| format!("Invalid regex pattern: {}", start_pat))?;
-        let end_re =
-            Regex::new(end_pat).with_context(|| format!("Invalid regex pattern: {}", end_pat))?;
+        let start_re = self.regex(start_pat, false, false)?;
+        let end_re = self.regex(end_pat, false, false)?;
 
         let mut in_delete_range = false;
         let mut indices_to_delete = Vec::new();
@@ -2737,7 +5323,13 @@ impl FileProcessor {
             Some((start, end)) => {
                 // Apply commands only to lines within the range
                 let start_idx = self.resolve_address(start, lines, 0)?;
-                let end_idx = self.resolve_address(end, lines, lines.len().saturating_sub(1))?;
+                let end_idx = self.resolve_range_end(
+                    start,
+                    start_idx,
+                    end,
+                    lines,
+                    lines.len().saturating_sub(1),
+                )?;
 
                 // For each line in the range, apply all commands
                 for i in start_idx..=end_idx.min(lines.len() - 1) {
@@ -2771,21 +5363,41 @@ impl FileProcessor {
 
     fn apply_insert(&self, lines: &mut Vec<String>, text: &str, address: &Address) -> Result<()> {
         let idx = self.resolve_address(address, lines, 0)?;
-        lines.insert(idx, text.to_string());
+        lines.splice(idx..idx, text.split('\n').map(String::from));
         Ok(())
     }
 
     fn apply_append(&self, lines: &mut Vec<String>, text: &str, address: &Address) -> Result<()> {
         let idx = self.resolve_address(address, lines, 0)?;
         let insert_pos = (idx + 1).min(lines.len());
-        lines.insert(insert_pos, text.to_string());
+        lines.splice(insert_pos..insert_pos, text.split('\n').map(String::from));
         Ok(())
     }
 
-    fn apply_change(&self, lines: &mut [String], text: &str, address: &Address) -> Result<()> {
-        let idx = self.resolve_address(address, lines, 0)?;
-        if idx < lines.len() {
-            lines[idx] = text.to_string();
+    fn apply_change(
+        &self,
+        lines: &mut Vec<String>,
+        text: &str,
+        range: &(Address, Address),
+    ) -> Result<()> {
+        let idx = self.resolve_address(&range.0, lines, 0)?;
+        if idx >= lines.len() {
+            return Ok(());
+        }
+        let text_lines = text.split('\n').map(String::from);
+        if range.0 == range.1 {
+            lines.splice(idx..=idx, text_lines);
+            return Ok(());
+        }
+
+        // A range is replaced as a whole, and only once it is complete
+        let end_idx = self
+            .resolve_range_end(&range.0, idx, &range.1, lines, usize::MAX)?
+            .max(idx);
+        if end_idx < lines.len() {
+            lines.splice(idx..=end_idx, text_lines);
+        } else {
+            lines.truncate(idx);
         }
         Ok(())
     }
@@ -2797,9 +5409,7 @@ impl FileProcessor {
                 (start_inner.as_ref(), end_inner.as_ref())
         {
             // Print lines NOT matching the pattern
-            use regex::Regex;
-            let re = Regex::new(start_pat)
-                .with_context(|| format!("Invalid regex pattern: {}", start_pat))?;
+            let re = self.regex(start_pat, false, false)?;
 
             for line in lines {
                 if !re.is_match(line) {
@@ -2810,7 +5420,13 @@ impl FileProcessor {
         }
 
         let start_idx = self.resolve_address(&range.0, lines, 0)?;
-        let end_idx = self.resolve_address(&range.1, lines, lines.len().saturating_sub(1))?;
+        let end_idx = self.resolve_range_end(
+            &range.0,
+            start_idx,
+            &range.1,
+            lines,
+            lines.len().saturating_sub(1),
+        )?;
 
         for line in lines
             .iter()
@@ -2823,6 +5439,35 @@ impl FileProcessor {
         Ok(())
     }
 
+    /// Resolve the end of a range that starts at `start_idx`. A pattern end
+    /// is searched for after the start line, except in `0,/re/` where it may
+    /// match line 1 (GNU extension).
+    fn resolve_range_end(
+        &self,
+        start: &Address,
+        start_idx: usize,
+        end: &Address,
+        lines: &[String],
+        default: usize,
+    ) -> Result<usize> {
+        match (start, end) {
+            (Address::FirstLine, _) => self.resolve_address(end, lines, default),
+            (_, Address::Pattern(pattern)) => {
+                let re = self.regex(pattern, false, false)?;
+                Ok(lines
+                    .iter()
+                    .enumerate()
+                    .skip(start_idx + 1)
+                    .find(|(_, line)| re.is_match(line))
+                    .map_or(default, |(i, _)| i))
+            }
+            // addr,~N: the next multiple of N after the start line (~0 ends on it)
+            (_, Address::MultipleOf(0)) => Ok(start_idx),
+            (_, Address::MultipleOf(n)) => Ok((start_idx + 2).next_multiple_of(*n) - 1),
+            _ => self.resolve_address(end, lines, default),
+        }
+    }
+
     fn resolve_address(
         &self,
         address: &Address,
@@ -2840,8 +5485,7 @@ impl FileProcessor {
                 }
             }
             Address::Pattern(pattern) => {
-                let re = Regex::new(pattern)
-                    .with_context(|| format!("Invalid regex pattern: {}", pattern))?;
+                let re = self.regex(pattern, false, false)?;
 
                 for (i, line) in lines.iter().enumerate() {
                     if re.is_match(line) {
@@ -2866,8 +5510,7 @@ impl FileProcessor {
 
                 // For pattern negation, find first non-matching line
                 if let Address::Pattern(pattern) = inner.as_ref() {
-                    let re = Regex::new(pattern)
-                        .with_context(|| format!("Invalid regex pattern: {}", pattern))?;
+                    let re = self.regex(pattern, false, false)?;
 
                     for (i, line) in lines.iter().enumerate() {
                         if !re.is_match(line) {
@@ -2893,14 +5536,15 @@ impl FileProcessor {
             }
             // Chunk 8: Step address - find first matching line in the sequence
             Address::Step { start, step } => {
-                // Find the first line that matches the stepping pattern
-                // start is 1-indexed, so convert to 0-indexed
-                let start_0idx = if *start == 0 { 0 } else { start - 1 };
-                (start_0idx..lines.len())
-                    .step_by(*step)
-                    .next()
-                    .map_or(Ok(lines.len()), Ok)
+                // Find the first line that matches the stepping pattern;
+                // 0~N has no line 0, so it first matches line N
+                let first_line = if *start == 0 { *step } else { *start };
+                Ok((first_line - 1).min(lines.len()))
             }
+            Address::MultipleOf(n) => match n {
+                0 => Ok(default),
+                n => Ok((n - 1).min(lines.len())),
+            },
         }
     }
 
@@ -2920,8 +5564,14 @@ impl FileProcessor {
                 }
             }
             Some((start, end)) => {
-                let _start_idx = self.resolve_address(start, lines, 0)?;
-                let end_idx = self.resolve_address(end, lines, lines.len().saturating_sub(1))?;
+                let start_idx = self.resolve_address(start, lines, 0)?;
+                let end_idx = self.resolve_range_end(
+                    start,
+                    start_idx,
+                    end,
+                    lines,
+                    lines.len().saturating_sub(1),
+                )?;
 
                 // Apply to range - hold space gets set to each line in sequence
                 // Final value is the last line in range (GNU sed behavior)
@@ -2933,6 +5583,57 @@ impl FileProcessor {
         Ok(())
     }
 
+    /// e command in batch mode: replace each addressed line with the output
+    /// of running it, or insert the output of `command` before it
+    fn apply_execute(
+        &mut self,
+        lines: &mut Vec<String>,
+        command: &Option<String>,
+        range: &Option<(Address, Address)>,
+    ) -> Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let last_idx = lines.len() - 1;
+
+        let targets: Vec<usize> = match range {
+            None => (0..lines.len()).collect(),
+            Some((Address::Pattern(start_pat), Address::Pattern(end_pat)))
+                if start_pat == end_pat =>
+            {
+                let re = self.regex(start_pat, false, false)?;
+                (0..lines.len())
+                    .filter(|&i| re.is_match(&lines[i]))
+                    .collect()
+            }
+            Some((start, end)) => {
+                let start_idx = self.resolve_address(start, lines, 0)?;
+                let end_idx = self.resolve_range_end(start, start_idx, end, lines, last_idx)?;
+                (start_idx..=end_idx.min(last_idx)).collect()
+            }
+        };
+
+        // Inserted output shifts the remaining targets down
+        let mut offset = 0;
+        for idx in targets {
+            let idx = idx + offset;
+            match command {
+                None => lines[idx] = self.exec_shell(&lines[idx])?,
+                Some(command) => {
+                    let output = self.exec_shell(command)?;
+                    if !output.is_empty() {
+                        let output_lines: Vec<String> =
+                            output.split('\n').map(String::from).collect();
+                        offset += output_lines.len();
+                        lines.splice(idx..idx, output_lines);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// H command: Append pattern space to hold space (with newline)
     fn apply_hold_append(
         &mut self,
@@ -2953,7 +5654,13 @@ impl FileProcessor {
             }
             Some((start, end)) => {
                 let start_idx = self.resolve_address(start, lines, 0)?;
-                let end_idx = self.resolve_address(end, lines, lines.len().saturating_sub(1))?;
+                let end_idx = self.resolve_range_end(
+                    start,
+                    start_idx,
+                    end,
+                    lines,
+                    lines.len().saturating_sub(1),
+                )?;
 
                 // Append all lines in range to hold space
                 for line in lines
@@ -2992,7 +5699,13 @@ impl FileProcessor {
             }
             Some((start, end)) => {
                 let start_idx = self.resolve_address(start, lines, 0)?;
-                let end_idx = self.resolve_address(end, lines, lines.len().saturating_sub(1))?;
+                let end_idx = self.resolve_range_end(
+                    start,
+                    start_idx,
+                    end,
+                    lines,
+                    lines.len().saturating_sub(1),
+                )?;
 
                 // Replace each line in range with hold space content
                 // For multiline hold space with single-line address, use first line
@@ -3027,7 +5740,13 @@ impl FileProcessor {
             }
             Some((start, end)) => {
                 let start_idx = self.resolve_address(start, lines, 0)?;
-                let end_idx = self.resolve_address(end, lines, lines.len().saturating_sub(1))?;
+                let end_idx = self.resolve_range_end(
+                    start,
+                    start_idx,
+                    end,
+                    lines,
+                    lines.len().saturating_sub(1),
+                )?;
 
                 for i in start_idx..=end_idx.min(lines.len() - 1) {
                     lines[i].push('\n');
@@ -3067,7 +5786,13 @@ impl FileProcessor {
             }
             Some((start, end)) => {
                 let start_idx = self.resolve_address(start, lines, 0)?;
-                let end_idx = self.resolve_address(end, lines, lines.len().saturating_sub(1))?;
+                let end_idx = self.resolve_range_end(
+                    start,
+                    start_idx,
+                    end,
+                    lines,
+                    lines.len().saturating_sub(1),
+                )?;
 
                 // Exchange each line in range with hold space
                 for i in start_idx..=end_idx.min(lines.len() - 1) {
@@ -3150,8 +5875,8 @@ impl FileProcessor {
             if let Some(pos) = lines[0].find('\n') {
                 // Remove first line (up to and including newline)
                 lines[0] = lines[0][pos + 1..].to_string();
-                // NOTE: Batch mode doesn't restart cycle - it continues with next command.
-                // Cycle mode (apply_cycle_based) handles D correctly by restarting.
+                // NOTE: Batch mode can't restart the cycle, so scripts using D
+                // always run in the cycle engine (supports_cycle_based_processing)
             } else {
                 // No newline - delete entire pattern space and start new cycle
                 lines.remove(0);
@@ -3201,15 +5926,576 @@ mod tests {
             "Should have at most 5 line changes (likely fewer due to optimized buffering)"
         );
 
-        // Verify content is unchanged
-        let processed_content =
-            fs::read_to_string(test_file_path).expect("Failed to read processed file");
+        // Verify content is unchanged
+        let processed_content =
+            fs::read_to_string(test_file_path).expect("Failed to read processed file");
+        assert_eq!(
+            processed_content, original_content,
+            "Content should be unchanged"
+        );
+
+        // Clean up
+        fs::remove_file(test_file_path).ok();
+    }
+
+    #[test]
+    fn test_newline_escape_in_pattern_matches_across_engines() {
+        // s/foo\nbar/X/ matches the newline that N puts in the pattern space
+        let expected = vec!["X".to_string(), "baz".to_string()];
+        let input: Vec<String> = ["foo", "bar", "baz"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        for flavor in [RegexFlavor::PCRE, RegexFlavor::ERE, RegexFlavor::BRE] {
+            let commands = Parser::new(flavor).parse("N;s/foo\\nbar/X/").unwrap();
+            let mut processor = FileProcessor::with_regex_flavor(commands, flavor);
+            assert_eq!(
+                processor.apply_cycle_based(input.clone()).unwrap(),
+                expected,
+                "{:?}",
+                flavor
+            );
+        }
+
+        // `.` does not match the embedded newline (unlike GNU sed); (?s) opts in
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let mut processor = FileProcessor::new(parser.parse("N;s/foo.bar/X/").unwrap());
+        assert_eq!(
+            processor.apply_cycle_based(input.clone()).unwrap(),
+            vec!["foo\nbar", "baz"]
+        );
+        let mut processor = FileProcessor::new(parser.parse("N;s/(?s)foo.bar/X/").unwrap());
+        assert_eq!(processor.apply_cycle_based(input).unwrap(), expected);
+
+        // File preview
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "foo\nbar\nbaz\n").unwrap();
+        let mut processor = FileProcessor::new(parser.parse("N;s/foo\\nbar/X/").unwrap());
+        let diff = processor.process_file_with_context(file.path()).unwrap();
+        assert_eq!(diff.changes[0].content, "X");
+        assert_eq!(diff.changes[0].old_content.as_deref(), Some("foo"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_buffer_anchors_match_only_at_pattern_space_ends() {
+        // After N the pattern space is "foo\nfoo": \` and \' only match at
+        // its ends, while ^ and $ with M also match around the newline
+        let input: Vec<String> = ["foo", "foo", "bar"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        for (flavor, script, expected) in [
+            (RegexFlavor::BRE, r"N;s/\`foo/X/g", "X\nfoo"),
+            (RegexFlavor::ERE, r"N;s/\`foo/X/g", "X\nfoo"),
+            (RegexFlavor::BRE, r"N;s/foo\'/X/g", "foo\nX"),
+            (RegexFlavor::ERE, r"N;s/foo\'/X/g", "foo\nX"),
+            (RegexFlavor::BRE, r"N;s/^foo/X/Mg", "X\nX"),
+        ] {
+            let commands = Parser::new(flavor).parse(script).unwrap();
+            let mut processor = FileProcessor::with_regex_flavor(commands, flavor);
+            assert_eq!(
+                processor.apply_cycle_based(input.clone()).unwrap(),
+                vec![expected, "bar"],
+                "{:?} {}",
+                flavor,
+                script
+            );
+        }
+    }
+
+    #[test]
+    fn test_unique_files_applies_a_file_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        let link = dir.path().join("link.txt");
+        fs::write(&file, "a\n").unwrap();
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        let path = file.display().to_string();
+        let dotted = dir.path().join(".").join("file.txt").display().to_string();
+        let link = link.display().to_string();
+        let files = [path.clone(), path.clone(), dotted.clone(), link.clone()];
+        let (unique, duplicates) = unique_files(&files);
+        assert_eq!(unique, vec![path]);
+        assert_eq!(duplicates, vec![file.display().to_string(), dotted, link]);
+
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/a/aa/").unwrap();
+        for file in &unique {
+            let mut processor = StreamProcessor::new(commands.clone());
+            processor.process_streaming_forced(Path::new(file)).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&file).unwrap(), "aa\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_edits_keep_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("script.sh");
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/old/new/").unwrap();
+        let mode = || fs::metadata(&script).unwrap().permissions().mode() & 0o7777;
+
+        for streaming in [true, false] {
+            fs::write(&script, "#!/bin/sh\necho old\n").unwrap();
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+            if streaming {
+                let mut processor = StreamProcessor::new(commands.clone());
+                processor.process_streaming_forced(&script).unwrap();
+            } else {
+                let mut processor = FileProcessor::new(commands.clone());
+                processor.apply_to_file(&script).unwrap();
+            }
+
+            assert_eq!(
+                fs::read_to_string(&script).unwrap(),
+                "#!/bin/sh\necho new\n"
+            );
+            assert_eq!(mode(), 0o755, "streaming: {}", streaming);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_editing_through_a_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real.txt");
+        let link = dir.path().join("link.txt");
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/a/b/").unwrap();
+        let is_symlink = |path: &Path| fs::symlink_metadata(path).unwrap().is_symlink();
+
+        for streaming in [true, false] {
+            for follow in [true, false] {
+                fs::write(&real, "a\n").unwrap();
+                fs::remove_file(&link).ok();
+                std::os::unix::fs::symlink(&real, &link).unwrap();
+
+                let target = write_target(&link, follow).unwrap();
+                if streaming {
+                    let mut processor = StreamProcessor::new(commands.clone());
+                    processor.process_streaming_forced(&target).unwrap();
+                } else {
+                    let mut processor = FileProcessor::new(commands.clone());
+                    processor.apply_to_file(&target).unwrap();
+                }
+
+                if follow {
+                    // The link survives and its target is edited
+                    assert!(is_symlink(&link));
+                    assert_eq!(fs::read_to_string(&real).unwrap(), "b\n");
+                } else {
+                    // The link is replaced by the edited file
+                    assert!(!is_symlink(&link));
+                    assert_eq!(fs::read_to_string(&link).unwrap(), "b\n");
+                    assert_eq!(fs::read_to_string(&real).unwrap(), "a\n");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_out_dir_writes_transformed_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let out = dir.path().join("out");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        let files = [src.join("a.txt"), src.join("sub/b.txt")];
+        fs::write(&files[0], "foo\nbar\n").unwrap();
+        fs::write(&files[1], "x foo\n").unwrap();
+
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/foo/FOO/").unwrap();
+        for file in &files {
+            let target = copy_to_out_dir(&out, file).unwrap();
+            assert!(target.starts_with(&out));
+            assert!(target.ends_with(file.strip_prefix(dir.path()).unwrap()));
+
+            let mut processor = StreamProcessor::new(commands.clone());
+            processor.process_streaming_forced(&target).unwrap();
+        }
+
+        // Sources are untouched, copies carry the transformation
+        assert_eq!(fs::read_to_string(&files[0]).unwrap(), "foo\nbar\n");
+        assert_eq!(fs::read_to_string(&files[1]).unwrap(), "x foo\n");
+        let copy = |file: &Path| fs::read_to_string(out_dir_path(&out, file).unwrap()).unwrap();
+        assert_eq!(copy(&files[0]), "FOO\nbar\n");
+        assert_eq!(copy(&files[1]), "x FOO\n");
+
+        // Paths that escape the current directory are rejected
+        assert!(out_dir_path(&out, Path::new("../a.txt")).is_err());
+    }
+
+    #[test]
+    fn test_numbered_global_substitution_across_engines() {
+        // s/o/0/2g replaces the 2nd and all later matches, in either flag order
+        let input = "foo o o o";
+        let expected = "fo0 0 0 0";
+
+        for script in ["s/o/0/2g", "s/o/0/g2"] {
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+
+            let mut processor = FileProcessor::new(commands.clone());
+            assert_eq!(
+                processor
+                    .apply_cycle_based(vec![input.to_string()])
+                    .unwrap(),
+                vec![expected],
+                "{}",
+                script
+            );
+
+            let mut processor = FileProcessor::new(commands.clone());
+            let mut lines = vec![input.to_string()];
+            processor.apply_command(&mut lines, &commands[0]).unwrap();
+            assert_eq!(lines, vec![expected], "{}", script);
+
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), format!("{}\n", input)).unwrap();
+            let mut processor = StreamProcessor::new(commands);
+            processor.process_streaming_forced(file.path()).unwrap();
+            assert_eq!(
+                fs::read_to_string(file.path()).unwrap(),
+                format!("{}\n", expected),
+                "{}",
+                script
+            );
+        }
+
+        // Without g only the 2nd match changes; fewer matches leave the line alone
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/o/0/2").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        assert_eq!(
+            processor
+                .apply_cycle_based(vec![input.to_string(), "o".to_string()])
+                .unwrap(),
+            vec!["fo0 o o o", "o"]
+        );
+    }
+
+    #[test]
+    fn test_ampersand_and_dollar_in_replacement() {
+        for (flavor, script, expected) in [
+            (RegexFlavor::BRE, r"s/foo/[&]/", "[foo] bar"),
+            (RegexFlavor::PCRE, r"s/foo/[&]/", "[foo] bar"),
+            (RegexFlavor::BRE, r"s/foo/\&/", "& bar"),
+            (RegexFlavor::ERE, r"s/foo/a$b/", "a$b bar"),
+            (
+                RegexFlavor::PCRE,
+                r"s/(\w+) (\w+)/$2 costs \$1 &/",
+                "bar costs $1 foo bar",
+            ),
+            (RegexFlavor::BRE, r"s/\(f\)oo/\1st/", "fst bar"),
+            (RegexFlavor::PCRE, r"s/(?P<w>foo)/[$w]/", "[foo] bar"),
+            (
+                RegexFlavor::PCRE,
+                r"s/(?<w>foo) (?P<x>bar)/$x $w/",
+                "bar foo",
+            ),
+        ] {
+            let commands = Parser::new(flavor).parse(script).unwrap();
+            let mut processor = FileProcessor::with_regex_flavor(commands, flavor);
+            let result = processor
+                .apply_cycle_based(vec!["foo bar".to_string()])
+                .unwrap();
+            assert_eq!(result, vec![expected], "{}", script);
+        }
+    }
+
+    #[test]
+    fn test_escaped_metacharacter_delimiter_is_literal() {
+        for flavor in [RegexFlavor::BRE, RegexFlavor::ERE, RegexFlavor::PCRE] {
+            for (script, input, expected) in [
+                (r"s|a\|b|X|", "a|b", "X"),
+                (r"s.a\.b.X.g", "a.b axb", "X axb"),
+                (r"s+a\+b+X+", "aab a+b", "aab X"),
+                (r"\|a\|b|s/b/B/", "a|b", "a|B"),
+            ] {
+                let commands = Parser::new(flavor).parse(script).unwrap();
+                let mut processor = FileProcessor::with_regex_flavor(commands, flavor);
+                let result = processor
+                    .apply_cycle_based(vec![input.to_string()])
+                    .unwrap();
+                assert_eq!(result, vec![expected], "{:?} {}", flavor, script);
+            }
+        }
+    }
+
+    #[test]
+    fn test_case_conversion_escapes_in_replacement() {
+        let input = vec!["hello world".to_string()];
+        let cases = [
+            (RegexFlavor::PCRE, "s/(.*)/\\U$1/", "HELLO WORLD"),
+            (RegexFlavor::BRE, "s/\\(.*\\)/\\U\\1/", "HELLO WORLD"),
+            (
+                RegexFlavor::ERE,
+                "s/(\\w+) (\\w+)/\\u\\1 \\u\\2/",
+                "Hello World",
+            ),
+            (
+                RegexFlavor::PCRE,
+                "s/(\\w+) (\\w+)/\\U$1\\E $2/",
+                "HELLO world",
+            ),
+            (
+                RegexFlavor::PCRE,
+                "s/(\\w+) (\\w+)/\\U$1 \\L\\u$2/",
+                "HELLO World",
+            ),
+            (RegexFlavor::PCRE, "s/(\\w+)/\\u$1/g", "Hello World"),
+            (RegexFlavor::PCRE, "s/(o)/\\U$1/2", "hello wOrld"),
+        ];
+
+        for (flavor, script, expected) in cases {
+            let commands = Parser::new(flavor).parse(script).unwrap();
+            let mut processor = FileProcessor::with_regex_flavor(commands, flavor);
+            assert_eq!(
+                processor.apply_cycle_based(input.clone()).unwrap(),
+                vec![expected],
+                "{}",
+                script
+            );
+        }
+
+        // \L and \l lower-case the expanded text
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("s/(\\w+) (\\w+)/\\l$1 \\L$2\\E!/")
+            .unwrap();
+        let mut processor = FileProcessor::new(commands);
+        assert_eq!(
+            processor
+                .apply_cycle_based(vec!["HELLO WORLD".to_string()])
+                .unwrap(),
+            vec!["hELLO world!"]
+        );
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_streaming_case_conversion_substitution() {
+        let test_file_path = "/tmp/test_streaming_case_conversion.txt";
+        fs::write(test_file_path, "foo bar\nbaz\n").expect("Failed to create test file");
+
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let commands = parser.parse("s/(\\w+)/\\u$1/g").unwrap();
+        let mut processor = StreamProcessor::new(commands);
+        processor
+            .process_streaming_forced(Path::new(test_file_path))
+            .unwrap();
+
+        let processed_content = fs::read_to_string(test_file_path).unwrap();
+        assert_eq!(processed_content, "Foo Bar\nBaz\n");
+
+        // \u followed by four hex digits is still a Unicode escape
+        let commands = parser.parse("s/Baz/\\u00e9/").unwrap();
+        let mut processor = StreamProcessor::new(commands);
+        processor
+            .process_streaming_forced(Path::new(test_file_path))
+            .unwrap();
+
+        let processed_content = fs::read_to_string(test_file_path).unwrap();
+        assert_eq!(processed_content, "Foo Bar\né\n");
+
+        fs::remove_file(test_file_path).ok();
+    }
+
+    #[test]
+    fn test_insert_and_append_preview_as_single_additions() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "one\ntwo\nthree\nfour\nfive\n").unwrap();
+        let parser = Parser::new(RegexFlavor::PCRE);
+
+        for (script, added_at) in [("3i\\new", 3), ("3a\\new", 4)] {
+            let mut processor = FileProcessor::new(parser.parse(script).unwrap());
+            let diff = processor.process_file_with_context(file.path()).unwrap();
+
+            assert_eq!(
+                diff.changes
+                    .iter()
+                    .map(|c| (c.line_number, c.change_type.clone(), c.content.as_str()))
+                    .collect::<Vec<_>>(),
+                vec![(added_at, ChangeType::Added, "new")],
+                "{}",
+                script
+            );
+            let unchanged: Vec<&str> = diff
+                .all_lines
+                .iter()
+                .filter(|(_, _, change_type)| *change_type == ChangeType::Unchanged)
+                .map(|(_, content, _)| content.as_str())
+                .collect();
+            assert_eq!(unchanged, vec!["one", "two", "three", "four", "five"]);
+        }
+    }
+
+    #[test]
+    fn test_rejecting_a_hunk_after_an_insert_keeps_the_original_line() {
+        // The second hunk's Modified line is line 4 of the new version but
+        // line 3 of the original
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "a\nb\nc\nd\n").unwrap();
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("1a\\new\ns/c/C/")
+            .unwrap();
+
+        let mut processor = FileProcessor::new(commands);
+        processor.apply_selected_changes(file.path(), &[0]).unwrap();
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "a\nnew\nb\nc\nd\n"
+        );
+    }
+
+    /// Render a diff as one entry per line: `=` unchanged, `-` deleted,
+    /// `+` added, `~` modified (old>new)
+    fn render_diff(original: &[&str], modified: &[&str]) -> Vec<String> {
+        let modified: Vec<String> = modified.iter().map(|s| s.to_string()).collect();
+        diff_lines(original, &modified)
+            .into_iter()
+            .map(|op| match op {
+                DiffOp::Unchanged { old, new } => {
+                    assert_eq!(original[old], modified[new]);
+                    format!("={}", original[old])
+                }
+                DiffOp::Modified { old, new } => format!("~{}>{}", original[old], modified[new]),
+                DiffOp::Deleted { old } => format!("-{}", original[old]),
+                DiffOp::Added { new } => format!("+{}", modified[new]),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_lines_known_edits() {
+        // Pure insert
+        assert_eq!(
+            render_diff(&["a", "b", "c"], &["a", "x", "b", "y", "c", "z"]),
+            vec!["=a", "+x", "=b", "+y", "=c", "+z"]
+        );
+        // Pure delete
+        assert_eq!(
+            render_diff(&["a", "b", "c", "d", "e"], &["a", "c", "e"]),
+            vec!["=a", "-b", "=c", "-d", "=e"]
+        );
+        // Mixed: a change, a delete and an append
+        assert_eq!(
+            render_diff(&["a", "b", "c", "d"], &["a", "B", "d", "e"]),
+            vec!["=a", "~b>B", "-c", "=d", "+e"]
+        );
+        // A line moved down: one delete, one add, the rest unchanged
+        assert_eq!(
+            render_diff(&["x", "a", "b", "c"], &["a", "b", "x", "c"]),
+            vec!["-x", "=a", "=b", "+x", "=c"]
+        );
+        assert_eq!(render_diff(&[], &["a"]), vec!["+a"]);
+        assert_eq!(render_diff(&["a"], &[]), vec!["-a"]);
+        assert!(render_diff(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_diff_lines_large_edits() {
+        // Deleting many lines is still exact, however many there are
+        let original: Vec<String> = (0..10_000).map(|i| i.to_string()).collect();
+        let original: Vec<&str> = original.iter().map(String::as_str).collect();
+        let kept: Vec<&str> = original
+            .iter()
+            .copied()
+            .filter(|l| !l.ends_with('7'))
+            .collect();
+        let rendered = render_diff(&original, &kept);
+        assert_eq!(rendered.len(), 10_000);
+        assert_eq!(
+            rendered.iter().filter(|r| r.starts_with('-')).count(),
+            1_000
+        );
+
+        // Every line changed and one appended: too many edits to search, so
+        // lines are paired by position
+        let mut changed: Vec<String> = original.iter().map(|l| format!("{}!", l)).collect();
+        changed.push("end".to_string());
+        let changed: Vec<&str> = changed.iter().map(String::as_str).collect();
+        let rendered = render_diff(&original, &changed);
+        assert_eq!(rendered[0], "~0>0!");
+        assert_eq!(rendered[9_999], "~9999>9999!");
+        assert_eq!(rendered[10_000], "+end");
+    }
+
+    #[test]
+    fn test_print_command_output_with_and_without_quiet() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "a\nb\n").unwrap();
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/a/X/; p").unwrap();
+        let new_lines = |diff: &FileDiff| -> Vec<String> {
+            diff.all_lines
+                .iter()
+                .filter(|(_, _, change_type)| *change_type != ChangeType::Deleted)
+                .map(|(_, content, _)| content.clone())
+                .collect()
+        };
+
+        // Without -n, p doubles each line it prints, as in sed, and the
+        // diff shows that rather than just the printed copies
+        let mut processor = FileProcessor::new(commands.clone());
+        let diff = processor.process_file_with_context(file.path()).unwrap();
+        assert!(diff.printed_lines.is_empty());
+        assert_eq!(new_lines(&diff), vec!["X", "X", "b", "b"]);
+
+        let mut processor = FileProcessor::new(commands.clone());
+        let results = processor
+            .process_files_concatenated(&[file.path().to_path_buf()])
+            .unwrap();
+        let (diff, lines) = results[0].as_ref().unwrap();
+        assert!(diff.printed_lines.is_empty());
+        assert_eq!(lines, &vec!["X", "X", "b", "b"]);
+
+        // With -n, only the printed lines are output
+        let mut processor = FileProcessor::new(commands);
+        processor.set_no_default_output(true);
+        let diff = processor.process_file_with_context(file.path()).unwrap();
+        assert_eq!(diff.printed_lines, vec!["X", "b"]);
+        assert_eq!(new_lines(&diff), vec!["X", "b"]);
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_substitute_print_flag_quiet_prints_once_across_engines() {
+        // -n 's/a/b/p': autoprint suppressed, the p flag prints each changed line once
+        let test_file_path = "/tmp/test_quiet_subst_print.txt";
+        let original_content = "a1\nc2\na3\n";
+        fs::write(test_file_path, original_content).expect("Failed to create test file");
+        let expected = vec!["b1".to_string(), "b3".to_string()];
+
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let commands = parser.parse("s/a/b/p").expect("Failed to parse");
+
+        // In-memory
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.set_no_default_output(true);
+        let diff = processor
+            .process_file_with_context(Path::new(test_file_path))
+            .unwrap();
+        assert_eq!(diff.printed_lines, expected);
+
+        // Stdin (cycle engine output is exactly what reaches stdout)
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.set_no_default_output(true);
+        let lines = original_content.lines().map(String::from).collect();
+        assert_eq!(processor.apply_cycle_based(lines).unwrap(), expected);
+
+        // Forced streaming
+        let mut processor = StreamProcessor::new(commands).with_dry_run(true);
+        let diff = processor
+            .process_streaming_forced(Path::new(test_file_path))
+            .unwrap();
+        assert_eq!(diff.printed_lines, expected);
         assert_eq!(
-            processed_content, original_content,
-            "Content should be unchanged"
+            fs::read_to_string(test_file_path).unwrap(),
+            original_content,
+            "Dry run must not touch the file"
         );
 
-        // Clean up
         fs::remove_file(test_file_path).ok();
     }
 
@@ -3443,147 +6729,1014 @@ mod tests {
         fs::remove_file(test_file_path).ok();
     }
 
-    #[cfg_attr(not(unix), ignore)]
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_streaming_print() {
+        // Test print command (prints to stdout, file unchanged)
+        let test_file_path = "/tmp/test_print.txt";
+        let original_content = "line 1\nline 2\nline 3\n";
+
+        {
+            let mut file = fs::File::create(test_file_path).expect("Failed to create test file");
+            file.write_all(original_content.as_bytes())
+                .expect("Failed to write to test file");
+        }
+
+        // Parse print command (1,$p means print from line 1 to last line)
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let commands = parser.parse(r"1,$p").expect("Failed to parse print");
+        let mut processor = StreamProcessor::new(commands);
+
+        // Process the file (force streaming for testing)
+        // Note: this will print to stdout during test
+        let result = processor.process_streaming_forced(Path::new(test_file_path));
+        assert!(result.is_ok(), "Processing should succeed");
+
+        // Verify file content is unchanged (print doesn't modify file)
+        let processed_content =
+            fs::read_to_string(test_file_path).expect("Failed to read processed file");
+        assert_eq!(
+            processed_content, original_content,
+            "File should be unchanged"
+        );
+
+        // Clean up
+        fs::remove_file(test_file_path).ok();
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_streaming_insert() {
+        // Test insert command (inserts text before specified line)
+        let test_file_path = "/tmp/test_insert.txt";
+        let original_content = "line 1\nline 2\nline 3\n";
+
+        {
+            let mut file = fs::File::create(test_file_path).expect("Failed to create test file");
+            file.write_all(original_content.as_bytes())
+                .expect("Failed to write to test file");
+        }
+
+        // Parse insert command (2i\TEXT means insert TEXT before line 2)
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let commands = parser
+            .parse(r"2i\INSERTED LINE")
+            .expect("Failed to parse insert");
+        let mut processor = StreamProcessor::new(commands);
+
+        // Process the file (force streaming for testing)
+        let result = processor.process_streaming_forced(Path::new(test_file_path));
+        assert!(result.is_ok(), "Processing should succeed");
+
+        // Verify the line was inserted
+        let processed_content =
+            fs::read_to_string(test_file_path).expect("Failed to read processed file");
+        let expected = "line 1\nINSERTED LINE\nline 2\nline 3\n";
+        assert_eq!(
+            processed_content, expected,
+            "Line should be inserted before line 2"
+        );
+
+        // Clean up
+        fs::remove_file(test_file_path).ok();
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_streaming_append() {
+        // Test append command (appends text after specified line)
+        let test_file_path = "/tmp/test_append.txt";
+        let original_content = "line 1\nline 2\nline 3\n";
+
+        {
+            let mut file = fs::File::create(test_file_path).expect("Failed to create test file");
+            file.write_all(original_content.as_bytes())
+                .expect("Failed to write to test file");
+        }
+
+        // Parse append command (2a\TEXT means append TEXT after line 2)
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let commands = parser
+            .parse(r"2a\APPENDED LINE")
+            .expect("Failed to parse append");
+        let mut processor = StreamProcessor::new(commands);
+
+        // Process the file (force streaming for testing)
+        let result = processor.process_streaming_forced(Path::new(test_file_path));
+        assert!(result.is_ok(), "Processing should succeed");
+
+        // Verify the line was appended
+        let processed_content =
+            fs::read_to_string(test_file_path).expect("Failed to read processed file");
+        let expected = "line 1\nline 2\nAPPENDED LINE\nline 3\n";
+        assert_eq!(
+            processed_content, expected,
+            "Line should be appended after line 2"
+        );
+
+        // Clean up
+        fs::remove_file(test_file_path).ok();
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_streaming_change() {
+        // Test change command (replaces specified line with new text)
+        let test_file_path = "/tmp/test_change.txt";
+        let original_content = "line 1\nline 2\nline 3\n";
+
+        {
+            let mut file = fs::File::create(test_file_path).expect("Failed to create test file");
+            file.write_all(original_content.as_bytes())
+                .expect("Failed to write to test file");
+        }
+
+        // Parse change command (2c\TEXT means change line 2 to TEXT)
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let commands = parser
+            .parse(r"2c\CHANGED LINE")
+            .expect("Failed to parse change");
+        let mut processor = StreamProcessor::new(commands);
+
+        // Process the file (force streaming for testing)
+        let result = processor.process_streaming_forced(Path::new(test_file_path));
+        assert!(result.is_ok(), "Processing should succeed");
+
+        // Verify the line was changed
+        let processed_content =
+            fs::read_to_string(test_file_path).expect("Failed to read processed file");
+        let expected = "line 1\nCHANGED LINE\nline 3\n";
+        assert_eq!(processed_content, expected, "Line 2 should be changed");
+
+        // Clean up
+        fs::remove_file(test_file_path).ok();
+    }
+
+    #[test]
+    fn test_change_over_range_prints_text_once() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let lines = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let commands = parser.parse(r"2,4c\X").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        let result = processor
+            .apply_cycle_based(lines(&["1", "2", "3", "4", "5", "6"]))
+            .unwrap();
+        assert_eq!(result, lines(&["1", "X", "5", "6"]));
+
+        let commands = parser.parse(r"/START/,/END/c\REPLACED").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        let result = processor
+            .apply_cycle_based(lines(&["a", "START", "b", "END", "c"]))
+            .unwrap();
+        assert_eq!(result, lines(&["a", "REPLACED", "c"]));
+    }
+
+    #[test]
+    fn test_multiline_append_and_change() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let lines = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let commands = parser.parse("2a\\\nline1\\\nline2").unwrap();
+        let mut processor = FileProcessor::new(commands.clone());
+        let mut result = lines(&["1", "2", "3"]);
+        processor.apply_command(&mut result, &commands[0]).unwrap();
+        assert_eq!(result, lines(&["1", "2", "line1", "line2", "3"]));
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "1\n2\n3\n").unwrap();
+        let mut processor = StreamProcessor::new(commands);
+        let diff = processor.process_streaming_forced(file.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "1\n2\nline1\nline2\n3\n"
+        );
+        let added: Vec<_> = diff
+            .changes
+            .iter()
+            .filter(|c| c.change_type == ChangeType::Added)
+            .map(|c| c.content.as_str())
+            .collect();
+        assert_eq!(added, vec!["line1", "line2"]);
+
+        let commands = parser.parse("2,3c\\\nA\\\nB").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        let result = processor
+            .apply_cycle_based(lines(&["1", "2", "3", "4"]))
+            .unwrap();
+        assert_eq!(result, lines(&["1", "A", "B", "4"]));
+    }
+
+    #[test]
+    fn test_streaming_insert_append_at_pattern() {
+        // Pattern addresses stay in the streaming engine instead of
+        // falling back to in-memory processing
+        for (script, expected) in [
+            (
+                r"/marker/i\BEFORE",
+                "a\nBEFORE\nmarker 1\nb\nBEFORE\nmarker 2\n",
+            ),
+            (
+                r"/marker/a\AFTER",
+                "a\nmarker 1\nAFTER\nb\nmarker 2\nAFTER\n",
+            ),
+            (r"/marker/c\NEW LINE", "a\nNEW LINE\nb\nNEW LINE\n"),
+        ] {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), "a\nmarker 1\nb\nmarker 2\n").unwrap();
+
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+            let mut processor = StreamProcessor::new(commands);
+            let diff = processor.process_streaming_forced(file.path()).unwrap();
+            assert!(diff.is_streaming, "{}", script);
+            assert_eq!(
+                fs::read_to_string(file.path()).unwrap(),
+                expected,
+                "{}",
+                script
+            );
+        }
+    }
+
+    #[test]
+    fn test_final_newline_is_preserved() {
+        for input in ["foo\nbar", "foo\nbar\n\n\n", "foo\nbar\n"] {
+            let expected = input.replace("foo", "baz");
+            let commands = Parser::new(RegexFlavor::PCRE).parse("s/foo/baz/").unwrap();
+
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), input).unwrap();
+            let mut processor = FileProcessor::new(commands.clone());
+            processor.apply_to_file(file.path()).unwrap();
+            assert_eq!(
+                fs::read_to_string(file.path()).unwrap(),
+                expected,
+                "{:?}",
+                input
+            );
+
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), input).unwrap();
+            let mut processor = StreamProcessor::new(commands);
+            processor.process_streaming_forced(file.path()).unwrap();
+            assert_eq!(
+                fs::read_to_string(file.path()).unwrap(),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_final_newline_stays_with_the_last_line() {
+        // Only the input's last line, as it was or edited, keeps its
+        // missing newline; text after it or a line before it ends in one
+        let cases = [
+            ("$a\\END", "a\nb\nEND\n"),
+            ("$d", "a\n"),
+            ("$i\\X", "a\nX\nb"),
+            ("s/b/B/", "a\nB"),
+            ("$!N; s/\\n/+/", "a+b"),
+            // The same, streamed
+            ("/b/a\\END", "a\nb\nEND\n"),
+            ("/b/d", "a\n"),
+            ("/b/i\\X", "a\nX\nb"),
+        ];
+        for (script, expected) in cases {
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), "a\nb").unwrap();
+            let mut processor = FileProcessor::new(commands.clone());
+            processor.apply_to_file(file.path()).unwrap();
+            assert_eq!(
+                fs::read_to_string(file.path()).unwrap(),
+                expected,
+                "{}",
+                script
+            );
+
+            if crate::capability::can_stream(&commands) {
+                let file = tempfile::NamedTempFile::new().unwrap();
+                fs::write(file.path(), "a\nb").unwrap();
+                let mut processor = StreamProcessor::new(commands.clone());
+                processor.process_streaming_forced(file.path()).unwrap();
+                assert_eq!(
+                    fs::read_to_string(file.path()).unwrap(),
+                    expected,
+                    "{}",
+                    script
+                );
+            }
+
+            // Read from disk over the memory limit (cycle engine scripts only)
+            if FileProcessor::supports_cycle_based_processing(&commands) {
+                let file = tempfile::NamedTempFile::new().unwrap();
+                fs::write(file.path(), "a\nb").unwrap();
+                let mut processor = FileProcessor::new(commands);
+                processor.set_memory_limit(Some(1));
+                processor.apply_to_file(file.path()).unwrap();
+                assert_eq!(
+                    fs::read_to_string(file.path()).unwrap(),
+                    expected,
+                    "{}",
+                    script
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_file_processor_regex_cache() {
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("s/foo/bar/;/foo/d;/x/,/y/p")
+            .unwrap();
+        let mut processor = FileProcessor::new(commands.clone());
+        let mut lines = vec!["foo".to_string(), "x".to_string(), "y".to_string()];
+        for _ in 0..3 {
+            for cmd in &commands {
+                processor.apply_command(&mut lines, cmd).unwrap();
+            }
+        }
+        // foo, x and y, each compiled once however often the commands ran
+        assert_eq!(processor.regex_cache.len(), 3);
+        let cached = processor.regex("foo", false, false).unwrap();
+        assert!(cached.is_match("a foo b"));
+        assert_eq!(processor.regex_cache.len(), 3);
+
+        // An invalid pattern is still rejected, every time it is used
+        let invalid = Command::Delete {
+            range: (
+                Address::Pattern("(foo".to_string()),
+                Address::Pattern("(foo".to_string()),
+            ),
+        };
+        for _ in 0..2 {
+            let mut lines = vec!["foo".to_string()];
+            assert!(processor.apply_command(&mut lines, &invalid).is_err());
+        }
+        assert_eq!(processor.regex_cache.len(), 3);
+    }
+
+    #[test]
+    fn test_streaming_regex_cache_matches_fresh_compile() {
+        let processor = StreamProcessor::new(Vec::new());
+        let lines = ["Foo bar", "foo", "no match", "FOO\nfoo"];
+        for (pattern, case_insensitive, multiline) in [
+            ("foo", false, false),
+            ("foo", true, false),
+            ("^foo$", false, true),
+        ] {
+            let fresh = regex::RegexBuilder::new(pattern)
+                .case_insensitive(case_insensitive)
+                .multi_line(multiline)
+                .build()
+                .unwrap();
+            for _ in 0..2 {
+                let cached = processor
+                    .regex(pattern, case_insensitive, multiline)
+                    .unwrap();
+                for line in lines {
+                    assert_eq!(
+                        cached
+                            .find_iter(line)
+                            .map(|m| m.range())
+                            .collect::<Vec<_>>(),
+                        fresh.find_iter(line).map(|m| m.range()).collect::<Vec<_>>(),
+                        "{} on {:?}",
+                        pattern,
+                        line
+                    );
+                }
+            }
+        }
+        // The case-insensitive and multiline variants are cached separately
+        assert_eq!(processor.regex_cache.len(), 3);
+    }
+
+    #[test]
+    fn test_streaming_many_lines_with_patterns() {
+        let line_count = 50_000;
+        let input: String = (0..line_count)
+            .map(|i| format!("line {} foo\n", i))
+            .collect();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), &input).unwrap();
+
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("/^line 1/,/5 foo$/s/foo/bar/;/7 foo$/d")
+            .unwrap();
+        let mut processor = StreamProcessor::new(commands);
+        let diff = processor.process_streaming_forced(file.path()).unwrap();
+        assert!(diff.is_streaming);
+
+        let output = fs::read_to_string(file.path()).unwrap();
+        assert_eq!(output.lines().count(), line_count - line_count / 10);
+        assert!(output.starts_with("line 0 foo\nline 1 bar\n"));
+        assert_eq!(processor.regex_cache.len(), 4);
+    }
+
+    #[test]
+    fn test_map_in_parallel_matches_sequential() {
+        let dir = tempfile::tempdir().unwrap();
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("s/foo/bar/;/drop/d")
+            .unwrap();
+        let make_files = |name: &str| -> Vec<PathBuf> {
+            (0..12)
+                .map(|i| {
+                    let path = dir.path().join(format!("{}{}.txt", name, i));
+                    let content = "foo\nkeep\ndrop\n".repeat(i + 1);
+                    fs::write(&path, content).unwrap();
+                    path
+                })
+                .collect()
+        };
+        let preview = |path: &PathBuf| {
+            let mut processor = StreamProcessor::new(commands.clone()).with_dry_run(true);
+            format!("{:?}", processor.process_streaming_forced(path).unwrap())
+        };
+        let apply = |path: &PathBuf| {
+            let mut processor = FileProcessor::new(commands.clone());
+            processor.apply_to_file(path).unwrap()
+        };
+
+        let sequential = make_files("seq");
+        let parallel = make_files("par");
+        let rename = |diff: String| diff.replace("/par", "/seq");
+        let previews_1 = map_in_parallel(&sequential, 1, preview);
+        let previews_4: Vec<String> = map_in_parallel(&parallel, 4, preview)
+            .into_iter()
+            .map(rename)
+            .collect();
+        assert_eq!(previews_1, previews_4);
+
+        assert_eq!(
+            map_in_parallel(&sequential, 1, apply),
+            map_in_parallel(&parallel, 4, apply)
+        );
+        for (seq, par) in sequential.iter().zip(&parallel) {
+            assert_eq!(fs::read(seq).unwrap(), fs::read(par).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8_file_is_reported() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), b"foo\nba\xffr\n").unwrap();
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/foo/baz/").unwrap();
+
+        let mut processor = FileProcessor::new(commands.clone());
+        let err = processor
+            .process_file_with_context(file.path())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.ends_with("is not valid UTF-8 (invalid byte at offset 6)"),
+            "{}",
+            err
+        );
+        assert!(processor.apply_to_file(file.path()).is_err());
+
+        let mut processor = StreamProcessor::new(commands);
+        let err = processor
+            .process_streaming_forced(file.path())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.ends_with("is not valid UTF-8 (invalid byte on line 2)"),
+            "{}",
+            err
+        );
+
+        // The file is left as it was
+        assert_eq!(fs::read(file.path()).unwrap(), b"foo\nba\xffr\n");
+    }
+
+    #[test]
+    fn test_crlf_line_endings_round_trip() {
+        // (input, expected after s/foo/baz/); mixed files follow the first line
+        for (input, expected) in [
+            ("foo\r\nbar\r\n", "baz\r\nbar\r\n"),
+            ("keep\r\nbar\r\n", "keep\r\nbar\r\n"),
+            ("foo\r\nbar", "baz\r\nbar"),
+            ("foo\r\nbar\nqux\r\n", "baz\r\nbar\r\nqux\r\n"),
+            ("foo\nbar\r\n", "baz\nbar\n"),
+        ] {
+            let commands = Parser::new(RegexFlavor::PCRE).parse("s/foo/baz/").unwrap();
+
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), input).unwrap();
+            let mut processor = FileProcessor::new(commands.clone());
+            processor.apply_to_file(file.path()).unwrap();
+            assert_eq!(
+                fs::read_to_string(file.path()).unwrap(),
+                expected,
+                "{:?}",
+                input
+            );
+
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), input).unwrap();
+            let mut processor = StreamProcessor::new(commands);
+            processor.process_streaming_forced(file.path()).unwrap();
+            assert_eq!(
+                fs::read_to_string(file.path()).unwrap(),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_streaming_modified_lines_keep_original_text() {
+        for script in ["s/foo/bar/", "s/foo/bar/;s/missing/x/"] {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), "keep\nfoo here\nkeep\n").unwrap();
+
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+            let mut processor = StreamProcessor::new(commands).with_dry_run(true);
+            let diff = processor.process_streaming_forced(file.path()).unwrap();
+
+            let modified: Vec<&LineChange> = diff
+                .changes
+                .iter()
+                .filter(|c| c.change_type == ChangeType::Modified)
+                .collect();
+            assert_eq!(modified.len(), 1, "{}", script);
+            assert_eq!(modified[0].content, "bar here", "{}", script);
+            assert_eq!(
+                modified[0].old_content.as_deref(),
+                Some("foo here"),
+                "{}",
+                script
+            );
+        }
+    }
+
+    #[test]
+    fn test_streaming_no_default_output() {
+        for (script, expected) in [("2p", "l2\n"), ("s/l3/L3/p", "L3\n")] {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), "l1\nl2\nl3\n").unwrap();
+
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+            let mut processor = StreamProcessor::new(commands)
+                .with_no_default_output(true)
+                .with_dry_run(false);
+            let diff = processor.process_streaming_forced(file.path()).unwrap();
+
+            assert!(diff.is_streaming, "{}", script);
+            assert_eq!(
+                diff.printed_lines,
+                vec![expected.trim_end().to_string()],
+                "{}",
+                script
+            );
+            assert_eq!(
+                fs::read_to_string(file.path()).unwrap(),
+                expected,
+                "{}",
+                script
+            );
+        }
+    }
+
     #[test]
-    fn test_streaming_print() {
-        // Test print command (prints to stdout, file unchanged)
-        let test_file_path = "/tmp/test_print.txt";
-        let original_content = "line 1\nline 2\nline 3\n";
+    fn test_delete_first_line_restarts_cycle_with_insert() {
+        // i would otherwise send the script to the batch engine, where D
+        // can't restart the cycle
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("1i\\top\n$!N; /^\\n$/D; P; D")
+            .unwrap();
+        assert!(FileProcessor::supports_cycle_based_processing(&commands));
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "a\n\n\n\nb\n\nc\n").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.apply_to_file(file.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "top\na\n\nb\n\nc\n"
+        );
 
-        {
-            let mut file = fs::File::create(test_file_path).expect("Failed to create test file");
-            file.write_all(original_content.as_bytes())
-                .expect("Failed to write to test file");
-        }
+        // The plain sliding window prints every line, the last one included
+        let commands = Parser::new(RegexFlavor::PCRE).parse("$!N; P; D").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        let output = processor
+            .apply_cycle_based(vec!["1".into(), "2".into(), "3".into()])
+            .unwrap();
+        assert_eq!(output, vec!["1", "2", "3"]);
+    }
 
-        // Parse print command (1,$p means print from line 1 to last line)
-        let parser = Parser::new(RegexFlavor::PCRE);
-        let commands = parser.parse(r"1,$p").expect("Failed to parse print");
-        let mut processor = StreamProcessor::new(commands);
+    #[test]
+    fn test_only_matching_lines_bypass_the_script() {
+        // Skipped lines run no commands, but line numbers still count them
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("s/foo/FOO/; 3s/^/> /")
+            .unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "ERROR foo\ninfo foo\nERROR foo again\n").unwrap();
+
+        let mut processor = StreamProcessor::new(commands)
+            .with_only_matching(Some(Regex::new("ERROR").unwrap()))
+            .with_dry_run(false);
+        let diff = processor.process_streaming_forced(file.path()).unwrap();
+        assert!(diff.is_streaming);
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "ERROR FOO\ninfo foo\n> ERROR FOO again\n"
+        );
 
-        // Process the file (force streaming for testing)
-        // Note: this will print to stdout during test
-        let result = processor.process_streaming_forced(Path::new(test_file_path));
-        assert!(result.is_ok(), "Processing should succeed");
+        // Falling back to the in-memory engine would lose the filter
+        let commands = Parser::new(RegexFlavor::PCRE).parse("$a end").unwrap();
+        let mut processor =
+            StreamProcessor::new(commands).with_only_matching(Some(Regex::new("ERROR").unwrap()));
+        let err = processor.process_streaming_forced(file.path()).unwrap_err();
+        assert!(err.to_string().starts_with("--only-matching"), "{}", err);
+    }
 
-        // Verify file content is unchanged (print doesn't modify file)
-        let processed_content =
-            fs::read_to_string(test_file_path).expect("Failed to read processed file");
+    #[test]
+    fn test_max_count_leaves_later_matches_unchanged() {
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/foo/bar/").unwrap();
+        let expected = "bar 1\nkeep\nbar 2\nfoo 3\nfoo 4\n";
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "foo 1\nkeep\nfoo 2\nfoo 3\nfoo 4\n").unwrap();
+        let mut processor = StreamProcessor::new(commands.clone())
+            .with_max_count(Some(SubstitutionBudget::new(2)))
+            .with_dry_run(false);
+        let diff = processor.process_streaming_forced(file.path()).unwrap();
+        assert!(diff.is_streaming);
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), expected);
+
+        // The budget is shared: a second file gets what the first left over
+        let budget = SubstitutionBudget::new(3);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "foo 1\nkeep\nfoo 2\nfoo 3\nfoo 4\n").unwrap();
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.set_max_count(Some(budget.clone()));
+        processor.apply_to_file(file.path()).unwrap();
         assert_eq!(
-            processed_content, original_content,
-            "File should be unchanged"
+            fs::read_to_string(file.path()).unwrap(),
+            "bar 1\nkeep\nbar 2\nbar 3\nfoo 4\n"
         );
+        assert_eq!(budget.remaining(), 0);
 
-        // Clean up
-        fs::remove_file(test_file_path).ok();
+        let mut processor = FileProcessor::new(commands);
+        processor.set_max_count(Some(budget));
+        assert_eq!(
+            processor
+                .apply_cycle_based(vec!["foo".to_string()])
+                .unwrap(),
+            vec!["foo"]
+        );
     }
 
-    #[cfg_attr(not(unix), ignore)]
     #[test]
-    fn test_streaming_insert() {
-        // Test insert command (inserts text before specified line)
-        let test_file_path = "/tmp/test_insert.txt";
-        let original_content = "line 1\nline 2\nline 3\n";
+    fn test_substitution_w_flag_writes_changed_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("matched.txt");
+        let script = format!("s/foo/bar/w {}", out.display());
+        let commands = Parser::new(RegexFlavor::PCRE).parse(&script).unwrap();
+
+        let file = dir.path().join("input.txt");
+        fs::write(&file, "foo 1\nkeep\nfoo 2\n").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.apply_to_file(&file).unwrap();
 
-        {
-            let mut file = fs::File::create(test_file_path).expect("Failed to create test file");
-            file.write_all(original_content.as_bytes())
-                .expect("Failed to write to test file");
-        }
+        assert_eq!(fs::read_to_string(&file).unwrap(), "bar 1\nkeep\nbar 2\n");
+        assert_eq!(fs::read_to_string(&out).unwrap(), "bar 1\nbar 2\n");
+    }
 
-        // Parse insert command (2i\TEXT means insert TEXT before line 2)
-        let parser = Parser::new(RegexFlavor::PCRE);
-        let commands = parser
-            .parse(r"2i\INSERTED LINE")
-            .expect("Failed to parse insert");
-        let mut processor = StreamProcessor::new(commands);
+    #[test]
+    fn test_slurp_collapses_blank_lines_across_file() {
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse(r"s/\n\n+/\n/g")
+            .unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "a\n\n\n\nb\n\nc\n").unwrap();
+
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.set_slurp(true);
+        let diff = processor.process_file_with_context(file.path()).unwrap();
+        assert!(!diff.changes.is_empty());
+        processor.apply_to_file(file.path()).unwrap();
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "a\nb\nc\n");
+
+        // Line by line, no single line holds two newlines to collapse
+        fs::write(file.path(), "a\n\n\nb\n").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.apply_to_file(file.path()).unwrap();
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "a\n\n\nb\n");
+        assert!(slurp_lines(Vec::new()).is_empty());
+    }
 
-        // Process the file (force streaming for testing)
-        let result = processor.process_streaming_forced(Path::new(test_file_path));
-        assert!(result.is_ok(), "Processing should succeed");
+    #[test]
+    fn test_paragraph_substitutes_across_lines_and_keeps_separators() {
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse(r"s/end\nof/END OF/; s/^/> /")
+            .unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            file.path(),
+            "\nthe end\nof the first\n\n\nsecond\nparagraph\n\n",
+        )
+        .unwrap();
 
-        // Verify the line was inserted
-        let processed_content =
-            fs::read_to_string(test_file_path).expect("Failed to read processed file");
-        let expected = "line 1\nINSERTED LINE\nline 2\nline 3\n";
+        let mut processor = FileProcessor::new(commands);
+        processor.set_paragraph(true);
+        processor.apply_to_file(file.path()).unwrap();
         assert_eq!(
-            processed_content, expected,
-            "Line should be inserted before line 2"
+            fs::read_to_string(file.path()).unwrap(),
+            "\n> the END OF the first\n\n\n> second\nparagraph\n\n"
         );
 
-        // Clean up
-        fs::remove_file(test_file_path).ok();
+        // Round trip, and a script that deletes a paragraph
+        let lines: Vec<String> = ["", "a", "b", "", "", "c", "", "d"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (records, layout) = split_paragraphs(lines.clone());
+        assert_eq!(records, vec!["a\nb", "c", "d"]);
+        assert_eq!(join_paragraphs(records, &layout), lines);
+        assert_eq!(
+            join_paragraphs(vec!["a\nb".to_string(), "d".to_string()], &layout),
+            vec!["", "a", "b", "", "", "d"]
+        );
     }
 
-    #[cfg_attr(not(unix), ignore)]
     #[test]
-    fn test_streaming_append() {
-        // Test append command (appends text after specified line)
-        let test_file_path = "/tmp/test_append.txt";
-        let original_content = "line 1\nline 2\nline 3\n";
+    fn test_memory_limit_refuses_large_in_memory_input() {
+        // 1,/x/! can't stream, and i runs in the batch engine, which needs
+        // the whole file loaded
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("1,/x/!d\n2i\\new")
+            .unwrap();
+        assert!(!crate::capability::can_stream(&commands));
+        assert!(!FileProcessor::supports_cycle_based_processing(&commands));
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let content = "line\n".repeat(100);
+        fs::write(file.path(), &content).unwrap();
+
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.set_memory_limit(Some(1024));
+        let err = processor
+            .process_file_with_context(file.path())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("over the 1.0 KB limit"), "{}", err);
+        assert!(err.contains("processing.max_memory_mb"), "{}", err);
+        assert!(processor.apply_to_file(file.path()).is_err());
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), content);
 
-        {
-            let mut file = fs::File::create(test_file_path).expect("Failed to create test file");
-            file.write_all(original_content.as_bytes())
-                .expect("Failed to write to test file");
-        }
+        let mut processor = FileProcessor::new(commands);
+        processor.set_memory_limit(Some(4 * 1024));
+        assert!(processor.process_file_with_context(file.path()).is_ok());
+    }
 
-        // Parse append command (2a\TEXT means append TEXT after line 2)
-        let parser = Parser::new(RegexFlavor::PCRE);
-        let commands = parser
-            .parse(r"2a\APPENDED LINE")
-            .expect("Failed to parse append");
-        let mut processor = StreamProcessor::new(commands);
+    #[test]
+    fn test_timeout_stops_a_branch_loop() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "a\nb\n").unwrap();
+        let soon = || {
+            Some(Deadline {
+                at: Instant::now() + Duration::from_millis(50),
+                secs: 1,
+            })
+        };
+        let timed_out = |result: Result<()>| {
+            let err = result.unwrap_err();
+            assert!(err.downcast_ref::<TimedOut>().is_some(), "{}", err);
+            assert_eq!(
+                err.to_string(),
+                "Timed out after 1 s (--timeout); the script may loop forever"
+            );
+        };
 
-        // Process the file (force streaming for testing)
-        let result = processor.process_streaming_forced(Path::new(test_file_path));
-        assert!(result.is_ok(), "Processing should succeed");
+        // b back to the label never ends the cycle
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse(":a; s/a/A/; b a")
+            .unwrap();
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.set_deadline(soon());
+        timed_out(processor.process_file_with_context(file.path()).map(|_| ()));
+        let mut processor = FileProcessor::new(commands);
+        processor.set_deadline(soon());
+        timed_out(processor.apply_to_file(file.path()).map(|_| ()));
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "a\nb\n");
+
+        // Streaming checks as it reads each line, and leaves the file alone
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/a/A/").unwrap();
+        let mut processor = StreamProcessor::new(commands).with_deadline(Some(Deadline {
+            at: Instant::now(),
+            secs: 1,
+        }));
+        timed_out(processor.process_streaming_forced(file.path()).map(|_| ()));
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "a\nb\n");
+    }
 
-        // Verify the line was appended
-        let processed_content =
-            fs::read_to_string(test_file_path).expect("Failed to read processed file");
-        let expected = "line 1\nline 2\nAPPENDED LINE\nline 3\n";
-        assert_eq!(
-            processed_content, expected,
-            "Line should be appended after line 2"
+    #[test]
+    fn test_streaming_reports_progress_to_the_callback() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let content: String = (0..5_000).map(|i| format!("line {}\n", i)).collect();
+        fs::write(file.path(), &content).unwrap();
+        let total = content.len() as u64;
+
+        let stream = |script: &str| {
+            let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let sink = Arc::clone(&reports);
+            let callback: ProgressCallback =
+                Arc::new(move |progress| sink.lock().unwrap().push(*progress));
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+            StreamProcessor::new(commands)
+                .with_dry_run(true)
+                .with_progress(Some(callback))
+                .process_streaming_forced(file.path())
+                .unwrap();
+            Arc::try_unwrap(reports).unwrap().into_inner().unwrap()
+        };
+
+        // However many reports come on the way, the last covers the file
+        let reports = stream("s/line/LINE/");
+        let last = reports.last().unwrap();
+        assert_eq!((last.bytes_read, last.total_bytes), (total, total));
+        assert_eq!(last.lines, 5_000);
+        assert!(last.done);
+        assert!(reports[..reports.len() - 1].iter().all(|p| !p.done));
+        assert!(
+            reports
+                .windows(2)
+                .all(|w| w[0].bytes_read <= w[1].bytes_read)
         );
 
-        // Clean up
-        fs::remove_file(test_file_path).ok();
+        // A q stops the file early, and still sends the last report
+        let reports = stream("10q");
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].lines, 10);
+        assert_eq!(reports[0].bytes_read, "line 0\n".len() as u64 * 10);
+        assert!(reports[0].done);
     }
 
-    #[cfg_attr(not(unix), ignore)]
     #[test]
-    fn test_streaming_change() {
-        // Test change command (replaces specified line with new text)
-        let test_file_path = "/tmp/test_change.txt";
-        let original_content = "line 1\nline 2\nline 3\n";
-
-        {
-            let mut file = fs::File::create(test_file_path).expect("Failed to create test file");
-            file.write_all(original_content.as_bytes())
-                .expect("Failed to write to test file");
-        }
+    fn test_memory_limit_spills_cycle_engine_scripts_to_disk() {
+        // 1,/x/! can't stream, but the cycle engine reads lines in order,
+        // so a file over the limit runs from disk with the same result
+        let script = "1,/^0$/!{$!N; s/\\n/+/}; /^9/s/9/nine/";
+        let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+        assert!(!crate::capability::can_stream(&commands));
+
+        let content: String = (0..5_000).map(|i| format!("{}\n", i)).collect();
+        let content = format!("x\n{}last", content);
+        let dir = tempfile::tempdir().unwrap();
+        let (loaded, spilled) = (
+            dir.path().join("loaded.txt"),
+            dir.path().join("spilled.txt"),
+        );
+        fs::write(&loaded, &content).unwrap();
+        fs::write(&spilled, &content).unwrap();
+
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.apply_to_file(&loaded).unwrap();
+
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.set_memory_limit(Some(1024));
+        let diff = processor.process_file_with_context(&spilled).unwrap();
+        assert!(diff.is_streaming);
+        assert!(diff.all_lines.is_empty());
+        // The preview leaves the file alone and keeps no temp file
+        assert_eq!(fs::read_to_string(&spilled).unwrap(), content);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+        // Lines 1 and 2 are joined as new line 3, after x and 0 as context
+        let first: Vec<(usize, ChangeType, &str)> = diff.changes[..4]
+            .iter()
+            .map(|c| (c.line_number, c.change_type.clone(), c.content.as_str()))
+            .collect();
+        assert_eq!(
+            first,
+            vec![
+                (1, ChangeType::Unchanged, "x"),
+                (2, ChangeType::Unchanged, "0"),
+                (3, ChangeType::Modified, "1+2"),
+                (4, ChangeType::Deleted, "2"),
+            ]
+        );
+        assert_eq!(diff.changes[2].old_content.as_deref(), Some("1"));
 
-        // Parse change command (2c\TEXT means change line 2 to TEXT)
-        let parser = Parser::new(RegexFlavor::PCRE);
-        let commands = parser
-            .parse(r"2c\CHANGED LINE")
-            .expect("Failed to parse change");
-        let mut processor = StreamProcessor::new(commands);
+        let mut processor = FileProcessor::new(commands);
+        processor.set_memory_limit(Some(1024));
+        let lines = processor.apply_to_file(&spilled).unwrap();
+        let applied = fs::read_to_string(&spilled).unwrap();
+        assert_eq!(applied, fs::read_to_string(&loaded).unwrap());
+        assert_eq!(lines, applied.lines().count());
+        assert!(applied.ends_with("4999+last"));
+        assert!(applied.contains("\nnine+10\n"));
+
+        // A q leaves the rest of the file out, as in memory
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("3q; 1,/^0$/!d")
+            .unwrap();
+        fs::write(&spilled, &content).unwrap();
+        fs::write(&loaded, &content).unwrap();
+        FileProcessor::new(commands.clone())
+            .apply_to_file(&loaded)
+            .unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.set_memory_limit(Some(1024));
+        processor.apply_to_file(&spilled).unwrap();
+        assert_eq!(fs::read_to_string(&spilled).unwrap(), "x\n0\n1\n");
+        assert_eq!(
+            fs::read_to_string(&spilled).unwrap(),
+            fs::read_to_string(&loaded).unwrap()
+        );
+    }
 
-        // Process the file (force streaming for testing)
-        let result = processor.process_streaming_forced(Path::new(test_file_path));
-        assert!(result.is_ok(), "Processing should succeed");
+    #[test]
+    fn test_streaming_negated_addresses() {
+        for (script, input, expected) in [
+            (
+                "/keep/!d",
+                "keep 1\ndrop\nkeep 2\ndrop\n",
+                "keep 1\nkeep 2\n",
+            ),
+            (
+                "5!s/x/y/",
+                "x1\nx2\nx3\nx4\nx5\nx6\n",
+                "y1\ny2\ny3\ny4\nx5\ny6\n",
+            ),
+        ] {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), input).unwrap();
+
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+            let mut processor = StreamProcessor::new(commands);
+            let diff = processor.process_streaming_forced(file.path()).unwrap();
+            assert!(diff.is_streaming, "{}", script);
+            assert_eq!(
+                fs::read_to_string(file.path()).unwrap(),
+                expected,
+                "{}",
+                script
+            );
+        }
+    }
 
-        // Verify the line was changed
-        let processed_content =
-            fs::read_to_string(test_file_path).expect("Failed to read processed file");
-        let expected = "line 1\nCHANGED LINE\nline 3\n";
-        assert_eq!(processed_content, expected, "Line 2 should be changed");
+    #[test]
+    fn test_ascii_case_folds_only_ascii_letters() {
+        assert_eq!(ascii_case_insensitive("k+"), "[kK]+");
+        assert_eq!(
+            ascii_case_insensitive(r"(?P<id>a\w)[^b-dX]\p{Greek}{2}"),
+            r"(?P<id>[aA]\w)[^b-dB-DxX]\p{Greek}{2}"
+        );
+        assert_eq!(ascii_case_insensitive("[[:alpha:]0-9]"), "[[:alpha:]0-9]");
+
+        // Unicode folding matches the Kelvin sign (U+212A) and long s (U+017F)
+        // for k and s; ASCII folding doesn't. (The regex crate uses simple
+        // case folding, which never maps i to the Turkish dotted İ.)
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/[ks]/X/gi").unwrap();
+        let input = || vec!["k K \u{212A} s S \u{17F}".to_string()];
+
+        let mut processor = FileProcessor::new(commands.clone());
+        let output = processor.apply_cycle_based(input()).unwrap();
+        assert_eq!(output, vec!["X X X X X X"]);
+
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.set_ascii_case(true);
+        let output = processor.apply_cycle_based(input()).unwrap();
+        assert_eq!(output, vec!["X X \u{212A} X X \u{17F}"]);
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "k \u{212A}\n").unwrap();
+        let mut processor = StreamProcessor::new(commands)
+            .with_ascii_case(true)
+            .with_dry_run(false);
+        processor.process_streaming_forced(file.path()).unwrap();
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "X \u{212A}\n");
+    }
 
-        // Clean up
-        fs::remove_file(test_file_path).ok();
+    #[test]
+    fn test_cycle_negated_addresses() {
+        let lines = || vec!["a".to_string(), "skip".into(), "b".into(), "c".into()];
+        for (script, quiet, expected) in [
+            // $!G double-spaces every line but the last
+            ("$!G", false, vec!["a\n", "skip\n", "b\n", "c"]),
+            ("/skip/!p", true, vec!["a", "b", "c"]),
+            ("/skip/!s/^/> /", false, vec!["> a", "skip", "> b", "> c"]),
+            ("2!d", false, vec!["skip"]),
+            // Negated ranges: every line outside the range
+            ("2,3!d", false, vec!["skip", "b"]),
+            ("/a/,/skip/!d", false, vec!["a", "skip"]),
+            ("0,/skip/!s/^/> /", false, vec!["a", "skip", "> b", "> c"]),
+        ] {
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+            let mut processor = FileProcessor::new(commands);
+            processor.set_no_default_output(quiet);
+            let output = processor.apply_cycle_based(lines()).unwrap();
+            assert_eq!(output, expected, "{}", script);
+        }
     }
 
     #[cfg_attr(not(unix), ignore)]
@@ -3618,6 +7771,115 @@ mod tests {
         fs::remove_file(test_file_path).ok();
     }
 
+    #[test]
+    fn test_pipe_interleaves_printed_and_default_output() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let pipe = |script: &str, quiet: bool| {
+            let mut processor =
+                StreamProcessor::new(parser.parse(script).unwrap()).with_no_default_output(quiet);
+            let mut output = Vec::new();
+            processor
+                .process_pipe("ax\nb\ncx\n".as_bytes(), &mut output)
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(pipe("/x/p", true), "ax\ncx\n");
+        // Each line is printed by p, then again by the default output
+        assert_eq!(pipe("p", false), "ax\nax\nb\nb\ncx\ncx\n");
+        // p prints the pattern space as it is when p runs
+        assert_eq!(pipe("p;s/x/X/", false), "ax\naX\nb\nb\ncx\ncX\n");
+        // Inserted text and p output keep the order their commands ran in
+        assert_eq!(pipe("2p;2i\\\nnew", false), "ax\nb\nnew\nb\ncx\n");
+        // d ends the cycle before a later p
+        assert_eq!(pipe("/b/d;p", true), "ax\ncx\n");
+    }
+
+    #[test]
+    fn test_pipe_quit_ends_the_cycle() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let pipe = |script: &str, quiet: bool| {
+            let mut processor =
+                StreamProcessor::new(parser.parse(script).unwrap()).with_no_default_output(quiet);
+            let mut output = Vec::new();
+            processor
+                .process_pipe("1\n2\n3\n4\n5\n6\n7\n".as_bytes(), &mut output)
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // Commands after q don't run on its line, which -n leaves out
+        assert_eq!(pipe("3q;p", true), "1\n2\n");
+        assert_eq!(pipe("2q;s/$/!/", false), "1!\n2\n");
+    }
+
+    #[test]
+    fn test_streaming_quit_reports_exit_code() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "1\n2\n3\n4\n5\n6\n7\n").unwrap();
+
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let mut processor = StreamProcessor::new(parser.parse("5q3").unwrap()).with_dry_run(true);
+        let diff = processor.process_streaming_forced(file.path()).unwrap();
+        assert_eq!(diff.exit_code, Some(3));
+
+        let mut processor = StreamProcessor::new(parser.parse("9q3").unwrap()).with_dry_run(true);
+        let diff = processor.process_streaming_forced(file.path()).unwrap();
+        assert_eq!(diff.exit_code, None);
+    }
+
+    #[test]
+    fn test_streaming_reads_footer_once_at_end() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let footer = dir.path().join("footer.txt");
+        fs::write(&footer, "-- footer 1\n-- footer 2\n").unwrap();
+        let target = dir.path().join("target.txt");
+        fs::write(&target, "a\nb\nc\n").unwrap();
+
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let commands = parser.parse(&format!("$r {}", footer.display())).unwrap();
+        let mut processor = StreamProcessor::new(commands);
+        let diff = processor.process_streaming_forced(&target).unwrap();
+
+        assert!(diff.is_streaming);
+        assert_eq!(
+            fs::read_to_string(&target).unwrap(),
+            "a\nb\nc\n-- footer 1\n-- footer 2\n"
+        );
+        let added: Vec<&str> = diff
+            .changes
+            .iter()
+            .filter(|c| c.change_type == ChangeType::Added)
+            .map(|c| c.content.as_str())
+            .collect();
+        assert_eq!(added, vec!["-- footer 1", "-- footer 2"]);
+    }
+
+    #[test]
+    fn test_streaming_read_line_advances_across_cycles() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let source = dir.path().join("source.txt");
+        fs::write(&source, "one\ntwo\n").unwrap();
+
+        // Each R reads the next line; once the source runs out R adds nothing
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let commands = parser.parse(&format!("R {}", source.display())).unwrap();
+        let mut output = Vec::new();
+        StreamProcessor::new(commands)
+            .process_pipe("a\nb\nc\n".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "a\none\nb\ntwo\nc\n");
+
+        // r output still follows a line -n leaves out
+        let commands = parser.parse(&format!("/b/r {}", source.display())).unwrap();
+        let mut output = Vec::new();
+        StreamProcessor::new(commands)
+            .with_no_default_output(true)
+            .process_pipe("a\nb\nc\n".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "one\ntwo\n");
+    }
+
     #[cfg_attr(not(unix), ignore)]
     #[test]
     fn test_streaming_quit_immediately() {
@@ -3882,31 +8144,122 @@ mod tests {
             "Line 1 should NOT be changed"
         );
 
-        // Lines 2 and 3 should be "bar"
-        let lines: Vec<&str> = processed_content.lines().collect();
-        assert_eq!(lines[0], "keep", "Line 1 should be 'keep'");
-        assert_eq!(lines[1], "bar", "Line 2 should be changed to 'bar'");
-        assert_eq!(lines[2], "bar", "Line 3 should be changed to 'bar'");
+        // Lines 2 and 3 should be "bar"
+        let lines: Vec<&str> = processed_content.lines().collect();
+        assert_eq!(lines[0], "keep", "Line 1 should be 'keep'");
+        assert_eq!(lines[1], "bar", "Line 2 should be changed to 'bar'");
+        assert_eq!(lines[2], "bar", "Line 3 should be changed to 'bar'");
+        assert_eq!(
+            lines[3], "foo",
+            "Line 4 should still be 'foo' (not in range)"
+        );
+        assert_eq!(lines[4], "keep", "Line 5 should be 'keep'");
+
+        // Count: should have exactly 2 "bar" (lines 2 and 3)
+        let bar_count = processed_content.matches("bar").count();
+        assert_eq!(bar_count, 2, "Should have exactly 2 'bar' (lines 2,3)");
+
+        // Count: should still have 2 "keep" (lines 1 and 5)
+        let keep_count = processed_content.matches("keep").count();
+        assert_eq!(keep_count, 2, "Should have exactly 2 'keep' (lines 1,5)");
+
+        // Count: should have 1 "foo" remaining (line 4)
+        let foo_count = processed_content.matches("foo").count();
+        assert_eq!(foo_count, 1, "Should have exactly 1 'foo' (line 4)");
+
+        // Clean up
+        fs::remove_file(test_file_path).ok();
+    }
+
+    #[test]
+    fn test_streaming_group_inner_commands_keep_own_range_state() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "x\nA x\nskip x\nx\nB x\nskip\nx\n").unwrap();
+
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("/A/,/B/{s/x/y/; /skip/d}")
+            .unwrap();
+        let diff = StreamProcessor::new(commands)
+            .process_streaming_forced(file.path())
+            .unwrap();
+
+        assert!(diff.is_streaming);
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "x\nA y\ny\nB y\nskip\nx\n"
+        );
+    }
+
+    #[test]
+    fn test_streaming_nested_group() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "x\nA x\nskip x\nx\nB x\nskip\nx\n").unwrap();
+
+        // The inner range matches the outer one; each must track its own state
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .parse("/A/,/B/{/A/,/B/{s/x/z/};s/x/y/}")
+            .unwrap();
+        let diff = StreamProcessor::new(commands)
+            .process_streaming_forced(file.path())
+            .unwrap();
+
+        assert!(diff.is_streaming);
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "x\nA z\nskip z\nz\nB z\nskip\nx\n"
+        );
+    }
+
+    #[test]
+    fn test_cycle_group_runs_once_per_line() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let input: Vec<String> = ["aa", "aa", "aa", "aa"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        // Each line in 2,3 is substituted once and printed once
+        let commands = parser.parse("2,3{s/a/b/; p}").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        assert_eq!(
+            processor.apply_cycle_based(input.clone()).unwrap(),
+            vec!["aa", "ba", "ba", "ba", "ba", "aa"]
+        );
+
+        let commands = parser.parse("2,3{d}").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        assert_eq!(
+            processor.apply_cycle_based(input).unwrap(),
+            vec!["aa", "aa"]
+        );
+    }
+
+    #[test]
+    fn test_cycle_nested_groups_check_inner_addresses() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let input: Vec<String> = ["x1", "y2", "x3", "x4", "y5"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let commands = parser.parse("2,4{/x/{s/x/X/;4d}}").unwrap();
+        let mut processor = FileProcessor::new(commands);
         assert_eq!(
-            lines[3], "foo",
-            "Line 4 should still be 'foo' (not in range)"
+            processor.apply_cycle_based(input).unwrap(),
+            vec!["x1", "y2", "X3", "y5"]
         );
-        assert_eq!(lines[4], "keep", "Line 5 should be 'keep'");
-
-        // Count: should have exactly 2 "bar" (lines 2 and 3)
-        let bar_count = processed_content.matches("bar").count();
-        assert_eq!(bar_count, 2, "Should have exactly 2 'bar' (lines 2,3)");
+    }
 
-        // Count: should still have 2 "keep" (lines 1 and 5)
-        let keep_count = processed_content.matches("keep").count();
-        assert_eq!(keep_count, 2, "Should have exactly 2 'keep' (lines 1,5)");
+    #[test]
+    fn test_apply_to_file_uses_cycle_groups() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "a\nb\nc\nd\n").unwrap();
 
-        // Count: should have 1 "foo" remaining (line 4)
-        let foo_count = processed_content.matches("foo").count();
-        assert_eq!(foo_count, 1, "Should have exactly 1 'foo' (line 4)");
+        let commands = Parser::new(RegexFlavor::PCRE).parse("2,3{p;d}").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.apply_to_file(file.path()).unwrap();
 
-        // Clean up
-        fs::remove_file(test_file_path).ok();
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "a\nb\nc\nd\n");
     }
 
     #[test]
@@ -3950,7 +8303,9 @@ mod tests {
 #[cfg(test)]
 mod cycle_tests {
     use super::*;
+    use crate::cli::RegexFlavor;
     use crate::command::{Address, Command, SubstitutionFlags};
+    use crate::parser::Parser;
 
     /// Helper to parse a simple sed expression for testing
     /// NOTE: This is a test helper that manually constructs commands for specific test cases.
@@ -4029,6 +8384,73 @@ mod cycle_tests {
         assert_eq!(result, vec!["1", "3"]);
     }
 
+    #[test]
+    fn test_dump_cycle_trace_records() {
+        // "x; G" on 3 lines: two command executions per cycle
+        let commands = vec![
+            Command::Exchange { range: None },
+            Command::GetAppend { range: None },
+        ];
+        let mut processor = FileProcessor::new(commands);
+        let trace = tempfile::NamedTempFile::new().unwrap();
+        processor.set_cycle_trace(trace.reopen().unwrap());
+
+        let input = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        processor.apply_cycle_based(input).unwrap();
+
+        let content = std::fs::read_to_string(trace.path()).unwrap();
+        let records: Vec<CycleTraceRecord> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(records.len(), 6);
+
+        assert_eq!(records[0].line_number, 1);
+        assert_eq!(records[0].command_index, 0);
+        assert_eq!(records[0].pattern_space_before, "a");
+        assert_eq!(records[0].pattern_space_after, "");
+        assert_eq!(records[0].hold_space_after, "a");
+        assert_eq!(records[0].result, "Continue");
+
+        let last = &records[5];
+        assert_eq!(last.line_number, 3);
+        assert_eq!(last.command_index, 1);
+        assert_eq!(last.command, Command::GetAppend { range: None });
+        assert_eq!(last.pattern_space_before, "b");
+        assert_eq!(last.pattern_space_after, "b\nc");
+    }
+
+    #[test]
+    fn test_dump_cycle_trace_records_in_file_mode() {
+        // i and a usually run in the batch engine, which writes no trace
+        let commands = Parser::new(RegexFlavor::PCRE).parse("2i\\foo").unwrap();
+        assert!(!FileProcessor::supports_cycle_based_processing(&commands));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f.txt");
+        fs::write(&path, "1\n2\n3\n").unwrap();
+        let trace = tempfile::NamedTempFile::new().unwrap();
+
+        let mut processor = FileProcessor::new(commands.clone());
+        processor.set_cycle_trace(trace.reopen().unwrap());
+        let diff = processor.process_file_with_context(&path).unwrap();
+        assert_eq!(diff.changes.len(), 1);
+        let content = fs::read_to_string(trace.path()).unwrap();
+        let records: Vec<CycleTraceRecord> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].line_number, 2);
+        assert_eq!(records[1].pattern_space_before, "2");
+
+        let trace = tempfile::NamedTempFile::new().unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.set_cycle_trace(trace.reopen().unwrap());
+        processor.apply_to_file(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\nfoo\n2\n3\n");
+        assert_eq!(fs::read_to_string(trace.path()).unwrap().lines().count(), 3);
+    }
+
     #[test]
     fn test_n_command_alone() {
         // Test n command alone (should print all lines)
@@ -4054,6 +8476,9 @@ mod cycle_tests {
                 case_insensitive: false,
                 print: false,
                 nth: None,
+                exec: false,
+                multiline: false,
+                write_file: None,
             },
             range: None, // No range - applies to all lines
         }];
@@ -4077,6 +8502,9 @@ mod cycle_tests {
                 case_insensitive: false,
                 print: false,
                 nth: None,
+                exec: false,
+                multiline: false,
+                write_file: None,
             },
             range: None,
         }];
@@ -4100,6 +8528,9 @@ mod cycle_tests {
                 case_insensitive: false,
                 print: true, // p flag
                 nth: None,
+                exec: false,
+                multiline: false,
+                write_file: None,
             },
             range: None,
         }];
@@ -4166,6 +8597,9 @@ mod cycle_tests {
                     case_insensitive: false,
                     print: false,
                     nth: None,
+                    exec: false,
+                    multiline: false,
+                    write_file: None,
                 },
                 range: None, // Applies to all lines when None
             },
@@ -4182,4 +8616,605 @@ mod cycle_tests {
         // "foo baz" -> s -> "bar baz" -> h (hold="bar baz") -> g (pattern="bar baz")
         assert_eq!(result, vec!["bar baz"]);
     }
+
+    #[test]
+    fn test_list_escapes_tabs_and_backslashes() {
+        let commands = vec![Command::List {
+            range: None,
+            width: None,
+        }];
+        let mut processor = FileProcessor::new(commands);
+        processor.set_no_default_output(true);
+
+        let input = vec!["a\tb\\c".to_string()];
+        let result = processor.apply_cycle_based(input).unwrap();
+        assert_eq!(result, vec!["a\\tb\\\\c$"]);
+    }
+
+    #[test]
+    fn test_list_escapes_multibyte_and_embedded_newline() {
+        // "é" is 0xC3 0xA9 in UTF-8; each byte is shown in octal
+        assert_eq!(
+            FileProcessor::format_list_output("caf\u{e9}\nx", 70),
+            "caf\\303\\251\\nx$"
+        );
+    }
+
+    #[test]
+    fn test_list_wraps_at_width() {
+        assert_eq!(
+            FileProcessor::format_list_output("abcdefghij", 5),
+            "abcd\\\nefgh\\\nij$"
+        );
+        // Escapes are never split across lines
+        assert_eq!(FileProcessor::format_list_output("abc\t", 5), "abc\\\n\\t$");
+        // Width 0 disables wrapping
+        assert_eq!(
+            FileProcessor::format_list_output("abcdefghij", 0),
+            "abcdefghij$"
+        );
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_substitution_exec_flag_replaces_with_output() {
+        let parser = crate::parser::Parser::new(crate::cli::RegexFlavor::PCRE);
+        let commands = parser.parse("s/.*/printf X/e").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.set_allow_exec(true);
+
+        let input = vec!["a".to_string(), "b".to_string()];
+        let result = processor.apply_cycle_based(input).unwrap();
+        assert_eq!(result, vec!["X", "X"]);
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_substitution_exec_flag_skips_unchanged_lines() {
+        let marker = std::env::temp_dir().join("sedx_exec_flag_marker");
+        std::fs::remove_file(&marker).ok();
+
+        let parser = crate::parser::Parser::new(crate::cli::RegexFlavor::PCRE);
+        let commands = parser.parse("s/^go$/printf X/ep").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.set_allow_exec(true);
+        processor.set_no_default_output(true);
+
+        // The second line is a command, but the substitution doesn't change it
+        let touch = format!("touch {}", marker.display());
+        let input = vec!["go".to_string(), touch];
+        let result = processor.apply_cycle_based(input).unwrap();
+
+        assert_eq!(result, vec!["X"]);
+        assert!(!marker.exists(), "Non-matching line must not be executed");
+    }
+
+    #[test]
+    fn test_substitution_exec_flag_requires_allow_exec() {
+        let parser = crate::parser::Parser::new(crate::cli::RegexFlavor::PCRE);
+        let commands = parser.parse("s/.*/printf X/e").unwrap();
+        let mut processor = FileProcessor::new(commands);
+
+        let err = processor
+            .apply_cycle_based(vec!["a".to_string()])
+            .unwrap_err();
+        assert!(err.to_string().contains("--allow-exec"));
+    }
+
+    #[test]
+    fn test_print_filename_precedes_matching_lines() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "ok\nerr: one\nok\n").unwrap();
+        let path = file.path().display().to_string();
+
+        let parser = crate::parser::Parser::new(crate::cli::RegexFlavor::PCRE);
+        let commands = parser.parse("/err/{F;=;p}").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.set_no_default_output(true);
+        let diff = processor.process_file_with_context(file.path()).unwrap();
+
+        let output: Vec<&str> = diff
+            .all_lines
+            .iter()
+            .filter(|(_, _, change)| *change != ChangeType::Deleted)
+            .map(|(_, line, _)| line.as_str())
+            .collect();
+        assert_eq!(output, vec![path.as_str(), "2", "err: one"]);
+
+        // Without a file, F prints "-" like GNU sed does for stdin
+        let commands = parser.parse("F").unwrap();
+        let mut processor = FileProcessor::new(commands);
+        processor.set_no_default_output(true);
+        let result = processor.apply_cycle_based(vec!["a".to_string()]).unwrap();
+        assert_eq!(result, vec!["-"]);
+
+        // --stdin-filename names piped input after the file it holds
+        let mut processor = FileProcessor::new(parser.parse("F").unwrap());
+        processor.set_no_default_output(true);
+        processor.set_input_name("config.yaml");
+        let result = processor.apply_cycle_based(vec!["a".to_string()]).unwrap();
+        assert_eq!(result, vec!["config.yaml"]);
+        // and so do errors from streaming it
+        let err = StreamProcessor::new(parser.parse("p").unwrap())
+            .with_input_name(Some("config.yaml".to_string()))
+            .process_pipe(&b"ok\n\xff\n"[..], Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "config.yaml is not valid UTF-8 (invalid byte on line 2)"
+        );
+    }
+
+    #[test]
+    fn test_last_line_spans_concatenated_files() {
+        let first = tempfile::NamedTempFile::new().unwrap();
+        let second = tempfile::NamedTempFile::new().unwrap();
+        fs::write(first.path(), "a1\na2\n").unwrap();
+        fs::write(second.path(), "b1\nb2\n").unwrap();
+        let paths = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+        let commands = crate::parser::Parser::new(crate::cli::RegexFlavor::PCRE)
+            .parse("$d")
+            .unwrap();
+
+        // One stream: only the last line of the last file is `$`
+        let mut processor = FileProcessor::new(commands.clone());
+        let results: Vec<_> = processor
+            .process_files_concatenated(&paths)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(results[0].1, vec!["a1", "a2"]);
+        assert_eq!(results[1].1, vec!["b1"]);
+
+        // Separate files (-s): each file has its own last line
+        for (path, expected) in paths.iter().zip([vec!["a1"], vec!["b1"]]) {
+            let mut processor = FileProcessor::new(commands.clone());
+            let diff = processor.process_file_with_context(path).unwrap();
+            let output: Vec<&str> = diff
+                .all_lines
+                .iter()
+                .filter(|(_, _, change)| *change != ChangeType::Deleted)
+                .map(|(_, line, _)| line.as_str())
+                .collect();
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_concatenated_files_share_line_numbers_and_quit() {
+        let first = tempfile::NamedTempFile::new().unwrap();
+        let second = tempfile::NamedTempFile::new().unwrap();
+        let third = tempfile::NamedTempFile::new().unwrap();
+        fs::write(first.path(), "a1\na2\n").unwrap();
+        fs::write(second.path(), "b1\nb2\n").unwrap();
+        fs::write(third.path(), "c1\n").unwrap();
+        let paths = vec![
+            first.path().to_path_buf(),
+            second.path().to_path_buf(),
+            third.path().to_path_buf(),
+        ];
+        let commands = crate::parser::Parser::new(crate::cli::RegexFlavor::PCRE)
+            .parse("3s/^/X/;4q")
+            .unwrap();
+
+        let mut processor = FileProcessor::new(commands);
+        let results: Vec<_> = processor
+            .process_files_concatenated(&paths)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(results[0].1, vec!["a1", "a2"]);
+        assert_eq!(results[1].1, vec!["Xb1", "b2"]);
+        // Never reached after q: left as it was
+        assert_eq!(results[2].1, vec!["c1"]);
+        assert!(!results[2].0.has_changes());
+    }
+
+    #[test]
+    fn test_concatenated_files_across_file_boundaries() {
+        let first = tempfile::NamedTempFile::new().unwrap();
+        let second = tempfile::NamedTempFile::new().unwrap();
+        fs::write(first.path(), "1\n2\n").unwrap();
+        fs::write(second.path(), "3\n").unwrap();
+        let missing = first.path().with_extension("missing");
+        let paths = vec![
+            first.path().to_path_buf(),
+            missing,
+            second.path().to_path_buf(),
+        ];
+        let parser = crate::parser::Parser::new(crate::cli::RegexFlavor::PCRE);
+        let run = |script: &str| {
+            let mut processor = FileProcessor::new(parser.parse(script).unwrap());
+            processor.process_files_concatenated(&paths).unwrap()
+        };
+        let lines = |result: &Result<FileOutput>| result.as_ref().unwrap().1.clone();
+
+        // A cycle that reads past the end of a file writes to the next one
+        let results = run("N;N;s/\\n/+/g");
+        assert_eq!(lines(&results[0]), Vec::<String>::new());
+        assert_eq!(lines(&results[2]), vec!["1+2+3"]);
+
+        // The missing file is reported on its own and left out of the stream
+        assert!(
+            results[1]
+                .as_ref()
+                .unwrap_err()
+                .to_string()
+                .contains("Failed to read file")
+        );
+
+        // i, a and c run in the same stream
+        let results = run("2i\\X\n$a\\END\n3c\\C");
+        assert_eq!(lines(&results[0]), vec!["1", "X", "2"]);
+        assert_eq!(lines(&results[2]), vec!["C", "END"]);
+    }
+
+    #[test]
+    fn test_empty_script_passes_input_through() {
+        let input = vec!["one".to_string(), "".to_string(), "three".to_string()];
+
+        let mut processor = FileProcessor::new(Vec::new());
+        assert_eq!(processor.apply_cycle_based(input.clone()).unwrap(), input);
+
+        let mut processor = FileProcessor::new(Vec::new());
+        processor.set_no_default_output(true);
+        assert!(processor.apply_cycle_based(input).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_substitution_multiline_flag_anchors_at_embedded_newlines() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let input = vec!["a".to_string(), "b".to_string()];
+
+        // Without M, ^ only matches at the start of the pattern space
+        let mut processor = FileProcessor::new(parser.parse("N;s/^b/X/").unwrap());
+        assert_eq!(
+            processor.apply_cycle_based(input.clone()).unwrap(),
+            vec!["a\nb"]
+        );
+
+        let mut processor = FileProcessor::new(parser.parse("N;s/^b/X/M").unwrap());
+        assert_eq!(
+            processor.apply_cycle_based(input.clone()).unwrap(),
+            vec!["a\nX"]
+        );
+
+        let mut processor = FileProcessor::new(parser.parse("N;s/a$/Y/m").unwrap());
+        assert_eq!(processor.apply_cycle_based(input).unwrap(), vec!["Y\nb"]);
+    }
+
+    #[test]
+    fn test_cycle_addresses_match_across_embedded_newlines() {
+        let lines = || {
+            ["foo", "bar", "baz", "qux"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        for (script, quiet, expected) in [
+            // \n matches the newline N put in the pattern space
+            (r"N; /foo\nbar/d", false, vec!["baz\nqux"]),
+            // ^ and $ anchor the whole pattern space, or each line with M
+            ("N; /^qux/p", true, vec![]),
+            ("N; /^qux/Mp", true, vec!["baz\nqux"]),
+            ("N; /foo$/M!s/^/> /", false, vec!["foo\nbar", "> baz\nqux"]),
+        ] {
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+            let mut processor = FileProcessor::new(commands);
+            processor.set_no_default_output(quiet);
+            let output = processor.apply_cycle_based(lines()).unwrap();
+            assert_eq!(output, expected, "{}", script);
+        }
+    }
+
+    #[test]
+    fn test_quit_with_exit_code_stops_at_its_line() {
+        let input: Vec<String> = (1..=7).map(|n| n.to_string()).collect();
+        let parser = Parser::new(RegexFlavor::PCRE);
+
+        let mut processor = FileProcessor::new(parser.parse("5q3").unwrap());
+        let output = processor.apply_cycle_based(input.clone()).unwrap();
+        assert_eq!(output, vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(processor.exit_code(), Some(3));
+
+        // Q leaves out the line it quits at
+        let mut processor = FileProcessor::new(parser.parse("5Q3").unwrap());
+        let output = processor.apply_cycle_based(input.clone()).unwrap();
+        assert_eq!(output, vec!["1", "2", "3", "4"]);
+        assert_eq!(processor.exit_code(), Some(3));
+
+        // A q that never runs asks for nothing
+        let mut processor = FileProcessor::new(parser.parse("9q3").unwrap());
+        processor.apply_cycle_based(input).unwrap();
+        assert_eq!(processor.exit_code(), None);
+    }
+
+    #[test]
+    fn test_next_append_at_eof_gnu_vs_posix() {
+        // Odd line count: the final N has no line to append
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let input = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+
+        // GNU: print the pattern space and stop, skipping later commands
+        let mut processor = FileProcessor::new(parser.parse("N;s/$/X/").unwrap());
+        assert_eq!(
+            processor.apply_cycle_based(input.clone()).unwrap(),
+            vec!["a\nbX", "c"]
+        );
+
+        // POSIX: discard the pattern space
+        let mut processor = FileProcessor::new(parser.parse("N;s/$/X/").unwrap());
+        processor.set_compat_mode(CompatMode::Posix);
+        assert_eq!(processor.apply_cycle_based(input).unwrap(), vec!["a\nbX"]);
+
+        // File preview: streaming delegates N to the in-memory engine and
+        // must pass the mode along
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "a\nb\nc\n").unwrap();
+        for (mode, keeps_last_line) in [(CompatMode::Gnu, true), (CompatMode::Posix, false)] {
+            let mut processor = StreamProcessor::new(parser.parse("N;s/$/X/").unwrap())
+                .with_compat_mode(mode)
+                .with_dry_run(true);
+            let diff = processor.process_streaming_forced(file.path()).unwrap();
+            let kept = diff.all_lines.iter().any(|(_, content, change_type)| {
+                *change_type != ChangeType::Deleted && content == "c"
+            });
+            assert_eq!(kept, keeps_last_line, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_first_line_address_range_can_end_on_line_one() {
+        // 0,/re/ ends on line 1 when it matches there; 1,/re/ looks from line 2
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let first_only = parser.parse("0,/version/s/version/V/").unwrap();
+        let input: Vec<String> = ["version 1", "version 2", "x"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let expected = vec!["V 1", "version 2", "x"];
+
+        let mut processor = FileProcessor::new(first_only.clone());
+        assert_eq!(
+            processor.apply_cycle_based(input.clone()).unwrap(),
+            expected
+        );
+
+        // Batch engine (execute mode)
+        let mut processor = FileProcessor::new(first_only.clone());
+        let mut lines = input.clone();
+        processor.apply_command(&mut lines, &first_only[0]).unwrap();
+        assert_eq!(lines, expected);
+
+        let mut processor = FileProcessor::new(parser.parse("1,/version/s/version/V/").unwrap());
+        let mut lines = input;
+        let cmd = processor.commands[0].clone();
+        processor.apply_command(&mut lines, &cmd).unwrap();
+        assert_eq!(lines, vec!["V 1", "V 2", "x"]);
+
+        // Streaming engine
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "version 1\nversion 2\nx\n").unwrap();
+        let mut processor = StreamProcessor::new(first_only);
+        processor.process_streaming_forced(file.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "V 1\nversion 2\nx\n"
+        );
+    }
+
+    #[test]
+    fn test_first_line_ranges_sharing_an_end_apply_to_the_same_lines() {
+        // Both commands are in range on line 1, where the end matches
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let mut processor = FileProcessor::new(parser.parse("0,/x/s/a/A/;0,/x/s/c/C/").unwrap());
+        assert_eq!(
+            processor
+                .apply_cycle_based(vec!["a c x".to_string(), "a c".to_string()])
+                .unwrap(),
+            vec!["A C x", "a c"]
+        );
+    }
+
+    #[test]
+    fn test_range_ending_at_or_before_its_start_covers_one_line() {
+        // 1,0p and 3,1d are valid: an end already reached closes the range
+        // on its start line
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let input: Vec<String> = ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect();
+        for (script, expected) in [
+            ("1,0p", vec!["a", "a", "b", "c", "d"]),
+            ("2,0s/^/X/", vec!["a", "Xb", "c", "d"]),
+            ("3,1d", vec!["a", "b", "d"]),
+        ] {
+            let commands = parser.parse(script).unwrap();
+            let mut processor = FileProcessor::new(commands.clone());
+            assert_eq!(
+                processor.apply_cycle_based(input.clone()).unwrap(),
+                expected,
+                "{script}"
+            );
+
+            // Streaming engine
+            let mut output = Vec::new();
+            StreamProcessor::new(commands)
+                .process_pipe("a\nb\nc\nd\n".as_bytes(), &mut output)
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                format!("{}\n", expected.join("\n")),
+                "{script}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_zero_step_and_multiple_range_end() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let input: Vec<String> = (1..=10).map(|n| n.to_string()).collect();
+        let print = |script: &str| {
+            let mut processor = FileProcessor::new(parser.parse(script).unwrap());
+            processor.set_no_default_output(true);
+            processor.apply_cycle_based(input.clone()).unwrap()
+        };
+
+        assert_eq!(print("0~3p"), vec!["3", "6", "9"]);
+        assert_eq!(print("2,~4p"), vec!["2", "3", "4"]);
+        // Like GNU sed, the end is looked for after the start line
+        assert_eq!(print("4,~4p"), vec!["4", "5", "6", "7", "8"]);
+        assert_eq!(print("/7/,~4p"), vec!["7", "8"]);
+        assert_eq!(print("3,~0p"), vec!["3"]);
+
+        // Batch engine (execute mode)
+        for (script, expected) in [
+            ("2,~4d", vec!["1", "5", "6", "7", "8", "9", "10"]),
+            ("4,~4d", vec!["1", "2", "3", "9", "10"]),
+        ] {
+            let commands = parser.parse(script).unwrap();
+            let mut processor = FileProcessor::new(commands.clone());
+            let mut lines = input.clone();
+            processor.apply_command(&mut lines, &commands[0]).unwrap();
+            assert_eq!(lines, expected, "{}", script);
+        }
+    }
+
+    #[test]
+    fn test_last_line_address_matches_only_final_line() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let input = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+
+        let mut processor = FileProcessor::new(parser.parse("$d").unwrap());
+        assert_eq!(
+            processor.apply_cycle_based(input.clone()).unwrap(),
+            vec!["a", "b"]
+        );
+
+        let mut processor = FileProcessor::new(parser.parse("$!d").unwrap());
+        assert_eq!(
+            processor.apply_cycle_based(input.clone()).unwrap(),
+            vec!["c"]
+        );
+
+        let mut processor = FileProcessor::new(parser.parse("2,$d").unwrap());
+        assert_eq!(processor.apply_cycle_based(input).unwrap(), vec!["a"]);
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_execute_command_replaces_pattern_space() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let mut processor = FileProcessor::new(parser.parse("2e").unwrap());
+        processor.set_allow_exec(true);
+
+        let input = vec!["keep".to_string(), "echo hi".to_string()];
+        assert_eq!(
+            processor.apply_cycle_based(input).unwrap(),
+            vec!["keep", "hi"]
+        );
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_execute_command_output_precedes_pattern_space() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let mut processor = FileProcessor::new(parser.parse("1e echo hi").unwrap());
+        processor.set_allow_exec(true);
+
+        let input = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(
+            processor.apply_cycle_based(input).unwrap(),
+            vec!["hi", "a", "b"]
+        );
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_execute_command_batch_mode() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let mut processor = FileProcessor::new(Vec::new());
+        processor.set_allow_exec(true);
+
+        let mut lines = vec!["echo hi".to_string(), "b".to_string()];
+        let cmd = parser.parse("1e").unwrap().remove(0);
+        processor.apply_command(&mut lines, &cmd).unwrap();
+        assert_eq!(lines, vec!["hi", "b"]);
+
+        let cmd = parser.parse("/b/e printf 'x\\ny'").unwrap().remove(0);
+        processor.apply_command(&mut lines, &cmd).unwrap();
+        assert_eq!(lines, vec!["hi", "x", "y", "b"]);
+    }
+
+    #[cfg_attr(not(unix), ignore)]
+    #[test]
+    fn test_process_file_with_output_runs_commands_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let file_path = dir.path().join("input.txt");
+        fs::write(&file_path, "go\nstay\n").unwrap();
+
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let script = format!("s|go|echo ran >> {} \\&\\& echo done|e", marker.display());
+        let mut processor = FileProcessor::new(parser.parse(&script).unwrap());
+        processor.set_allow_exec(true);
+
+        let (diff, lines) = processor.process_file_with_output(&file_path).unwrap();
+        assert_eq!(lines, vec!["done", "stay"]);
+        assert_eq!(diff.changes.len(), 1);
+        // The preview ran the command; writing its lines doesn't again
+        write_output_lines(&file_path, &lines).unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "done\nstay\n");
+        assert_eq!(fs::read_to_string(&marker).unwrap(), "ran\n");
+    }
+
+    #[test]
+    fn test_execute_command_requires_allow_exec() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let mut processor = FileProcessor::new(parser.parse("e echo hi").unwrap());
+
+        let err = processor
+            .apply_cycle_based(vec!["a".to_string()])
+            .unwrap_err();
+        assert!(err.to_string().contains("--allow-exec"));
+    }
+
+    #[test]
+    fn test_substitution_spans_mark_the_replaced_bytes() {
+        let spans_for = |script: &str| {
+            let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), "hello world\nfoo\n").unwrap();
+            let diff = FileProcessor::new(commands)
+                .process_file_with_context(file.path())
+                .unwrap();
+            let change = &diff.changes[0];
+            (change.content.clone(), change.spans.clone())
+        };
+
+        let (content, spans) = spans_for("s/world/there/");
+        assert_eq!(spans, vec![(6, 5)]);
+        assert_eq!(&content[6..11], "there");
+
+        // Later substitutions move earlier spans, and one cutting into
+        // them grows to cover both
+        assert_eq!(
+            spans_for("s/world/there/; s/hello/hi/"),
+            ("hi there".to_string(), vec![(0, 2), (3, 5)])
+        );
+        assert_eq!(
+            spans_for("s/world/there/; s/hello/hi/; s/i t/I T/"),
+            ("hI There".to_string(), vec![(0, 8)])
+        );
+        assert_eq!(
+            spans_for("s/o/0/2g"),
+            ("hello w0rld".to_string(), vec![(7, 1)])
+        );
+
+        // Spans are dropped when the pattern space is replaced
+        assert_eq!(
+            spans_for("s/world/there/; h; x"),
+            ("hello there".to_string(), vec![])
+        );
+    }
 }
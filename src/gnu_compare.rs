@@ -0,0 +1,190 @@
+//! GNU sed comparison harness (`--compare-gnu`, feature `compare-gnu`)
+//!
+//! Runs the same script over the same input through SedX's cycle engine and
+//! the system `sed`, then reports the lines where the outputs diverge. This is
+//! a compatibility-testing aid for maintainers; it never modifies files.
+
+use crate::cli::RegexFlavor;
+use crate::file_processor::FileProcessor;
+use crate::parser::Parser;
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::process::{Command as ProcessCommand, Stdio};
+
+/// One output line where SedX and GNU sed disagree (None = line missing)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub line_number: usize,
+    pub sedx: Option<String>,
+    pub gnu: Option<String>,
+}
+
+/// Check whether the `sed` on PATH is GNU sed
+pub fn gnu_sed_available() -> bool {
+    ProcessCommand::new("sed")
+        .arg("--version")
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).contains("GNU sed"))
+        .unwrap_or(false)
+}
+
+/// Run the script through SedX's cycle engine, as stdin mode does
+pub fn run_sedx(
+    script: &str,
+    input: &str,
+    quiet: bool,
+    flavor: RegexFlavor,
+) -> Result<Vec<String>> {
+    let commands = Parser::new(flavor).parse(script)?;
+    let mut processor = FileProcessor::with_regex_flavor(commands, flavor);
+    processor.set_no_default_output(quiet);
+
+    let lines = input.lines().map(String::from).collect();
+    let output = processor.apply_cycle_based(lines)?;
+
+    // Pattern spaces joined by N/G hold embedded newlines; split them so
+    // both sides are compared as physical lines
+    Ok(output
+        .iter()
+        .flat_map(|line| line.split('\n').map(String::from))
+        .collect())
+}
+
+/// Run the script through the system `sed`, feeding `input` on stdin
+pub fn run_gnu_sed(
+    script: &str,
+    input: &str,
+    quiet: bool,
+    flavor: RegexFlavor,
+) -> Result<Vec<String>> {
+    let mut cmd = ProcessCommand::new("sed");
+    if quiet {
+        cmd.arg("-n");
+    }
+    // GNU sed has no PCRE mode; ERE is the closest match
+    if flavor != RegexFlavor::BRE {
+        cmd.arg("-E");
+    }
+    cmd.arg("-e")
+        .arg(script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = cmd.spawn().context("Failed to run GNU sed")?;
+    child
+        .stdin
+        .take()
+        .context("Failed to open GNU sed stdin")?
+        .write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "GNU sed failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+/// Run both implementations and return every diverging line
+pub fn compare(
+    script: &str,
+    input: &str,
+    quiet: bool,
+    flavor: RegexFlavor,
+) -> Result<Vec<Divergence>> {
+    let sedx = run_sedx(script, input, quiet, flavor)?;
+    let gnu = run_gnu_sed(script, input, quiet, flavor)?;
+
+    let len = sedx.len().max(gnu.len());
+    Ok((0..len)
+        .filter(|&i| sedx.get(i) != gnu.get(i))
+        .map(|i| Divergence {
+            line_number: i + 1,
+            sedx: sedx.get(i).cloned(),
+            gnu: gnu.get(i).cloned(),
+        })
+        .collect())
+}
+
+/// Format a divergence report for one input source
+pub fn format_report(source: &str, divergences: &[Divergence]) -> String {
+    if divergences.is_empty() {
+        return format!("{}: outputs match GNU sed\n", source);
+    }
+
+    let mut report = format!(
+        "{}: {} line(s) differ from GNU sed\n",
+        source,
+        divergences.len()
+    );
+    for d in divergences {
+        let show = |line: &Option<String>| match line {
+            Some(text) => format!("{:?}", text),
+            None => "<missing>".to_string(),
+        };
+        report.push_str(&format!("  L{}:\n", d.line_number));
+        report.push_str(&format!("    sedx: {}\n", show(&d.sedx)));
+        report.push_str(&format!("    gnu:  {}\n", show(&d.gnu)));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "alpha\nbanana\ncherry\n";
+
+    fn assert_matches_gnu(script: &str, quiet: bool) {
+        if !gnu_sed_available() {
+            eprintln!("GNU sed not found, skipping comparison for {:?}", script);
+            return;
+        }
+        let divergences = compare(script, INPUT, quiet, RegexFlavor::PCRE).unwrap();
+        assert!(
+            divergences.is_empty(),
+            "{}",
+            format_report(script, &divergences)
+        );
+    }
+
+    #[test]
+    fn test_compare_global_substitution() {
+        assert_matches_gnu("s/a/b/g", false);
+    }
+
+    #[test]
+    fn test_compare_tac() {
+        assert_matches_gnu("1!G;h;$!d", false);
+    }
+
+    #[test]
+    fn test_compare_quiet_print() {
+        assert_matches_gnu("2p", true);
+    }
+
+    #[test]
+    fn test_compare_delete_last_line() {
+        assert_matches_gnu("$d", false);
+    }
+
+    #[test]
+    fn test_compare_reports_divergence() {
+        let divergences = vec![Divergence {
+            line_number: 2,
+            sedx: Some("x".to_string()),
+            gnu: None,
+        }];
+        let report = format_report("stdin", &divergences);
+        assert!(report.contains("1 line(s) differ"));
+        assert!(report.contains("sedx: \"x\""));
+        assert!(report.contains("gnu:  <missing>"));
+    }
+}
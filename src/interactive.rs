@@ -0,0 +1,151 @@
+//! Interactive Confirmation (`--interactive`)
+//!
+//! Walks every hunk of every file's diff and asks whether to apply it,
+//! like `git add -p`: y applies the hunk, n skips it, a applies it and all
+//! remaining hunks, q skips it and all remaining hunks.
+
+use crate::file_processor::{ChangeType, FileDiff};
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "y - apply this change\n\
+                    n - skip this change\n\
+                    a - apply this change and all remaining changes\n\
+                    q - skip this change and all remaining changes";
+
+/// Ask about each hunk, reading answers from `input` and writing prompts to
+/// `output`. Returns the accepted hunk indices (see `FileDiff::hunks`) of
+/// each diff, in the same order as `diffs`. End of input counts as q.
+pub fn prompt_hunks<R: BufRead, W: Write>(
+    diffs: &[FileDiff],
+    input: &mut R,
+    output: &mut W,
+) -> Result<Vec<Vec<usize>>> {
+    let hunks: Vec<_> = diffs.iter().map(FileDiff::hunks).collect();
+    let total: usize = hunks.iter().map(Vec::len).sum();
+    let mut accepted: Vec<Vec<usize>> = vec![Vec::new(); diffs.len()];
+
+    let mut apply_rest = false;
+    let mut asked = 0;
+    'files: for (file_index, (diff, file_hunks)) in diffs.iter().zip(&hunks).enumerate() {
+        for (hunk_index, hunk) in file_hunks.iter().enumerate() {
+            if apply_rest {
+                accepted[file_index].push(hunk_index);
+                continue;
+            }
+
+            asked += 1;
+            writeln!(output, "\n{} (change {}/{})", diff.file_path, asked, total)?;
+            for (line_num, content, change_type) in &diff.all_lines[hunk.clone()] {
+                let indicator = match change_type {
+                    ChangeType::Unchanged => "=",
+                    ChangeType::Modified => "~",
+                    ChangeType::Added => "+",
+                    ChangeType::Deleted => "-",
+                };
+                writeln!(output, "L{}: {} {}", line_num, indicator, content)?;
+            }
+
+            loop {
+                write!(output, "Apply this change? [y,n,a,q,?] ")?;
+                output.flush()?;
+
+                let mut answer = String::new();
+                if input.read_line(&mut answer)? == 0 {
+                    writeln!(output)?;
+                    break 'files;
+                }
+                match answer.trim().to_lowercase().as_str() {
+                    "y" | "yes" => accepted[file_index].push(hunk_index),
+                    "n" | "no" => {}
+                    "a" | "all" => {
+                        accepted[file_index].push(hunk_index);
+                        apply_rest = true;
+                    }
+                    "q" | "quit" => break 'files,
+                    _ => {
+                        writeln!(output, "{}", HELP)?;
+                        continue;
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    Ok(accepted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(path: &str, original: &[&str], modified: &[&str]) -> FileDiff {
+        let modified: Vec<String> = modified.iter().map(|s| s.to_string()).collect();
+        FileDiff::between(path, original, &modified)
+    }
+
+    fn answer(diffs: &[FileDiff], answers: &str) -> Vec<Vec<usize>> {
+        let mut output = Vec::new();
+        prompt_hunks(diffs, &mut answers.as_bytes(), &mut output).unwrap()
+    }
+
+    #[test]
+    fn test_prompt_hunks_yes_and_no() {
+        let diffs = vec![diff("a.txt", &["x", "keep", "x"], &["y", "keep", "y"])];
+        assert_eq!(answer(&diffs, "n\ny\n"), vec![vec![1]]);
+    }
+
+    #[test]
+    fn test_prompt_hunks_all_and_quit() {
+        let diffs = vec![
+            diff("a.txt", &["x", "keep", "x"], &["y", "keep", "y"]),
+            diff("b.txt", &["x"], &["y"]),
+        ];
+        assert_eq!(answer(&diffs, "n\na\n"), vec![vec![1], vec![0]]);
+        assert_eq!(answer(&diffs, "y\nq\n"), vec![vec![0], vec![]]);
+    }
+
+    #[test]
+    fn test_prompt_hunks_reprompts_and_stops_at_end_of_input() {
+        let diffs = vec![diff("a.txt", &["x", "keep", "x"], &["y", "keep", "y"])];
+        let mut output = Vec::new();
+        let accepted = prompt_hunks(&diffs, &mut "what\ny\n".as_bytes(), &mut output).unwrap();
+
+        assert_eq!(accepted, vec![vec![0]]);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("a.txt (change 1/2)"), "{}", output);
+        assert!(output.contains("L1: ~ y"), "{}", output);
+        assert!(
+            output.contains("a - apply this change and all remaining"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_partial_application_writes_accepted_hunks_only() {
+        use crate::cli::RegexFlavor;
+        use crate::file_processor::FileProcessor;
+        use crate::parser::Parser;
+        use std::fs;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "foo 1\nkeep\nfoo 2\nkeep\nfoo 3\n").unwrap();
+        let commands = Parser::new(RegexFlavor::PCRE).parse("s/foo/bar/").unwrap();
+
+        let mut processor = FileProcessor::new(commands.clone());
+        let diffs = vec![processor.process_file_with_context(file.path()).unwrap()];
+        let accepted = answer(&diffs, "y\nn\ny\n");
+        assert_eq!(accepted, vec![vec![0, 2]]);
+
+        let mut processor = FileProcessor::new(commands);
+        processor
+            .apply_selected_changes(file.path(), &accepted[0])
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "bar 1\nkeep\nfoo 2\nkeep\nbar 3\n"
+        );
+    }
+}
@@ -13,13 +13,18 @@ pub mod diff_formatter;
 pub mod disk_space;
 pub mod ere_converter;
 pub mod file_processor;
+#[cfg(feature = "compare-gnu")]
+pub mod gnu_compare;
+pub mod interactive;
 pub mod parser;
+pub mod posix;
 pub mod regex_error;
+pub mod sandbox;
 pub mod sed_parser;
 
 // Re-export commonly used types for convenience
 pub use backup_manager::{BackupManager, BackupMetadata, FileBackup};
-pub use capability::can_stream;
+pub use capability::{StreamDecision, can_stream, stream_decision};
 pub use cli::RegexFlavor;
 pub use command::{Address, Command, SubstitutionFlags};
 pub use file_processor::{ChangeType, FileProcessor, LineChange, StreamProcessor};
@@ -8,38 +8,66 @@ mod diff_formatter;
 mod disk_space;
 mod ere_converter;
 mod file_processor;
+#[cfg(feature = "compare-gnu")]
+mod gnu_compare;
+mod interactive;
 mod logger;
 mod parser;
+mod posix;
 mod regex_error;
+mod sandbox;
 mod sed_parser;
 
 use anyhow::{Context, Result};
-use cli::{Args, RegexFlavor, parse_args};
+use capability::StreamDecision;
+use cli::{Args, ColorChoice, OutputFormat, RegexFlavor, parse_args, read_files0_from};
 use command::{Address, Command};
-use config::{config_file_path, ensure_complete_config, load_config};
+use config::{CompatMode, config_file_path, ensure_complete_config, load_config};
+use diff_formatter::RunSummary;
 use logger::init_debug_logging;
 use parser::Parser;
+use std::collections::HashMap;
 use std::fs;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
-use std::process::Command as ProcessCommand;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, ExitCode};
+use std::sync::Mutex;
 use std::time::Instant;
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+    match run() {
+        Ok(status) => ExitCode::from(status),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            // A run where only some files failed has its own exit code
+            ExitCode::from(
+                e.downcast_ref::<RunSummary>()
+                    .map_or(1, RunSummary::exit_code),
+            )
+        }
+    }
+}
+
+/// Run the command line, returning the exit status (non-zero only when a
+/// q or Q command asked for one)
+fn run() -> Result<u8> {
     let args = parse_args()?;
 
     // Initialize debug logging early (before any operations)
-    // We need to check the config, but only for the Execute command
-    let log_path = if matches!(args, Args::Execute { .. }) {
-        // Load config to check if debug is enabled
-        let config = load_config();
-        match config {
-            Ok(cfg) => {
-                let debug_enabled = cfg.processing.debug.unwrap_or(false);
-                init_debug_logging(debug_enabled)?
-            }
-            Err(_) => None, // If config fails, no logging
+    // We need to check the config, but only for the Execute command;
+    // --debug turns logging on whatever the config says
+    let log_path = if let Args::Execute { debug, .. } = args {
+        // Load config to check if debug is enabled (if it fails, no logging
+        // unless --debug asked for it)
+        let debug_enabled = load_config()
+            .ok()
+            .and_then(|cfg| cfg.processing.debug)
+            .unwrap_or(false);
+        let log_path = init_debug_logging(debug || debug_enabled)?;
+        if debug && let Some(ref path) = log_path {
+            eprintln!("Debug log: {}", path.display());
         }
+        log_path
     } else {
         None
     };
@@ -53,41 +81,162 @@ fn main() -> Result<()> {
         Args::Execute {
             expression,
             files,
+            files0_from,
             dry_run,
+            stat,
             interactive,
+            no_preview,
             context,
             streaming,
             regex_flavor,
             no_backup,
+            keep_going,
             backup_dir,
+            backup_compression,
+            out_dir,
+            follow_symlinks,
             quiet,
+            format,
+            color,
+            dump_cycle_trace,
+            allow_exec,
+            sandbox,
+            compat_mode,
+            ascii_case,
+            word_regexp,
+            separate,
+            concatenate,
+            slurp,
+            paragraph,
+            max_count,
+            only_matching,
+            timeout,
+            require_change,
+            progress,
+            stdin_filename,
+            jobs,
+            explain,
+            dump_ast,
+            debug,
+            #[cfg(feature = "compare-gnu")]
+            compare_gnu,
         } => {
+            if dump_ast {
+                dump_program(&expression, regex_flavor, word_regexp)?;
+                return Ok(0);
+            }
+
+            // --files0-from supplies the file list; an empty list means there
+            // is nothing to do, not stdin mode (stdin may hold the list itself)
+            let files = match files0_from {
+                Some(source) => {
+                    let files = read_files0_from(&source)?;
+                    if files.is_empty() {
+                        status_line(format, "No files to process.");
+                        return Ok(0);
+                    }
+                    files
+                }
+                None => files,
+            };
+
+            #[cfg(feature = "compare-gnu")]
+            if compare_gnu {
+                execute_compare_gnu(&expression, &files, regex_flavor, quiet)?;
+                return Ok(0);
+            }
+
+            if require_change && files.is_empty() {
+                anyhow::bail!(
+                    "--require-change only works on files: standard input isn't previewed, so there is no change to check"
+                );
+            }
+
+            if stdin_filename.is_some() && !files.is_empty() {
+                anyhow::bail!(
+                    "--stdin-filename names standard input, which isn't read when files are given"
+                );
+            }
+
             // Check if we're in stdin mode (no files specified)
-            if files.is_empty() {
-                execute_stdin(&expression, regex_flavor, quiet)?;
+            return if files.is_empty() {
+                execute_stdin(
+                    &expression,
+                    regex_flavor,
+                    quiet,
+                    dump_cycle_trace.as_deref(),
+                    allow_exec,
+                    sandbox,
+                    compat_mode,
+                    ascii_case,
+                    word_regexp,
+                    slurp,
+                    paragraph,
+                    max_count,
+                    only_matching.as_deref(),
+                    timeout,
+                    stdin_filename.as_deref(),
+                    explain,
+                    debug,
+                )
             } else {
                 execute_command(
                     &expression,
                     &files,
                     dry_run,
+                    stat,
                     interactive,
+                    no_preview,
                     context,
                     streaming,
                     regex_flavor,
                     no_backup,
+                    keep_going,
                     backup_dir,
+                    backup_compression,
+                    out_dir.as_deref(),
+                    follow_symlinks,
                     quiet,
-                )?;
-            }
+                    format,
+                    color,
+                    dump_cycle_trace.as_deref(),
+                    allow_exec,
+                    sandbox,
+                    compat_mode,
+                    ascii_case,
+                    word_regexp,
+                    separate,
+                    concatenate,
+                    slurp,
+                    paragraph,
+                    max_count,
+                    only_matching.as_deref(),
+                    timeout,
+                    require_change,
+                    progress,
+                    jobs,
+                    explain,
+                    debug,
+                )
+            };
+        }
+        Args::Rollback { id, file } => {
+            rollback(id, file)?;
         }
-        Args::Rollback { id } => {
-            rollback(id)?;
+        Args::History { json, file, grep } => {
+            show_history(json, file, grep)?;
         }
-        Args::History => {
-            show_history()?;
+        Args::Status { json } => {
+            show_status(json)?;
         }
-        Args::Status => {
-            show_status()?;
+        Args::Commands { json } => {
+            list_commands(json)?;
+        }
+        Args::Check {
+            expression,
+            regex_flavor,
+        } => {
+            check_script(&expression, regex_flavor)?;
         }
         Args::BackupList { verbose } => {
             backup_list(verbose)?;
@@ -95,9 +244,21 @@ fn main() -> Result<()> {
         Args::BackupShow { id } => {
             backup_show(&id)?;
         }
+        Args::BackupDiff { id } => {
+            backup_diff(&id)?;
+        }
+        Args::BackupVerify => {
+            return backup_verify();
+        }
         Args::BackupRestore { id } => {
             backup_restore(&id)?;
         }
+        Args::BackupExport { id, archive } => {
+            backup_export(&id, &archive)?;
+        }
+        Args::BackupImport { archive } => {
+            backup_import(&archive)?;
+        }
         Args::BackupRemove { id, force } => {
             backup_remove(&id, force)?;
         }
@@ -117,17 +278,44 @@ fn main() -> Result<()> {
                 config_edit()?;
             }
         }
+        Args::ConfigGet { key } => {
+            config_get(&key)?;
+        }
+        Args::ConfigSet { key, value } => {
+            config_set(&key, &value)?;
+        }
     }
 
-    Ok(())
+    Ok(0)
 }
 
 /// Process stdin and write to stdout (pipeline mode, like sed)
-fn execute_stdin(expression: &str, regex_flavor: RegexFlavor, quiet: bool) -> Result<()> {
+#[allow(clippy::too_many_arguments)]
+fn execute_stdin(
+    expression: &str,
+    regex_flavor: RegexFlavor,
+    quiet: bool,
+    dump_cycle_trace: Option<&str>,
+    allow_exec: bool,
+    sandbox: bool,
+    compat_mode: Option<CompatMode>,
+    ascii_case: bool,
+    word_regexp: bool,
+    slurp: bool,
+    paragraph: bool,
+    max_count: Option<usize>,
+    only_matching: Option<&str>,
+    timeout: Option<u64>,
+    stdin_filename: Option<&str>,
+    explain: bool,
+    debug: bool,
+) -> Result<u8> {
+    // Stdin mode tolerates a missing or broken config
+    let config = load_config().unwrap_or_default();
+    let compat_mode = compat_mode.unwrap_or_else(|| config.compatibility.compat_mode());
+
     // Check if debug logging is enabled
-    let debug_enabled = load_config()
-        .map(|c| c.processing.debug.unwrap_or(false))
-        .unwrap_or(false);
+    let debug_enabled = debug || config.processing.debug.unwrap_or(false);
 
     let start_time = Instant::now();
 
@@ -136,12 +324,13 @@ fn execute_stdin(expression: &str, regex_flavor: RegexFlavor, quiet: bool) -> Re
             expression = expression,
             regex_flavor = ?regex_flavor,
             mode = "stdin",
+            input = stdin_filename.unwrap_or("-"),
             "Stdin processing started"
         );
     }
 
     // Parse sed expression
-    let parser = Parser::new(regex_flavor);
+    let parser = Parser::new(regex_flavor).with_word_regexp(word_regexp);
     let commands = match parser.parse(expression) {
         Ok(cmds) => cmds,
         Err(e) => {
@@ -156,17 +345,109 @@ fn execute_stdin(expression: &str, regex_flavor: RegexFlavor, quiet: bool) -> Re
         }
     };
 
+    if sandbox {
+        sandbox::check_sandbox(&commands)?;
+    }
+    if compat_mode == CompatMode::Posix {
+        posix::check_posix(&commands)?;
+    }
+    check_exec_allowed(&commands, allow_exec)?;
+    let deadline = timeout.map(file_processor::Deadline::after);
+
+    // Streamable scripts run line by line, writing each line's output as
+    // it's produced, like sed in a pipeline; the rest read stdin whole
+    let stream_decision = if slurp {
+        StreamDecision::rejected("--slurp reads the input whole", None)
+    } else if paragraph {
+        StreamDecision::rejected("--paragraph groups lines into paragraphs", None)
+    } else if dump_cycle_trace.is_some() {
+        StreamDecision::rejected("--dump-cycle-trace traces the in-memory engine", None)
+    } else {
+        capability::stream_decision(&commands)
+    };
+    if explain {
+        eprintln!("Processing: {}", stream_decision);
+    }
+
+    if stream_decision.streamable {
+        let only_matching = only_matching
+            .map(|pattern| {
+                regex_error::compile_regex_with_context(
+                    &parser.convert_pattern(pattern),
+                    regex_flavor,
+                    false,
+                )
+            })
+            .transpose()?;
+        let mut processor =
+            file_processor::StreamProcessor::with_regex_flavor(commands, regex_flavor)
+                .with_compat_mode(compat_mode)
+                .with_ascii_case(ascii_case)
+                .with_no_default_output(quiet)
+                .with_max_count(max_count.map(file_processor::SubstitutionBudget::new))
+                .with_only_matching(only_matching)
+                .with_deadline(deadline)
+                .with_input_name(stdin_filename.map(str::to_string));
+        let exit_code = processor.process_pipe(io::stdin().lock(), io::stdout().lock())?;
+
+        if debug_enabled {
+            tracing::info!(
+                status = "success",
+                elapsed_ms = start_time.elapsed().as_millis(),
+                "Stdin processing completed (streaming)"
+            );
+        }
+        return Ok(exit_status(exit_code));
+    }
+    if only_matching.is_some() {
+        anyhow::bail!(
+            "--only-matching needs a script that can be streamed, but this one is processed {}",
+            stream_decision
+        );
+    }
+
     // Read all input from stdin
     let mut input = String::new();
-    io::stdin().read_to_string(&mut input)?;
+    io::stdin().read_to_string(&mut input).with_context(|| {
+        format!(
+            "Failed to read {}",
+            stdin_filename.unwrap_or("standard input")
+        )
+    })?;
 
     // Process the input using cycle-based or batch processing
     let lines: Vec<String> = input.lines().map(|s| s.to_string()).collect();
+    let lines = if slurp {
+        file_processor::slurp_lines(lines)
+    } else {
+        lines
+    };
+    let (lines, layout) = if paragraph {
+        let (records, layout) = file_processor::split_paragraphs(lines);
+        (records, Some(layout))
+    } else {
+        (lines, None)
+    };
     let mut processor =
         file_processor::FileProcessor::with_regex_flavor(commands.clone(), regex_flavor);
     processor.set_no_default_output(quiet); // Wire up -n flag
+    processor.set_allow_exec(allow_exec);
+    processor.set_compat_mode(compat_mode);
+    processor.set_ascii_case(ascii_case);
+    processor.set_max_count(max_count.map(file_processor::SubstitutionBudget::new));
+    processor.set_deadline(deadline);
+    if let Some(name) = stdin_filename {
+        processor.set_input_name(name);
+    }
+    if let Some(path) = dump_cycle_trace {
+        processor.set_cycle_trace(open_cycle_trace(path)?);
+    }
 
     let result_lines = processor.apply_cycle_based(lines)?;
+    let result_lines = match layout {
+        Some(layout) => file_processor::join_paragraphs(result_lines, &layout),
+        None => result_lines,
+    };
     let output_line_count = result_lines.len();
 
     // Write output to stdout
@@ -184,97 +465,137 @@ fn execute_stdin(expression: &str, regex_flavor: RegexFlavor, quiet: bool) -> Re
         );
     }
 
-    Ok(())
+    Ok(exit_status(processor.exit_code()))
 }
 
-/// Check if commands can be executed in streaming mode
-fn can_use_streaming(commands: &[Command]) -> bool {
-    use Command::*;
+/// Run the script through both SedX and GNU sed and report divergences.
+/// Reads stdin when no files are given; files are only read, never modified.
+#[cfg(feature = "compare-gnu")]
+fn execute_compare_gnu(
+    expression: &str,
+    files: &[String],
+    regex_flavor: RegexFlavor,
+    quiet: bool,
+) -> Result<()> {
+    if !gnu_compare::gnu_sed_available() {
+        anyhow::bail!("--compare-gnu requires GNU sed on PATH");
+    }
 
-    for cmd in commands {
-        match cmd {
-            // Chunk 10: Groups SHOULD use streaming mode to avoid in-memory bugs
-            // The in-memory group implementation has issues with nested command ranges
-            Group { .. } => {
-                // Force streaming mode for groups
-                // The streaming group handler is correct, in-memory has bugs
-                return true;
-            }
-            // Chunk 9: Hold space operations ARE streamable
-            Hold { .. } | HoldAppend { .. } | Get { .. } | GetAppend { .. } | Exchange { .. } => {
-                // These are now supported in streaming mode
-                // But need to check address types
-                if let Some(range) = get_command_range_option(cmd)
-                    && !is_range_supported_in_streaming(&range)
-                {
-                    return false;
-                }
-            }
-            _ => {
-                // s, d, p, a, i, c, q are supported
-                // But need to check address types
-                if let Some(range) = get_command_range_option(cmd)
-                    && !is_range_supported_in_streaming(&range)
-                {
-                    return false;
-                }
-            }
+    let mut inputs = Vec::new();
+    if files.is_empty() {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        inputs.push(("<stdin>".to_string(), input));
+    } else {
+        for file in files {
+            let content = fs::read_to_string(file)
+                .with_context(|| format!("Failed to read file: {}", file))?;
+            inputs.push((file.clone(), content));
         }
     }
 
-    true
-}
-
-/// Extract range from a command (if any)
-fn get_command_range_option(cmd: &Command) -> Option<(Address, Address)> {
-    match cmd {
-        Command::Substitution { range, .. } => range.as_ref().map(|r| (r.0.clone(), r.1.clone())),
-        Command::Delete { range } => Some(range.clone()),
-        Command::Print { range } => Some(range.clone()),
-        Command::Insert {
-            address: Address::LineNumber(_),
-            ..
-        } => Some((Address::LineNumber(0), Address::LineNumber(0))),
-        Command::Append {
-            address: Address::LineNumber(_),
-            ..
-        } => Some((Address::LineNumber(0), Address::LineNumber(0))),
-        Command::Change {
-            address: Address::LineNumber(_),
-            ..
-        } => Some((Address::LineNumber(0), Address::LineNumber(0))),
-        Command::Quit {
-            address: Some(Address::LineNumber(_)) | None,
-            ..
+    let mut diverged = 0;
+    for (source, input) in &inputs {
+        let divergences = gnu_compare::compare(expression, input, quiet, regex_flavor)?;
+        if !divergences.is_empty() {
+            diverged += 1;
         }
-        | Command::Quit {
-            address: Some(Address::LastLine),
-            ..
-        } => Some((Address::LineNumber(0), Address::LineNumber(0))),
-        _ => None,
+        print!("{}", gnu_compare::format_report(source, &divergences));
+    }
+
+    if diverged > 0 {
+        anyhow::bail!("Output differs from GNU sed for {} input(s)", diverged);
     }
+    Ok(())
 }
 
-/// Check if a range is supported in streaming mode
-fn is_range_supported_in_streaming(range: &(Address, Address)) -> bool {
-    use Address::*;
+/// Check whether any command (including inside groups) runs shell commands
+/// (the e command or the s///e flag)
+fn commands_use_exec(commands: &[Command]) -> bool {
+    commands.iter().any(|cmd| match cmd {
+        Command::Substitution { flags, .. } => flags.exec,
+        Command::Execute { .. } => true,
+        Command::Group { commands, .. } => commands_use_exec(commands),
+        _ => false,
+    })
+}
 
-    match (&range.0, &range.1) {
-        // Chunk 8: Supported ranges
-        (Pattern(_), Pattern(_)) => true,       // /start/,/end/
-        (LineNumber(1), LastLine) => true,      // 1,$
-        (LineNumber(_), LineNumber(_)) => true, // 5,10
-        (Pattern(_), LineNumber(_)) => true,    // /start/,10 (Chunk 8)
-        (LineNumber(_), Pattern(_)) => true,    // 5,/end/ (Chunk 8)
-        (Pattern(_), Relative { base: _, offset: _ }) => true, // /start/,+5 (Chunk 8)
+/// Check whether any command (including inside groups) writes to a file
+/// named in the script (w, W or the s///w flag)
+fn commands_write_files(commands: &[Command]) -> bool {
+    commands.iter().any(|cmd| match cmd {
+        Command::WriteFile { .. } | Command::WriteFirstLine { .. } => true,
+        Command::Substitution { flags, .. } => flags.write_file.is_some(),
+        Command::Group { commands, .. } => commands_write_files(commands),
+        _ => false,
+    })
+}
 
-        // Stepping addresses (Chunk 8)
-        (Step { .. }, _) | (_, Step { .. }) => true, // 1~2
+/// Check whether the script's result can depend on where a line sits in
+/// the input or on lines before it: line number, `$` and range addresses,
+/// and commands that carry state between cycles (n, N, D, q, Q, =, hold space)
+fn commands_depend_on_position(commands: &[Command]) -> bool {
+    fn single(address: &Address) -> bool {
+        match address {
+            Address::Pattern(_) => false,
+            Address::Negated(inner) => single(inner),
+            _ => true,
+        }
+    }
+    fn range((start, end): &(Address, Address)) -> bool {
+        match (start, end) {
+            // Every line: bare d and p
+            (Address::LineNumber(1), Address::LastLine) => false,
+            _ if start == end => single(start),
+            _ => true,
+        }
+    }
 
-        // Not supported (delegate to in-memory):
-        (Negated(_), _) | (_, Negated(_)) => false, // /pattern/!s/foo/bar/
-        _ => false,
+    commands.iter().any(|cmd| match cmd {
+        Command::Substitution { range: r, .. }
+        | Command::PrintFirstLine { range: r }
+        | Command::Branch { range: r, .. }
+        | Command::Test { range: r, .. }
+        | Command::TestFalse { range: r, .. }
+        | Command::List { range: r, .. }
+        | Command::Execute { range: r, .. } => r.as_ref().is_some_and(range),
+        Command::Delete { range: r }
+        | Command::Print { range: r }
+        | Command::Change { range: r, .. } => range(r),
+        Command::Group { commands, range: r } => {
+            r.as_ref().is_some_and(range) || commands_depend_on_position(commands)
+        }
+        Command::Insert { address, .. } | Command::Append { address, .. } => single(address),
+        Command::ReadFile { range: r, .. }
+        | Command::WriteFile { range: r, .. }
+        | Command::ReadLine { range: r, .. }
+        | Command::WriteFirstLine { range: r, .. }
+        | Command::PrintFilename { range: r }
+        | Command::ClearPatternSpace { range: r } => r.as_ref().is_some_and(single),
+        Command::Label { .. } => false,
+        Command::Quit { .. }
+        | Command::QuitWithoutPrint { .. }
+        | Command::Next { .. }
+        | Command::NextAppend { .. }
+        | Command::DeleteFirstLine { .. }
+        | Command::Hold { .. }
+        | Command::HoldAppend { .. }
+        | Command::Get { .. }
+        | Command::GetAppend { .. }
+        | Command::Exchange { .. }
+        | Command::PrintLineNumber { .. } => true,
+    })
+}
+
+/// Refuse to run scripts that execute shell commands unless --allow-exec was given
+fn check_exec_allowed(commands: &[Command], allow_exec: bool) -> Result<()> {
+    if !allow_exec && commands_use_exec(commands) {
+        anyhow::bail!(
+            "The e command and s///e flag execute shell commands and are disabled by default.\n\
+             Use --allow-exec to enable them (only with scripts you trust)."
+        );
     }
+    Ok(())
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -282,15 +603,46 @@ fn execute_command(
     expression: &str,
     files: &[String],
     dry_run: bool,
+    stat: bool,
     interactive: bool,
-    context: usize,
+    no_preview: bool,
+    context: Option<usize>,
     streaming: bool,
     regex_flavor: RegexFlavor,
     no_backup: bool,
+    keep_going: bool,
     backup_dir: Option<String>,
+    backup_compression: bool,
+    out_dir: Option<&str>,
+    follow_symlinks: bool,
     quiet: bool,
-) -> Result<()> {
+    format: OutputFormat,
+    color: ColorChoice,
+    dump_cycle_trace: Option<&str>,
+    allow_exec: bool,
+    sandbox: bool,
+    compat_mode: Option<CompatMode>,
+    ascii_case: bool,
+    word_regexp: bool,
+    separate: bool,
+    concatenate: bool,
+    slurp: bool,
+    paragraph: bool,
+    max_count: Option<usize>,
+    only_matching: Option<&str>,
+    timeout: Option<u64>,
+    require_change: bool,
+    progress: bool,
+    jobs: usize,
+    explain: bool,
+    debug: bool,
+) -> Result<u8> {
     let start_time = Instant::now();
+    // --timeout covers the whole run: the previews, which run the script on
+    // every file before any is written, and then applying it
+    let deadline = timeout.map(file_processor::Deadline::after);
+    // A progress bar redrawn in place only makes sense on a terminal
+    let progress = progress && io::stderr().is_terminal();
 
     // Load configuration file
     let config = load_config()?;
@@ -298,8 +650,13 @@ fn execute_command(
     // Use backup_dir from config if not specified via CLI
     let backup_dir = backup_dir.or_else(|| config.backup.backup_dir.clone());
 
+    // --context/--no-context override processing.context_lines
+    let context = config.processing.context_size(context);
+
     // Check if debug logging is enabled
-    let debug_enabled = config.processing.debug.unwrap_or(false);
+    let debug_enabled = debug || config.processing.debug.unwrap_or(false);
+
+    let compat_mode = compat_mode.unwrap_or_else(|| config.compatibility.compat_mode());
 
     // Log the start of operation
     if debug_enabled {
@@ -313,7 +670,7 @@ fn execute_command(
     }
 
     // Parse sed expression using unified parser
-    let parser = Parser::new(regex_flavor);
+    let parser = Parser::new(regex_flavor).with_word_regexp(word_regexp);
     let commands = match parser.parse(expression) {
         Ok(cmds) => cmds,
         Err(e) => {
@@ -335,12 +692,130 @@ fn execute_command(
         );
     }
 
+    if sandbox {
+        sandbox::check_sandbox(&commands)?;
+    }
+    if compat_mode == CompatMode::Posix {
+        posix::check_posix(&commands)?;
+    }
+    check_exec_allowed(&commands, allow_exec)?;
+    // The preview runs the script, so it would run e and s///e commands
+    // as well. Applying writes what the preview computed instead of
+    // running them a second time.
+    let uses_exec = commands_use_exec(&commands);
+    if uses_exec && (dry_run || interactive) {
+        let flag = if dry_run {
+            "--dry-run"
+        } else {
+            "--interactive"
+        };
+        anyhow::bail!(
+            "{flag} can't preview a script that executes shell commands (e or s///e): \
+             previewing it would run them.\n\
+             Run it without {flag} to apply it; a backup is taken first."
+        );
+    }
+
+    // An empty (whitespace/comment-only) script is a no-op: like GNU sed,
+    // copy the input to stdout rather than reporting "No changes"
+    if commands.is_empty() {
+        echo_files_unchanged(files, quiet, format)?;
+        return Ok(if require_change {
+            diff_formatter::EXIT_NO_CHANGE
+        } else {
+            0
+        });
+    }
+
+    // A file listed twice (or as ./file, or through a symlink) would be
+    // backed up and edited twice
+    let (files, duplicates) = file_processor::unique_files(files);
+    for file in &duplicates {
+        eprintln!(
+            "Warning: skipping {}, which names the same file as an earlier argument",
+            file
+        );
+    }
+    if debug_enabled && !duplicates.is_empty() {
+        tracing::warn!(duplicates = ?duplicates, "Duplicate files dropped");
+    }
+    let files = files.as_slice();
+
     // Check if commands can modify files
-    // Commands like 'p', 'n', 'q', 'Q', '=', 'l' only read/print, don't modify
-    let can_modify_files = commands_can_modify_files(&commands);
+    // Commands like 'p', 'n', 'q', 'Q', '=', 'l' only read/print, don't modify,
+    // except with -n, where every line that isn't printed is dropped
+    let can_modify_files = quiet || commands_can_modify_files(&commands);
+
+    // Files edited in place are processed one by one, as GNU sed -i implies
+    // -s: a single stream would move lines from one file into another.
+    // They form one input stream only with --concatenate, or when the
+    // script just prints and no file is written. Scripts that look at each
+    // line on its own give the same result either way, so they keep
+    // processing (and streaming) file by file. --slurp and --paragraph make
+    // records out of each file's own lines, so files are never joined.
+    let concatenated = (concatenate || (!separate && !can_modify_files))
+        && !slurp
+        && !paragraph
+        && files.len() > 1
+        && commands_depend_on_position(&commands);
+
+    // Check if commands support streaming mode. The cycle trace is produced
+    // by the in-memory cycle engine, so tracing disables streaming.
+    // Interactive mode picks changes hunk by hunk, which needs the full
+    // in-memory diff.
+    let stream_decision = if concatenated && concatenate {
+        StreamDecision::rejected("--concatenate joins the files into one input stream", None)
+    } else if concatenated {
+        StreamDecision::rejected(
+            "the files form one input stream; use -s to stream each",
+            None,
+        )
+    } else if slurp {
+        StreamDecision::rejected("--slurp reads each file whole", None)
+    } else if paragraph {
+        StreamDecision::rejected("--paragraph groups lines into paragraphs", None)
+    } else if interactive {
+        StreamDecision::rejected("--interactive needs the whole diff", None)
+    } else if dump_cycle_trace.is_some() {
+        StreamDecision::rejected("--dump-cycle-trace traces the in-memory engine", None)
+    } else {
+        capability::stream_decision(&commands)
+    };
+    if explain {
+        status_line(format, &format!("Processing: {}", stream_decision));
+    }
+    let supports_streaming = stream_decision.streamable;
+
+    // --only-matching filters lines in the streaming loop, so the script
+    // has to stream
+    let only_matching = match only_matching {
+        Some(_) if !supports_streaming => anyhow::bail!(
+            "--only-matching needs a script that can be streamed, but this one is processed {}",
+            stream_decision
+        ),
+        Some(pattern) => Some(regex_error::compile_regex_with_context(
+            &parser.convert_pattern(pattern),
+            regex_flavor,
+            false,
+        )?),
+        None => None,
+    };
+
+    let cycle_trace = dump_cycle_trace.map(open_cycle_trace).transpose()?;
 
-    // Check if commands support streaming mode
-    let supports_streaming = can_use_streaming(&commands);
+    // The cycle trace and w/W output files are shared by every input file,
+    // so their records are only in a deterministic order with one job.
+    // --max-count is spent file by file, in the order the files were given.
+    let jobs = if cycle_trace.is_some() || commands_write_files(&commands) || max_count.is_some() {
+        1
+    } else {
+        jobs
+    };
+
+    let budget = max_count.map(file_processor::SubstitutionBudget::new);
+    // What was left of the --max-count budget when each file's preview
+    // started, so applying the file spends it the same way
+    let file_budgets: Mutex<HashMap<PathBuf, usize>> = Mutex::new(HashMap::new());
 
     let file_paths: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
 
@@ -348,30 +823,22 @@ fn execute_command(
     // For each file, decide whether to use streaming or in-memory processing
     let mut diffs = Vec::new();
     let mut streaming_files: Vec<PathBuf> = Vec::new(); // Track which files should use streaming
-
-    for file_path in &file_paths {
+    let mut skipped_files: Vec<PathBuf> = Vec::new(); // Files the preview couldn't process
+
+    // Get streaming threshold from config (default: 100MB)
+    let streaming_threshold_mb = config.processing.max_memory_mb.unwrap_or(100);
+    let streaming_threshold_bytes = (streaming_threshold_mb * 1024 * 1024) as u64;
+    // Scripts that can't stream are held to the same amount of memory
+    let memory_limit = Some(streaming_threshold_bytes);
+
+    // Preview one file; returns its diff, whether it was streamed and, for
+    // scripts that run shell commands, its new lines
+    type Preview = (file_processor::FileDiff, bool, Option<Vec<String>>);
+    let preview_file = |file_path: &PathBuf| -> Result<Preview> {
         // Get file metadata to check size
-        let metadata = match fs::metadata(file_path) {
-            Ok(meta) => meta,
-            Err(e) => {
-                if debug_enabled {
-                    tracing::warn!(
-                        file = %file_path.display(),
-                        error = %e,
-                        "Failed to read file"
-                    );
-                }
-                eprintln!("Error reading file {}: {}", file_path.display(), e);
-                continue;
-            }
-        };
-
+        let metadata = fs::metadata(file_path)?;
         let file_size_mb = metadata.len() / 1024 / 1024;
 
-        // Get streaming threshold from config (default: 100MB)
-        let streaming_threshold_mb = config.processing.max_memory_mb.unwrap_or(100);
-        let streaming_threshold_bytes = (streaming_threshold_mb * 1024 * 1024) as u64;
-
         // Decide: use streaming if (streaming flag OR file >= threshold OR commands support it)
         let use_streaming = if !supports_streaming {
             false // Commands don't support streaming
@@ -392,29 +859,100 @@ fn execute_command(
             true
         };
 
-        // Track which files should use streaming
-        if use_streaming {
-            streaming_files.push(file_path.clone());
-        }
+        let file_budget = budget.as_ref().map(|budget| {
+            file_budgets
+                .lock()
+                .unwrap()
+                .insert(file_path.clone(), budget.remaining());
+            budget.clone()
+        });
 
         // Process file with appropriate processor (ALWAYS dry_run for preview)
-        let diff = if use_streaming {
+        let (diff, lines) = if use_streaming {
             // Use streaming processor with dry_run=true for preview
             let mut stream_processor =
                 file_processor::StreamProcessor::with_regex_flavor(commands.clone(), regex_flavor)
                     .with_context_size(context)
+                    .with_compat_mode(compat_mode)
+                    .with_ascii_case(ascii_case)
+                    .with_no_default_output(quiet)
+                    .with_max_count(file_budget)
+                    .with_only_matching(only_matching.clone())
+                    .with_deadline(deadline)
+                    .with_progress(progress_callback(progress, file_path))
                     .with_dry_run(true); // Always preview first
-            stream_processor.process_streaming_forced(file_path)
+            (stream_processor.process_streaming_forced(file_path)?, None)
         } else {
             // Use in-memory processor (preview is built-in)
             let mut processor =
                 file_processor::FileProcessor::with_regex_flavor(commands.clone(), regex_flavor);
             processor.set_no_default_output(quiet); // Wire up -n flag
-            processor.process_file_with_context(file_path)
+            processor.set_allow_exec(allow_exec);
+            processor.set_compat_mode(compat_mode);
+            processor.set_ascii_case(ascii_case);
+            processor.set_max_count(file_budget);
+            processor.set_memory_limit(memory_limit);
+            processor.set_context_size(context);
+            processor.set_deadline(deadline);
+            processor.set_slurp(slurp);
+            processor.set_paragraph(paragraph);
+            if let Some(trace) = &cycle_trace {
+                processor.set_cycle_trace(trace.try_clone()?);
+            }
+            if uses_exec {
+                let (diff, lines) = processor.process_file_with_output(file_path)?;
+                (diff, Some(lines))
+            } else {
+                (processor.process_file_with_context(file_path)?, None)
+            }
         };
+        Ok((diff, use_streaming, lines))
+    };
 
-        match diff {
-            Ok(diff) => diffs.push(diff),
+    // New contents computed by the preview, which applying writes as they
+    // are: concatenated input, and scripts that run shell commands
+    let mut computed_lines: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    let previews = if concatenated {
+        let mut processor =
+            file_processor::FileProcessor::with_regex_flavor(commands.clone(), regex_flavor);
+        processor.set_no_default_output(quiet); // Wire up -n flag
+        processor.set_allow_exec(allow_exec);
+        processor.set_compat_mode(compat_mode);
+        processor.set_ascii_case(ascii_case);
+        processor.set_max_count(budget.clone());
+        processor.set_memory_limit(memory_limit);
+        processor.set_deadline(deadline);
+        if let Some(trace) = &cycle_trace {
+            processor.set_cycle_trace(trace.try_clone()?);
+        }
+        let results = processor.process_files_concatenated(&file_paths)?;
+        file_paths
+            .iter()
+            .zip(results)
+            .map(|(file_path, result)| {
+                let (diff, lines) = result?;
+                computed_lines.insert(file_path.clone(), lines);
+                Ok((diff, false, None))
+            })
+            .collect()
+    } else {
+        file_processor::map_in_parallel(&file_paths, jobs, preview_file)
+    };
+    let file_budgets = file_budgets.into_inner().unwrap();
+    for (file_path, preview) in file_paths.iter().zip(previews) {
+        match preview {
+            Ok((diff, use_streaming, lines)) => {
+                // Track which files should use streaming
+                if use_streaming {
+                    streaming_files.push(file_path.clone());
+                }
+                if let Some(lines) = lines {
+                    computed_lines.insert(file_path.clone(), lines);
+                }
+                diffs.push(diff);
+            }
+            // Out of time: stop before writing anything
+            Err(e) if e.downcast_ref::<file_processor::TimedOut>().is_some() => return Err(e),
             Err(e) => {
                 if debug_enabled {
                     tracing::error!(
@@ -423,21 +961,58 @@ fn execute_command(
                         "Failed to process file"
                     );
                 }
-                eprintln!("Error processing {}: {}", file_path.display(), e);
+                eprintln!("Error processing {}: {} (skipped)", file_path.display(), e);
+                skipped_files.push(file_path.clone());
             }
         }
     }
 
     // Check if there are any changes or printed lines
+    // The first file a q or Q command stopped in decides the exit status
+    let quit_status = exit_status(diffs.iter().find_map(|d| d.exit_code));
+
     let total_changes: usize = diffs.iter().map(|d| d.changes.len()).sum();
     let has_printed_lines: bool = diffs.iter().any(|d| !d.printed_lines.is_empty());
 
-    if total_changes == 0 && !has_printed_lines {
+    // --require-change looks past the unchanged context lines streaming
+    // diffs list. A q can cut a file short without listing the lines it
+    // drops, so a run it stopped counts as a change.
+    let nothing_changed = !diffs
+        .iter()
+        .any(|d| d.has_changes() || d.exit_code.is_some());
+
+    if (total_changes == 0 && !has_printed_lines) || (require_change && nothing_changed) {
         if debug_enabled {
             tracing::info!("No changes would be made");
         }
-        println!("No changes would be made.");
-        return Ok(());
+        status_line(format, "No changes would be made.");
+        // --out-dir still mirrors every input, changed or not
+        if let Some(dir) = out_dir
+            && !dry_run
+        {
+            for file_path in &file_paths {
+                file_processor::copy_to_out_dir(Path::new(dir), file_path)?;
+            }
+            status_line(
+                format,
+                &format!("Copied {} file(s) to {}", file_paths.len(), dir),
+            );
+        }
+        let status = finish_run(
+            format,
+            RunSummary {
+                processed: diffs.len(),
+                changed: 0,
+                failed: skipped_files.len(),
+                quit_status,
+            },
+        )?;
+        // A pattern that should have matched (a typo, say) fails the run
+        return Ok(if require_change {
+            diff_formatter::EXIT_NO_CHANGE
+        } else {
+            status
+        });
     }
 
     if debug_enabled {
@@ -448,33 +1023,85 @@ fn execute_command(
         );
     }
 
-    // Show preview (always show in dry-run or interactive mode)
-    if dry_run || interactive {
-        let header = diff_formatter::DiffFormatter::format_dry_run_header(expression);
-        println!("{}", header);
+    // Show preview (interactive mode shows each hunk as it asks)
+    if dry_run {
+        if !format.is_machine_readable() {
+            let header = diff_formatter::DiffFormatter::format_dry_run_header(expression, color);
+            println!("{}", header);
+        }
 
-        for diff in &diffs {
-            let output =
-                diff_formatter::DiffFormatter::format_diff_with_context(diff, context, expression);
-            print!("{}", output);
+        if stat {
+            print!("{}", diff_formatter::DiffFormatter::format_stat(&diffs));
+        } else if !no_preview {
+            for diff in &diffs {
+                print!("{}", render_diff(diff, format, color, context, expression));
+            }
         }
     }
 
-    // Interactive mode: ask for confirmation
+    // Only files the preview shows changes for need backing up
+    let changed_paths: Vec<PathBuf> = file_paths
+        .iter()
+        .filter(|path| {
+            let name = path.display().to_string();
+            diffs.iter().any(|d| d.file_path == name && d.has_changes())
+        })
+        .cloned()
+        .collect();
+
+    // Pre-apply summary; in interactive mode this is the prompt preamble
+    if let Some(dir) = out_dir
+        && !dry_run
+    {
+        status_line(
+            format,
+            &format!("Will write {} file(s) to {}", file_paths.len(), dir),
+        );
+    } else if !dry_run {
+        let will_backup = !no_backup && can_modify_files;
+        let summary = diff_formatter::ApplySummary::from_diffs(
+            &diffs,
+            will_backup.then_some(changed_paths.as_slice()),
+        );
+        let backup_location = backup_dir.as_deref().unwrap_or("~/.sedx/backups");
+        status_line(format, &summary.format(backup_location));
+    }
+
+    // Interactive mode: ask about each hunk. Files where only some hunks
+    // were accepted are applied partially; files with none are left alone.
+    let mut partial_hunks: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut declined_files: Vec<String> = Vec::new();
     if interactive && !dry_run {
-        print!("Apply changes? [y/N] ");
-        io::stdout().flush()?;
+        let stdin = io::stdin();
+        let answers = if format.is_machine_readable() {
+            interactive::prompt_hunks(&diffs, &mut stdin.lock(), &mut io::stderr())?
+        } else {
+            interactive::prompt_hunks(&diffs, &mut stdin.lock(), &mut io::stdout())?
+        };
 
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
+        for (diff, accepted) in diffs.iter().zip(answers) {
+            let hunk_count = diff.hunks().len();
+            if accepted.is_empty() {
+                declined_files.push(diff.file_path.clone());
+            } else if accepted.len() < hunk_count {
+                partial_hunks.insert(diff.file_path.clone(), accepted);
+            }
+        }
 
-        let input = input.trim().to_lowercase();
-        if input != "y" && input != "yes" {
+        if declined_files.len() == diffs.len() {
             if debug_enabled {
                 tracing::info!("User declined changes in interactive mode");
             }
-            println!("Changes not applied.");
-            return Ok(());
+            status_line(format, "Changes not applied.");
+            return finish_run(
+                format,
+                RunSummary {
+                    processed: diffs.len(),
+                    changed: 0,
+                    failed: skipped_files.len(),
+                    quit_status,
+                },
+            );
         }
     }
 
@@ -483,23 +1110,38 @@ fn execute_command(
         if debug_enabled {
             tracing::info!("Dry run completed, no changes applied");
         }
-        return Ok(());
+        return finish_run(
+            format,
+            RunSummary {
+                processed: diffs.len(),
+                changed: diffs.iter().filter(|d| d.has_changes()).count(),
+                failed: skipped_files.len(),
+                quit_status,
+            },
+        );
     }
 
     // Execute mode: apply with backup (unless --no-backup --force)
-    let backup_id = if no_backup {
+    let mut backup_failures: Vec<PathBuf> = Vec::new(); // --keep-going: files left unbacked
+    let backup_id = if let Some(dir) = out_dir {
+        // Sources are left untouched, so there is nothing to back up
+        if debug_enabled {
+            tracing::info!(out_dir = dir, "No backup created (writing to --out-dir)");
+        }
+        None
+    } else if no_backup {
         // Skip backup creation
         if debug_enabled {
             tracing::warn!("Backup skipped (--no-backup flag)");
         }
-        println!("⚠️  Skipping backup (changes cannot be undone)");
+        status_line(format, "⚠️  Skipping backup (changes cannot be undone)");
         None
     } else if !can_modify_files {
         // Skip backup if commands don't modify files (optimization)
         if debug_enabled {
             tracing::info!("No backup created (read-only command)");
         }
-        println!("ℹ️  No backup needed (read-only command)");
+        status_line(format, "ℹ️  No backup needed (read-only command)");
         None
     } else {
         // Create backup with custom or default directory
@@ -507,15 +1149,55 @@ fn execute_command(
             backup_manager::BackupManager::with_directory(dir)?
         } else {
             backup_manager::BackupManager::new()?
-        };
+        }
+        .with_limits(&config.backup)
+        .with_compression(
+            if backup_compression || config.backup.compression.as_deref() == Some("gzip") {
+                backup_manager::Compression::Gzip
+            } else {
+                backup_manager::Compression::None
+            },
+        );
 
-        // Create backup BEFORE applying changes
-        match backup_manager.create_backup(expression, &file_paths) {
+        // Files declined in interactive mode are left alone
+        let changed_files: Vec<PathBuf> = changed_paths
+            .iter()
+            .filter(|path| !declined_files.contains(&path.display().to_string()))
+            .cloned()
+            .collect();
+
+        // Create backup BEFORE applying changes. With --keep-going a file
+        // that can't be backed up is skipped rather than failing the run.
+        let backup = if keep_going {
+            backup_manager
+                .create_backup_keep_going(expression, &changed_files)
+                .map(|(id, failures)| {
+                    for (file_path, e) in failures {
+                        if debug_enabled {
+                            tracing::error!(
+                                file = %file_path.display(),
+                                error = %e,
+                                "Failed to back up file"
+                            );
+                        }
+                        eprintln!(
+                            "Warning: skipping {}, which could not be backed up: {:#}",
+                            file_path.display(),
+                            e
+                        );
+                        backup_failures.push(file_path);
+                    }
+                    id
+                })
+        } else {
+            backup_manager.create_backup(expression, &changed_files)
+        };
+        match backup {
             Ok(id) => {
                 if debug_enabled {
                     tracing::info!(backup_id = %id, "Backup created");
                 }
-                println!("✅ Backup created: {}", id);
+                status_line(format, &format!("✅ Backup created: {}", id));
                 Some(id)
             }
             Err(e) => {
@@ -531,88 +1213,151 @@ fn execute_command(
     };
 
     // Apply changes
-    let mut apply_errors = Vec::new();
-    for file_path in &file_paths {
-        if streaming_files.contains(file_path) {
+    let apply_file = |file_path: &PathBuf| -> Result<()> {
+        let file_budget = file_budgets
+            .get(file_path)
+            .map(|remaining| file_processor::SubstitutionBudget::new(*remaining));
+
+        // With --out-dir, edit a fresh copy instead of the source
+        let target = match out_dir {
+            Some(dir) => file_processor::copy_to_out_dir(Path::new(dir), file_path)?,
+            None => file_processor::write_target(file_path, follow_symlinks)?,
+        };
+
+        let name = file_path.display().to_string();
+        let mode = if let Some(accepted) = partial_hunks.get(&name) {
+            // Interactive mode: only the hunks the user accepted
+            if computed_lines.contains_key(file_path) {
+                let diff = diffs
+                    .iter()
+                    .find(|d| d.file_path == name)
+                    .context("No preview for file")?;
+                file_processor::write_selected_hunks(&target, diff, accepted)?;
+            } else {
+                let mut processor = file_processor::FileProcessor::with_regex_flavor(
+                    commands.clone(),
+                    regex_flavor,
+                );
+                processor.set_no_default_output(quiet); // Wire up -n flag
+                processor.set_allow_exec(allow_exec);
+                processor.set_compat_mode(compat_mode);
+                processor.set_ascii_case(ascii_case);
+                processor.set_max_count(file_budget);
+                processor.set_memory_limit(memory_limit);
+                processor.set_deadline(deadline);
+                processor.set_slurp(slurp);
+                processor.set_paragraph(paragraph);
+                processor.apply_selected_changes(&target, accepted)?;
+            }
+            "partial"
+        } else if let Some(lines) = computed_lines.get(file_path) {
+            // Contents were already computed in preview
+            file_processor::write_output_lines(&target, lines)?;
+            "precomputed"
+        } else if streaming_files.contains(file_path) {
             // Streaming files: Re-process with dry_run=false to apply changes
             let mut stream_processor =
                 file_processor::StreamProcessor::with_regex_flavor(commands.clone(), regex_flavor)
                     .with_context_size(context)
+                    .with_compat_mode(compat_mode)
+                    .with_ascii_case(ascii_case)
+                    .with_no_default_output(quiet)
+                    .with_max_count(file_budget)
+                    .with_only_matching(only_matching.clone())
+                    .with_deadline(deadline)
+                    .with_progress(progress_callback(progress, file_path))
                     .with_dry_run(false); // Apply changes now
-            match stream_processor.process_streaming_forced(file_path) {
-                Ok(_) => {
-                    if debug_enabled {
-                        tracing::debug!(
-                            file = %file_path.display(),
-                            mode = "streaming",
-                            "Changes applied successfully"
-                        );
-                    }
-                }
-                Err(e) => {
-                    if debug_enabled {
-                        tracing::error!(
-                            file = %file_path.display(),
-                            error = %e,
-                            "Failed to apply changes"
-                        );
-                    }
-                    eprintln!("Error applying to {}: {}", file_path.display(), e);
-                    apply_errors.push((file_path.clone(), e));
-                }
-            }
+            stream_processor.process_streaming_forced(&target)?;
+            "streaming"
         } else {
             // In-memory files: Apply using apply_to_file()
             let mut processor =
                 file_processor::FileProcessor::with_regex_flavor(commands.clone(), regex_flavor);
             processor.set_no_default_output(quiet); // Wire up -n flag
-            match processor.apply_to_file(file_path) {
-                Ok(_) => {
-                    if debug_enabled {
-                        tracing::debug!(
-                            file = %file_path.display(),
-                            mode = "in-memory",
-                            "Changes applied successfully"
-                        );
-                    }
-                }
-                Err(e) => {
-                    if debug_enabled {
-                        tracing::error!(
-                            file = %file_path.display(),
-                            error = %e,
-                            "Failed to apply changes"
-                        );
-                    }
-                    eprintln!("Error applying to {}: {}", file_path.display(), e);
-                    apply_errors.push((file_path.clone(), e));
-                }
+            processor.set_allow_exec(allow_exec);
+            processor.set_compat_mode(compat_mode);
+            processor.set_ascii_case(ascii_case);
+            processor.set_max_count(file_budget);
+            processor.set_memory_limit(memory_limit);
+            processor.set_deadline(deadline);
+            processor.set_slurp(slurp);
+            processor.set_paragraph(paragraph);
+            processor.apply_to_file(&target)?;
+            "in-memory"
+        };
+
+        if debug_enabled {
+            tracing::debug!(
+                file = %file_path.display(),
+                mode = mode,
+                "Changes applied successfully"
+            );
+        }
+        Ok(())
+    };
+
+    let to_apply: Vec<PathBuf> = file_paths
+        .iter()
+        .filter(|f| !skipped_files.contains(f) && !backup_failures.contains(f))
+        .filter(|f| !declined_files.contains(&f.display().to_string()))
+        .cloned()
+        .collect();
+    let results = file_processor::map_in_parallel(&to_apply, jobs, apply_file);
+
+    let mut apply_errors = Vec::new();
+    for (file_path, result) in to_apply.into_iter().zip(results) {
+        if let Err(e) = result {
+            if debug_enabled {
+                tracing::error!(
+                    file = %file_path.display(),
+                    error = %e,
+                    "Failed to apply changes"
+                );
             }
+            eprintln!("Error applying to {}: {}", file_path.display(), e);
+            apply_errors.push((file_path, e));
         }
     }
 
     // Show result
-    if !interactive {
+    if !interactive && !no_preview {
         // Show what was applied
         for diff in &diffs {
-            let output =
-                diff_formatter::DiffFormatter::format_diff_with_context(diff, context, expression);
-            print!("{}", output);
+            print!("{}", render_diff(diff, format, color, context, expression));
         }
     }
 
     // Show rollback info only if backup was created
-    if let Some(id) = backup_id {
-        println!("\nBackup ID: {}", id);
-        println!("Rollback with: sedx rollback {}", id);
+    if let Some(dir) = out_dir {
+        status_line(
+            format,
+            &format!(
+                "\nWrote {} file(s) to {}",
+                file_paths.len() - skipped_files.len() - apply_errors.len(),
+                dir
+            ),
+        );
+    } else if let Some(id) = backup_id {
+        status_line(format, &format!("\nBackup ID: {}", id));
+        status_line(format, &format!("Rollback with: sedx rollback {}", id));
     } else {
-        println!("\nNo backup created - changes cannot be undone");
+        status_line(format, "\nNo backup created - changes cannot be undone");
+    }
+
+    if !skipped_files.is_empty() {
+        status_line(
+            format,
+            &format!(
+                "Skipped {} file(s) that could not be processed",
+                skipped_files.len()
+            ),
+        );
     }
 
     // Log completion
     let elapsed = start_time.elapsed();
     if debug_enabled {
-        let status = if apply_errors.is_empty() {
+        let status = if apply_errors.is_empty() && backup_failures.is_empty() {
             "success"
         } else {
             "partial_failure"
@@ -620,19 +1365,102 @@ fn execute_command(
         tracing::info!(
             status = status,
             elapsed_ms = elapsed.as_millis(),
-            files_processed = file_paths.len() - apply_errors.len(),
-            errors = apply_errors.len(),
+            files_processed =
+                file_paths.len() - skipped_files.len() - backup_failures.len() - apply_errors.len(),
+            errors = apply_errors.len() + backup_failures.len(),
             "Operation completed"
         );
     }
 
-    if !apply_errors.is_empty() {
-        Err(anyhow::anyhow!(
-            "Failed to apply changes to {} file(s)",
-            apply_errors.len()
-        ))
+    let failed_files: Vec<&PathBuf> = skipped_files
+        .iter()
+        .chain(&backup_failures)
+        .chain(apply_errors.iter().map(|(path, _)| path))
+        .collect();
+    let changed = diffs
+        .iter()
+        .filter(|d| d.has_changes() && !declined_files.contains(&d.file_path))
+        .filter(|d| {
+            !failed_files
+                .iter()
+                .any(|f| f.display().to_string() == d.file_path)
+        })
+        .count();
+    finish_run(
+        format,
+        RunSummary {
+            processed: file_paths.len() - failed_files.len(),
+            changed,
+            failed: failed_files.len(),
+            quit_status,
+        },
+    )
+}
+
+/// Exit status for the exit code a q or Q command asked for, reduced
+/// modulo 256 the way the operating system would
+fn exit_status(exit_code: Option<i32>) -> u8 {
+    exit_code.map_or(0, |code| code as u8)
+}
+
+/// End a run over files, returning its exit status: when some failed,
+/// report the totals and fail with the summary so `main` can pick the
+/// exit code
+fn finish_run(format: OutputFormat, summary: RunSummary) -> Result<u8> {
+    if summary.failed == 0 {
+        return Ok(summary.exit_code());
+    }
+    status_line(format, &summary.format());
+    Err(summary.into())
+}
+
+/// Copy files to stdout untouched (empty script). Machine-readable formats
+/// keep stdout for diffs, so they only get a status message.
+fn echo_files_unchanged(files: &[String], quiet: bool, format: OutputFormat) -> Result<()> {
+    if format.is_machine_readable() {
+        status_line(format, "Empty script: files left unchanged.");
+        return Ok(());
+    }
+    if quiet {
+        return Ok(());
+    }
+    for file in files {
+        let content =
+            fs::read_to_string(file).with_context(|| format!("Failed to read file: {}", file))?;
+        print!("{}", content);
+    }
+    Ok(())
+}
+
+/// Create (or truncate) the --dump-cycle-trace output file
+fn open_cycle_trace(path: &str) -> Result<fs::File> {
+    fs::File::create(path).with_context(|| format!("Failed to create cycle trace file: {}", path))
+}
+
+/// Render one file's diff in the requested output format
+fn render_diff(
+    diff: &file_processor::FileDiff,
+    format: OutputFormat,
+    color: ColorChoice,
+    context: usize,
+    expression: &str,
+) -> String {
+    match format {
+        OutputFormat::Human => diff_formatter::DiffFormatter::format_diff_with_context(
+            diff, context, expression, color,
+        ),
+        OutputFormat::Json => diff_formatter::DiffFormatter::format_json(diff) + "\n",
+        OutputFormat::Unified => diff_formatter::DiffFormatter::format_unified(diff, context),
+    }
+}
+
+/// Print a status message; machine-readable formats send it to stderr so
+/// stdout carries only the diff
+fn status_line(format: OutputFormat, message: &str) {
+    if format.is_machine_readable() {
+        eprintln!("{}", message);
     } else {
-        Ok(())
+        println!("{}", message);
     }
 }
 
@@ -650,7 +1478,7 @@ fn commands_can_modify_files(commands: &[crate::command::Command]) -> bool {
             // Phase 5: Flow control commands don't modify files
             | Command::Label { .. } | Command::Branch { .. } | Command::Test { .. } | Command::TestFalse { .. }
             // Phase 5: Print commands don't modify files (they write to stdout)
-            | Command::PrintLineNumber { .. } | Command::PrintFilename { .. }
+            | Command::PrintLineNumber { .. } | Command::PrintFilename { .. } | Command::List { .. }
             => continue,  // Skip read-only commands, keep checking
 
             // Commands that MIGHT modify files
@@ -660,7 +1488,7 @@ fn commands_can_modify_files(commands: &[crate::command::Command]) -> bool {
             | Command::GetAppend { .. } | Command::Exchange { .. }
             | Command::Group { .. } | Command::DeleteFirstLine { .. }
             | Command::ReadFile { .. } | Command::WriteFile { .. } | Command::ReadLine { .. } | Command::WriteFirstLine { .. }
-            | Command::ClearPatternSpace { .. }
+            | Command::ClearPatternSpace { .. } | Command::Execute { .. }
             => return true,  // Found a modifying command
         }
     }
@@ -669,7 +1497,7 @@ fn commands_can_modify_files(commands: &[crate::command::Command]) -> bool {
     false
 }
 
-fn rollback(id: Option<String>) -> Result<()> {
+fn rollback(id: Option<String>, file: Option<PathBuf>) -> Result<()> {
     let backup_manager = backup_manager::BackupManager::new()?;
 
     let backup_id = match id {
@@ -685,30 +1513,141 @@ fn rollback(id: Option<String>) -> Result<()> {
         },
     };
 
+    if let Some(file) = file {
+        backup_manager.restore_file(&backup_id, &file)?;
+        println!(
+            "\n✅ Rollback complete (backup {} kept for its other files)",
+            backup_id
+        );
+        return Ok(());
+    }
+
     backup_manager.restore_backup(&backup_id)?;
     println!("\n✅ Rollback complete");
 
     Ok(())
 }
 
-fn show_history() -> Result<()> {
+fn show_history(json: bool, file: Option<PathBuf>, grep: Option<String>) -> Result<()> {
     let backup_manager = backup_manager::BackupManager::new()?;
-    let backups = backup_manager.list_backups()?;
+    let filter = backup_manager::BackupFilter {
+        file,
+        expression: grep,
+    };
+    let backups = backup_manager.list_backups_matching(&filter)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&backups)?);
+        return Ok(());
+    }
 
-    let output = diff_formatter::DiffFormatter::format_history(backups);
+    let output = diff_formatter::DiffFormatter::format_history(backups, ColorChoice::Auto);
     println!("{}", output);
 
     Ok(())
 }
 
-fn show_status() -> Result<()> {
-    let backup_manager = backup_manager::BackupManager::new()?;
-    let backups = backup_manager.list_backups()?;
+/// `--progress` bar for streaming `file`, drawn on stderr
+fn progress_callback(show: bool, file: &Path) -> Option<file_processor::ProgressCallback> {
+    let name = file.display().to_string();
+    show.then(|| -> file_processor::ProgressCallback {
+        std::sync::Arc::new(move |progress| {
+            eprint!(
+                "{}",
+                diff_formatter::DiffFormatter::format_progress(&name, progress)
+            );
+        })
+    })
+}
+
+/// `sedx check`: print every problem in the script, one per line
+fn check_script(expression: &str, regex_flavor: RegexFlavor) -> Result<()> {
+    let Err(diagnostics) = Parser::new(regex_flavor).validate(expression) else {
+        println!("No problems found.");
+        return Ok(());
+    };
+
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    anyhow::bail!("{} problem(s) found in the script", diagnostics.len())
+}
+
+/// `--dump-ast`: print the program as parsed, without processing anything
+fn dump_program(expression: &str, regex_flavor: RegexFlavor, word_regexp: bool) -> Result<()> {
+    let commands = Parser::new(regex_flavor)
+        .with_word_regexp(word_regexp)
+        .parse(expression)
+        .context("Failed to parse expression")?;
+    print!("{}", command::format_program(&commands));
+    Ok(())
+}
+
+/// `sedx commands`: every command, its syntax, and the engines that run it
+fn list_commands(json: bool) -> Result<()> {
+    let support = capability::command_support();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&support)?);
+        return Ok(());
+    }
+
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    let syntax_width = support
+        .iter()
+        .map(|row| row.info.syntax.len())
+        .max()
+        .unwrap_or(0);
+    println!(
+        "CMD  {:<syntax_width$}  STREAMING  IN MEMORY  DESCRIPTION",
+        "SYNTAX"
+    );
+    for row in &support {
+        println!(
+            "{:<3}  {:<syntax_width$}  {:<9}  {:<9}  {}",
+            row.info.command,
+            row.info.syntax,
+            yes_no(row.streaming),
+            yes_no(row.in_memory),
+            row.info.description
+        );
+    }
+
+    println!("\nNot streamable (scripts using these run in memory):");
+    for row in &support {
+        if let Some(note) = &row.streaming_note {
+            println!("  {:<3}  {}", row.info.command, note);
+        }
+    }
+
+    Ok(())
+}
+
+fn show_status(json: bool) -> Result<()> {
+    // Report on the directory backups are written to, as configured
+    let config = load_config()?;
+    let backup_manager = match config.backup.backup_dir {
+        Some(dir) => backup_manager::BackupManager::with_directory(dir)?,
+        None => backup_manager::BackupManager::new()?,
+    };
+    let status = backup_manager.status()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
 
     println!("Current backup status:\n");
-    println!("Total backups: {}\n", backups.len());
+    println!("Backup directory: {}", status.backup_dir.display());
+    println!("Total backups: {}", status.total_backups);
+    println!("Disk usage: {}", status.total_size);
+    if let (Some(oldest), Some(newest)) = (status.oldest, status.newest) {
+        println!("Oldest: {}", oldest.format("%Y-%m-%d %H:%M:%S"));
+        println!("Newest: {}", newest.format("%Y-%m-%d %H:%M:%S"));
+    }
+    println!();
 
-    if let Some(last) = backups.last() {
+    if let Some(last) = status.last_operation {
         println!("Last operation:");
         println!("  ID: {}", last.id);
         println!("  Time: {}", last.timestamp.format("%Y-%m-%d %H:%M:%S"));
@@ -777,13 +1716,136 @@ fn backup_show(id: &str) -> Result<()> {
             .unwrap_or(0);
         println!("  {}", file_backup.original_path.display());
         println!("    Backup: {}", file_backup.backup_path.display());
+        if file_backup.compression == backup_manager::Compression::Gzip {
+            let original_size = file_backup
+                .read_contents()
+                .map(|contents| contents.len() as u64)
+                .unwrap_or(0);
+            println!(
+                "    Size: {} (gzip, {} uncompressed)",
+                disk_space::DiskSpaceInfo::bytes_to_human(size),
+                disk_space::DiskSpaceInfo::bytes_to_human(original_size)
+            );
+        } else {
+            println!(
+                "    Size: {}",
+                disk_space::DiskSpaceInfo::bytes_to_human(size)
+            );
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn backup_diff(id: &str) -> Result<()> {
+    let backup_manager = backup_manager::BackupManager::new()?;
+    let backups = backup_manager.list_backups()?;
+
+    let backup = backups
+        .iter()
+        .find(|b| b.id.starts_with(id))
+        .ok_or_else(|| anyhow::anyhow!("Backup not found: {}", id))?;
+
+    let verification = backup_manager.verify_backup(&backup.id)?;
+    let context = load_config()
+        .map(|config| config.processing.context_size(None))
+        .unwrap_or(config::DEFAULT_CONTEXT_LINES);
+
+    println!("Changes a restore of {} would make:\n", backup.id);
+
+    let mut changed = 0;
+    for (file_backup, (path, differs)) in backup.files.iter().zip(&verification) {
+        if !differs {
+            println!("{}: unchanged\n", path.display());
+            continue;
+        }
+        changed += 1;
+
+        let current = std::fs::read_to_string(path).unwrap_or_default();
+        let backed_up = String::from_utf8_lossy(&file_backup.read_contents()?).into_owned();
+        let current_lines: Vec<&str> = current.lines().collect();
+        let backup_lines: Vec<String> = backed_up.lines().map(str::to_string).collect();
+
+        let diff = file_processor::FileDiff::between(
+            &path.display().to_string(),
+            &current_lines,
+            &backup_lines,
+        );
         println!(
-            "    Size: {}",
-            disk_space::DiskSpaceInfo::bytes_to_human(size)
+            "{}",
+            diff_formatter::DiffFormatter::format_diff_with_context(
+                &diff,
+                context,
+                &backup.expression,
+                ColorChoice::Auto,
+            )
         );
-        println!();
     }
 
+    println!(
+        "{} of {} file(s) differ from the backup",
+        changed,
+        verification.len()
+    );
+
+    Ok(())
+}
+
+/// Check every backup, printing a pass/fail report; the exit status is 1
+/// if any backup failed
+fn backup_verify() -> Result<u8> {
+    let backup_manager = backup_manager::BackupManager::new()?;
+    let checks = backup_manager.verify_all()?;
+
+    if checks.is_empty() {
+        println!("No backups found.");
+        return Ok(0);
+    }
+
+    for check in &checks {
+        if check.is_ok() {
+            println!("PASS  {} ({} file(s))", check.id, check.files);
+        } else {
+            println!("FAIL  {}", check.id);
+            for problem in &check.problems {
+                println!("        {}", problem);
+            }
+        }
+    }
+
+    let failed = checks.iter().filter(|check| !check.is_ok()).count();
+    println!(
+        "\n{} of {} backup(s) passed, {} failed",
+        checks.len() - failed,
+        checks.len(),
+        failed
+    );
+
+    Ok(if failed > 0 { 1 } else { 0 })
+}
+
+fn backup_export(id: &str, archive: &str) -> Result<()> {
+    let backup_manager = backup_manager::BackupManager::new()?;
+    let backups = backup_manager.list_backups()?;
+
+    let backup = backups
+        .iter()
+        .find(|b| b.id.starts_with(id))
+        .ok_or_else(|| anyhow::anyhow!("Backup not found: {}", id))?;
+
+    backup_manager.export_backup(&backup.id, Path::new(archive))?;
+    println!("Exported backup {} to {}", backup.id, archive);
+
+    Ok(())
+}
+
+fn backup_import(archive: &str) -> Result<()> {
+    let mut backup_manager = backup_manager::BackupManager::new()?;
+    let id = backup_manager.import_backup(Path::new(archive))?;
+    println!("Imported backup {} from {}", id, archive);
+    println!("Restore with: sedx rollback {}", id);
+
     Ok(())
 }
 
@@ -856,14 +1918,7 @@ fn backup_prune(keep: Option<usize>, keep_days: Option<usize>, force: bool) -> R
         println!("Pruning backups older than {} days:", days);
     } else {
         // Prune by count
-        let sorted = backups.clone();
-        let mut backups_by_date = sorted.into_iter().enumerate().collect::<Vec<_>>();
-        backups_by_date.sort_by_key(|(_, b)| b.timestamp);
-
-        // Keep the N most recent
-        for (_idx, backup) in backups_by_date.into_iter().rev().skip(keep) {
-            to_remove.push(backup);
-        }
+        to_remove = backup_manager.backups_to_prune(keep)?;
 
         println!("Pruning backups, keeping only {} most recent:", keep);
     }
@@ -920,7 +1975,7 @@ fn config_show() -> Result<()> {
     if let Some(max_size_gb) = config.backup.max_size_gb {
         println!("  max_size_gb = {}", max_size_gb);
     } else {
-        println!("  max_size_gb = (not set)");
+        println!("  max_size_gb = (not set, warns above 2 GB)");
     }
     if let Some(max_disk) = config.backup.max_disk_usage_percent {
         println!("  max_disk_usage_percent = {}", max_disk);
@@ -932,6 +1987,11 @@ fn config_show() -> Result<()> {
     } else {
         println!("  backup_dir = (not set)");
     }
+    if let Some(ref compression) = config.backup.compression {
+        println!("  compression = \"{}\"", compression);
+    } else {
+        println!("  compression = (not set)");
+    }
 
     println!("\n[compatibility]");
     if let Some(ref mode) = config.compatibility.mode {
@@ -1033,6 +2093,22 @@ fn config_edit() -> Result<()> {
     Ok(())
 }
 
+fn config_get(key: &str) -> Result<()> {
+    let config = load_config()?;
+    if let Some(value) = config::get_config_value(&config, key)? {
+        println!("{}", value);
+    }
+    Ok(())
+}
+
+fn config_set(key: &str, value: &str) -> Result<()> {
+    let mut config = load_config()?;
+    config::set_config_value(&mut config, key, value)?;
+    config::save_config(&config)?;
+    println!("✅ Set {} = {}", key, value);
+    Ok(())
+}
+
 fn config_log_path() -> Result<()> {
     use logger::get_current_log_path;
 
@@ -5,35 +5,140 @@
 
 use crate::cli::RegexFlavor;
 use crate::command::{Address, Command, SubstitutionFlags};
-use crate::sed_parser::{Address as LegacyAddress, SedCommand as LegacySedCommand};
+use crate::regex_error::EnhancedRegexError;
+use crate::sed_parser::{
+    Address as LegacyAddress, SedCommand as LegacySedCommand, format_parse_error,
+};
 use anyhow::Result;
 
+/// A problem found by `Parser::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDiagnostic {
+    /// Byte offset in the script of the command with the problem
+    pub offset: usize,
+    /// What is wrong, e.g. "unknown command 'k'"
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "byte {}: {}", self.offset, self.message)
+    }
+}
+
 /// Unified parser that supports sed syntax with configurable regex flavor
 pub struct Parser {
     /// Regex flavor to use for parsing
     regex_flavor: RegexFlavor,
+    /// Match regexes only as whole words (--word-regexp)
+    word_regexp: bool,
 }
 
 impl Parser {
     /// Create a new parser with the specified regex flavor
     pub fn new(regex_flavor: RegexFlavor) -> Self {
-        Self { regex_flavor }
+        Self {
+            regex_flavor,
+            word_regexp: false,
+        }
+    }
+
+    /// Wrap every substitution and address regex in word boundaries
+    pub fn with_word_regexp(mut self, value: bool) -> Self {
+        self.word_regexp = value;
+        self
     }
 
     /// Parse a sed expression into unified Command list
+    ///
+    /// Once the whole program is assembled, every b, t and T must branch to
+    /// a label defined somewhere in it. With several -e and -f fragments a
+    /// branch may jump forward into a later fragment, so this can't be
+    /// checked command by command.
     pub fn parse(&self, expression: &str) -> Result<Vec<Command>> {
-        // Use existing sed_parser to parse the expression
-        let legacy_commands = crate::sed_parser::parse_sed_expression(expression)?;
+        let mut commands = Vec::new();
+        let mut fragments = Vec::new();
+
+        // Use existing sed_parser to parse each command, remembering where
+        // it starts in case it branches nowhere
+        for (offset, part) in crate::sed_parser::split_sed_expression(expression) {
+            for cmd in crate::sed_parser::parse_sed_expression(part)? {
+                // Convert LegacySedCommand to Command
+                commands.push(self.convert_legacy_command(cmd)?);
+                fragments.push((offset, part));
+            }
+        }
 
-        // Convert LegacySedCommand to Command
-        let commands = legacy_commands
-            .into_iter()
-            .map(|cmd| self.convert_legacy_command(cmd))
-            .collect::<Result<Vec<_>>>()?;
+        let mut labels = Vec::new();
+        collect_labels(commands.iter(), &mut labels);
+        for (command, &(offset, part)) in commands.iter().zip(&fragments) {
+            let mut targets = Vec::new();
+            collect_branch_targets(std::iter::once(command), &mut targets);
+            if let Some(target) = targets.into_iter().find(|t| !labels.contains(t)) {
+                anyhow::bail!(
+                    "{}",
+                    format_parse_error(
+                        part,
+                        Some(0),
+                        &format!("undefined label '{}' at byte {}", target, offset),
+                        Some(&format!(
+                            "Define it with ':{}' in this or another -e/-f fragment",
+                            target
+                        )),
+                    )
+                );
+            }
+        }
 
         Ok(commands)
     }
 
+    /// Check a script without running it, reporting every problem rather
+    /// than stopping at the first: commands that don't parse, unknown flags,
+    /// and branches to labels that are never defined
+    pub fn validate(&self, expression: &str) -> std::result::Result<(), Vec<ParseDiagnostic>> {
+        let mut diagnostics = Vec::new();
+        let mut parsed = Vec::new();
+
+        for (offset, part) in crate::sed_parser::split_sed_expression(expression) {
+            let command = crate::sed_parser::parse_sed_expression(part).and_then(|legacy| {
+                legacy
+                    .into_iter()
+                    .map(|cmd| self.convert_legacy_command(cmd))
+                    .collect::<Result<Vec<_>>>()
+            });
+            match command {
+                Ok(commands) => parsed.extend(commands.into_iter().map(|cmd| (offset, cmd))),
+                Err(e) => diagnostics.push(ParseDiagnostic {
+                    offset,
+                    message: diagnostic_message(&e),
+                }),
+            }
+        }
+
+        let mut labels = Vec::new();
+        collect_labels(parsed.iter().map(|(_, cmd)| cmd), &mut labels);
+        for (offset, command) in &parsed {
+            let mut targets = Vec::new();
+            collect_branch_targets(std::iter::once(command), &mut targets);
+            for target in targets {
+                if !labels.contains(&target) {
+                    diagnostics.push(ParseDiagnostic {
+                        offset: *offset,
+                        message: format!("undefined label '{}'", target),
+                    });
+                }
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            diagnostics.sort_by_key(|d| d.offset);
+            Err(diagnostics)
+        }
+    }
+
     /// Convert legacy SedCommand to unified Command
     fn convert_legacy_command(&self, legacy: LegacySedCommand) -> Result<Command> {
         match legacy {
@@ -41,34 +146,48 @@ impl Parser {
                 pattern,
                 replacement,
                 flags,
+                write_file,
                 range,
             } => {
-                // Convert pattern based on regex flavor
-                let pattern = self.convert_pattern(&pattern);
-                let replacement = self.convert_replacement(&replacement);
+                // Convert Vec<char> flags to SubstitutionFlags; the command
+                // is rebuilt to show where an unknown flag is
+                let command = format!(
+                    "s/{}/{}/{}",
+                    pattern,
+                    replacement,
+                    flags.iter().collect::<String>()
+                );
+                let mut substitution_flags = self.convert_flags(&flags, &command)?;
 
-                // Convert Vec<char> flags to SubstitutionFlags
-                let substitution_flags = self.convert_flags(&flags);
+                // Convert pattern based on regex flavor; the replacement is
+                // already in regex crate style, whatever the flavor
+                let pattern = self.convert_pattern(&pattern);
+                self.check_pattern(&pattern, &command)?;
+                substitution_flags.write_file = write_file;
 
                 Ok(Command::Substitution {
                     pattern,
                     replacement,
                     flags: substitution_flags,
-                    range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                    range: range.map(|range| self.convert_range(range)),
                 })
             }
             LegacySedCommand::Delete { range } => Ok(Command::Delete {
-                range: (self.convert_address(range.0), self.convert_address(range.1)),
+                range: self.convert_range(range),
             }),
             LegacySedCommand::Print { range } => Ok(Command::Print {
-                range: (self.convert_address(range.0), self.convert_address(range.1)),
-            }),
-            LegacySedCommand::Quit { address } => Ok(Command::Quit {
-                address: address.map(|a| self.convert_address(a)),
+                range: self.convert_range(range),
             }),
-            LegacySedCommand::QuitWithoutPrint { address } => Ok(Command::QuitWithoutPrint {
+            LegacySedCommand::Quit { address, exit_code } => Ok(Command::Quit {
                 address: address.map(|a| self.convert_address(a)),
+                exit_code,
             }),
+            LegacySedCommand::QuitWithoutPrint { address, exit_code } => {
+                Ok(Command::QuitWithoutPrint {
+                    address: address.map(|a| self.convert_address(a)),
+                    exit_code,
+                })
+            }
             LegacySedCommand::Insert { text, address } => Ok(Command::Insert {
                 text,
                 address: self.convert_address(address),
@@ -77,9 +196,9 @@ impl Parser {
                 text,
                 address: self.convert_address(address),
             }),
-            LegacySedCommand::Change { text, address } => Ok(Command::Change {
+            LegacySedCommand::Change { text, range } => Ok(Command::Change {
                 text,
-                address: self.convert_address(address),
+                range: self.convert_range(range),
             }),
             LegacySedCommand::Group { range, commands } => {
                 let converted_commands = commands
@@ -89,49 +208,49 @@ impl Parser {
 
                 Ok(Command::Group {
                     commands: converted_commands,
-                    range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                    range: range.map(|range| self.convert_range(range)),
                 })
             }
             LegacySedCommand::Hold { range } => Ok(Command::Hold {
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::HoldAppend { range } => Ok(Command::HoldAppend {
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::Get { range } => Ok(Command::Get {
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::GetAppend { range } => Ok(Command::GetAppend {
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::Exchange { range } => Ok(Command::Exchange {
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::Next { range } => Ok(Command::Next {
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::NextAppend { range } => Ok(Command::NextAppend {
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::PrintFirstLine { range } => Ok(Command::PrintFirstLine {
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::DeleteFirstLine { range } => Ok(Command::DeleteFirstLine {
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             // Phase 5: Flow control commands
             LegacySedCommand::Label { name } => Ok(Command::Label { name }),
             LegacySedCommand::Branch { label, range } => Ok(Command::Branch {
                 label,
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::Test { label, range } => Ok(Command::Test {
                 label,
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::TestFalse { label, range } => Ok(Command::TestFalse {
                 label,
-                range: range.map(|(a, b)| (self.convert_address(a), self.convert_address(b))),
+                range: range.map(|range| self.convert_range(range)),
             }),
             LegacySedCommand::ReadFile { filename, range } => Ok(Command::ReadFile {
                 filename,
@@ -158,14 +277,38 @@ impl Parser {
             LegacySedCommand::ClearPatternSpace { range } => Ok(Command::ClearPatternSpace {
                 range: range.map(|a| self.convert_address(a)),
             }),
+            LegacySedCommand::List { range, width } => Ok(Command::List {
+                range: range.map(|range| self.convert_range(range)),
+                width,
+            }),
+            LegacySedCommand::Execute { command, range } => Ok(Command::Execute {
+                command,
+                range: range.map(|range| self.convert_range(range)),
+            }),
         }
     }
 
-    /// Convert legacy Address to unified Address
+    /// Convert a legacy range. A line 0 end (`2,0p`) has always been
+    /// reached, so like any end at or before the start line it closes the
+    /// range on the line it starts
+    fn convert_range(&self, (start, end): (LegacyAddress, LegacyAddress)) -> (Address, Address) {
+        let end = match (&start, end) {
+            (LegacyAddress::FirstLine, end) => self.convert_address(end),
+            (_, LegacyAddress::FirstLine) => Address::LineNumber(0),
+            (_, end) => self.convert_address(end),
+        };
+        (self.convert_address(start), end)
+    }
+
+    /// Convert legacy Address to unified Address, translating regex
+    /// addresses to PCRE like substitution patterns
     fn convert_address(&self, legacy: LegacyAddress) -> Address {
         match legacy {
             LegacyAddress::LineNumber(n) => Address::LineNumber(n),
-            LegacyAddress::Pattern(s) => Address::Pattern(s),
+            LegacyAddress::Pattern(s) => Address::Pattern(self.convert_pattern(&s)),
+            LegacyAddress::MultilinePattern(s) => {
+                Address::Pattern(format!("(?m){}", self.convert_pattern(&s)))
+            }
             LegacyAddress::FirstLine => Address::FirstLine,
             LegacyAddress::LastLine => Address::LastLine,
             LegacyAddress::Negated(a) => Address::Negated(Box::new(self.convert_address(*a))),
@@ -174,38 +317,77 @@ impl Parser {
                 offset,
             },
             LegacyAddress::Step { start, step } => Address::Step { start, step },
+            LegacyAddress::MultipleOf(n) => Address::MultipleOf(n),
         }
     }
 
     /// Convert Vec<char> flags to SubstitutionFlags
-    fn convert_flags(&self, flags: &[char]) -> SubstitutionFlags {
+    fn convert_flags(&self, flags: &[char], command: &str) -> Result<SubstitutionFlags> {
         let mut result = SubstitutionFlags::default();
+        let flags_start = command.len() - flags.iter().map(|c| c.len_utf8()).sum::<usize>();
 
-        for flag in flags {
+        for (i, flag) in flags.iter().enumerate() {
             match flag {
                 'g' => result.global = true,
                 'p' => result.print = true,
                 'i' | 'I' => result.case_insensitive = true,
+                'e' => result.exec = true,
+                'M' | 'm' => result.multiline = true,
                 '0'..='9' => {
-                    // Nth occurrence flag (e.g., 2 for second occurrence)
+                    // Nth occurrence flag (e.g., 2 for second occurrence, 12 for twelfth);
+                    // combined with g (2g or g2) it means the Nth and all later ones
                     // SAFETY: The match pattern '0'..='9' guarantees flag is an ASCII digit,
                     // so to_digit(10) will always return Some(digit_value).
                     let n = flag.to_digit(10).unwrap() as usize;
-                    result.nth = Some(n);
+                    result.nth = Some(result.nth.unwrap_or(0) * 10 + n);
+                }
+                c if c.is_whitespace() => {}
+                _ => {
+                    let pos = flags_start + flags[..i].iter().map(|c| c.len_utf8()).sum::<usize>();
+                    anyhow::bail!(
+                        "{}",
+                        format_parse_error(
+                            command,
+                            Some(pos),
+                            &format!("unknown flag '{}' in s command", flag),
+                            Some(
+                                "Valid flags: g (all matches), N (Nth match), p (print), i/I (ignore case),\n  m/M (multiline), e (execute, needs --allow-exec) and w FILE (write, last)"
+                            ),
+                        )
+                    );
                 }
-                _ => {} // Ignore unknown flags
             }
         }
 
-        result
+        Ok(result)
     }
 
     /// Convert pattern based on regex flavor to PCRE
-    fn convert_pattern(&self, pattern: &str) -> String {
-        match self.regex_flavor {
+    /// Reject a pattern the regex crate won't compile, naming the problem
+    /// and where it is, instead of failing once the files are being read.
+    /// An empty pattern reuses the last regex, so there's nothing to check.
+    fn check_pattern(&self, pattern: &str, command: &str) -> Result<()> {
+        if pattern.is_empty() {
+            return Ok(());
+        }
+        if let Err(err) = regex::Regex::new(pattern) {
+            let enhanced = EnhancedRegexError::from_regex_error(&err, pattern, self.regex_flavor);
+            anyhow::bail!(format_parse_error(
+                command,
+                Some(0),
+                &enhanced.summary,
+                enhanced.suggestion.as_deref()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Convert a pattern in this parser's flavor to the regex crate's syntax
+    pub fn convert_pattern(&self, pattern: &str) -> String {
+        let pattern = match self.regex_flavor {
             RegexFlavor::BRE => {
                 // BRE needs to be converted to PCRE
-                crate::bre_converter::convert_bre_to_pcre(pattern)
+                crate::bre_converter::convert(pattern)
             }
             RegexFlavor::ERE => {
                 // ERE needs to be converted to PCRE (mostly pass-through)
@@ -215,24 +397,84 @@ impl Parser {
                 // Already PCRE, no conversion needed
                 pattern.to_string()
             }
+        };
+
+        if self.word_regexp {
+            word_bounded(&pattern)
+        } else {
+            pattern
         }
     }
+}
 
-    /// Convert replacement based on regex flavor to PCRE format
-    fn convert_replacement(&self, replacement: &str) -> String {
-        match self.regex_flavor {
-            RegexFlavor::BRE => {
-                // BRE uses \1, \2 for backreferences → convert to $1, $2
-                crate::bre_converter::convert_sed_backreferences(replacement)
-            }
-            RegexFlavor::ERE => {
-                // ERE uses \1, \2 for backreferences → convert to $1, $2
-                crate::ere_converter::convert_ere_backreferences(replacement)
+/// Wrap a converted pattern as `\b(?:...)\b`, leaving out a boundary on
+/// a side the pattern already anchors (`^`, `\b`, `\A` at the start; `$`,
+/// `\b`, `\z`, `\b{end}` at the end). An empty pattern reuses the last regex, so it
+/// stays empty.
+fn word_bounded(pattern: &str) -> String {
+    if pattern.is_empty() {
+        return String::new();
+    }
+
+    let anchored_start = ["^", "\\b", "\\A"]
+        .iter()
+        .any(|anchor| pattern.starts_with(anchor));
+    let anchored_end = ["$", "\\b", "\\z", "\\b{end}"].iter().any(|anchor| {
+        pattern.strip_suffix(anchor).is_some_and(|rest| {
+            // The anchor's own backslash (or `$`) mustn't itself be escaped
+            let escapes = rest.chars().rev().take_while(|&c| c == '\\').count();
+            escapes % 2 == 0
+        })
+    });
+
+    format!(
+        "{}(?:{}){}",
+        if anchored_start { "" } else { "\\b" },
+        pattern,
+        if anchored_end { "" } else { "\\b" }
+    )
+}
+
+/// The first line of a parse error, without its "Parse error: " prefix
+/// (the rest is context and hints meant for a terminal)
+fn diagnostic_message(error: &anyhow::Error) -> String {
+    let text = error.to_string();
+    let first = text.lines().next().unwrap_or_default();
+    first
+        .strip_prefix("Parse error: ")
+        .unwrap_or(first)
+        .to_string()
+}
+
+/// Names of every label defined in `commands`, including inside groups
+fn collect_labels<'a>(commands: impl Iterator<Item = &'a Command>, labels: &mut Vec<&'a str>) {
+    for command in commands {
+        match command {
+            Command::Label { name } => labels.push(name),
+            Command::Group { commands, .. } => collect_labels(commands.iter(), labels),
+            _ => {}
+        }
+    }
+}
+
+/// Labels that b, t and T in `commands` branch to, including inside groups
+fn collect_branch_targets<'a>(
+    commands: impl Iterator<Item = &'a Command>,
+    targets: &mut Vec<&'a str>,
+) {
+    for command in commands {
+        match command {
+            Command::Branch {
+                label: Some(label), ..
             }
-            RegexFlavor::PCRE => {
-                // Already PCRE format with $1, $2
-                replacement.to_string()
+            | Command::Test {
+                label: Some(label), ..
             }
+            | Command::TestFalse {
+                label: Some(label), ..
+            } => targets.push(label),
+            Command::Group { commands, .. } => collect_branch_targets(commands.iter(), targets),
+            _ => {}
         }
     }
 }
@@ -322,6 +564,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_nested_group() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let commands = parser.parse("2,4{/x/{s/a/b/;p};d}").unwrap();
+
+        let Command::Group { commands, .. } = &commands[0] else {
+            panic!("Expected Group command");
+        };
+        assert_eq!(commands.len(), 2);
+        match &commands[0] {
+            Command::Group { commands, range } => {
+                assert_eq!(commands.len(), 2);
+                assert!(range.is_some());
+            }
+            other => panic!("Expected nested Group, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_empty_script_is_no_op() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        for script in ["", "   ", " \n\t\n"] {
+            let commands = parser.parse(script).unwrap();
+            assert!(
+                commands.is_empty(),
+                "{:?} should parse to no commands",
+                script
+            );
+        }
+    }
+
     #[test]
     fn test_convert_pattern_bre() {
         let parser = Parser::new(RegexFlavor::BRE);
@@ -342,6 +615,103 @@ mod tests {
         assert_eq!(parser.convert_pattern(r#"foo|bar"#), "foo|bar");
     }
 
+    #[test]
+    fn test_word_regexp_wraps_patterns() {
+        let parser = Parser::new(RegexFlavor::PCRE).with_word_regexp(true);
+        assert_eq!(parser.convert_pattern("foo"), r"\b(?:foo)\b");
+        assert_eq!(parser.convert_pattern("foo|bar"), r"\b(?:foo|bar)\b");
+        assert_eq!(parser.convert_pattern(""), "");
+
+        // Sides that are already anchored aren't wrapped again
+        assert_eq!(parser.convert_pattern(r"\bfoo\b"), r"(?:\bfoo\b)");
+        assert_eq!(parser.convert_pattern("^foo"), r"(?:^foo)\b");
+        assert_eq!(parser.convert_pattern("foo$"), r"\b(?:foo$)");
+        // An escaped `$` is a literal, so it still gets a boundary
+        assert_eq!(parser.convert_pattern(r"foo\$"), r"\b(?:foo\$)\b");
+
+        // BRE groups are converted before wrapping
+        let bre = Parser::new(RegexFlavor::BRE).with_word_regexp(true);
+        assert_eq!(bre.convert_pattern(r"\(foo\)"), r"\b(?:(foo))\b");
+    }
+
+    #[test]
+    fn test_word_regexp_matches_whole_words_only() {
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .with_word_regexp(true)
+            .parse("s/foo/X/g")
+            .unwrap();
+        let Command::Substitution { pattern, .. } = &commands[0] else {
+            panic!("expected a substitution, got {:?}", commands[0]);
+        };
+        let re = regex::Regex::new(pattern).unwrap();
+        assert_eq!(re.replace_all("foo bar", "X"), "X bar");
+        assert_eq!(re.replace_all("foobar", "X"), "foobar");
+
+        let commands = Parser::new(RegexFlavor::PCRE)
+            .with_word_regexp(true)
+            .parse("/foo/d")
+            .unwrap();
+        let Command::Delete {
+            range: (Address::Pattern(pattern), _),
+        } = &commands[0]
+        else {
+            panic!("expected a pattern delete, got {:?}", commands[0]);
+        };
+        let re = regex::Regex::new(pattern).unwrap();
+        assert!(re.is_match("a foo."));
+        assert!(!re.is_match("food"));
+    }
+
+    #[test]
+    fn test_word_anchors_match_whole_words() {
+        for flavor in [RegexFlavor::BRE, RegexFlavor::ERE] {
+            let commands = Parser::new(flavor).parse(r"s/\<cat\>/X/g").unwrap();
+            let Command::Substitution { pattern, .. } = &commands[0] else {
+                panic!("expected a substitution, got {:?}", commands[0]);
+            };
+            let re = regex::Regex::new(pattern).unwrap();
+            assert_eq!(re.replace_all("a cat b", "X"), "a X b", "{:?}", flavor);
+            assert_eq!(re.replace_all("concatenate", "X"), "concatenate");
+            assert_eq!(re.replace_all("cats, cat.", "X"), "cats, X.");
+        }
+    }
+
+    #[test]
+    fn test_posix_classes_match_alike_in_bre_and_ere() {
+        fn substitute(flavor: RegexFlavor, script: &str, input: &str) -> String {
+            let commands = Parser::new(flavor).parse(script).unwrap();
+            let Command::Substitution {
+                pattern,
+                replacement,
+                ..
+            } = &commands[0]
+            else {
+                panic!("expected a substitution, got {:?}", commands[0]);
+            };
+            let re = regex::Regex::new(pattern).unwrap();
+            re.replace_all(input, replacement.as_str()).into_owned()
+        }
+
+        let input = "a  \t b [12]-c";
+        for (bre, ere, expected) in [
+            (r"s/[[:space:]]\+/ /g", r"s/[[:space:]]+/ /g", "a b [12]-c"),
+            (r"s/[[:digit:]]/D/g", r"s/[[:digit:]]/D/g", "a  \t b [DD]-c"),
+            (
+                r"s/[^[:alpha:][:space:]]/N/g",
+                r"s/[^[:alpha:][:space:]]/N/g",
+                "a  \t b NNNNNc",
+            ),
+            (r"s/[^[:alnum:]]\+/_/g", r"s/[^[:alnum:]]+/_/g", "a_b_12_c"),
+            (r"s/[[.-.]]/H/g", r"s/[[.-.]]/H/g", "a  \t b [12]Hc"),
+            // A literal [ is still a literal, escaped or in a class
+            (r"s/\[1/</", r"s/\[1/</", "a  \t b <2]-c"),
+            (r"s/[[]/</", r"s/[[]/</", "a  \t b <12]-c"),
+        ] {
+            assert_eq!(substitute(RegexFlavor::BRE, bre, input), expected, "{bre}");
+            assert_eq!(substitute(RegexFlavor::ERE, ere, input), expected, "{ere}");
+        }
+    }
+
     #[test]
     fn test_convert_pattern_pcre() {
         let parser = Parser::new(RegexFlavor::PCRE);
@@ -353,46 +723,163 @@ mod tests {
     }
 
     #[test]
-    fn test_convert_replacement_bre() {
-        let parser = Parser::new(RegexFlavor::BRE);
+    fn test_replacement_is_converted_once_in_every_flavor() {
+        for (flavor, script) in [
+            (RegexFlavor::BRE, r"s/\(a\)/\1&\&\\1$x/"),
+            (RegexFlavor::ERE, r"s/(a)/\1&\&\\1$x/"),
+            (RegexFlavor::PCRE, r"s/(a)/\1&\&\\1$x/"),
+        ] {
+            let commands = Parser::new(flavor).parse(script).unwrap();
+            let Command::Substitution { replacement, .. } = &commands[0] else {
+                panic!("Expected substitution, got {:?}", commands[0]);
+            };
+            assert_eq!(replacement, r"$1${0}&\1$$x", "{:?}", flavor);
+        }
+    }
 
-        // BRE replacements should convert backreferences to PCRE format
-        assert_eq!(parser.convert_replacement(r#"\1"#), "$1");
-        assert_eq!(parser.convert_replacement(r#"\2\1"#), "$2$1");
-        assert_eq!(parser.convert_replacement(r#"\&"#), "$&");
+    #[test]
+    fn test_bre_and_ere_groups_compile_to_same_matcher() {
+        let bre = Parser::new(RegexFlavor::BRE);
+        let ere = Parser::new(RegexFlavor::ERE);
+
+        // Substitution patterns
+        assert_eq!(
+            bre.parse("s/\\(foo\\)\\+/X/").unwrap(),
+            ere.parse("s/(foo)+/X/").unwrap()
+        );
+
+        // Address patterns, including ranges and groups
+        assert_eq!(
+            bre.parse("/\\(foo\\|bar\\)/d").unwrap(),
+            ere.parse("/(foo|bar)/d").unwrap()
+        );
+        assert_eq!(
+            bre.parse("/\\(a\\)/,/b\\+/{s/x/y/}").unwrap(),
+            ere.parse("/(a)/,/b+/{s/x/y/}").unwrap()
+        );
+
+        let commands = bre.parse("/^\\(foo\\)$/d").unwrap();
+        let Command::Delete {
+            range: (Address::Pattern(pattern), _),
+        } = &commands[0]
+        else {
+            panic!("Expected pattern delete, got {:?}", commands[0]);
+        };
+        let re = regex::Regex::new(pattern).unwrap();
+        assert!(re.is_match("foo"));
+        assert!(!re.is_match("(foo)"));
     }
 
     #[test]
-    fn test_convert_replacement_ere() {
-        let parser = Parser::new(RegexFlavor::ERE);
+    fn test_convert_flags() {
+        let parser = Parser::new(RegexFlavor::PCRE);
+        let convert = |flags: &[char]| {
+            let command = format!("s/a/b/{}", flags.iter().collect::<String>());
+            parser.convert_flags(flags, &command).unwrap()
+        };
+
+        let flags = convert(&['g', 'p', 'i']);
+        assert!(flags.global);
+        assert!(flags.print);
+        assert!(flags.case_insensitive);
+
+        let flags_nth = convert(&['g', '2']);
+        assert!(flags_nth.global);
+        assert_eq!(flags_nth.nth, Some(2));
 
-        // ERE replacements should convert backreferences to PCRE format
-        assert_eq!(parser.convert_replacement(r#"\1"#), "$1");
-        assert_eq!(parser.convert_replacement(r#"\2\1"#), "$2$1");
-        assert_eq!(parser.convert_replacement(r#"\&"#), "$&");
+        let flags_nth = convert(&['2', 'g']);
+        assert!(flags_nth.global);
+        assert_eq!(flags_nth.nth, Some(2));
+
+        assert_eq!(convert(&['1', '2']).nth, Some(12));
+
+        assert!(convert(&['M']).multiline);
+        assert!(convert(&['m']).multiline);
+        assert!(!convert(&['g']).multiline);
     }
 
     #[test]
-    fn test_convert_replacement_pcre() {
+    fn test_unknown_substitution_flag_is_rejected() {
         let parser = Parser::new(RegexFlavor::PCRE);
 
-        // PCRE replacements should pass through unchanged
-        assert_eq!(parser.convert_replacement(r#"$1"#), "$1");
-        assert_eq!(parser.convert_replacement(r#"$2$1"#), "$2$1");
-        assert_eq!(parser.convert_replacement(r#"$&"#), "$&");
+        let err = parser.parse("s/a/b/gq").unwrap_err().to_string();
+        assert!(err.contains("unknown flag 'q' in s command"), "{}", err);
+        assert!(err.contains("Near: \"s/a/b/gq\""), "{}", err);
+        assert!(err.contains("Valid flags: g"), "{}", err);
+
+        let commands = parser.parse("s/a/b/gip").unwrap();
+        let Command::Substitution { flags, .. } = &commands[0] else {
+            panic!("Expected substitution, got {:?}", commands[0]);
+        };
+        assert!(flags.global && flags.case_insensitive && flags.print);
     }
 
     #[test]
-    fn test_convert_flags() {
+    fn test_invalid_regex_is_reported_with_the_expression() {
+        let err = Parser::new(RegexFlavor::PCRE)
+            .parse("s/(foo/x/")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("Parse error: unclosed group at position 0 in pattern `(foo`"),
+            "{}",
+            err
+        );
+        assert!(err.contains("Near: \"s/(foo/x/\""), "{}", err);
+
+        let err = Parser::new(RegexFlavor::ERE)
+            .parse("s/a|*b/x/")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with(
+                "Parse error: repetition operator missing expression at position 2 in pattern `a|*b`"
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem_with_its_offset() {
         let parser = Parser::new(RegexFlavor::PCRE);
+        assert_eq!(parser.validate(":a; s/x/y/; t a"), Ok(()));
+
+        let diagnostics = parser.validate("s/a/b/; b missing; k; 1{t a}").unwrap_err();
+        assert_eq!(
+            diagnostics,
+            vec![
+                ParseDiagnostic {
+                    offset: 8,
+                    message: "undefined label 'missing'".to_string(),
+                },
+                ParseDiagnostic {
+                    offset: 19,
+                    message: "unknown command 'k'".to_string(),
+                },
+                ParseDiagnostic {
+                    offset: 22,
+                    message: "undefined label 'a'".to_string(),
+                },
+            ]
+        );
+        assert_eq!(diagnostics[1].to_string(), "byte 19: unknown command 'k'");
+    }
 
-        let flags = parser.convert_flags(&['g', 'p', 'i']);
-        assert!(flags.global);
-        assert!(flags.print);
-        assert!(flags.case_insensitive);
+    #[test]
+    fn test_parse_checks_branch_targets_across_the_program() {
+        let parser = Parser::new(RegexFlavor::PCRE);
 
-        let flags_nth = parser.convert_flags(&['g', '2']);
-        assert!(flags_nth.global);
-        assert_eq!(flags_nth.nth, Some(2));
+        // A branch may jump forward, even into a later -e fragment
+        assert!(parser.parse("b loop; s/a/b/; :loop").is_ok());
+        assert!(parser.parse("1{t done}; s/x/y/; :done").is_ok());
+        // A bare b branches to the end and needs no label
+        assert!(parser.parse("/x/b; s/a/b/").is_ok());
+
+        let err = parser.parse("s/a/b/; b loop").unwrap_err().to_string();
+        assert!(err.contains("undefined label 'loop' at byte 8"), "{err}");
+        assert!(err.contains("Near: \"b loop\""), "{err}");
+        let err = parser.parse(":a; 2{T b}").unwrap_err().to_string();
+        assert!(err.contains("undefined label 'b' at byte 4"), "{err}");
     }
 }
@@ -0,0 +1,79 @@
+//! POSIX Mode (`--posix`)
+//!
+//! Rejects the GNU extensions POSIX sed doesn't have: the `e`, `F`, `Q`,
+//! `R`, `T`, `W` and `z` commands, the `q` exit code and the `s///e` and
+//! `s///M` flags.
+
+use crate::command::Command;
+use anyhow::Result;
+
+/// Describe the first GNU-only command or flag, searching inside groups
+fn find_gnu_extension(commands: &[Command]) -> Option<&'static str> {
+    commands.iter().find_map(|cmd| match cmd {
+        Command::Execute { .. } => Some("e command"),
+        Command::PrintFilename { .. } => Some("F command"),
+        Command::QuitWithoutPrint { .. } => Some("Q command"),
+        Command::Quit {
+            exit_code: Some(_), ..
+        } => Some("q exit code"),
+        Command::ReadLine { .. } => Some("R command"),
+        Command::TestFalse { .. } => Some("T command"),
+        Command::WriteFirstLine { .. } => Some("W command"),
+        Command::ClearPatternSpace { .. } => Some("z command"),
+        Command::Substitution { flags, .. } if flags.exec => Some("s///e flag"),
+        Command::Substitution { flags, .. } if flags.multiline => Some("s///M flag"),
+        Command::Group { commands, .. } => find_gnu_extension(commands),
+        _ => None,
+    })
+}
+
+/// Fail if the script uses a GNU extension, which POSIX mode disables
+pub fn check_posix(commands: &[Command]) -> Result<()> {
+    if let Some(name) = find_gnu_extension(commands) {
+        anyhow::bail!(
+            "The {} is a GNU extension, not available in POSIX mode (drop --posix or set compatibility.mode to \"gnu\")",
+            name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::RegexFlavor;
+    use crate::parser::Parser;
+
+    fn check(script: &str) -> Result<()> {
+        let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+        check_posix(&commands)
+    }
+
+    #[test]
+    fn test_posix_allows_posix_commands() {
+        assert!(check("s/foo/bar/g").is_ok());
+        assert!(check("$!N; P; D").is_ok());
+        assert!(check("/x/{h; x; q}").is_ok());
+    }
+
+    #[test]
+    fn test_posix_rejects_gnu_commands() {
+        let err = check("F").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The F command is a GNU extension, not available in POSIX mode (drop --posix or set compatibility.mode to \"gnu\")"
+        );
+
+        for (script, name) in [
+            ("z", "The z command"),
+            ("2Q", "The Q command"),
+            ("5q3", "The q exit code"),
+            ("R in.txt", "The R command"),
+            ("s/a/b/M", "The s///M flag"),
+            ("1,3{s/a/b/; z}", "The z command"),
+        ] {
+            let err = check(script).unwrap_err().to_string();
+            assert!(err.starts_with(name), "{}: {}", script, err);
+        }
+    }
+}
@@ -17,6 +17,8 @@ pub struct EnhancedRegexError {
     pub error_type: RegexErrorType,
     /// Suggested fix for the error
     pub suggestion: Option<String>,
+    /// One-line description, e.g. "unclosed group at position 0 in pattern `(foo`"
+    pub summary: String,
 }
 
 /// Types of regex errors with specific diagnostic information
@@ -61,12 +63,48 @@ impl EnhancedRegexError {
         let error_type = Self::classify_error(&error_msg, pattern, flavor);
 
         let suggestion = Self::generate_suggestion(&error_type, pattern, flavor);
+        let summary = Self::summarize(&error_msg, &error_type, pattern);
 
         EnhancedRegexError {
             pattern: pattern.to_string(),
             flavor,
             error_type,
             suggestion,
+            summary,
+        }
+    }
+
+    /// Condense the regex crate's multi-line message (pattern, caret line,
+    /// `error: ...`) into one line naming the position and the pattern
+    fn summarize(error_msg: &str, error_type: &RegexErrorType, pattern: &str) -> String {
+        let lines: Vec<&str> = error_msg.lines().collect();
+        let description = lines
+            .iter()
+            .find_map(|line| line.strip_prefix("error: "))
+            .unwrap_or_else(|| lines.last().copied().unwrap_or(error_msg));
+
+        // The caret line sits under the pattern line, with the same indent
+        let caret_position = lines.windows(2).find_map(|pair| {
+            let caret = pair[1].trim_end();
+            if !caret.trim_start().starts_with('^') {
+                return None;
+            }
+            let indent = pair[0].len() - pair[0].trim_start().len();
+            let column = caret.len() - caret.trim_start().len();
+            column.checked_sub(indent)
+        });
+        let position = match error_type {
+            RegexErrorType::UnclosedDelimiter { position, .. }
+            | RegexErrorType::InvalidEscape { position, .. } => Some(*position),
+            _ => caret_position,
+        };
+
+        match position {
+            Some(pos) => format!(
+                "{} at position {} in pattern `{}`",
+                description, pos, pattern
+            ),
+            None => format!("{} in pattern `{}`", description, pattern),
         }
     }
 
@@ -289,7 +327,7 @@ impl EnhancedRegexError {
             RegexFlavor::BRE => "BRE (basic regex, -B flag)",
         };
 
-        let mut output = format!("Regex Error in {} mode\n", flavor_name);
+        let mut output = format!("Regex Error in {} mode: {}\n", flavor_name, self.summary);
         output.push_str(&format!("  Pattern: \"{}\"\n", self.pattern));
 
         match &self.error_type {
@@ -529,13 +567,23 @@ pub fn compile_regex_with_context(
     flavor: RegexFlavor,
     case_insensitive: bool,
 ) -> Result<regex::Regex, anyhow::Error> {
-    use regex::{Regex, RegexBuilder};
+    compile_regex_with_options(pattern, flavor, case_insensitive, false)
+}
 
-    let result = if case_insensitive {
-        RegexBuilder::new(pattern).case_insensitive(true).build()
-    } else {
-        Regex::new(pattern)
-    };
+/// Like [`compile_regex_with_context`], optionally letting `^`/`$` match at
+/// embedded newlines (the `M` flag of `s`)
+pub fn compile_regex_with_options(
+    pattern: &str,
+    flavor: RegexFlavor,
+    case_insensitive: bool,
+    multi_line: bool,
+) -> Result<regex::Regex, anyhow::Error> {
+    use regex::RegexBuilder;
+
+    let result = RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .multi_line(multi_line)
+        .build();
 
     match result {
         Ok(re) => Ok(re),
@@ -585,6 +633,45 @@ mod tests {
         assert!(enhanced.suggestion.is_some());
     }
 
+    #[test]
+    fn test_summary_for_unbalanced_group() {
+        let pattern = "a(foo";
+        let err = regex::Regex::new(pattern).unwrap_err();
+        let enhanced = EnhancedRegexError::from_regex_error(&err, pattern, RegexFlavor::PCRE);
+
+        assert_eq!(
+            enhanced.summary,
+            "unclosed group at position 1 in pattern `a(foo`"
+        );
+        assert!(enhanced.display().starts_with(
+            "Regex Error in PCRE (default) mode: unclosed group at position 1 in pattern `a(foo`\n"
+        ));
+    }
+
+    #[test]
+    fn test_summary_for_invalid_repetition() {
+        let pattern = "x|+y";
+        let err = regex::Regex::new(pattern).unwrap_err();
+        let enhanced = EnhancedRegexError::from_regex_error(&err, pattern, RegexFlavor::ERE);
+
+        assert_eq!(
+            enhanced.summary,
+            "repetition operator missing expression at position 2 in pattern `x|+y`"
+        );
+
+        let pattern = "a{3,1}";
+        let err = regex::Regex::new(pattern).unwrap_err();
+        let enhanced = EnhancedRegexError::from_regex_error(&err, pattern, RegexFlavor::PCRE);
+        assert!(
+            enhanced
+                .summary
+                .starts_with("invalid repetition count range"),
+            "{}",
+            enhanced.summary
+        );
+        assert!(enhanced.summary.ends_with("in pattern `a{3,1}`"));
+    }
+
     #[test]
     fn test_find_unclosed_delimiter() {
         assert_eq!(find_unclosed_delimiter("(foo", '(', ')'), Some(0));
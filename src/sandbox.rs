@@ -0,0 +1,84 @@
+//! Sandbox Mode (`--sandbox`)
+//!
+//! Rejects scripts that could touch files other than the inputs or run
+//! shell commands, mirroring GNU sed's `--sandbox`: the `e`, `r`, `R`, `w`
+//! and `W` commands and the `s///e` and `s///w` flags.
+
+use crate::command::Command;
+use anyhow::Result;
+
+/// Describe the first command or flag that sandbox mode forbids,
+/// searching inside groups
+fn find_disallowed(commands: &[Command]) -> Option<&'static str> {
+    commands.iter().find_map(|cmd| match cmd {
+        Command::Execute { .. } => Some("e command"),
+        Command::ReadFile { .. } => Some("r command"),
+        Command::ReadLine { .. } => Some("R command"),
+        Command::WriteFile { .. } => Some("w command"),
+        Command::WriteFirstLine { .. } => Some("W command"),
+        Command::Substitution { flags, .. } if flags.exec => Some("s///e flag"),
+        Command::Substitution { flags, .. } if flags.write_file.is_some() => Some("s///w flag"),
+        Command::Group { commands, .. } => find_disallowed(commands),
+        _ => None,
+    })
+}
+
+/// Fail if the script uses a command that is disabled in sandbox mode
+pub fn check_sandbox(commands: &[Command]) -> Result<()> {
+    if let Some(name) = find_disallowed(commands) {
+        anyhow::bail!(
+            "The {} is disabled in sandbox mode (--sandbox forbids e, r, R, w, W, s///e and s///w)",
+            name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::RegexFlavor;
+    use crate::parser::Parser;
+
+    fn check(script: &str) -> Result<()> {
+        let commands = Parser::new(RegexFlavor::PCRE).parse(script).unwrap();
+        check_sandbox(&commands)
+    }
+
+    #[test]
+    fn test_sandbox_allows_substitution_and_delete() {
+        assert!(check("s/foo/bar/g").is_ok());
+        assert!(check("5d").is_ok());
+        assert!(check("/x/{s/a/b/;p}").is_ok());
+    }
+
+    #[test]
+    fn test_sandbox_rejects_write() {
+        let err = check("w out.txt").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The w command is disabled in sandbox mode (--sandbox forbids e, r, R, w, W, s///e and s///w)"
+        );
+    }
+
+    #[test]
+    fn test_sandbox_rejects_io_and_exec_commands() {
+        for (script, name) in [
+            ("r in.txt", "The r command"),
+            ("R in.txt", "The R command"),
+            ("W out.txt", "The W command"),
+            ("e", "The e command"),
+            ("s/a/date/e", "The s///e flag"),
+            ("s/a/b/w out.txt", "The s///w flag"),
+        ] {
+            let err = check(script).unwrap_err().to_string();
+            assert!(err.starts_with(name), "{}: {}", script, err);
+        }
+    }
+
+    #[test]
+    fn test_sandbox_checks_inside_groups() {
+        let err = check("1,3{s/a/b/;w out.txt}").unwrap_err().to_string();
+        assert!(err.starts_with("The w command"), "{}", err);
+    }
+}
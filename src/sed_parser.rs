@@ -23,7 +23,7 @@ fn extract_context(full_text: &str, pos: usize) -> String {
 }
 
 /// Format an error with context and suggestions
-fn format_parse_error(
+pub(crate) fn format_parse_error(
     expression: &str,
     error_pos: Option<usize>,
     description: &str,
@@ -49,6 +49,7 @@ pub enum SedCommand {
         pattern: String,
         replacement: String,
         flags: Vec<char>,
+        write_file: Option<String>, // w flag: file to write changed lines to
         range: Option<(Address, Address)>, // Line range for substitution
     },
     Delete {
@@ -64,17 +65,19 @@ pub enum SedCommand {
     },
     Change {
         text: String,
-        address: Address, // Which line(s) to change
+        range: (Address, Address), // Which line(s) to change
     },
     Print {
         range: (Address, Address), // What to print
     },
     Quit {
         address: Option<Address>, // q or 10q or /pattern/q
+        exit_code: Option<i32>,   // 5q3 quits with status 3
     },
     // Phase 4: Quit without printing
     QuitWithoutPrint {
         address: Option<Address>, // Q or 10Q or /pattern/Q
+        exit_code: Option<i32>,   // 5Q3 quits with status 3
     },
     Group {
         range: Option<(Address, Address)>, // Optional range for the group
@@ -152,120 +155,223 @@ pub enum SedCommand {
     ClearPatternSpace {
         range: Option<Address>, // z - clear pattern space (optional address)
     },
+    List {
+        range: Option<(Address, Address)>, // l [width] - print pattern space unambiguously
+        width: Option<usize>,              // Line wrap width (None = default)
+    },
+    Execute {
+        command: Option<String>, // e [command] - run command (or the pattern space) in a shell
+        range: Option<(Address, Address)>, // Optional address/range
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Address {
     LineNumber(usize),
     Pattern(String),
-    FirstLine,             // Special address "0" for first-match substitution
-    LastLine,              // Special address "$" for last line
-    Negated(Box<Address>), // Negation: !/pattern/ or !10
+    MultilinePattern(String), // /pattern/M: ^ and $ also match at embedded newlines
+    FirstLine,                // "0": only starts a 0,/re/ range (see check_line_zero)
+    LastLine,                 // Special address "$" for last line
+    Negated(Box<Address>),    // Negation: !/pattern/ or !10
     // Chunk 8: New address types
     Relative { base: Box<Address>, offset: isize }, // /pattern/,+5 or 10,+3
     Step { start: usize, step: usize },             // 1~2 (every 2nd line from line 1)
+    MultipleOf(usize),                              // 2,~4 (up to the next multiple of 4)
 }
 
 pub fn parse_sed_expression(expr: &str) -> Result<Vec<SedCommand>> {
-    let mut commands = Vec::new();
-
-    // Handle multiple expressions separated by ;
-    // But skip semicolons inside braces { ... }
-    let mut current_expr = String::new();
-    let mut in_braces = 0;
-    let chars = expr.chars().peekable();
+    split_sed_expression(expr)
+        .into_iter()
+        .map(|(_, part)| {
+            let cmd = parse_single_command(part)?;
+            check_line_zero(part, &cmd)?;
+            Ok(cmd)
+        })
+        .collect()
+}
 
-    for c in chars {
-        match c {
-            '{' => {
-                in_braces += 1;
-                current_expr.push(c);
-            }
-            '}' => {
-                in_braces -= 1;
-                current_expr.push(c);
-            }
-            ';' if in_braces == 0 => {
-                // Semicolon at top level - command separator
-                let part = current_expr.trim();
-                if !part.is_empty() {
-                    commands.push(parse_single_command(part)?);
-                }
-                current_expr.clear();
-            }
-            _ => {
-                current_expr.push(c);
-            }
+/// Line 0 only means something at the start of a `0,/re/` range, which
+/// lets the regex end the range on line 1 itself. Anywhere else (`0p`,
+/// `0i\text`, `0,5d`) it is an error, as in GNU sed, rather than a second
+/// name for line 1.
+fn check_line_zero(part: &str, cmd: &SedCommand) -> Result<()> {
+    use SedCommand::*;
+
+    fn mentions_zero(addr: &Address) -> bool {
+        match addr {
+            Address::FirstLine => true,
+            Address::Negated(inner) => mentions_zero(inner),
+            Address::Relative { base, .. } => mentions_zero(base),
+            _ => false,
         }
     }
-
-    // Don't forget the last expression
-    let part = current_expr.trim();
-    if !part.is_empty() {
-        commands.push(parse_single_command(part)?);
+    fn ends_at_regex(addr: &Address) -> bool {
+        match addr {
+            Address::Pattern(_) | Address::MultilinePattern(_) => true,
+            Address::Negated(inner) => ends_at_regex(inner),
+            _ => false,
+        }
     }
 
-    Ok(commands)
-}
-
-/// Helper function to check if a position is inside a pattern address
-/// Pattern addresses are delimited by '/' or '\', e.g., /pattern/ or \pattern\
-/// Returns true if the position is inside the delimiters (not at the delimiters themselves)
-fn is_inside_pattern_address(cmd: &str, pos: usize) -> bool {
-    let bytes = cmd.as_bytes();
-    let n = bytes.len();
-
-    // We need to count delimiter pairs before the position
-    // Each pair consists of an opening delimiter and its matching closing delimiter
-    // We're "inside" if we've seen an odd number of opening delimiters before this position
-
-    // For simplicity, let's just look for the pattern: /.../ where pos is between the slashes
-    // We need to find the LAST '/' BEFORE pos and check if it has a matching '/' AFTER pos
-
-    // Find the last '/' or '\' before pos
-    let mut last_delim_before = None;
-    for i in (0..pos).rev() {
-        if bytes[i] == b'/' || bytes[i] == b'\\' {
-            last_delim_before = Some(i);
-            break;
+    // Single addresses are stored as a range from the address to itself,
+    // so a lone `0` is caught here as a range that doesn't end at a regex.
+    // A 0 end is fine, as in GNU sed: `2,0p` is just line 2.
+    let range_ok = |(start, end): &(Address, Address)| {
+        if *start == Address::FirstLine {
+            ends_at_regex(end)
+        } else {
+            !mentions_zero(start)
+        }
+    };
+    let valid = match cmd {
+        Substitution { range, .. }
+        | Hold { range }
+        | HoldAppend { range }
+        | Get { range }
+        | GetAppend { range }
+        | Exchange { range }
+        | Next { range }
+        | NextAppend { range }
+        | PrintFirstLine { range }
+        | DeleteFirstLine { range }
+        | Branch { range, .. }
+        | Test { range, .. }
+        | TestFalse { range, .. }
+        | List { range, .. }
+        | Execute { range, .. } => range.as_ref().is_none_or(range_ok),
+        Delete { range } | Change { range, .. } | Print { range } => range_ok(range),
+        Group { range, commands } => {
+            for inner in commands {
+                check_line_zero(part, inner)?;
+            }
+            range.as_ref().is_none_or(range_ok)
+        }
+        Insert { address, .. } | Append { address, .. } => !mentions_zero(address),
+        Quit { address, .. } | QuitWithoutPrint { address, .. } => {
+            address.as_ref().is_none_or(|addr| !mentions_zero(addr))
         }
+        ReadFile { range, .. }
+        | WriteFile { range, .. }
+        | ReadLine { range, .. }
+        | WriteFirstLine { range, .. }
+        | PrintLineNumber { range }
+        | PrintFilename { range }
+        | ClearPatternSpace { range } => range.as_ref().is_none_or(|addr| !mentions_zero(addr)),
+        Label { .. } => true,
+    };
+    if !valid {
+        bail!(
+            "{}",
+            format_parse_error(
+                part,
+                Some(0),
+                "invalid usage of line address 0",
+                Some(
+                    "Line 0 only starts a 0,/regex/ range, so the regex can match on line 1.\nUse 1 for the first line, e.g. 1i\\text"
+                ),
+            )
+        );
     }
+    Ok(())
+}
 
-    let start_pos = match last_delim_before {
-        Some(sp) => sp,
-        None => return false, // No delimiter before pos, so we're not inside
+/// Split a script into its top-level commands, each with its byte offset
+/// in `expr`. Commands are separated by `;` or a newline, except inside
+/// braces { ... }, and empty commands are dropped.
+pub fn split_sed_expression(expr: &str) -> Vec<(usize, &str)> {
+    let mut parts = Vec::new();
+    let mut push_part = |start: usize, end: usize| {
+        let part = &expr[start..end];
+        let trimmed = part.trim_start();
+        if !trimmed.trim_end().is_empty() {
+            parts.push((start + part.len() - trimmed.len(), trimmed.trim_end()));
+        }
     };
 
-    // Look for the NEXT '/' or '\' after start_pos
-    for i in (start_pos + 1)..n {
-        if bytes[i] == bytes[start_pos] {
-            // Same delimiter character
-            // Found matching closing delimiter
-            // Check if pos is between the delimiters
-            return pos > start_pos && pos < i;
+    let mut start = 0;
+    let mut in_braces = 0;
+    let mut escaped = false;
+
+    for (i, c) in expr.char_indices() {
+        // `\{` and `\}` are BRE interval braces, not a group
+        let was_escaped = escaped;
+        escaped = c == '\\' && !was_escaped;
+        match c {
+            '{' if !was_escaped => in_braces += 1,
+            '}' if !was_escaped => in_braces -= 1,
+            // A newline ends a command too, unless it is escaped, which
+            // continues the text of i, a and c on another line
+            ';' | '\n' if in_braces == 0 && (c == ';' || !was_escaped) => {
+                // Semicolon or newline at top level - command separator
+                push_part(start, i);
+                start = i + 1;
+            }
+            _ => {}
         }
     }
 
-    // No matching closing delimiter found
-    // Assume we're NOT inside (unclosed pattern)
-    false
+    // Don't forget the last expression
+    push_part(start, expr.len());
+    parts
+}
+
+/// Split an i, a or c command into its letter, address and text. Both the
+/// `a\text` form and GNU's one-line `a text` form are recognized.
+fn split_text_command(cmd: &str) -> Option<(char, &str, String)> {
+    let pos = skip_address(cmd);
+    let mut rest = cmd[pos..].chars();
+    let letter = rest.next().filter(|c| matches!(c, 'i' | 'a' | 'c'))?;
+    let after = &cmd[pos + 1..];
+
+    let text = if let Some(text) = after.strip_prefix('\\') {
+        // A newline right after the backslash is dropped (`a\` on a line
+        // of its own)
+        text.strip_prefix('\n').unwrap_or(text)
+    } else if after.starts_with(char::is_whitespace) && !after.trim().is_empty() {
+        after.trim_start()
+    } else {
+        return None;
+    };
+
+    // Each backslash-newline continues the text on another line
+    Some((letter, cmd[..pos].trim(), text.replace("\\\n", "\n")))
 }
 
 fn parse_single_command(cmd: &str) -> Result<SedCommand> {
     let cmd = cmd.trim();
 
     // Check for command grouping with braces
-    if cmd.contains('{') {
+    if find_group_brace(cmd).is_some() {
         return parse_group(cmd);
     }
 
+    // The e command takes an arbitrary shell command, which may contain
+    // anything (including "s/"), so it must be recognized before the others
+    if let Some(e_pos) = find_execute_command(cmd) {
+        return parse_execute(cmd, e_pos);
+    }
+
     // IMPORTANT: Check for substitution commands FIRST
     // because substitution commands can end with 'g' (global flag), 'p' (print flag), etc.
     // which would otherwise be misidentified as get/print/hold commands
-    if cmd.contains("s/") || cmd.contains("s#") || cmd.contains("s:") || cmd.contains("s|") {
+    if find_substitution(cmd).is_some() {
         return parse_substitution(cmd);
     }
 
+    // IMPORTANT: Check for insert/append/change commands next, because
+    // i\a\c commands take the rest of the command as text, which may end in
+    // a command letter (c\REPLACED) or contain 'b', 'r', 'w', '=' and so on
+    if let Some((letter, addr_part, text)) = split_text_command(cmd) {
+        return match letter {
+            // Insert command: addr i\text
+            'i' => parse_insert(cmd, addr_part, text),
+            // Append command: addr a\text
+            'a' => parse_append(cmd, addr_part, text),
+            // Change command: addr c\text
+            _ => parse_change(cmd, addr_part, text),
+        };
+    }
+
     // Check for hold space commands
     // These need to be checked carefully to avoid confusion with substitution patterns
     let last_char = cmd.chars().last().unwrap_or(' ');
@@ -335,9 +441,22 @@ fn parse_single_command(cmd: &str) -> Result<SedCommand> {
         return parse_label(cmd);
     }
 
+    let trimmed = cmd.trim();
+
+    // A filename may contain any command letter ("$r foot"), so a file
+    // command right after the address is recognized before the searches
+    // for command letters below
+    if let Some(file_command) = find_file_command(trimmed) {
+        return match file_command {
+            'r' => parse_read_file(cmd),
+            'R' => parse_read_line(cmd),
+            'w' => parse_write_file(cmd),
+            _ => parse_write_first_line(cmd),
+        };
+    }
+
     // Check for b/t/T commands anywhere in the command
     // Examples: "b", "b label", "10b", "10b label", "/pat/b label"
-    let trimmed = cmd.trim();
     if trimmed.contains('b') || trimmed.contains('t') || trimmed.contains('T') {
         // Verify it's actually a flow control command by checking the position
         // For "b", "b label", "10b", "10b label" - the b/t/T should be followed by space or end of string
@@ -413,22 +532,6 @@ fn parse_single_command(cmd: &str) -> Result<SedCommand> {
         }
     }
 
-    // IMPORTANT: Check for insert/append/change commands BEFORE file I/O
-    // because i\a\c commands use backslash followed by text, and the text may
-    // contain letters like 'r', 'R', 'w', 'W' that would be misidentified as file I/O
-    if cmd.contains("i\\") {
-        // Insert command: addr i\text
-        return parse_insert(cmd);
-    }
-    if cmd.contains("a\\") {
-        // Append command: addr a\text
-        return parse_append(cmd);
-    }
-    if cmd.contains("c\\") {
-        // Change command: addr c\text
-        return parse_change(cmd);
-    }
-
     // Check for r/R/w/W commands (file I/O) - AFTER i/a/c checks
     // Examples: "r /path/file", "5r file.txt", "/pat/r file"
     // These commands have filenames after them, so they don't "end with" the command char
@@ -449,10 +552,11 @@ fn parse_single_command(cmd: &str) -> Result<SedCommand> {
 
         // Filter out positions that are inside pattern addresses (between '/' characters)
         // Pattern addresses have the form /pattern/ or \pattern\
-        r_positions.retain(|&pos| !is_inside_pattern_address(trimmed, pos));
-        r_upper_positions.retain(|&pos| !is_inside_pattern_address(trimmed, pos));
-        w_positions.retain(|&pos| !is_inside_pattern_address(trimmed, pos));
-        w_upper_positions.retain(|&pos| !is_inside_pattern_address(trimmed, pos));
+        let command_pos = skip_address(trimmed);
+        r_positions.retain(|&pos| pos >= command_pos);
+        r_upper_positions.retain(|&pos| pos >= command_pos);
+        w_positions.retain(|&pos| pos >= command_pos);
+        w_upper_positions.retain(|&pos| pos >= command_pos);
 
         // Find which position comes first among the remaining (non-pattern) positions
         let all_positions: Vec<(usize, char)> = r_positions
@@ -482,7 +586,13 @@ fn parse_single_command(cmd: &str) -> Result<SedCommand> {
     }
 
     // Determine command type by looking at the last character or special patterns
-    if cmd.ends_with('Q') && !cmd.starts_with('s') {
+    if let Some(quit) = find_quit_command(cmd) {
+        // q or Q followed by an exit code: 5q3
+        match quit {
+            'Q' => parse_quit_without_print(cmd),
+            _ => parse_quit(cmd),
+        }
+    } else if cmd.ends_with('Q') && !cmd.starts_with('s') {
         // Quit without printing command (Phase 4)
         parse_quit_without_print(cmd)
     } else if cmd.ends_with('q') && !cmd.starts_with('s') {
@@ -494,6 +604,14 @@ fn parse_single_command(cmd: &str) -> Result<SedCommand> {
     } else if cmd.ends_with('p') && !cmd.starts_with('s') {
         // Print command (but not s/pattern/replacement/p which is a flag)
         parse_print(cmd)
+    } else if !cmd.starts_with('s')
+        && cmd
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .trim_end()
+            .ends_with('l')
+    {
+        // List command: l or l 40 (optional wrap width after the command)
+        parse_list(cmd)
     } else {
         // Try to determine by last character for other commands
         let command_char = cmd.chars().last().ok_or_else(|| anyhow!("Empty command"))?;
@@ -542,7 +660,8 @@ fn parse_single_command(cmd: &str) -> Result<SedCommand> {
                              i (insert), a (append), c (change), q (quit),\n\
                              h/H (hold), g/G (get), x (exchange), n/N (next),\n\
                              b/t/T (branch), r/R (read file), w/W (write file),\n\
-                             = (line number), F (filename), z (clear pattern space)".to_string()
+                             = (line number), F (filename), z (clear pattern space),\n\
+                             l (list), e (execute)".to_string()
                     }
                 };
 
@@ -561,28 +680,102 @@ fn parse_single_command(cmd: &str) -> Result<SedCommand> {
     }
 }
 
-fn parse_substitution(cmd: &str) -> Result<SedCommand> {
-    // Find the 's' that starts the substitution command
-    // It's the first 's' followed by a delimiter (/, #, :, etc.)
-    let bytes = cmd.as_bytes();
-    let mut s_pos = None;
-
-    for (i, &byte) in bytes.iter().enumerate() {
-        if byte == b's' && i + 1 < bytes.len() {
-            let next_byte = bytes[i + 1];
-            // Check if next char is a valid delimiter
-            if next_byte == b'/' || next_byte == b'#' || next_byte == b':' || next_byte == b'|' {
-                s_pos = Some(i);
-                break;
+/// Turn `\<delimiter>` back into a plain delimiter character, leaving
+/// every other escape (including `\\`) untouched
+fn unescape_delimiter(text: &str, delimiter: char) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(next) if next == delimiter => result.push(next),
+                Some(next) => {
+                    result.push('\\');
+                    result.push(next);
+                }
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Like `unescape_delimiter`, for a regex: when the delimiter is a regex
+/// metacharacter, `\<delimiter>` becomes a literal one (`s|a\|b|X|` matches
+/// `a|b`). The literal is written so every flavor reads it as one: `\.` for
+/// characters an escape makes literal everywhere, `[|]` for those that are
+/// operators when escaped in BRE. Inside a bracket expression the plain
+/// character is already literal.
+fn unescape_pattern_delimiter(text: &str, delimiter: char) -> String {
+    let literal = match delimiter {
+        '.' | '*' | '[' | ']' | '^' | '$' => format!("\\{}", delimiter),
+        '|' | '+' | '?' | '(' | ')' | '{' | '}' => format!("[{}]", delimiter),
+        _ => return unescape_delimiter(text, delimiter),
+    };
+
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    // Inside [...]: the first character (after any ^) may be a literal ]
+    let mut in_bracket = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => {
+                    if in_bracket {
+                        result.push(next);
+                    } else {
+                        result.push_str(&literal);
+                    }
+                }
+                Some(next) => {
+                    result.push('\\');
+                    result.push(next);
+                }
+                None => result.push('\\'),
+            },
+            '[' if !in_bracket => {
+                in_bracket = true;
+                result.push(c);
+                if let Some(caret) = chars.next_if_eq(&'^') {
+                    result.push(caret);
+                }
+                if let Some(close) = chars.next_if_eq(&']') {
+                    result.push(close);
+                }
+            }
+            // [:alpha:], [.x.] and [=x=] hold a ] of their own
+            '[' if matches!(chars.peek(), Some(':' | '.' | '=')) => {
+                let kind = chars.next().unwrap_or_default();
+                result.push(c);
+                result.push(kind);
+                while let Some(inner) = chars.next() {
+                    result.push(inner);
+                    if inner == kind && chars.peek() == Some(&']') {
+                        result.push(chars.next().unwrap_or_default());
+                        break;
+                    }
+                }
             }
+            ']' if in_bracket => {
+                in_bracket = false;
+                result.push(c);
+            }
+            _ => result.push(c),
         }
     }
+    result
+}
 
-    let s_pos = s_pos.ok_or_else(|| anyhow!("{}", format_parse_error(
+fn parse_substitution(cmd: &str) -> Result<SedCommand> {
+    // Find the 's' that starts the substitution command: the first
+    // character after the address, followed by a delimiter
+    let s_pos = find_substitution(cmd).ok_or_else(|| anyhow!("{}", format_parse_error(
         cmd,
         None,
         "'s' command not followed by a valid delimiter",
-        Some("Substitution format: s<delimiter>pattern<delimiter>replacement<delimiter>[flags]\nDelimiters: / (slash), # (hash), : (colon), | (pipe)\nExample: s/foo/bar/ or s#old#new#g"),
+        Some("Substitution format: s<delimiter>pattern<delimiter>replacement<delimiter>[flags]\nDelimiters: any character except letters, digits, whitespace and backslash\nExample: s/foo/bar/ or s#old#new#g or s,/usr,/opt,"),
     )))?;
 
     // Everything before 's' is the address/range
@@ -598,12 +791,18 @@ fn parse_substitution(cmd: &str) -> Result<SedCommand> {
             Some("Expected format: s<delimiter>pattern<delimiter>replacement<delimiter>[flags]\nExample: s/foo/bar/ or s#old#new#g"),
         )))?;
 
-    // Find all delimiter positions
+    // Find all delimiter positions, skipping backslash-escaped characters
+    // so that `s/a\/b/c/` splits after `a\/b`
     let mut delimiter_positions: Vec<usize> = Vec::new();
+    let mut escaped = false;
 
     // Use char_indices() to get correct byte positions for UTF-8 strings
     for (byte_pos, c) in rest.char_indices() {
-        if c == delimiter {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
             delimiter_positions.push(byte_pos);
         }
     }
@@ -642,53 +841,78 @@ fn parse_substitution(cmd: &str) -> Result<SedCommand> {
             _ => unreachable!(),
         };
 
+        // Point at the end of the command, where the delimiter is missing
         return Err(anyhow!(
             "{}",
-            format_parse_error(cmd, None, &description, suggestion,)
+            format_parse_error(
+                cmd,
+                Some(cmd.len()),
+                &format!("{} (at position {})", description, cmd.len() + 1),
+                suggestion,
+            )
         ));
     }
 
-    let pattern = &rest[delimiter_positions[0] + 1..delimiter_positions[1]];
-    let replacement_raw = &rest[delimiter_positions[1] + 1..delimiter_positions[2]];
-    let replacement = convert_sed_backreferences(replacement_raw);
-    let flags: Vec<char> = if delimiter_positions[2] + 1 < rest.len() {
-        rest[delimiter_positions[2] + 1..].chars().collect()
-    } else {
-        Vec::new()
+    let pattern = unescape_pattern_delimiter(
+        &rest[delimiter_positions[0] + 1..delimiter_positions[1]],
+        delimiter,
+    );
+    let replacement_raw = unescape_delimiter(
+        &rest[delimiter_positions[1] + 1..delimiter_positions[2]],
+        delimiter,
+    );
+    let replacement = convert_sed_backreferences(&replacement_raw, &group_names(&pattern));
+    // w must be the last flag: the rest of the command is the file name
+    let flag_text = &rest[delimiter_positions[2] + 1..];
+    let (flag_text, write_file) = match flag_text.find('w') {
+        Some(w_pos) => {
+            let filename = flag_text[w_pos + 1..].trim();
+            if filename.is_empty() {
+                return Err(anyhow!(
+                    "{}",
+                    format_parse_error(
+                        cmd,
+                        Some(cmd.len()),
+                        "missing file name after the w flag",
+                        Some(
+                            "The w flag takes the rest of the command as a file name:\n  s/foo/bar/w changed.txt"
+                        ),
+                    )
+                ));
+            }
+            (&flag_text[..w_pos], Some(filename.to_string()))
+        }
+        None => (flag_text, None),
     };
+    let flags: Vec<char> = flag_text.chars().collect();
 
     // Parse address/range if present
-    let range = if address_part.contains(',') {
+    let range = if let Some(comma_pos) = find_range_comma(address_part) {
         // Range: start,ends/pattern/replacement/
-        let parts: Vec<&str> = address_part.splitn(2, ',').collect();
-        if parts.len() == 2 {
-            let start = parse_address(parts[0])?;
-            let end_str = parts[1].trim();
-
-            // Chunk 8: Check if end has relative offset (+N or -N)
-            if end_str.starts_with('+') || end_str.starts_with('-') {
-                // Relative range: /pattern/,+5
-                let offset_str = &end_str[1..]; // Skip +/-
-                let offset: isize = offset_str.parse()
-                    .map_err(|_| anyhow!("{}", format_parse_error(
-                        cmd,
-                        None,
-                        &format!("invalid relative offset '{}'", end_str),
-                        Some("Relative offset format: start,+N or start,-N\nExample: /pattern/,+5  - 5 lines after pattern match\n         10,-3       - 3 lines before line 10"),
-                    )))?;
-
-                let end = Address::Relative {
-                    base: Box::new(start.clone()),
-                    offset,
-                };
-                Some((start, end))
-            } else {
-                // Normal range
-                let end = parse_address(end_str)?;
-                Some((start, end))
-            }
+        let start = parse_address(&address_part[..comma_pos])?;
+        let end_str = address_part[comma_pos + 1..].trim();
+
+        // Chunk 8: Check if end has relative offset (+N or -N)
+        if end_str.starts_with('+') || end_str.starts_with('-') {
+            // Relative range: /pattern/,+5
+            let offset_str = &end_str[1..]; // Skip +/-
+            let offset: isize = offset_str.parse()
+                .map_err(|_| anyhow!("{}", format_parse_error(
+                    cmd,
+                    None,
+                    &format!("invalid relative offset '{}'", end_str),
+                    Some("Relative offset format: start,+N or start,-N\nExample: /pattern/,+5  - 5 lines after pattern match\n         10,-3       - 3 lines before line 10"),
+                )))?;
+
+            let end = Address::Relative {
+                base: Box::new(start.clone()),
+                offset,
+            };
+            Some((start, end))
         } else {
-            None
+            // Normal range
+            let end = parse_address(end_str)?;
+            Some((start, end))
         }
     } else if !address_part.trim().is_empty() {
         // Single address: addrs/pattern/replacement/
@@ -699,9 +923,10 @@ fn parse_substitution(cmd: &str) -> Result<SedCommand> {
     };
 
     Ok(SedCommand::Substitution {
-        pattern: pattern.to_string(),
+        pattern,
         replacement: replacement.to_string(),
         flags,
+        write_file,
         range,
     })
 }
@@ -719,7 +944,7 @@ fn parse_delete(cmd: &str) -> Result<SedCommand> {
     }
 
     // Check for range: start,endd
-    if let Some(comma_pos) = addr_part.find(',') {
+    if let Some(comma_pos) = find_range_comma(addr_part) {
         let start = &addr_part[..comma_pos];
         let end = &addr_part[comma_pos + 1..];
 
@@ -748,7 +973,7 @@ fn parse_print(cmd: &str) -> Result<SedCommand> {
     }
 
     // Check for range: start,endp
-    if let Some(comma_pos) = addr_part.find(',') {
+    if let Some(comma_pos) = find_range_comma(addr_part) {
         let start = &addr_part[..comma_pos];
         let end = &addr_part[comma_pos + 1..];
 
@@ -765,45 +990,68 @@ fn parse_print(cmd: &str) -> Result<SedCommand> {
 }
 
 fn parse_quit(cmd: &str) -> Result<SedCommand> {
-    let cmd = cmd.trim();
-    let addr_part = &cmd[..cmd.len() - 1]; // Remove 'q'
-
-    // Check if there's an address
-    if addr_part.trim().is_empty() {
-        // Just 'q' - quit immediately
-        return Ok(SedCommand::Quit { address: None });
-    }
-
-    // '10q' or '/pattern/q' - quit at that address
-    let addr = parse_address(addr_part)?;
-    Ok(SedCommand::Quit {
-        address: Some(addr),
-    })
+    // 'q', '10q', '/pattern/q' or '5q3' (quit with exit status 3)
+    let (address, exit_code) = parse_quit_parts(cmd)?;
+    Ok(SedCommand::Quit { address, exit_code })
 }
 
 // Phase 4: Parse Q command (quit without printing)
 fn parse_quit_without_print(cmd: &str) -> Result<SedCommand> {
+    // 'Q', '10Q', '/pattern/Q' or '5Q3' - quit at that address without printing
+    let (address, exit_code) = parse_quit_parts(cmd)?;
+    Ok(SedCommand::QuitWithoutPrint { address, exit_code })
+}
+
+/// Split `[address]q [exit-code]` into its optional address and exit code
+fn parse_quit_parts(cmd: &str) -> Result<(Option<Address>, Option<i32>)> {
     let cmd = cmd.trim();
-    let addr_part = &cmd[..cmd.len() - 1]; // Remove 'Q'
 
-    // Check if there's an address
+    // Split off the optional exit code: "5q3" -> ("5q", "3")
+    let without_code = cmd.trim_end_matches(|c: char| c.is_ascii_digit());
+    let code_str = &cmd[without_code.len()..];
+    let without_code = without_code.trim_end();
+
+    let exit_code = if code_str.is_empty() {
+        None
+    } else {
+        Some(code_str.parse::<i32>().map_err(|_| {
+            anyhow!(
+                "{}",
+                format_parse_error(
+                    cmd,
+                    Some(without_code.len()),
+                    &format!("invalid exit code '{}'", code_str),
+                    Some("Quit format: [address]q [exit-code]\nExample: q, 10q, 5q3, /error/Q 1"),
+                )
+            )
+        })?)
+    };
+
+    let addr_part = &without_code[..without_code.len() - 1]; // Remove 'q' or 'Q'
     if addr_part.trim().is_empty() {
-        // Just 'Q' - quit immediately without printing
-        return Ok(SedCommand::QuitWithoutPrint { address: None });
+        return Ok((None, exit_code));
     }
+    Ok((Some(parse_address(addr_part)?), exit_code))
+}
 
-    // '10Q' or '/pattern/Q' - quit at that address without printing
-    let addr = parse_address(addr_part)?;
-    Ok(SedCommand::QuitWithoutPrint {
-        address: Some(addr),
-    })
+/// Find the `{` that opens a command group, skipping escaped `\{`
+/// (a BRE interval such as `a\{2,3\}`)
+fn find_group_brace(cmd: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in cmd.char_indices() {
+        if c == '{' && !escaped {
+            return Some(i);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    None
 }
 
 fn parse_group(cmd: &str) -> Result<SedCommand> {
     let cmd = cmd.trim();
 
     // Find the opening brace
-    let open_brace = cmd.find('{')
+    let open_brace = find_group_brace(cmd)
         .ok_or_else(|| anyhow!("{}", format_parse_error(
             cmd,
             None,
@@ -818,8 +1066,14 @@ fn parse_group(cmd: &str) -> Result<SedCommand> {
     let brace_start = open_brace + 1;
     let mut depth = 1;
     let mut close_brace = None;
+    let mut escaped = false;
 
-    for (i, c) in cmd[brace_start..].chars().enumerate() {
+    for (i, c) in cmd[brace_start..].char_indices() {
+        let was_escaped = escaped;
+        escaped = c == '\\' && !was_escaped;
+        if was_escaped {
+            continue;
+        }
         if c == '{' {
             depth += 1;
         } else if c == '}' {
@@ -845,31 +1099,21 @@ fn parse_group(cmd: &str) -> Result<SedCommand> {
     // Parse the range if present
     let range = if addr_part.is_empty() {
         None
-    } else if addr_part.contains(',') {
+    } else if let Some(comma_pos) = find_range_comma(addr_part) {
         // Range: start,end{...}
-        let parts: Vec<&str> = addr_part.splitn(2, ',').collect();
-        if parts.len() == 2 {
-            Some((
-                parse_address(parts[0].trim())?,
-                parse_address(parts[1].trim())?,
-            ))
-        } else {
-            None
-        }
+        Some((
+            parse_address(addr_part[..comma_pos].trim())?,
+            parse_address(addr_part[comma_pos + 1..].trim())?,
+        ))
     } else {
         // Single address: addr{...}
         let addr = parse_address(addr_part)?;
         Some((addr.clone(), addr))
     };
 
-    // Parse commands inside the group (separated by semicolons)
-    let mut commands = Vec::new();
-    for cmd_str in commands_str.split(';') {
-        let cmd_str = cmd_str.trim();
-        if !cmd_str.is_empty() {
-            commands.push(parse_single_command(cmd_str)?);
-        }
-    }
+    // Parse commands inside the group (separated by semicolons, which
+    // may also appear inside nested groups)
+    let commands = parse_sed_expression(commands_str)?;
 
     if commands.is_empty() {
         return Err(anyhow!(
@@ -888,26 +1132,10 @@ fn parse_group(cmd: &str) -> Result<SedCommand> {
     Ok(SedCommand::Group { range, commands })
 }
 
-fn parse_insert(cmd: &str) -> Result<SedCommand> {
-    // Insert: i\text or addr i\text
-    let parts: Vec<&str> = cmd.splitn(2, "i\\").collect();
-    if parts.len() != 2 {
-        // Check if it looks like user forgot the backslash
-        let suggestion = if cmd.contains('i') && !cmd.contains("i\\") {
-            Some(
-                "Insert command requires a backslash after 'i':\n  Format: [address]i\\text\n  Example: 5i\\INSERTED LINE\n  Example: /pattern/i\\New line before match",
-            )
-        } else {
-            Some("Valid insert format: [address]i\\text\nExample: 5i\\INSERTED LINE")
-        };
-        return Err(anyhow!(
-            "{}",
-            format_parse_error(cmd, None, "invalid insert command syntax", suggestion,)
-        ));
-    }
-
-    let address = if !parts[0].trim().is_empty() {
-        parse_address(parts[0].trim())?
+fn parse_insert(cmd: &str, addr_part: &str, text: String) -> Result<SedCommand> {
+    // Insert: addr i\text or addr i text
+    let address = if !addr_part.is_empty() {
+        parse_address(addr_part)?
     } else {
         return Err(anyhow!(
             "{}",
@@ -922,31 +1150,13 @@ fn parse_insert(cmd: &str) -> Result<SedCommand> {
         ));
     };
 
-    Ok(SedCommand::Insert {
-        text: parts[1].to_string(),
-        address,
-    })
+    Ok(SedCommand::Insert { text, address })
 }
 
-fn parse_append(cmd: &str) -> Result<SedCommand> {
-    // Append: a\text or addr a\text
-    let parts: Vec<&str> = cmd.splitn(2, "a\\").collect();
-    if parts.len() != 2 {
-        let suggestion = if cmd.contains('a') && !cmd.contains("a\\") {
-            Some(
-                "Append command requires a backslash after 'a':\n  Format: [address]a\\text\n  Example: 5a\\APPENDED LINE\n  Example: /pattern/a\\New line after match",
-            )
-        } else {
-            Some("Valid append format: [address]a\\text\nExample: 5a\\APPENDED LINE")
-        };
-        return Err(anyhow!(
-            "{}",
-            format_parse_error(cmd, None, "invalid append command syntax", suggestion,)
-        ));
-    }
-
-    let address = if !parts[0].trim().is_empty() {
-        parse_address(parts[0].trim())?
+fn parse_append(cmd: &str, addr_part: &str, text: String) -> Result<SedCommand> {
+    // Append: addr a\text or addr a text
+    let address = if !addr_part.is_empty() {
+        parse_address(addr_part)?
     } else {
         return Err(anyhow!(
             "{}",
@@ -961,31 +1171,13 @@ fn parse_append(cmd: &str) -> Result<SedCommand> {
         ));
     };
 
-    Ok(SedCommand::Append {
-        text: parts[1].to_string(),
-        address,
-    })
+    Ok(SedCommand::Append { text, address })
 }
 
-fn parse_change(cmd: &str) -> Result<SedCommand> {
-    // Change: c\text or addr c\text
-    let parts: Vec<&str> = cmd.splitn(2, "c\\").collect();
-    if parts.len() != 2 {
-        let suggestion = if cmd.contains('c') && !cmd.contains("c\\") {
-            Some(
-                "Change command requires a backslash after 'c':\n  Format: [address]c\\text\n  Example: 5c\\NEW LINE\n  Example: /pattern/c\\REPLACED TEXT",
-            )
-        } else {
-            Some("Valid change format: [address]c\\text\nExample: 5c\\NEW LINE")
-        };
-        return Err(anyhow!(
-            "{}",
-            format_parse_error(cmd, None, "invalid change command syntax", suggestion,)
-        ));
-    }
-
-    let address = if !parts[0].trim().is_empty() {
-        parse_address(parts[0].trim())?
+fn parse_change(cmd: &str, addr_part: &str, text: String) -> Result<SedCommand> {
+    // Change: addr c\text or addr c text
+    let range = if let Some(range) = parse_optional_range(addr_part)? {
+        range
     } else {
         return Err(anyhow!(
             "{}",
@@ -1000,10 +1192,7 @@ fn parse_change(cmd: &str) -> Result<SedCommand> {
         ));
     };
 
-    Ok(SedCommand::Change {
-        text: parts[1].to_string(),
-        address,
-    })
+    Ok(SedCommand::Change { text, range })
 }
 
 // Hold space command parsing functions
@@ -1110,7 +1299,7 @@ fn parse_optional_range(addr_part: &str) -> Result<Option<(Address, Address)>> {
         return Ok(None); // No address = applies to all lines
     }
 
-    if let Some(comma_pos) = addr_part.find(',') {
+    if let Some(comma_pos) = find_range_comma(addr_part) {
         // Range: addr1,addr2
         let start = &addr_part[..comma_pos];
         let end = &addr_part[comma_pos + 1..];
@@ -1163,6 +1352,13 @@ fn parse_address(addr: &str) -> Result<Address> {
         return Ok(Address::Negated(Box::new(parsed)));
     }
 
+    // GNU's M modifier after a pattern address (/re/M, \cREcM)
+    if let Some(inner_addr) = addr.strip_suffix('M')
+        && let Ok(Address::Pattern(pattern)) = parse_address(inner_addr)
+    {
+        return Ok(Address::MultilinePattern(pattern));
+    }
+
     // Special address: 0 (for first-match substitution)
     if addr == "0" {
         return Ok(Address::FirstLine);
@@ -1173,6 +1369,22 @@ fn parse_address(addr: &str) -> Result<Address> {
         return Ok(Address::LastLine);
     }
 
+    // Range end ~N: up to the next line whose number is a multiple of N
+    if let Some(multiple) = addr.strip_prefix('~') {
+        let n: usize = multiple.parse().map_err(|_| {
+            anyhow!(
+                "{}",
+                format_parse_error(
+                    addr,
+                    Some(1),
+                    &format!("invalid multiple '{}'", multiple),
+                    Some("Format: addr,~N - from addr up to the next line that is a multiple of N\nExample: 2,~4 - lines 2 to 4"),
+                )
+            )
+        })?;
+        return Ok(Address::MultipleOf(n));
+    }
+
     // Chunk 8: Stepping address: 1~2 (every 2nd line starting from line 1)
     if let Some(tilde_pos) = addr.find('~') {
         let start_str = &addr[..tilde_pos];
@@ -1215,6 +1427,28 @@ fn parse_address(addr: &str) -> Result<Address> {
         return Ok(Address::LineNumber(num));
     }
 
+    // Pattern with a custom delimiter: \cpatternc
+    if let Some(rest) = addr.strip_prefix('\\') {
+        let mut chars = rest.chars();
+        if let Some(delim) = chars.next() {
+            let body = chars.as_str();
+            if let Some(pattern) = body.strip_suffix(delim)
+                && (pattern.len() - pattern.trim_end_matches('\\').len()) % 2 == 0
+            {
+                return Ok(Address::Pattern(unescape_pattern_delimiter(pattern, delim)));
+            }
+        }
+        return Err(anyhow!(
+            "{}",
+            format_parse_error(
+                addr,
+                Some(addr.len()),
+                "pattern address is missing its closing delimiter",
+                Some("Custom-delimited address format: \\cPATTERNc\nExample: \\#/usr/local#d"),
+            )
+        ));
+    }
+
     // Pattern: /pattern/
     if addr.starts_with('/') && addr.ends_with('/') {
         let pattern = &addr[1..addr.len() - 1];
@@ -1258,7 +1492,7 @@ fn parse_address(addr: &str) -> Result<Address> {
             None,
             &format!("invalid address '{}'", addr),
             Some(
-                "Valid address formats:\n  - Line number: 5, 10, 42\n  - Last line: $\n  - Pattern: /regex/\n  - Range: 1,10 or /start/,/end/\n  - Stepping: 1~2 (every 2nd line)\n  - Relative: /pat/,+5 (5 lines after pattern match)"
+                "Valid address formats:\n  - Line number: 5, 10, 42\n  - Last line: $\n  - Pattern: /regex/\n  - Range: 1,10 or /start/,/end/\n  - Stepping: 1~2 (every 2nd line)\n  - Relative: /pat/,+5 (5 lines after pattern match)\n  - Up to a multiple: 2,~4 (lines 2 to 4)"
             ),
         )
     ))
@@ -1270,52 +1504,118 @@ fn parse_address(addr: &str) -> Result<Address> {
 /// Rust's `regex` crate uses `$1`, `$2`. This function converts between the two.
 ///
 /// Handles:
-/// - `\1`, `\2`, etc. → `$1`, `$2`, etc. (numbered backreferences)
+/// - `\1`, `\2`, etc. → `$1`, `$2`, etc. (numbered backreferences; `$1` is
+///   accepted as is)
+/// - `&` → `${0}` (entire match) and `\&` → `&` (literal ampersand)
 /// - `\\` → `\` (escaped backslash)
-/// - `\&` → `$&` (entire match)
-fn convert_sed_backreferences(replacement: &str) -> String {
+/// - `$name` → `${name}` when the pattern has a group called `name`
+///   (`(?P<name>...)`)
+/// - any other `$` → `$$` (literal dollar sign)
+fn convert_sed_backreferences(replacement: &str, group_names: &[&str]) -> String {
     let mut result = String::with_capacity(replacement.len());
     let mut chars = replacement.chars().peekable();
 
     while let Some(c) = chars.next() {
-        if c == '\\' {
-            if let Some(&next_char) = chars.peek() {
-                if next_char.is_ascii_digit() {
-                    // Convert \1, \2, etc. to $1, $2, etc.
-                    result.push('$');
-                    chars.next(); // consume the digit
-                    result.push(next_char);
-                } else if next_char == '\\' {
+        match c {
+            '\\' => match chars.peek().copied() {
+                Some(digit) if digit.is_ascii_digit() => {
+                    chars.next();
+                    push_group_reference(&mut result, &digit.to_string(), chars.peek());
+                }
+                Some('\\') => {
                     // Escaped backslash - keep one
                     result.push('\\');
-                    chars.next(); // consume second backslash
-                    if let Some(&third) = chars.peek() {
-                        chars.next();
-                        result.push(third);
-                    }
-                } else if next_char == '&' {
-                    // Matched string
-                    result.push('$');
+                    chars.next();
+                }
+                Some('&') => {
+                    // Literal ampersand
                     result.push('&');
                     chars.next();
-                } else {
+                }
+                Some('n') => {
+                    // \n inserts a newline, as in GNU sed
+                    result.push('\n');
+                    chars.next();
+                }
+                Some('$') => {
+                    // Literal dollar sign
+                    result.push_str("$$");
+                    chars.next();
+                }
+                Some(next) => {
                     // Other escape sequence - keep both
                     result.push(c);
-                    if let Some(next) = chars.next() {
-                        result.push(next);
+                    result.push(next);
+                    chars.next();
+                }
+                None => result.push(c),
+            },
+            // Matched string
+            '&' => result.push_str("${0}"),
+            '$' => match chars.peek().copied() {
+                Some(digit) if digit.is_ascii_digit() => {
+                    let mut group = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        group.push(digit);
                     }
+                    push_group_reference(&mut result, &group, chars.peek());
                 }
-            } else {
-                result.push(c);
-            }
-        } else {
-            result.push(c);
+                // ${1} and ${name} are already in regex crate style
+                Some('{') => result.push('$'),
+                Some(start) if start.is_ascii_alphabetic() || start == '_' => {
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                        name.push(c);
+                    }
+                    if group_names.contains(&name.as_str()) {
+                        result.push_str(&format!("${{{}}}", name));
+                    } else {
+                        result.push_str("$$");
+                        result.push_str(&name);
+                    }
+                }
+                _ => result.push_str("$$"),
+            },
+            _ => result.push(c),
         }
     }
 
     result
 }
 
+/// Names of the named groups in `pattern`: `(?P<name>...)` and `(?<name>...)`
+fn group_names(pattern: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    for (open, _) in pattern.match_indices("(?") {
+        let rest = &pattern[open + 2..];
+        let rest = rest.strip_prefix('P').unwrap_or(rest);
+        let Some(rest) = rest.strip_prefix('<') else {
+            continue;
+        };
+        // (?<= and (?<! are lookbehinds, not groups
+        if let Some(end) = rest.find('>')
+            && rest[..end]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        {
+            names.push(&rest[..end]);
+        }
+    }
+    names
+}
+
+/// Push `$group`, braced when the next character would otherwise be read as
+/// part of the group name (`\1st` → `${1}st`)
+fn push_group_reference(result: &mut String, group: &str, next: Option<&char>) {
+    if next.is_some_and(|&c| c.is_alphanumeric() || c == '_') {
+        result.push_str(&format!("${{{}}}", group));
+    } else {
+        result.push('$');
+        result.push_str(group);
+    }
+}
+
 // Phase 5: Parse label definition (:label)
 fn parse_label(cmd: &str) -> Result<SedCommand> {
     let cmd = cmd.trim();
@@ -1628,6 +1928,171 @@ fn parse_write_first_line(cmd: &str) -> Result<SedCommand> {
     })
 }
 
+// Parse list command (l [width])
+fn parse_list(cmd: &str) -> Result<SedCommand> {
+    let cmd = cmd.trim();
+
+    // Split off the optional width: "1,5l 40" -> ("1,5l", "40")
+    let without_width = cmd.trim_end_matches(|c: char| c.is_ascii_digit());
+    let width_str = &cmd[without_width.len()..];
+    let without_width = without_width.trim_end();
+
+    let width = if width_str.is_empty() {
+        None
+    } else {
+        Some(width_str.parse::<usize>().map_err(|_| {
+            anyhow!(
+                "{}",
+                format_parse_error(
+                    cmd,
+                    Some(without_width.len()),
+                    &format!("invalid line wrap width '{}'", width_str),
+                    Some("List format: [address]l [width]\nExample: l, l 40, 1,10l 0 (0 disables wrapping)"),
+                )
+            )
+        })?)
+    };
+
+    let addr_part = &without_width[..without_width.len() - 1]; // Remove 'l'
+    let range = parse_optional_range(addr_part)?;
+
+    Ok(SedCommand::List { range, width })
+}
+
+/// Locate the `q` or `Q` of an `[address]q [exit-code]` command, which must
+/// directly follow the address and be followed only by digits
+fn find_quit_command(cmd: &str) -> Option<char> {
+    let pos = skip_address(cmd);
+    let mut rest = cmd[pos..].chars();
+    match rest.next() {
+        Some(quit @ ('q' | 'Q')) if rest.as_str().trim().bytes().all(|b| b.is_ascii_digit()) => {
+            Some(quit)
+        }
+        _ => None,
+    }
+}
+
+/// The command character of an `[address]r file` style command (`r`, `R`,
+/// `w` or `W` followed by whitespace and a filename), if `cmd` is one
+fn find_file_command(cmd: &str) -> Option<char> {
+    let pos = skip_address(cmd);
+    let mut rest = cmd[pos..].chars();
+    match (rest.next(), rest.next()) {
+        (Some(command @ ('r' | 'R' | 'w' | 'W')), Some(next))
+            if next.is_whitespace() && !rest.as_str().trim().is_empty() =>
+        {
+            Some(command)
+        }
+        _ => None,
+    }
+}
+
+/// Locate the `e` of an `[address]e [command]` command. Everything before it
+/// must be address syntax (line numbers, `$`, `/regex/`, `,`, `!`, `+`, `~`),
+/// and it must be followed by whitespace or the end of the command.
+fn find_execute_command(cmd: &str) -> Option<usize> {
+    let pos = skip_address(cmd);
+    let mut rest = cmd[pos..].chars();
+    match (rest.next(), rest.next()) {
+        (Some('e'), None) => Some(pos),
+        (Some('e'), Some(next)) if next.is_whitespace() => Some(pos),
+        _ => None,
+    }
+}
+
+/// Byte offset of the command character, skipping a leading address or
+/// range such as `5`, `$`, `/re/`, `\#re#`, `1,+3`, `1~2`, `/re/M` or `/re/!`
+fn skip_address(cmd: &str) -> usize {
+    let mut chars = cmd.char_indices();
+    let mut delimiter = None;
+
+    while let Some((pos, c)) = chars.next() {
+        if let Some(delim) = delimiter {
+            if c == '\\' {
+                chars.next(); // Skip the escaped character
+            } else if c == delim {
+                delimiter = None;
+                // The M modifier belongs to the pattern
+                if chars.clone().next().is_some_and(|(_, c)| c == 'M') {
+                    chars.next();
+                }
+            }
+            continue;
+        }
+
+        match c {
+            '/' => delimiter = Some('/'),
+            // \cREGEXc - pattern address with a custom delimiter
+            '\\' => match chars.next() {
+                Some((_, delim)) => delimiter = Some(delim),
+                None => return pos,
+            },
+            c if c.is_ascii_digit() || c.is_whitespace() => {}
+            '$' | ',' | '!' | '+' | '~' => {}
+            _ => return pos,
+        }
+    }
+
+    cmd.len()
+}
+
+/// Position of the comma separating the two addresses of a range, ignoring
+/// commas inside `/re/` and `\cREc` patterns
+fn find_range_comma(addr: &str) -> Option<usize> {
+    let mut chars = addr.char_indices();
+    let mut delimiter = None;
+
+    while let Some((pos, c)) = chars.next() {
+        if let Some(delim) = delimiter {
+            if c == '\\' {
+                chars.next(); // Skip the escaped character
+            } else if c == delim {
+                delimiter = None;
+            }
+            continue;
+        }
+
+        match c {
+            ',' => return Some(pos),
+            '/' => delimiter = Some('/'),
+            '\\' => delimiter = chars.next().map(|(_, delim)| delim),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Any character but letters, digits, whitespace and backslash can
+/// delimit an `s` command, as in GNU sed
+fn is_valid_delimiter(c: char) -> bool {
+    !c.is_alphanumeric() && !c.is_whitespace() && c != '\\'
+}
+
+/// Position of the `s` that starts a substitution command, if `cmd` is one
+fn find_substitution(cmd: &str) -> Option<usize> {
+    let pos = skip_address(cmd);
+    let mut rest = cmd[pos..].chars();
+    match (rest.next(), rest.next()) {
+        (Some('s'), Some(delim)) if is_valid_delimiter(delim) => Some(pos),
+        _ => None,
+    }
+}
+
+fn parse_execute(cmd: &str, e_pos: usize) -> Result<SedCommand> {
+    let addr_part = &cmd[..e_pos];
+    let command = cmd[e_pos + 1..].trim();
+
+    let range = parse_optional_range(addr_part)?;
+    let command = if command.is_empty() {
+        None
+    } else {
+        Some(command.to_string())
+    };
+
+    Ok(SedCommand::Execute { command, range })
+}
+
 // Phase 5: Parse print line number command (=)
 fn parse_print_line_number(cmd: &str) -> Result<SedCommand> {
     let cmd = cmd.trim();
@@ -1707,9 +2172,173 @@ mod tests {
                 pattern: "foo".to_string(),
                 replacement: "bar".to_string(),
                 flags: vec!['g'],
+                write_file: None,
+                range: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_substitution_escaped_delimiter() {
+        let cmd = parse_single_command(r"s/a\/b/c/").unwrap();
+        assert!(
+            matches!(&cmd, SedCommand::Substitution { pattern, replacement, .. } if pattern == "a/b" && replacement == "c")
+        );
+
+        let cmd = parse_single_command(r"s|x|y\|z|g").unwrap();
+        assert!(
+            matches!(&cmd, SedCommand::Substitution { replacement, flags, .. } if replacement == "y|z" && flags == &['g'])
+        );
+
+        // Other escapes are kept for the regex engine
+        let cmd = parse_single_command(r"s/a\.b\\/c/").unwrap();
+        assert!(matches!(&cmd, SedCommand::Substitution { pattern, .. } if pattern == r"a\.b\\"));
+
+        // An escaped metacharacter delimiter is a literal in the pattern
+        assert_eq!(unescape_pattern_delimiter(r"a\|b", '|'), "a[|]b");
+        assert_eq!(unescape_pattern_delimiter(r"a\.b", '.'), r"a\.b");
+        assert_eq!(unescape_pattern_delimiter(r"[\|x]\|", '|'), "[|x][|]");
+        assert_eq!(
+            unescape_pattern_delimiter(r"[]\|[:alpha:]]", '|'),
+            "[]|[:alpha:]]"
+        );
+        assert_eq!(unescape_pattern_delimiter(r"a\#b", '#'), "a#b");
+    }
+
+    #[test]
+    fn test_parse_substitution_any_delimiter() {
+        let cmd = parse_single_command("s,a/b,c/d,").unwrap();
+        assert_eq!(
+            cmd,
+            SedCommand::Substitution {
+                pattern: "a/b".to_string(),
+                replacement: "c/d".to_string(),
+                flags: vec![],
+                write_file: None,
                 range: None,
             }
         );
+
+        let cmd = parse_single_command("2s@x@y@g").unwrap();
+        assert!(
+            matches!(&cmd, SedCommand::Substitution { pattern, range: Some((Address::LineNumber(2), _)), .. } if pattern == "x")
+        );
+
+        // A letter after `s` is not a delimiter
+        assert!(find_substitution("sat").is_none());
+    }
+
+    #[test]
+    fn test_parse_custom_delimiter_address() {
+        let cmd = parse_single_command(r"\#/usr/local#d").unwrap();
+        assert_eq!(
+            cmd,
+            SedCommand::Delete {
+                range: (
+                    Address::Pattern("/usr/local".to_string()),
+                    Address::Pattern("/usr/local".to_string())
+                ),
+            }
+        );
+
+        // Commas inside the pattern do not split a range
+        let cmd = parse_single_command(r"\,a\,b,,$s/x/y/").unwrap();
+        assert!(
+            matches!(&cmd, SedCommand::Substitution { range: Some((Address::Pattern(p), Address::LastLine)), .. } if p == "a,b")
+        );
+
+        assert!(parse_address(r"\#abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_newline_escape_and_m_modifier_in_address() {
+        // The `r` after `\n` is part of the pattern, not an r command
+        let cmd = parse_single_command(r"/foo\nbar/d").unwrap();
+        assert_eq!(
+            cmd,
+            SedCommand::Delete {
+                range: (
+                    Address::Pattern(r"foo\nbar".to_string()),
+                    Address::Pattern(r"foo\nbar".to_string())
+                ),
+            }
+        );
+
+        let cmd = parse_single_command(r"/^bar/M,\,x,M!p").unwrap();
+        assert_eq!(
+            cmd,
+            SedCommand::Print {
+                range: (
+                    Address::MultilinePattern("^bar".to_string()),
+                    Address::Negated(Box::new(Address::MultilinePattern("x".to_string())))
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_quit_exit_code() {
+        assert_eq!(
+            parse_single_command("5q3").unwrap(),
+            SedCommand::Quit {
+                address: Some(Address::LineNumber(5)),
+                exit_code: Some(3),
+            }
+        );
+        assert_eq!(
+            parse_single_command("/err/Q 1").unwrap(),
+            SedCommand::QuitWithoutPrint {
+                address: Some(Address::Pattern("err".to_string())),
+                exit_code: Some(1),
+            }
+        );
+        // A line number alone is still just an address
+        assert_eq!(
+            parse_single_command("10q").unwrap(),
+            SedCommand::Quit {
+                address: Some(Address::LineNumber(10)),
+                exit_code: None,
+            }
+        );
+
+        let err = parse_single_command("q99999999999")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid exit code '99999999999'"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_substitution_unterminated_reports_position() {
+        let err = parse_single_command("s/foo/bar").unwrap_err().to_string();
+        assert!(
+            err.contains("missing final delimiter to close the substitution (at position 10)"),
+            "{}",
+            err
+        );
+        assert!(err.contains("Near: \"s/foo/bar\""), "{}", err);
+
+        // An escaped final delimiter does not close the command
+        let err = parse_single_command(r"s/foo/bar\/")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("missing final delimiter"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_substitution_write_flag() {
+        let cmd = parse_single_command("s/foo/bar/gw out dir/matched.txt").unwrap();
+        assert!(
+            matches!(&cmd, SedCommand::Substitution { flags, write_file: Some(f), .. } if flags == &['g'] && f == "out dir/matched.txt")
+        );
+
+        let err = parse_single_command("s/foo/bar/w ")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("missing file name after the w flag"),
+            "{}",
+            err
+        );
     }
 
     #[test]
@@ -1721,6 +2350,7 @@ mod tests {
                 pattern: "foo".to_string(),
                 replacement: "bar".to_string(),
                 flags: vec![],
+                write_file: None,
                 range: Some((Address::LineNumber(10), Address::LineNumber(10))),
             }
         );
@@ -1735,6 +2365,7 @@ mod tests {
                 pattern: "foo".to_string(),
                 replacement: "bar".to_string(),
                 flags: vec![],
+                write_file: None,
                 range: Some((Address::LineNumber(1), Address::LineNumber(10))),
             }
         );
@@ -1798,43 +2429,98 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_zero_step_and_multiple_end() {
+        assert_eq!(
+            parse_single_command("0~3p").unwrap(),
+            SedCommand::Print {
+                range: (
+                    Address::Step { start: 0, step: 3 },
+                    Address::Step { start: 0, step: 3 }
+                ),
+            }
+        );
+        assert_eq!(
+            parse_single_command("2,~4p").unwrap(),
+            SedCommand::Print {
+                range: (Address::LineNumber(2), Address::MultipleOf(4)),
+            }
+        );
+        assert!(parse_single_command("2,~xp").is_err());
+    }
+
     // Bug 3: Backreference conversion tests
     #[test]
     fn test_backreference_conversion_single() {
-        let result = convert_sed_backreferences(r"\1");
+        let result = convert_sed_backreferences(r"\1", &[]);
         assert_eq!(result, "$1");
     }
 
     #[test]
     fn test_backreference_conversion_multiple() {
-        let result = convert_sed_backreferences(r"\1 \2 \3");
+        let result = convert_sed_backreferences(r"\1 \2 \3", &[]);
         assert_eq!(result, "$1 $2 $3");
     }
 
     #[test]
     fn test_backreference_conversion_mixed() {
-        let result = convert_sed_backreferences(r"foo \1 bar \2 baz");
+        let result = convert_sed_backreferences(r"foo \1 bar \2 baz", &[]);
         assert_eq!(result, "foo $1 bar $2 baz");
     }
 
     #[test]
     fn test_backreference_conversion_escaped_backslash() {
-        let result = convert_sed_backreferences(r"\\");
+        let result = convert_sed_backreferences(r"\\", &[]);
         assert_eq!(result, r"\");
     }
 
     #[test]
     fn test_backreference_conversion_ampersand() {
-        let result = convert_sed_backreferences(r"\&");
-        assert_eq!(result, "$&");
+        // & is the whole match; \& is a literal ampersand
+        assert_eq!(convert_sed_backreferences(r"[&]", &[]), "[${0}]");
+        assert_eq!(convert_sed_backreferences(r"\&", &[]), "&");
+        assert_eq!(convert_sed_backreferences(r"&\1x", &[]), "${0}${1}x");
+    }
+
+    #[test]
+    fn test_backreference_conversion_dollar() {
+        // A $ that isn't a group reference is literal
+        assert_eq!(convert_sed_backreferences("a$b", &[]), "a$$b");
+        assert_eq!(convert_sed_backreferences(r"cost: \$5", &[]), "cost: $$5");
+        assert_eq!(
+            convert_sed_backreferences("$1 ${2}x $12", &[]),
+            "$1 ${2}x $12"
+        );
+        assert_eq!(convert_sed_backreferences("$1st", &[]), "${1}st");
+        assert_eq!(convert_sed_backreferences("$PATH", &[]), "$$PATH");
+    }
+
+    #[test]
+    fn test_backreference_conversion_named_groups() {
+        // $name refers to a group the pattern names; ${name} always does
+        assert_eq!(convert_sed_backreferences("[$w]", &["w"]), "[${w}]");
+        assert_eq!(
+            convert_sed_backreferences("$w_1 $x", &["w_1"]),
+            "${w_1} $$x"
+        );
+        assert_eq!(convert_sed_backreferences("${x}", &[]), "${x}");
+        assert_eq!(
+            group_names(r"(?P<w>foo)(?<n2>\d)(?<=a)(?<!b)(x)"),
+            ["w", "n2"]
+        );
     }
 
     #[test]
     fn test_backreference_conversion_complex() {
-        let result = convert_sed_backreferences(r"\1: \2 \\ \1");
+        let result = convert_sed_backreferences(r"\1: \2 \\ \1", &[]);
         assert_eq!(result, r"$1: $2 \ $1");
     }
 
+    #[test]
+    fn test_backreference_conversion_newline() {
+        assert_eq!(convert_sed_backreferences(r"\1\n\2", &[]), "$1\n$2");
+    }
+
     // Bug 2: Command grouping tests
     #[test]
     fn test_parse_simple_group() {
@@ -1860,6 +2546,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_escaped_braces_are_not_a_group() {
+        let cmds = parse_sed_expression(r"s/a\{2,3\}/X/").unwrap();
+        assert!(
+            matches!(&cmds[..], [SedCommand::Substitution { pattern, .. }] if pattern == r"a\{2,3\}")
+        );
+
+        let cmd = parse_single_command(r"/x/{s/x\{2\}/Y/}").unwrap();
+        match cmd {
+            SedCommand::Group { commands, .. } => assert_eq!(commands.len(), 1),
+            _ => panic!("Expected Group command"),
+        }
+    }
+
     // Hold space command tests
     #[test]
     fn test_parse_hold_simple() {
@@ -1878,6 +2578,80 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_change_over_range() {
+        let cmd = parse_single_command(r"2,4c\X").unwrap();
+        assert_eq!(
+            cmd,
+            SedCommand::Change {
+                text: "X".to_string(),
+                range: (Address::LineNumber(2), Address::LineNumber(4))
+            }
+        );
+
+        // The trailing D of the text is not the D command
+        let cmd = parse_single_command(r"/START/,/END/c\REPLACED").unwrap();
+        assert_eq!(
+            cmd,
+            SedCommand::Change {
+                text: "REPLACED".to_string(),
+                range: (
+                    Address::Pattern("START".to_string()),
+                    Address::Pattern("END".to_string())
+                )
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_multiline_append() {
+        let cmds = parse_sed_expression("2a\\\nline1\\\nline2\n3d").unwrap();
+        assert_eq!(
+            cmds,
+            vec![
+                SedCommand::Append {
+                    text: "line1\nline2".to_string(),
+                    address: Address::LineNumber(2)
+                },
+                SedCommand::Delete {
+                    range: (Address::LineNumber(3), Address::LineNumber(3))
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_one_line_text_commands() {
+        assert_eq!(
+            parse_single_command("2a hello").unwrap(),
+            SedCommand::Append {
+                text: "hello".to_string(),
+                address: Address::LineNumber(2)
+            }
+        );
+        assert_eq!(
+            parse_single_command("/x/i note").unwrap(),
+            SedCommand::Insert {
+                text: "note".to_string(),
+                address: Address::Pattern("x".to_string())
+            }
+        );
+
+        // Letters inside patterns and replacements are not text commands
+        assert!(matches!(
+            parse_single_command("/a b/d").unwrap(),
+            SedCommand::Delete { .. }
+        ));
+        assert!(matches!(
+            parse_single_command("s/x/a b/").unwrap(),
+            SedCommand::Substitution { .. }
+        ));
+        assert!(matches!(
+            parse_single_command("/c d/h").unwrap(),
+            SedCommand::Hold { .. }
+        ));
+    }
+
     #[test]
     fn test_parse_hold_append_with_range() {
         let cmd = parse_single_command("1,5H").unwrap();
@@ -1951,4 +2725,118 @@ mod tests {
         let cmd = parse_single_command("x").unwrap();
         assert_eq!(cmd, SedCommand::Exchange { range: None });
     }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse_single_command("l").unwrap(),
+            SedCommand::List {
+                range: None,
+                width: None
+            }
+        );
+        assert_eq!(
+            parse_single_command("1,3l 20").unwrap(),
+            SedCommand::List {
+                range: Some((Address::LineNumber(1), Address::LineNumber(3))),
+                width: Some(20),
+            }
+        );
+        assert_eq!(
+            parse_single_command("/foo/l0").unwrap(),
+            SedCommand::List {
+                range: Some((
+                    Address::Pattern("foo".to_string()),
+                    Address::Pattern("foo".to_string())
+                )),
+                width: Some(0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_execute() {
+        assert_eq!(
+            parse_single_command("e").unwrap(),
+            SedCommand::Execute {
+                command: None,
+                range: None
+            }
+        );
+        assert_eq!(
+            parse_single_command("2e echo s/x/y/ | tr a-z A-Z").unwrap(),
+            SedCommand::Execute {
+                command: Some("echo s/x/y/ | tr a-z A-Z".to_string()),
+                range: Some((Address::LineNumber(2), Address::LineNumber(2))),
+            }
+        );
+        assert_eq!(
+            parse_single_command("/^cmd:/e").unwrap(),
+            SedCommand::Execute {
+                command: None,
+                range: Some((
+                    Address::Pattern("^cmd:".to_string()),
+                    Address::Pattern("^cmd:".to_string())
+                )),
+            }
+        );
+        // s///e is a substitution flag, not the e command
+        assert!(matches!(
+            parse_single_command("s/a/b/e").unwrap(),
+            SedCommand::Substitution { .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_file_io_not_mistaken_for_list() {
+        let cmd = parse_single_command("w out.html").unwrap();
+        assert!(matches!(cmd, SedCommand::WriteFile { .. }));
+    }
+
+    #[test]
+    fn test_parse_file_io_with_command_letters_in_filename() {
+        // "foot" ends in t and "b c.txt" has a b before a space, which used
+        // to be taken for t and b commands
+        let cmd = parse_single_command("$r foot").unwrap();
+        assert!(matches!(
+            cmd,
+            SedCommand::ReadFile { ref filename, range: Some(Address::LastLine) } if filename == "foot"
+        ));
+        let cmd = parse_single_command("2R b c.txt").unwrap();
+        assert!(matches!(
+            cmd,
+            SedCommand::ReadLine { ref filename, range: Some(Address::LineNumber(2)) } if filename == "b c.txt"
+        ));
+    }
+
+    #[test]
+    fn test_parse_line_zero_only_starts_regex_ranges() {
+        let cmds = parse_sed_expression("1i\\x").unwrap();
+        assert!(matches!(
+            cmds[0],
+            SedCommand::Insert {
+                address: Address::LineNumber(1),
+                ..
+            }
+        ));
+
+        for expr in ["0i\\x", "0p", "0,3d", "0!d", "0,+2d", "0q", "1{0p}"] {
+            let err = parse_sed_expression(expr).unwrap_err().to_string();
+            assert!(
+                err.contains("invalid usage of line address 0"),
+                "{expr}: {err}"
+            );
+        }
+
+        for expr in [
+            "0,/re/d",
+            "0,/re/!d",
+            "0,/re/Ms/a/b/",
+            "0~2p",
+            "1,0p",
+            "2,0p",
+        ] {
+            assert!(parse_sed_expression(expr).is_ok(), "{expr}");
+        }
+    }
 }
@@ -121,5 +121,25 @@ fi
 
 echo "✓ config --show shows debug setting"
 
+# Test --debug with debug disabled in the config
+echo "Testing --debug with debug = false..."
+sed -i 's/^debug = true/debug = false/' "$HOME/.sedx/config.toml"
+rm -f "$LOG_FILE"
+
+DEBUG_OUTPUT=$(echo "hello world" | "$SEDX" --debug 's/hello/HELLO/' 2>&1 >/dev/null)
+
+if [ ! -f "$LOG_FILE" ]; then
+    echo "FAIL: --debug did not create the log file"
+    exit 1
+fi
+
+if ! echo "$DEBUG_OUTPUT" | grep -q "Debug log: $LOG_FILE"; then
+    echo "FAIL: --debug did not print the log path"
+    echo "$DEBUG_OUTPUT"
+    exit 1
+fi
+
+echo "✓ --debug logs even when the config disables it"
+
 echo ""
 echo "=== All logging tests passed ==="
@@ -127,6 +127,180 @@ line 4"
 
 test_substitution "Group without range" "{s/line/LINE/g}" "$TEST4"
 
+echo ""
+echo "--- Keep-going Tests ---"
+echo -n "Testing: --keep-going applies the files it can ... "
+if [ "$(id -u)" -eq 0 ]; then
+    # root can write into read-only directories
+    echo "SKIPPED (running as root)"
+else
+    # Files are replaced by rename, so a read-only directory makes one
+    # file unwritable
+    mkdir -p "$TEMP_DIR/keep_ro"
+    printf 'a\n' > "$TEMP_DIR/keep_ro/file.txt"
+    printf 'a\n' > "$TEMP_DIR/keep_ok.txt"
+    chmod 555 "$TEMP_DIR/keep_ro"
+    $SEDX --keep-going --backup-dir "$TEMP_DIR/keep_backups" 's/a/b/' \
+        "$TEMP_DIR/keep_ro/file.txt" "$TEMP_DIR/keep_ok.txt" > /dev/null 2>&1
+    status=$?
+    if [ $status -ne 0 ] && [ "$(cat "$TEMP_DIR/keep_ok.txt")" = "b" ] \
+        && [ "$(cat "$TEMP_DIR/keep_ro/file.txt")" = "a" ]; then
+        echo -e "${GREEN}PASSED${NC}"
+        ((PASSED++))
+    else
+        echo -e "${RED}FAILED${NC}"
+        echo "  exit status: $status"
+        ((FAILED++))
+    fi
+    chmod 755 "$TEMP_DIR/keep_ro"
+fi
+
+echo "--- Duplicate File Tests ---"
+echo -n "Testing: a file listed twice or through a symlink is edited once ... "
+printf 'a\n' > "$TEMP_DIR/dup.txt"
+ln -sf "$TEMP_DIR/dup.txt" "$TEMP_DIR/dup_link.txt"
+$SEDX --backup-dir "$TEMP_DIR/dup_backups" 's/a/aa/' "$TEMP_DIR/dup.txt" \
+    "$TEMP_DIR/dup.txt" "$TEMP_DIR/./dup.txt" "$TEMP_DIR/dup_link.txt" > /dev/null 2>&1
+if [ "$(cat "$TEMP_DIR/dup.txt")" = "aa" ]; then
+    echo -e "${GREEN}PASSED${NC}"
+    ((PASSED++))
+else
+    echo -e "${RED}FAILED${NC}"
+    echo "  got: $(cat "$TEMP_DIR/dup.txt")"
+    ((FAILED++))
+fi
+
+echo "--- Multiple File Tests ---"
+echo -n "Testing: files edited in place stay separate unless --concatenate ... "
+printf '1\n2\n' > "$TEMP_DIR/multi_a.txt"
+printf '3\n' > "$TEMP_DIR/multi_b.txt"
+$SEDX --backup-dir "$TEMP_DIR/multi_backups" 'h;$!d;x' \
+    "$TEMP_DIR/multi_a.txt" "$TEMP_DIR/multi_b.txt" > /dev/null 2>&1
+separate="$(cat "$TEMP_DIR/multi_a.txt")|$(cat "$TEMP_DIR/multi_b.txt")"
+printf '1\n2\n' > "$TEMP_DIR/multi_a.txt"
+printf '3\n' > "$TEMP_DIR/multi_b.txt"
+$SEDX --backup-dir "$TEMP_DIR/multi_backups" --concatenate 'N;N;s/\n/+/g' \
+    "$TEMP_DIR/multi_a.txt" "$TEMP_DIR/missing.txt" "$TEMP_DIR/multi_b.txt" > /dev/null 2>&1
+rc=$?
+joined="$(cat "$TEMP_DIR/multi_a.txt")|$(cat "$TEMP_DIR/multi_b.txt")"
+if [ "$separate" = "2|3" ] && [ "$joined" = "|1+2+3" ] && [ $rc -eq 3 ]; then
+    echo -e "${GREEN}PASSED${NC}"
+    ((PASSED++))
+else
+    echo -e "${RED}FAILED${NC}"
+    echo "  separate: $separate, joined: $joined, exit code: $rc"
+    ((FAILED++))
+fi
+
+echo "--- Symlink Tests ---"
+echo -n "Testing: --follow-symlinks edits the link's target ... "
+printf 'a\n' > "$TEMP_DIR/sym_real.txt"
+ln -sf "$TEMP_DIR/sym_real.txt" "$TEMP_DIR/sym_link.txt"
+$SEDX --follow-symlinks --backup-dir "$TEMP_DIR/sym_backups" 's/a/b/' \
+    "$TEMP_DIR/sym_link.txt" > /dev/null 2>&1
+if [ -L "$TEMP_DIR/sym_link.txt" ] && [ "$(cat "$TEMP_DIR/sym_real.txt")" = "b" ]; then
+    echo -e "${GREEN}PASSED${NC}"
+    ((PASSED++))
+else
+    echo -e "${RED}FAILED${NC}"
+    ((FAILED++))
+fi
+
+echo -n "Testing: without --follow-symlinks the link is replaced ... "
+printf 'a\n' > "$TEMP_DIR/sym_real.txt"
+ln -sf "$TEMP_DIR/sym_real.txt" "$TEMP_DIR/sym_link.txt"
+$SEDX --backup-dir "$TEMP_DIR/sym_backups" 's/a/b/' "$TEMP_DIR/sym_link.txt" > /dev/null 2>&1
+if [ ! -L "$TEMP_DIR/sym_link.txt" ] && [ "$(cat "$TEMP_DIR/sym_link.txt")" = "b" ] \
+    && [ "$(cat "$TEMP_DIR/sym_real.txt")" = "a" ]; then
+    echo -e "${GREEN}PASSED${NC}"
+    ((PASSED++))
+else
+    echo -e "${RED}FAILED${NC}"
+    ((FAILED++))
+fi
+
+echo "--- Output Tests ---"
+echo -n "Testing: --no-preview prints only the summary and backup ID ... "
+printf 'a\nb\n' > "$TEMP_DIR/quiet_apply.txt"
+output=$($SEDX --no-preview --backup-dir "$TEMP_DIR/quiet_backups" 's/a/X/' \
+    "$TEMP_DIR/quiet_apply.txt" 2>&1)
+if [ "$(cat "$TEMP_DIR/quiet_apply.txt")" = "$(printf 'X\nb')" ] \
+    && ! echo "$output" | grep -q '^L1:\|^Total:' \
+    && echo "$output" | grep -q '^Will modify 1 file' \
+    && echo "$output" | grep -q '^Backup ID: '; then
+    echo -e "${GREEN}PASSED${NC}"
+    ((PASSED++))
+else
+    echo -e "${RED}FAILED${NC}"
+    echo "$output"
+    ((FAILED++))
+fi
+
+echo -n "Testing: the summary counts only changed files toward the backup ... "
+printf 'a\n' > "$TEMP_DIR/summary_changed.txt"
+printf 'unchanged line\n' > "$TEMP_DIR/summary_unchanged.txt"
+output=$($SEDX --no-preview --backup-dir "$TEMP_DIR/summary_backups" 's/^a$/X/' \
+    "$TEMP_DIR/summary_changed.txt" "$TEMP_DIR/summary_unchanged.txt" 2>&1)
+if echo "$output" | grep -q '^Will modify 1 file (2 B), backup 2 B to '; then
+    echo -e "${GREEN}PASSED${NC}"
+    ((PASSED++))
+else
+    echo -e "${RED}FAILED${NC}"
+    echo "$output"
+    ((FAILED++))
+fi
+
+echo "--- Standard Input Tests ---"
+echo -n "Testing: --stdin-filename is what F prints for piped input ... "
+output=$(printf 'key: 1\n' | $SEDX --stdin-filename config.yaml 'F' 2>&1)
+if [ "$output" = "$(printf 'config.yaml\nkey: 1')" ]; then
+    echo -e "${GREEN}PASSED${NC}"
+    ((PASSED++))
+else
+    echo -e "${RED}FAILED${NC}"
+    echo "  got: $output"
+    ((FAILED++))
+fi
+
+echo "--- Exec Tests ---"
+echo -n "Testing: s///e runs its command once, and never for --dry-run ... "
+printf 'go\n' > "$TEMP_DIR/exec.txt"
+rm -f "$TEMP_DIR/exec_marker"
+$SEDX --allow-exec --dry-run "s|go|echo run >> $TEMP_DIR/exec_marker|e" \
+    "$TEMP_DIR/exec.txt" > /dev/null 2>&1
+dry_run=$?
+$SEDX --allow-exec --backup-dir "$TEMP_DIR/exec_backups" \
+    "s|go|echo run >> $TEMP_DIR/exec_marker \&\& echo done|e" \
+    "$TEMP_DIR/exec.txt" > /dev/null 2>&1
+if [ $dry_run -ne 0 ] && [ "$(cat "$TEMP_DIR/exec_marker")" = "run" ] \
+    && [ "$(cat "$TEMP_DIR/exec.txt")" = "done" ]; then
+    echo -e "${GREEN}PASSED${NC}"
+    ((PASSED++))
+else
+    echo -e "${RED}FAILED${NC}"
+    echo "  marker: $(cat "$TEMP_DIR/exec_marker" 2>&1)"
+    ((FAILED++))
+fi
+
+echo "--- Exit Status Tests ---"
+echo -n "Testing: --require-change fails only when nothing would change ... "
+printf 'foo\n' > "$TEMP_DIR/require_change.txt"
+$SEDX --require-change --dry-run 's/foo/bar/' "$TEMP_DIR/require_change.txt" > /dev/null 2>&1
+matched=$?
+output=$($SEDX --require-change --dry-run 's/fooo/bar/' "$TEMP_DIR/require_change.txt" 2>&1)
+unmatched=$?
+$SEDX --dry-run 's/fooo/bar/' "$TEMP_DIR/require_change.txt" > /dev/null 2>&1
+default=$?
+if [ $matched -eq 0 ] && [ $unmatched -eq 4 ] && [ $default -eq 0 ] \
+    && echo "$output" | grep -q 'No changes would be made'; then
+    echo -e "${GREEN}PASSED${NC}"
+    ((PASSED++))
+else
+    echo -e "${RED}FAILED${NC}"
+    echo "  exit codes: matched=$matched unmatched=$unmatched default=$default"
+    ((FAILED++))
+fi
+
 echo ""
 echo "========================================"
 echo "  Results: $PASSED passed, $FAILED failed"